@@ -0,0 +1,25 @@
+#version 450
+
+// A corner of the unit quad, shared by every instance.
+layout(location = 0) in vec2 inCorner;
+
+// Where the instance's rect is in the window, and its color.
+layout(location = 1) in vec2 inOffset;
+layout(location = 2) in vec2 inSize;
+layout(location = 3) in vec4 inColor;
+
+layout(location = 0) out vec4 fragColor;
+
+layout (push_constant) uniform PushConstants
+{
+    vec2 scale;
+    vec2 translate;
+    mat2 rotation;
+    float depth;
+} constants;
+
+void main() {
+    vec2 position = (inOffset + inCorner * inSize) * constants.scale + constants.translate;
+    gl_Position = vec4(constants.rotation * position, constants.depth, 1.0);
+    fragColor = inColor;
+}
@@ -2,6 +2,9 @@
 
 layout(location = 0) in vec4 fragColor;
 
+// Must match `texture_set_layout_bindings()` in src/gfx/shared.rs.
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
 layout(location = 0) out vec4 outColor;
 
 void main() {
@@ -8,9 +8,13 @@ layout(location = 0) out vec4 fragColor;
 layout (push_constant) uniform PushConstants
 {
     vec2 scale;
+    vec2 translate;
+    mat2 rotation;
+    float depth;
 } constants;
 
 void main() {
-    gl_Position = vec4(inPosition * constants.scale + vec2(-1.0, -1.0), 0.0, 1.0);
+    vec2 position = inPosition * constants.scale + constants.translate;
+    gl_Position = vec4(constants.rotation * position, constants.depth, 1.0);
     fragColor = inColor;
 }
@@ -1,4 +1,9 @@
-use std::{iter::FromIterator, mem::MaybeUninit};
+use std::{
+    iter::FromIterator,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::{Bound, RangeBounds},
+};
 
 /// A fixed-capacity vector of T. Attempting to add elements beyond its
 /// capacity will cause a panic.
@@ -75,12 +80,49 @@ impl<T, const N: usize> ArrayVec<T, N> {
 
     /// Consumes the vector and calls a closure on every element, allowing it to
     /// drop at the end of the closure.
-    pub fn empty<F>(mut self, mut f: F)
+    pub fn empty<F>(mut self, f: F)
     where
         F: FnMut(T),
     {
-        for i in 0..self.length {
-            f(unsafe { std::ptr::read(self.as_mut_ptr().add(i)) });
+        self.drain(..).for_each(f);
+    }
+
+    /// Removes the elements in `range` from the vector, returning them in an
+    /// iterator. Elements after the range are shifted down to fill the gap
+    /// when the iterator is dropped, and any elements in the range that were
+    /// not iterated over are dropped along with it.
+    ///
+    /// # Panics
+    /// This function will panic if the range starts after it ends, or ends
+    /// after the end of the vector.
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> Drain<'_, T, N> {
+        let start = match range.start_bound() {
+            Bound::Included(i) => *i,
+            Bound::Excluded(i) => i + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(i) => i + 1,
+            Bound::Excluded(i) => *i,
+            Bound::Unbounded => self.length,
+        };
+
+        assert!(start <= end, "drain range starts after it ends");
+        assert!(end <= self.length, "drain range out of bounds");
+
+        let tail_len = self.length - end;
+
+        // If the Drain is leaked, the vector only keeps the elements before the
+        // range rather than exposing moved-out ones.
+        self.length = start;
+
+        Drain {
+            vec: self,
+            next: start,
+            end,
+            tail_len,
+            marker: PhantomData,
         }
     }
 
@@ -100,6 +142,55 @@ impl<T, const N: usize> ArrayVec<T, N> {
         }
     }
 
+    /// Inserts an element at `index`, shifting all elements after it to the
+    /// right.
+    ///
+    /// # Panics
+    /// This function will panic if `index > len()`, or if the vector is at
+    /// capacity.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(
+            index <= self.length,
+            "insertion index (is {}) should be <= len (is {})",
+            index,
+            self.length
+        );
+
+        if self.length == N {
+            panic!("ArrayVec out of capacity");
+        }
+
+        unsafe {
+            let ptr = self.as_mut_ptr().add(index);
+            std::ptr::copy(ptr, ptr.add(1), self.length - index);
+            ptr.write(value);
+        }
+
+        self.length += 1;
+    }
+
+    /// Removes and returns the element at `index`, shifting all elements after
+    /// it to the left.
+    ///
+    /// # Panics
+    /// This function will panic if `index >= len()`.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.length,
+            "removal index (is {}) should be < len (is {})",
+            index,
+            self.length
+        );
+
+        unsafe {
+            let ptr = self.as_mut_ptr().add(index);
+            let value = std::ptr::read(ptr);
+            std::ptr::copy(ptr.add(1), ptr, self.length - index - 1);
+            self.length -= 1;
+            value
+        }
+    }
+
     #[must_use]
     pub fn pop(&mut self) -> Option<T> {
         if self.length > 0 {
@@ -136,6 +227,55 @@ impl<T, const N: usize> ArrayVec<T, N> {
     }
 }
 
+/// An iterator over elements removed from an [`ArrayVec`]. See
+/// [`ArrayVec::drain()`].
+pub struct Drain<'a, T, const N: usize> {
+    vec: *mut ArrayVec<T, N>,
+    /// The next element to be yielded.
+    next: usize,
+    /// One past the last element in the drained range.
+    end: usize,
+    /// The number of elements after the drained range.
+    tail_len: usize,
+    marker: PhantomData<&'a mut ArrayVec<T, N>>,
+}
+
+impl<'a, T, const N: usize> Iterator for Drain<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.next < self.end {
+            let value = unsafe { std::ptr::read((*self.vec).as_ptr().add(self.next)) };
+            self.next += 1;
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a, T, const N: usize> ExactSizeIterator for Drain<'a, T, N> {}
+
+impl<'a, T, const N: usize> Drop for Drain<'a, T, N> {
+    fn drop(&mut self) {
+        // Drop the rest of the drained elements first.
+        self.for_each(drop);
+
+        unsafe {
+            let vec = &mut *self.vec;
+            let start = vec.length;
+            let ptr = vec.as_mut_ptr();
+            std::ptr::copy(ptr.add(self.end), ptr.add(start), self.tail_len);
+            vec.length = start + self.tail_len;
+        }
+    }
+}
+
 impl<T, const N: usize> Default for ArrayVec<T, N> {
     #[must_use]
     fn default() -> Self {
@@ -252,6 +392,53 @@ impl<T, const N: usize> FromIterator<T> for ArrayVec<T, N> {
     }
 }
 
+/// Serialized as a sequence. Deserializing a sequence with more than `N`
+/// elements fails rather than dropping the extra ones.
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use std::{fmt, marker::PhantomData};
+
+    use serde::{
+        de::{Error, SeqAccess, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    use super::ArrayVec;
+
+    impl<T: Serialize, const N: usize> Serialize for ArrayVec<T, N> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    impl<'de, T: Deserialize<'de>, const N: usize> Deserialize<'de> for ArrayVec<T, N> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_seq(ArrayVecVisitor(PhantomData))
+        }
+    }
+
+    struct ArrayVecVisitor<T, const N: usize>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>, const N: usize> Visitor<'de> for ArrayVecVisitor<T, N> {
+        type Value = ArrayVec<T, N>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a sequence of at most {} elements", N)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut vec = ArrayVec::new();
+            while let Some(value) = seq.next_element()? {
+                if vec.is_full() {
+                    return Err(A::Error::invalid_length(N + 1, &self));
+                }
+                vec.push(value);
+            }
+            Ok(vec)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -312,6 +499,126 @@ mod tests {
         unsafe { assert_eq!(K, 3) };
     }
 
+    #[test]
+    fn array_vec_insert_remove() {
+        let mut vec = ArrayVec::<u32, 5>::from_iter([1, 3]);
+
+        // Middle, front, and end
+        vec.insert(1, 2);
+        assert_eq!(vec.as_slice(), [1, 2, 3]);
+        vec.insert(0, 0);
+        assert_eq!(vec.as_slice(), [0, 1, 2, 3]);
+        vec.insert(4, 4);
+        assert_eq!(vec.as_slice(), [0, 1, 2, 3, 4]);
+        assert!(vec.is_full());
+
+        assert_eq!(vec.remove(2), 2);
+        assert_eq!(vec.as_slice(), [0, 1, 3, 4]);
+        assert_eq!(vec.remove(0), 0);
+        assert_eq!(vec.as_slice(), [1, 3, 4]);
+        assert_eq!(vec.remove(2), 4);
+        assert_eq!(vec.as_slice(), [1, 3]);
+        assert_eq!(vec.len(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_vec_insert_out_of_range() {
+        let mut vec = ArrayVec::<u32, 4>::from_iter([1]);
+        vec.insert(2, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_vec_insert_when_full() {
+        let mut vec = ArrayVec::<u32, 2>::from_iter([1, 2]);
+        vec.insert(0, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_vec_remove_out_of_range() {
+        let mut vec = ArrayVec::<u32, 4>::from_iter([1]);
+        vec.remove(1);
+    }
+
+    /// Counts how many times each of a set of values is dropped.
+    #[derive(Debug)]
+    struct Tracked<'a>(usize, &'a std::cell::RefCell<Vec<usize>>);
+
+    impl<'a> Drop for Tracked<'a> {
+        fn drop(&mut self) {
+            self.1.borrow_mut()[self.0] += 1;
+        }
+    }
+
+    fn tracked_vec(drops: &std::cell::RefCell<Vec<usize>>) -> ArrayVec<Tracked, 6> {
+        *drops.borrow_mut() = vec![0; 6];
+        (0..6).map(|i| Tracked(i, drops)).collect()
+    }
+
+    fn ids(vec: &ArrayVec<Tracked, 6>) -> Vec<usize> {
+        vec.iter().map(|t| t.0).collect()
+    }
+
+    #[test]
+    fn array_vec_drain_all() {
+        let drops = Default::default();
+        let mut vec = tracked_vec(&drops);
+
+        let drained = vec.drain(..).map(|t| t.0).collect::<Vec<_>>();
+        assert_eq!(drained, [0, 1, 2, 3, 4, 5]);
+        assert!(vec.is_empty());
+        assert_eq!(*drops.borrow(), [1; 6]);
+
+        drop(vec);
+        assert_eq!(*drops.borrow(), [1; 6]);
+    }
+
+    #[test]
+    fn array_vec_drain_middle() {
+        let drops = Default::default();
+        let mut vec = tracked_vec(&drops);
+
+        let drained = vec.drain(2..4).map(|t| t.0).collect::<Vec<_>>();
+        assert_eq!(drained, [2, 3]);
+        assert_eq!(ids(&vec), [0, 1, 4, 5]);
+        assert_eq!(*drops.borrow(), [0, 0, 1, 1, 0, 0]);
+
+        drop(vec);
+        assert_eq!(*drops.borrow(), [1; 6]);
+    }
+
+    #[test]
+    fn array_vec_drain_partially_consumed() {
+        let drops = Default::default();
+        let mut vec = tracked_vec(&drops);
+
+        {
+            let mut drain = vec.drain(1..=4);
+            assert_eq!(drain.len(), 4);
+            assert_eq!(drain.next().map(|t| t.0), Some(1));
+        }
+
+        // The rest of the range was dropped with the iterator.
+        assert_eq!(ids(&vec), [0, 5]);
+        assert_eq!(*drops.borrow(), [0, 1, 1, 1, 1, 0]);
+
+        drop(vec);
+        assert_eq!(*drops.borrow(), [1; 6]);
+    }
+
+    #[test]
+    fn array_vec_empty() {
+        let drops = Default::default();
+        let vec = tracked_vec(&drops);
+
+        let mut seen = vec![];
+        vec.empty(|t| seen.push(t.0));
+        assert_eq!(seen, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(*drops.borrow(), [1; 6]);
+    }
+
     #[test]
     fn array_vec_from_iter() {
         {
@@ -320,7 +627,7 @@ mod tests {
 
             assert_eq!(vec.len(), 0);
             assert_eq!(vec.len(), vec.capacity());
-            assert_eq!(vec.as_slice(), []);
+            assert_eq!(vec.as_slice(), [0u32; 0]);
         }
         {
             // Saturating N-sized array
@@ -338,4 +645,21 @@ mod tests {
             assert_eq!(vec.as_slice(), [100]);
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let vec = ArrayVec::<u32, 8>::from([3, 1, 4, 1, 5]);
+        let json = serde_json::to_string(&vec).unwrap();
+        assert_eq!(json, "[3,1,4,1,5]");
+
+        let back: ArrayVec<u32, 8> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_slice(), [3, 1, 4, 1, 5]);
+
+        let full: ArrayVec<u32, 8> = serde_json::from_str("[1,2,3,4,5,6,7,8]").unwrap();
+        assert!(full.is_full());
+
+        let too_long = serde_json::from_str::<ArrayVec<u32, 8>>("[1,2,3,4,5,6,7,8,9]");
+        assert!(too_long.is_err());
+    }
 }
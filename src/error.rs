@@ -0,0 +1,75 @@
+use ash::vk;
+
+use crate::{
+    gfx::{RenderThreadStopped, RenderTimeout},
+    sys::{LibraryError, WindowError},
+};
+
+/// Any of the errors that can be returned by maple's windowing and rendering
+/// APIs, for callers that don't need to tell them apart.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error(transparent)]
+    Window(#[from] WindowError),
+    #[error(transparent)]
+    Library(#[from] LibraryError),
+    #[error("Vulkan error: {0}")]
+    Vulkan(#[from] vk::Result),
+    #[error(transparent)]
+    RenderTimeout(#[from] RenderTimeout),
+    #[error(transparent)]
+    RenderThreadStopped(#[from] RenderThreadStopped),
+}
+
+impl Error {
+    /// The Vulkan result code that caused the error, if there was one.
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self {
+            Error::Vulkan(result) => Some(*result),
+            Error::Window(_)
+            | Error::Library(_)
+            | Error::RenderTimeout(_)
+            | Error::RenderThreadStopped(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        for result in [
+            vk::Result::ERROR_DEVICE_LOST,
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+            vk::Result::ERROR_SURFACE_LOST_KHR,
+        ] {
+            let error = Error::from(result);
+            assert_eq!(error, Error::Vulkan(result));
+            assert_eq!(error.vk_result(), Some(result));
+        }
+
+        let timeout = RenderTimeout { timeout_ns: 100 };
+        let error = Error::from(timeout);
+        assert_eq!(error, Error::RenderTimeout(timeout));
+        assert_eq!(error.vk_result(), None);
+        assert_eq!(error.to_string(), timeout.to_string());
+
+        let library = LibraryError::Load {
+            name: "vulkan-1.dll".to_owned(),
+            code: 126,
+        };
+        let error = Error::from(library.clone());
+        assert_eq!(error, Error::Library(library));
+        assert_eq!(error.vk_result(), None);
+
+        let window = WindowError { code: 1400 };
+        assert_eq!(Error::from(window), Error::Window(window));
+        assert_eq!(Error::from(window).vk_result(), None);
+
+        let error = Error::from(RenderThreadStopped);
+        assert_eq!(error, Error::RenderThreadStopped(RenderThreadStopped));
+        assert_eq!(error.vk_result(), None);
+    }
+}
@@ -1,42 +1,593 @@
-use crate::shapes::{Extent, Rect};
+use std::{
+    f32::consts::FRAC_PI_2,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use super::{Color, Vertex};
+use ahash::AHasher;
+
+use crate::{
+    px::{Physical, Px},
+    shapes::{Extent, Rect},
+};
+
+use super::{
+    shared::{is_front_facing, FAR_DEPTH},
+    Color, ColorFormat, Vertex,
+};
+
+/// Hands out a unique [`Canvas::generation()`] each time a canvas is cleared.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Default)]
 pub struct CanvasStorage {
+    mesh: Mesh,
+    /// The mesh drawn by the previous canvas, kept in case the next one draws
+    /// the same thing.
+    previous: Mesh,
+    /// A hash of the content that `previous` was drawn from, if it is still
+    /// valid.
+    previous_content: Option<u64>,
+    clip_stack: Vec<Rect>,
+    depth: f32,
+    /// How vertex colors are written, which must match the pipeline they are
+    /// drawn with.
+    color_format: ColorFormat,
+    /// The width of the feathered edge around filled shapes, in pixels.
+    aa_width: f32,
+}
+
+impl CanvasStorage {
+    /// Sets how colors are written to vertices. This must match the
+    /// [`RendererOptions::color_format`](super::RendererOptions::color_format)
+    /// of the renderer that the canvas is drawn with.
+    pub fn set_color_format(&mut self, format: ColorFormat) {
+        if self.color_format != format {
+            self.color_format = format;
+            self.force_invalidate();
+        }
+    }
+
+    /// Smooths the edges of rects and rounded rects by surrounding them with a
+    /// ring `aa_width` pixels wide that fades to transparent, as a cheaper
+    /// alternative to multisampling. The ring is drawn outside of the shape.
+    /// The default of 0 disables it, so that shapes cover exact pixels.
+    pub fn set_aa_width(&mut self, aa_width: f32) {
+        let aa_width = aa_width.max(0.0);
+        if self.aa_width != aa_width {
+            self.aa_width = aa_width;
+            self.force_invalidate();
+        }
+    }
+
+    /// Prevents the next canvas from reusing what was drawn before, such as
+    /// when the theme has changed without changing any draw commands.
+    pub fn force_invalidate(&mut self) {
+        self.mesh.content = None;
+        self.previous_content = None;
+    }
+}
+
+/// The tessellated output of a canvas.
+#[derive(Default)]
+struct Mesh {
     vertices: Vec<Vertex>,
+    index_format: IndexFormat,
     indices: Vec<u16>,
+    /// Used instead of `indices` once there are too many vertices for 16-bit
+    /// indices.
+    wide_indices: Vec<u32>,
+    batches: Vec<DrawBatch>,
+    generation: u64,
+    /// A hash of the content the mesh was drawn from, if it was set with
+    /// [`Canvas::reuse_if_unchanged()`].
+    content: Option<u64>,
+}
+
+/// The size of each index in an index buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+impl IndexFormat {
+    /// The size of an index in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Self::U16 => std::mem::size_of::<u16>(),
+            Self::U32 => std::mem::size_of::<u32>(),
+        }
+    }
+}
+
+impl Default for IndexFormat {
+    fn default() -> Self {
+        Self::U16
+    }
+}
+
+/// The indices of a canvas's triangles. 16-bit indices are used unless there
+/// are more vertices than they can address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indices<'a> {
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl<'a> Indices<'a> {
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            Self::U16(_) => IndexFormat::U16,
+            Self::U32(_) => IndexFormat::U32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U16(indices) => indices.len(),
+            Self::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The indices as they are laid out in memory.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        let (data, size) = match self {
+            Self::U16(indices) => (indices.as_ptr().cast(), std::mem::size_of_val(*indices)),
+            Self::U32(indices) => (indices.as_ptr().cast(), std::mem::size_of_val(*indices)),
+        };
+        unsafe { std::slice::from_raw_parts(data, size) }
+    }
+
+    /// The index at position `i`.
+    pub fn get(&self, i: usize) -> Option<u32> {
+        match self {
+            Self::U16(indices) => indices.get(i).map(|&index| index.into()),
+            Self::U32(indices) => indices.get(i).copied(),
+        }
+    }
+}
+
+/// A contiguous range of indices that share the same clip rect and depth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawBatch {
+    pub clip: Rect,
+    pub depth: f32,
+    pub first_index: u32,
+    pub num_indices: u32,
 }
 
 pub struct Canvas<'a> {
-    size: Extent,
+    size: Physical<Extent>,
     storage: &'a mut CanvasStorage,
 }
 
 impl<'a> Canvas<'a> {
-    pub fn new(size: Extent, storage: &'a mut CanvasStorage) -> Self {
-        storage.vertices.clear();
-        storage.indices.clear();
+    pub fn new(size: Physical<Extent>, storage: &'a mut CanvasStorage) -> Self {
+        // Keep the last mesh around, in case it can be reused.
+        std::mem::swap(&mut storage.mesh, &mut storage.previous);
+        storage.previous_content = storage.previous.content;
 
-        Self { size, storage }
+        let mut canvas = Self { size, storage };
+        canvas.clear();
+        canvas
     }
 
     pub fn clear(&mut self) {
-        self.storage.vertices.clear();
-        self.storage.indices.clear();
+        let mesh = &mut self.storage.mesh;
+        mesh.vertices.clear();
+        mesh.index_format = IndexFormat::U16;
+        mesh.indices.clear();
+        mesh.wide_indices.clear();
+        mesh.batches.clear();
+        mesh.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        mesh.content = None;
+        self.storage.clip_stack.clear();
+        self.storage.depth = FAR_DEPTH;
+        self.begin_batch(self.bounds());
+    }
+
+    /// Draws everything after this call on `layer`. Higher layers are drawn
+    /// over lower ones regardless of draw order, but only if the renderer has
+    /// a depth buffer. Everything is drawn on layer 0 by default.
+    pub fn set_layer(&mut self, layer: u16) {
+        self.storage.depth = FAR_DEPTH - f32::from(layer) / f32::from(u16::MAX);
+        self.begin_batch(self.clip());
+    }
+
+    /// Restricts drawing to `rect` until the matching call to `pop_clip()`.
+    /// Nested clips are intersected with each other.
+    pub fn push_clip(&mut self, rect: Rect) {
+        let clip = intersect_clip(self.clip(), rect);
+        self.storage.clip_stack.push(clip);
+        self.begin_batch(clip);
+    }
+
+    /// Restores the clip rect that was active before the last call to
+    /// `push_clip()`.
+    pub fn pop_clip(&mut self) {
+        let popped = self.storage.clip_stack.pop();
+        debug_assert!(popped.is_some(), "pop_clip() called without push_clip()");
+        self.begin_batch(self.clip());
+    }
+
+    /// The region of the canvas that can currently be drawn to.
+    pub fn clip(&self) -> Rect {
+        self.storage
+            .clip_stack
+            .last()
+            .copied()
+            .unwrap_or_else(|| self.bounds())
+    }
+
+    /// Restores what the previous canvas drew if it was drawn from the same
+    /// `content` at the same size, and returns `true`. Otherwise, the canvas
+    /// is cleared and `content` is recorded for next time.
+    ///
+    /// Call this before drawing anything, and only draw if it returns
+    /// `false`.
+    pub fn reuse_if_unchanged<T: Hash + ?Sized>(&mut self, content: &T) -> bool {
+        let mut hasher = AHasher::default();
+        self.size.hash(&mut hasher);
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self.storage.previous_content == Some(hash) {
+            std::mem::swap(&mut self.storage.mesh, &mut self.storage.previous);
+            self.storage.previous_content = None;
+            true
+        } else {
+            self.clear();
+            self.storage.mesh.content = Some(hash);
+            false
+        }
+    }
+
+    /// Identifies the current contents of the canvas. This changes whenever
+    /// the canvas is cleared, but is kept when the previous contents are
+    /// reused, so that they don't need to be copied to the GPU again.
+    pub fn generation(&self) -> u64 {
+        self.storage.mesh.generation
     }
 
-    pub fn size(&self) -> Extent {
+    pub fn batches(&self) -> &[DrawBatch] {
+        &self.storage.mesh.batches
+    }
+
+    pub fn size(&self) -> Physical<Extent> {
         self.size
     }
 
     pub fn vertices(&self) -> &[Vertex] {
-        &self.storage.vertices
+        &self.storage.mesh.vertices
+    }
+
+    pub fn indices(&self) -> Indices<'_> {
+        match self.storage.mesh.index_format {
+            IndexFormat::U16 => Indices::U16(&self.storage.mesh.indices),
+            IndexFormat::U32 => Indices::U32(&self.storage.mesh.wide_indices),
+        }
+    }
+
+    /// Draws `rect` with its corners rounded off to `radius` pixels. The
+    /// radius is clamped to half of the rect's smaller dimension.
+    pub fn draw_rounded(&mut self, rect: &Rect, radius: f32, color: Color) {
+        let radius = radius.min(f32::from(rect.width().min(rect.height())) / 2.0);
+        if radius <= 0.0 {
+            self.draw_styled(rect, color);
+            return;
+        }
+
+        let segments = corner_segments(radius);
+        let (left, top) = (f32::from(rect.left()), f32::from(rect.top()));
+        let (right, bottom) = (f32::from(rect.right()), f32::from(rect.bottom()));
+
+        // Corner centers, in the order that the outline visits them, along with
+        // the angle at which each corner's arc starts.
+        let corners = [
+            ((left + radius, top + radius), FRAC_PI_2),
+            ((left + radius, bottom - radius), 2.0 * FRAC_PI_2),
+            ((right - radius, bottom - radius), 3.0 * FRAC_PI_2),
+            ((right - radius, top + radius), 0.0),
+        ];
+
+        let center = ((left + right) / 2.0, (top + bottom) / 2.0);
+        let outline = corners.iter().flat_map(|&((x, y), start)| {
+            (0..=segments).map(move |i| {
+                // Angles are measured with y pointing up, so the outline is
+                // traversed counter-clockwise on screen.
+                let angle = start + (i as f32 / segments as f32) * FRAC_PI_2;
+                (x + radius * angle.cos(), y - radius * angle.sin())
+            })
+        });
+
+        self.push_fan(center, outline.clone(), color);
+        self.push_feather(outline, color);
+    }
+
+    /// Draws `rect` filled with `fill`, surrounded by a border `border_width`
+    /// pixels thick on the inside of the rect.
+    pub fn draw_bordered(&mut self, rect: &Rect, border_width: Px, fill: Color, border: Color) {
+        if border_width <= 0 {
+            self.draw_styled(rect, fill);
+            return;
+        }
+
+        if border_width * 2 >= rect.width().min(rect.height()) {
+            self.draw_styled(rect, border);
+            return;
+        }
+
+        let inner_height = rect.height() - border_width * 2;
+        let inner_width = rect.width() - border_width * 2;
+        let inner_y = rect.y() + border_width;
+
+        // Top and bottom span the full width; left and right fit between them.
+        // Only the outside of the border is feathered, since the pieces meet
+        // everywhere else.
+        self.push_rect(
+            &Rect::new(rect.x(), rect.y(), rect.width(), border_width),
+            border,
+        );
+        self.push_rect(
+            &Rect::new(
+                rect.x(),
+                rect.bottom() - border_width,
+                rect.width(),
+                border_width,
+            ),
+            border,
+        );
+        self.push_rect(
+            &Rect::new(rect.x(), inner_y, border_width, inner_height),
+            border,
+        );
+        self.push_rect(
+            &Rect::new(
+                rect.right() - border_width,
+                inner_y,
+                border_width,
+                inner_height,
+            ),
+            border,
+        );
+
+        self.push_rect(
+            &Rect::new(rect.x() + border_width, inner_y, inner_width, inner_height),
+            fill,
+        );
+
+        self.push_feather(
+            rect.points().map(|point| (point.x.into(), point.y.into())),
+            border,
+        );
+    }
+
+    /// Draws a straight line from `a` to `b`, `width` pixels wide. The line
+    /// does not extend past its end points.
+    pub fn draw_line(&mut self, a: (f32, f32), b: (f32, f32), width: f32, color: Color) {
+        let half_width = width / 2.0;
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = (dx * dx + dy * dy).sqrt();
+
+        if length == 0.0 {
+            // A zero-length line is drawn as a square dot.
+            self.push_quad(
+                [
+                    (a.0 - half_width, a.1 - half_width),
+                    (a.0 - half_width, a.1 + half_width),
+                    (a.0 + half_width, a.1 + half_width),
+                    (a.0 + half_width, a.1 - half_width),
+                ],
+                color,
+            );
+            return;
+        }
+
+        let (nx, ny) = (-dy / length * half_width, dx / length * half_width);
+        self.push_quad(
+            [
+                (a.0 + nx, a.1 + ny),
+                (a.0 - nx, a.1 - ny),
+                (b.0 - nx, b.1 - ny),
+                (b.0 + nx, b.1 + ny),
+            ],
+            color,
+        );
+    }
+
+    fn push_vertex(&mut self, position: (f32, f32), color: Color) -> u32 {
+        let index = self.storage.mesh.vertices.len() as u32;
+        let color = color.to_format(self.storage.color_format);
+        self.storage.mesh.vertices.push(Vertex { position, color });
+
+        if index > u16::MAX.into() && self.storage.mesh.index_format == IndexFormat::U16 {
+            let mesh = &mut self.storage.mesh;
+            mesh.index_format = IndexFormat::U32;
+            mesh.wide_indices.clear();
+            mesh.wide_indices
+                .extend(mesh.indices.iter().map(|&index| u32::from(index)));
+            mesh.indices.clear();
+        }
+
+        index
+    }
+
+    /// Pushes the indices of a triangle, reversing its winding if necessary so
+    /// that it is not culled by the pipeline.
+    fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| self.storage.mesh.vertices[i as usize].position);
+
+        if is_front_facing(pa, pb, pc) {
+            self.push_indices([a, b, c]);
+        } else {
+            self.push_indices([a, c, b]);
+        }
+    }
+
+    fn push_indices<const N: usize>(&mut self, indices: [u32; N]) {
+        match self.storage.mesh.index_format {
+            // push_vertex() switches to 32-bit indices before any index
+            // exceeds u16::MAX.
+            IndexFormat::U16 => self
+                .storage
+                .mesh
+                .indices
+                .extend(indices.iter().map(|&index| index as u16)),
+            IndexFormat::U32 => self.storage.mesh.wide_indices.extend_from_slice(&indices),
+        }
+        self.storage.mesh.batches.last_mut().unwrap().num_indices += N as u32;
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::from_extent(Px(0), Px(0), self.size.0)
+    }
+
+    /// Starts a new batch of indices drawn with `clip` at the current depth,
+    /// reusing the last batch if nothing was drawn with it.
+    fn begin_batch(&mut self, clip: Rect) {
+        let depth = self.storage.depth;
+        let first_index = self.indices().len() as u32;
+        match self.storage.mesh.batches.last_mut() {
+            Some(batch) if batch.num_indices == 0 => {
+                batch.clip = clip;
+                batch.depth = depth;
+            }
+            _ => self.storage.mesh.batches.push(DrawBatch {
+                clip,
+                depth,
+                first_index,
+                num_indices: 0,
+            }),
+        }
     }
 
-    pub fn indices(&self) -> &[u16] {
-        &self.storage.indices
+    /// Pushes the two triangles that fill `rect`, without feathering.
+    fn push_rect(&mut self, rect: &Rect, color: Color) {
+        let vertices = rect
+            .points()
+            .map(|point| self.push_vertex((point.x.into(), point.y.into()), color));
+
+        self.push_indices(Rect::INDICES.map(|index| vertices[index as usize]));
     }
+
+    /// Surrounds a convex outline, given counter-clockwise on screen, with a
+    /// ring that fades from `color` to transparent over
+    /// [`aa_width`](CanvasStorage::set_aa_width) pixels. Does nothing if
+    /// feathering is disabled.
+    fn push_feather(&mut self, outline: impl IntoIterator<Item = (f32, f32)>, color: Color) {
+        let width = self.storage.aa_width;
+        if width <= 0.0 {
+            return;
+        }
+
+        // Repeated points have no edge between them to take a normal from.
+        let mut points: Vec<(f32, f32)> = vec![];
+        for point in outline {
+            if !points.last().map_or(false, |&last| same_point(last, point)) {
+                points.push(point);
+            }
+        }
+
+        if points.len() > 1 && same_point(points[0], points[points.len() - 1]) {
+            points.pop();
+        }
+
+        if points.len() < 3 {
+            return;
+        }
+
+        let transparent = Color { a: 0, ..color };
+        let count = points.len();
+        let mut ring = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let previous = points[(i + count - 1) % count];
+            let point = points[i];
+            let next = points[(i + 1) % count];
+
+            // Offsetting along the average of the edges' normals, lengthened
+            // by the angle between them, keeps the ring the same width along
+            // both edges.
+            let before = outward_normal(previous, point);
+            let after = outward_normal(point, next);
+            let (x, y) = (before.0 + after.0, before.1 + after.1);
+            let length = (x * x + y * y).sqrt();
+            let (x, y) = (x / length, y / length);
+            let miter = width / (x * before.0 + y * before.1).max(0.5);
+
+            let inner = self.push_vertex(point, color);
+            let outer = self.push_vertex((point.0 + x * miter, point.1 + y * miter), transparent);
+            ring.push((inner, outer));
+        }
+
+        for i in 0..count {
+            let (inner, outer) = ring[i];
+            let (next_inner, next_outer) = ring[(i + 1) % count];
+            self.push_triangle(inner, outer, next_outer);
+            self.push_triangle(inner, next_outer, next_inner);
+        }
+    }
+
+    /// Pushes a quadrilateral with corners in either winding order.
+    fn push_quad(&mut self, corners: [(f32, f32); 4], color: Color) {
+        let [a, b, c, d] = corners.map(|p| self.push_vertex(p, color));
+        self.push_triangle(a, b, c);
+        self.push_triangle(a, c, d);
+    }
+
+    /// Pushes a triangle fan around `center`, closing the outline by joining
+    /// its last point back to its first. The shape must be star-shaped with
+    /// respect to `center` (e.g. any convex shape containing it).
+    fn push_fan(
+        &mut self,
+        center: (f32, f32),
+        outline: impl IntoIterator<Item = (f32, f32)>,
+        color: Color,
+    ) {
+        let center = self.push_vertex(center, color);
+        let first = self.storage.mesh.vertices.len() as u32;
+
+        for point in outline {
+            self.push_vertex(point, color);
+        }
+
+        let last = self.storage.mesh.vertices.len() as u32 - 1;
+        for i in first..last {
+            self.push_triangle(center, i, i + 1);
+        }
+        self.push_triangle(center, last, first);
+    }
+}
+
+/// Intersects two clip rects. Disjoint rects produce an empty clip rect
+/// instead of [`None`] so that the clip stack always has something in it.
+fn intersect_clip(outer: Rect, inner: Rect) -> Rect {
+    outer
+        .intersect(inner)
+        .unwrap_or_else(|| Rect::from_extent(outer.x(), outer.y(), Extent::default()))
+}
+
+fn same_point(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+}
+
+/// The unit normal pointing out of a counter-clockwise (on screen) outline
+/// for the edge from `a` to `b`.
+fn outward_normal(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    (-dy / length, dx / length)
+}
+
+/// The number of line segments used to approximate a quarter circle of the
+/// given radius, so that larger corners stay smooth.
+fn corner_segments(radius: f32) -> u16 {
+    ((radius.sqrt() * 2.0).ceil() as u16).clamp(1, 16)
 }
 
 pub trait Draw<T> {
@@ -49,17 +600,385 @@ pub trait DrawStyled<T> {
 
 impl<'a> DrawStyled<Rect> for Canvas<'a> {
     fn draw_styled(&mut self, shape: &Rect, color: Color) {
-        let offset = self.storage.vertices.len() as u16;
+        self.push_rect(shape, color);
+        self.push_feather(
+            shape.points().map(|point| (point.x.into(), point.y.into())),
+            color,
+        );
+    }
+}
 
-        for point in &shape.points() {
-            self.storage.vertices.push(Vertex {
-                position: (point.x.into(), point.y.into()),
-                color,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Point;
+
+    #[test]
+    fn clip_intersection() {
+        let outer = Rect::new(Px(0), Px(0), Px(100), Px(100));
+
+        // Overlapping
+        assert_eq!(
+            intersect_clip(outer, Rect::new(Px(50), Px(-10), Px(100), Px(20))),
+            Rect::new(Px(50), Px(0), Px(50), Px(10))
+        );
+
+        // Contained
+        let inner = Rect::new(Px(10), Px(10), Px(10), Px(10));
+        assert_eq!(intersect_clip(outer, inner), inner);
+
+        // Disjoint
+        let clip = intersect_clip(outer, Rect::new(Px(200), Px(0), Px(10), Px(10)));
+        assert_eq!(clip.extent, Extent::default());
+
+        // Touching edges don't overlap
+        let clip = intersect_clip(outer, Rect::new(Px(100), Px(0), Px(10), Px(10)));
+        assert_eq!(clip.extent, Extent::default());
+    }
+
+    #[test]
+    fn nested_clips_produce_batches() {
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        let rect = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let color = Color::rgb(0, 0, 0);
+
+        canvas.draw_styled(&rect, color);
+        canvas.push_clip(Rect::new(Px(10), Px(10), Px(50), Px(50)));
+        canvas.push_clip(Rect::new(Px(30), Px(30), Px(50), Px(50)));
+        canvas.draw_styled(&rect, color);
+        canvas.pop_clip();
+        canvas.pop_clip();
+        canvas.draw_styled(&rect, color);
+
+        assert_eq!(
+            canvas.batches(),
+            [
+                DrawBatch {
+                    clip: Rect::new(Px(0), Px(0), Px(100), Px(100)),
+                    depth: FAR_DEPTH,
+                    first_index: 0,
+                    num_indices: 6,
+                },
+                DrawBatch {
+                    clip: Rect::new(Px(30), Px(30), Px(30), Px(30)),
+                    depth: FAR_DEPTH,
+                    first_index: 6,
+                    num_indices: 6,
+                },
+                DrawBatch {
+                    clip: Rect::new(Px(0), Px(0), Px(100), Px(100)),
+                    depth: FAR_DEPTH,
+                    first_index: 12,
+                    num_indices: 6,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rounded_corner_tessellation() {
+        let rect = Rect::new(Px(10), Px(20), Px(40), Px(30));
+        let color = Color::rgb(0, 0, 0);
+
+        let mut vertex_counts = vec![];
+        for radius in [1.0, 4.0, 9.0, 100.0] {
+            let mut storage = CanvasStorage::default();
+            let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+            canvas.draw_rounded(&rect, radius, color);
+
+            // The center, plus each corner's arc.
+            let segments = corner_segments(radius.min(15.0));
+            assert_eq!(canvas.vertices().len(), 1 + 4 * (segments as usize + 1));
+            vertex_counts.push(canvas.vertices().len());
+
+            let (min, max) = canvas.vertices().iter().fold(
+                ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+                |(min, max), v| {
+                    let (x, y) = v.position;
+                    ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+                },
+            );
+            assert!((min.0 - 10.0).abs() < 1e-4 && (min.1 - 20.0).abs() < 1e-4);
+            assert!((max.0 - 50.0).abs() < 1e-4 && (max.1 - 50.0).abs() < 1e-4);
+        }
+
+        assert!(vertex_counts.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn shape_geometry() {
+        let fill = Color::rgb(1, 2, 3);
+        let border = Color::rgb(4, 5, 6);
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+
+        let positions = |canvas: &Canvas| {
+            canvas
+                .vertices()
+                .iter()
+                .map(|v| v.position)
+                .collect::<Vec<_>>()
+        };
+        let colors = |canvas: &Canvas| {
+            canvas
+                .vertices()
+                .iter()
+                .map(|v| v.color)
+                .collect::<Vec<_>>()
+        };
+        // The vertices of each triangle in ascending order, since triangles
+        // may be flipped to face the front.
+        let triangles = |canvas: &Canvas| {
+            let indices = canvas.indices();
+            (0..indices.len())
+                .step_by(3)
+                .map(|i| {
+                    let mut triangle = [0, 1, 2].map(|j| indices.get(i + j).unwrap());
+                    triangle.sort_unstable();
+                    triangle
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // A line is a quad `width` pixels wide, centered on the line.
+        canvas.draw_line((10.0, 10.0), (10.0, 30.0), 4.0, fill);
+        assert_eq!(
+            positions(&canvas),
+            [(8.0, 10.0), (12.0, 10.0), (12.0, 30.0), (8.0, 30.0)]
+        );
+        assert_eq!(triangles(&canvas), [[0, 1, 2], [0, 2, 3]]);
+
+        // A line with no length is a square dot.
+        canvas.clear();
+        canvas.draw_line((5.0, 5.0), (5.0, 5.0), 2.0, fill);
+        assert_eq!(
+            positions(&canvas),
+            [(4.0, 4.0), (4.0, 6.0), (6.0, 6.0), (6.0, 4.0)]
+        );
+        assert_eq!(triangles(&canvas), [[0, 1, 2], [0, 2, 3]]);
+
+        // A bordered rect is four border rects around the fill.
+        let rect = Rect::new(Px(0), Px(0), Px(10), Px(8));
+        canvas.clear();
+        canvas.draw_bordered(&rect, Px(2), fill, border);
+        assert_eq!(canvas.vertices().len(), 5 * 4);
+        assert_eq!(canvas.indices().len(), 5 * 6);
+        assert!(colors(&canvas)[..16].iter().all(|&c| c == border));
+        assert!(colors(&canvas)[16..].iter().all(|&c| c == fill));
+        let inner = &positions(&canvas)[16..];
+        for corner in [(2.0, 2.0), (8.0, 2.0), (8.0, 6.0), (2.0, 6.0)] {
+            assert!(inner.contains(&corner), "{:?}", corner);
+        }
+
+        // Borders that leave no room for the fill, or have no width, draw a
+        // single rect.
+        for (width, color) in [(Px(0), fill), (Px(4), border)] {
+            canvas.clear();
+            canvas.draw_bordered(&rect, width, fill, border);
+            assert_eq!(colors(&canvas), [color; 4]);
+            assert_eq!(canvas.indices().len(), 6);
+        }
+
+        // A rounded rect is a fan around its center, with an arc of
+        // `corner_segments() + 1` points at each corner.
+        let rect = Rect::new(Px(0), Px(0), Px(20), Px(10));
+        canvas.clear();
+        canvas.draw_rounded(&rect, 4.0, fill);
+        let outline = 4 * (corner_segments(4.0) as u32 + 1);
+        assert_eq!(canvas.vertices().len(), 1 + outline as usize);
+        assert_eq!(positions(&canvas)[0], (10.0, 5.0));
+        let fan = (1..outline)
+            .map(|i| [0, i, i + 1])
+            .chain([[0, 1, outline]])
+            .collect::<Vec<_>>();
+        assert_eq!(triangles(&canvas), fan);
+
+        // Without a radius, it is a plain rect.
+        canvas.clear();
+        canvas.draw_rounded(&rect, 0.0, fill);
+        assert_eq!(canvas.vertices().len(), 4);
+        assert_eq!(canvas.indices().len(), 6);
+    }
+
+    #[test]
+    fn feathered_edges() {
+        let rect = Rect::new(Px(10), Px(20), Px(40), Px(30));
+        let color = Color::rgb(10, 20, 30);
+
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        canvas.draw_styled(&rect, color);
+        assert_eq!(canvas.vertices().len(), 4);
+
+        let mut storage = CanvasStorage::default();
+        storage.set_aa_width(1.5);
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        canvas.draw_styled(&rect, color);
+
+        // The fill, then an inner and outer vertex for each corner.
+        let vertices = canvas.vertices();
+        assert_eq!(vertices.len(), 4 + 8);
+        assert!(vertices[..4].iter().all(|v| v.color == color));
+        for pair in vertices[4..].chunks(2) {
+            assert_eq!(pair[0].color, color);
+            assert_eq!(pair[1].color.a, 0);
+
+            // Corners are pushed out diagonally by the feather width.
+            let (dx, dy) = (
+                pair[1].position.0 - pair[0].position.0,
+                pair[1].position.1 - pair[0].position.1,
+            );
+            assert!((dx.abs() - 1.5).abs() < 1e-4 && (dy.abs() - 1.5).abs() < 1e-4);
+            assert!(!rect.contains_point(Point::new(
+                Px(pair[1].position.0.round() as i16),
+                Px(pair[1].position.1.round() as i16)
+            )));
+        }
+
+        // Two triangles for each side of the ring.
+        assert_eq!(canvas.indices().len(), 6 + 4 * 6);
+
+        // Rounded rects fade out radially, even where their corners meet.
+        canvas.clear();
+        canvas.draw_rounded(&Rect::new(Px(0), Px(0), Px(20), Px(20)), 10.0, color);
+        let center = canvas.vertices()[0].position;
+        let outer = canvas
+            .vertices()
+            .iter()
+            .filter(|v| v.color.a == 0)
+            .collect::<Vec<_>>();
+        assert!(!outer.is_empty());
+        for vertex in outer {
+            let (dx, dy) = (vertex.position.0 - center.0, vertex.position.1 - center.1);
+            let distance = (dx * dx + dy * dy).sqrt();
+            assert!(distance > 11.0 && distance < 11.6, "{}", distance);
+        }
+        assert!(canvas
+            .vertices()
+            .iter()
+            .all(|v| v.color == color || v.color.a == 0));
+    }
+
+    #[test]
+    fn layers_are_drawn_nearer() {
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        let rect = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let color = Color::rgb(0, 0, 0);
+
+        canvas.set_layer(2);
+        canvas.draw_styled(&rect, color);
+        canvas.set_layer(1);
+        canvas.draw_styled(&rect, color);
+
+        let depths = canvas.batches().iter().map(|b| b.depth).collect::<Vec<_>>();
+        assert_eq!(depths.len(), 2);
+        assert!(depths[0] < depths[1]);
+        assert!(depths[1] < FAR_DEPTH);
+    }
+
+    #[test]
+    fn triangles_are_not_culled() {
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        let rect = Rect::new(Px(10), Px(20), Px(40), Px(30));
+        let color = Color::rgb(0, 0, 0);
+
+        canvas.draw_styled(&rect, color);
+        canvas.draw_rounded(&rect, 8.0, color);
+        canvas.draw_bordered(&rect, Px(2), color, color);
+        canvas.draw_line((10.0, 10.0), (60.0, 40.0), 3.0, color);
+        canvas.draw_line((60.0, 40.0), (10.0, 10.0), 3.0, color);
+
+        let (vertices, indices) = (canvas.vertices(), canvas.indices());
+        for triangle in (0..indices.len()).step_by(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                let index = indices.get(triangle + i).unwrap();
+                vertices[index as usize].position
             });
+            assert!(is_front_facing(a, b, c), "{:?} would be culled", [a, b, c]);
         }
+    }
+
+    #[test]
+    fn indices_widen_past_u16() {
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(Extent::new(Px(100), Px(100))), &mut storage);
+        let rect = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let color = Color::rgb(0, 0, 0);
 
-        for index in &Rect::INDICES {
-            self.storage.indices.push(offset + index);
+        // 4 vertices per rect, so this fills every 16-bit index exactly.
+        for _ in 0..(1 << 16) / 4 {
+            canvas.draw_styled(&rect, color);
         }
+        assert_eq!(canvas.vertices().len(), 1 << 16);
+        assert_eq!(canvas.indices().format(), IndexFormat::U16);
+
+        canvas.draw_styled(&rect, color);
+        let indices = canvas.indices();
+        assert_eq!(indices.format(), IndexFormat::U32);
+        assert_eq!(indices.len(), ((1 << 16) / 4 + 1) * 6);
+        assert_eq!(indices.as_bytes().len(), indices.len() * 4);
+
+        // Indices from before the switch are kept.
+        assert_eq!(indices.get(6), Some(4));
+        let last = indices.len() - Rect::INDICES.len();
+        assert_eq!(indices.get(last), Some(1 << 16));
+        assert_eq!(canvas.batches()[0].num_indices as usize, indices.len());
+
+        // Batches started after the switch count the indices from before it.
+        canvas.push_clip(rect);
+        canvas.draw_styled(&rect, color);
+        assert_eq!(
+            canvas.batches()[1].first_index as usize,
+            ((1 << 16) / 4 + 1) * 6
+        );
+
+        canvas.clear();
+        assert_eq!(canvas.indices(), Indices::U16(&[]));
+    }
+
+    #[test]
+    fn unchanged_content_is_reused() {
+        let mut storage = CanvasStorage::default();
+        let size = Extent::new(Px(100), Px(100));
+        let rect = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let color = Color::rgb(0, 0, 0);
+
+        let draw = |storage: &mut CanvasStorage, size, content: &[Rect]| {
+            let mut canvas = Canvas::new(Physical(size), storage);
+            let reused = canvas.reuse_if_unchanged(content);
+            if !reused {
+                for rect in content {
+                    canvas.draw_styled(rect, color);
+                }
+            }
+            (reused, canvas.generation(), canvas.vertices().len())
+        };
+
+        let (reused, generation, num_vertices) = draw(&mut storage, size, &[rect]);
+        assert!(!reused);
+        assert_eq!(num_vertices, 4);
+
+        assert_eq!(draw(&mut storage, size, &[rect]), (true, generation, 4));
+        assert_eq!(draw(&mut storage, size, &[rect]), (true, generation, 4));
+
+        let (reused, changed, num_vertices) = draw(&mut storage, size, &[rect, rect]);
+        assert!(!reused);
+        assert_ne!(changed, generation);
+        assert_eq!(num_vertices, 8);
+
+        // Resizing the canvas invalidates it.
+        let (reused, ..) = draw(&mut storage, Extent::new(Px(50), Px(50)), &[rect, rect]);
+        assert!(!reused);
+
+        storage.force_invalidate();
+        let (reused, ..) = draw(&mut storage, Extent::new(Px(50), Px(50)), &[rect, rect]);
+        assert!(!reused);
+
+        // A canvas that isn't drawn from hashed content can't be reused.
+        Canvas::new(Physical(size), &mut storage).draw_styled(&rect, color);
+        assert!(!draw(&mut storage, size, &[rect]).0);
     }
 }
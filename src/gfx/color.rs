@@ -1,4 +1,18 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Whether or not colors have been multiplied by their alpha before blending.
+/// Premultiplied colors blend correctly at the edges of anti-aliased shapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorFormat {
+    Straight,
+    Premultiplied,
+}
+
+impl Default for ColorFormat {
+    fn default() -> Self {
+        Self::Straight
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -28,6 +42,38 @@ impl Color {
         }
     }
 
+    /// Interpolates between `self` and `other`, where a `t` of 0 produces
+    /// `self` and 1 produces `other`.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
+    /// Multiplies the color channels by alpha, rounding to the nearest value.
+    pub fn premultiplied(self) -> Self {
+        let premultiply = |c: u8| ((c as u32 * self.a as u32 + 127) / 255) as u8;
+        Color {
+            r: premultiply(self.r),
+            g: premultiply(self.g),
+            b: premultiply(self.b),
+            a: self.a,
+        }
+    }
+
+    /// Converts the color to `format`, assuming that it is straight.
+    pub fn to_format(self, format: ColorFormat) -> Self {
+        match format {
+            ColorFormat::Straight => self,
+            ColorFormat::Premultiplied => self.premultiplied(),
+        }
+    }
+
     pub fn random_rgb() -> Self {
         use rand::random;
         Color {
@@ -55,3 +101,33 @@ impl Color {
         packed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiply() {
+        let half_white = Color::normalized(1.0, 1.0, 1.0, 0.5).premultiplied();
+        for channel in [half_white.r, half_white.g, half_white.b] {
+            assert!((channel as f32 / 255.0 - 0.5).abs() < 0.01);
+            assert_eq!(channel, half_white.a);
+        }
+
+        assert_eq!(
+            Color::rgba(200, 100, 0, 255).premultiplied(),
+            Color::rgba(200, 100, 0, 255)
+        );
+        assert_eq!(
+            Color::rgba(200, 100, 50, 0).premultiplied(),
+            Color::rgba(0, 0, 0, 0)
+        );
+
+        let color = Color::rgba(200, 100, 50, 128);
+        assert_eq!(color.to_format(ColorFormat::Straight), color);
+        assert_eq!(
+            color.to_format(ColorFormat::Premultiplied),
+            Color::rgba(100, 50, 25, 128)
+        );
+    }
+}
@@ -1,21 +1,118 @@
+use std::{mem::ManuallyDrop, time::Instant};
+
 use ash::vk;
 
 use super::{
+    canvas::{Canvas, DrawBatch, Indices},
+    color::ColorFormat,
+    effect::{Effect, EffectChain},
+    frame_stats::FrameStats,
+    image::{Image, Rgba8},
+    recorder::Recorder,
     shared::{
-        create_pipeline, create_render_pass, record_command_buffer, to_extent, Request, Vertex,
-        PIPELINE_LAYOUT, VULKAN,
+        create_instanced_pipeline, create_pipeline, create_preserving_render_pass,
+        create_render_pass, record_command_buffer, record_instanced, record_viewports,
+        shader_generation, to_extent, Instance, PresentTransfer, Request, Vertex, ViewTransform,
+        ViewportDraw, CLEAR_COLOR, PIPELINE_LAYOUT, TRANSPARENT_CLEAR_COLOR, UNIT_QUAD, VULKAN,
+    },
+    vulkan::{
+        AcquiredImage, ColorSpacePreference, QueueFamilies, SurfaceData, SwapchainData,
+        DEFAULT_SWAPCHAIN_LENGTH,
     },
-    vulkan::{SurfaceData, SwapchainData},
 };
-use crate::{shapes::Extent, sys::Handle};
+use crate::{
+    error::Error,
+    px::Px,
+    shapes::{Extent, Rect},
+    sys::Handle,
+};
 
-pub const FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
 pub const DEFAULT_VERTEX_BUFFER_SIZE: usize = 8192;
 pub const MAX_SWAPCHAIN_DEPTH: usize = 8;
 
+/// The GPU did not finish with a frame's resources in time for them to be
+/// reused. This usually indicates that the device has hung.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("The GPU did not finish the previous frame within {timeout_ns}ns.")]
+pub struct RenderTimeout {
+    pub timeout_ns: u64,
+}
+
+/// A description of a window's swapchain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapchainInfo {
+    pub format: vk::Format,
+    pub present_mode: vk::PresentModeKHR,
+    pub image_count: u32,
+    /// The size of the swapchain's images, which may be rotated relative to
+    /// the window.
+    pub extent: vk::Extent2D,
+    /// Whether the window is composited with the images' alpha.
+    pub transparent: bool,
+    /// The color space that the images are presented in. See
+    /// [`RendererOptions::color_space`].
+    pub color_space: vk::ColorSpaceKHR,
+    /// Whether `color_space` is a high dynamic range color space.
+    pub is_hdr: bool,
+}
+
 pub struct SwapchainImage {
-    view: vk::ImageView,
-    frame_buffer: vk::Framebuffer,
+    pub(super) image: vk::Image,
+    pub(super) view: vk::ImageView,
+    pub(super) frame_buffer: vk::Framebuffer,
+    depth: Option<DepthImage>,
+}
+
+impl SwapchainImage {
+    /// Creates the view and framebuffer (and depth image, if `depth_format` is
+    /// set) needed to render to `image`. The image itself is not owned.
+    pub(super) fn new(
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent3D,
+        render_pass: vk::RenderPass,
+        depth_format: Option<vk::Format>,
+    ) -> Self {
+        let view = {
+            let create_info = vk::ImageViewCreateInfo::builder()
+                .image(image)
+                .format(format)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
+
+            VULKAN.create_image_view(&create_info)
+        };
+
+        let depth = depth_format.map(|format| DepthImage::new(format, extent));
+
+        let frame_buffer = {
+            let mut attachments = vec![view];
+            attachments.extend(depth.as_ref().map(|depth| depth.view));
+
+            let create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+
+            VULKAN.create_frame_buffer(&create_info)
+        };
+
+        Self {
+            image,
+            view,
+            frame_buffer,
+            depth,
+        }
+    }
 }
 
 impl Drop for SwapchainImage {
@@ -25,6 +122,443 @@ impl Drop for SwapchainImage {
     }
 }
 
+/// Moves `image` from the graphics queue's family to the present queue's,
+/// leaving it in `PRESENT_SRC_KHR`. The graphics queue records this to
+/// release the image and the present queue records it to acquire it.
+fn present_transfer_barrier(
+    image: vk::Image,
+    families: QueueFamilies,
+    src_access: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        src_access_mask: src_access,
+        dst_access_mask: vk::AccessFlags::empty(),
+        old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        src_queue_family_index: families.graphics,
+        dst_queue_family_index: families.present,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        ..Default::default()
+    }
+}
+
+/// A depth buffer to go with a swapchain image.
+pub struct DepthImage {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+impl DepthImage {
+    fn new(format: vk::Format, extent: vk::Extent3D) -> Self {
+        let image = VULKAN.create_image(&vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        });
+
+        let memory_requirements = VULKAN.image_memory_requirements(image);
+        let memory_type_index = VULKAN
+            .find_memory_type(
+                memory_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+
+        let memory = VULKAN.allocate(&vk::MemoryAllocateInfo {
+            allocation_size: memory_requirements.size,
+            memory_type_index,
+            ..Default::default()
+        });
+        VULKAN.bind_image(image, memory, 0);
+
+        let view = VULKAN.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(image)
+                .format(format)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::DEPTH,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                }),
+        );
+
+        Self {
+            image,
+            memory,
+            view,
+        }
+    }
+}
+
+impl Drop for DepthImage {
+    fn drop(&mut self) {
+        VULKAN.destroy_image_view(self.view);
+        VULKAN.destroy_image(self.image);
+        VULKAN.free(self.memory);
+    }
+}
+
+/// The size of the depth images for `swapchain`. They must match the size of
+/// the swapchain's images, which is not necessarily the size of the window.
+fn depth_image_extent(swapchain: &SwapchainData) -> vk::Extent3D {
+    vk::Extent3D {
+        width: swapchain.image_size.width,
+        height: swapchain.image_size.height,
+        depth: 1,
+    }
+}
+
+/// Creates a device-local 2D color image with a single mip level and layer,
+/// and the memory bound to it.
+pub(super) fn create_color_image(
+    format: vk::Format,
+    extent: vk::Extent3D,
+    usage: vk::ImageUsageFlags,
+) -> (vk::Image, vk::DeviceMemory) {
+    let image = VULKAN.create_image(&vk::ImageCreateInfo {
+        image_type: vk::ImageType::TYPE_2D,
+        format,
+        extent,
+        mip_levels: 1,
+        array_layers: 1,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        ..Default::default()
+    });
+
+    let memory_requirements = VULKAN.image_memory_requirements(image);
+    let memory_type_index = VULKAN
+        .find_memory_type(
+            memory_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .unwrap();
+
+    let memory = VULKAN.allocate(&vk::MemoryAllocateInfo {
+        allocation_size: memory_requirements.size,
+        memory_type_index,
+        ..Default::default()
+    });
+    VULKAN.bind_image(image, memory, 0);
+
+    (image, memory)
+}
+
+/// A host-visible buffer that a swapchain image is copied into so that it can
+/// be read on the CPU.
+struct Readback {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    /// The frame whose command buffer performs the copy.
+    frame: usize,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    /// The number of bytes between the start of each row in the buffer, which
+    /// may be more than the width of the image.
+    row_pitch: usize,
+}
+
+impl Readback {
+    fn new(frame: usize, extent: vk::Extent2D, format: vk::Format) -> Self {
+        let alignment = VULKAN.optimal_buffer_copy_row_pitch_alignment().max(1) as usize;
+        let row_pitch = ((extent.width as usize * 4 + alignment - 1) / alignment) * alignment;
+
+        let buffer = VULKAN.create_buffer(&vk::BufferCreateInfo {
+            size: (row_pitch * extent.height as usize) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        });
+
+        let memory_requirements = VULKAN.buffer_memory_requirements(buffer);
+        let memory_type_index = VULKAN
+            .find_memory_type(
+                memory_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .unwrap();
+
+        let memory = VULKAN.allocate(&vk::MemoryAllocateInfo {
+            allocation_size: memory_requirements.size,
+            memory_type_index,
+            ..Default::default()
+        });
+        VULKAN.bind(buffer, memory, 0);
+
+        Self {
+            buffer,
+            memory,
+            frame,
+            extent,
+            format,
+            row_pitch,
+        }
+    }
+
+    /// Copies `image` into the buffer, leaving it ready to be presented
+    /// afterwards.
+    fn record(&self, cmd: &Recorder, image: vk::Image) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        // The render pass leaves the image ready for presentation.
+        cmd.transition_image_layout(
+            image,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            subresource_range,
+        );
+
+        cmd.copy_image_to_buffer(
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.buffer,
+            &[vk::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_row_length: (self.row_pitch / 4) as u32,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: vk::Extent3D {
+                    width: self.extent.width,
+                    height: self.extent.height,
+                    depth: 1,
+                },
+            }],
+        );
+
+        cmd.transition_image_layout(
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            subresource_range,
+        );
+    }
+
+    fn read(&self) -> Image<Rgba8> {
+        let size = self.row_pitch * self.extent.height as usize;
+        unsafe {
+            let data =
+                VULKAN.map_memory(self.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty());
+            let image = read_pixels(
+                std::slice::from_raw_parts(data.cast(), size),
+                self.extent,
+                self.row_pitch,
+                self.format,
+            );
+            VULKAN.unmap_memory(self.memory);
+            image
+        }
+    }
+}
+
+impl Drop for Readback {
+    fn drop(&mut self) {
+        VULKAN.destroy_buffer(self.buffer);
+        VULKAN.free(self.memory);
+    }
+}
+
+/// Converts the difference between two timestamps to milliseconds. Only the
+/// lowest `valid_bits` bits of each timestamp are meaningful, and the counter
+/// may have wrapped around between them.
+fn ticks_to_ms(start: u64, end: u64, period_ns: f32, valid_bits: u32) -> f32 {
+    let mask = if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << valid_bits) - 1
+    };
+
+    let ticks = (end & mask).wrapping_sub(start & mask) & mask;
+    (ticks as f64 * period_ns as f64 / 1_000_000.0) as f32
+}
+
+/// Maps the result of `Vulkan::wait_for_fences()` to a `RenderTimeout` if the
+/// fences were not signaled.
+fn check_wait(signaled: bool, timeout_ns: u64) -> Result<(), RenderTimeout> {
+    if signaled {
+        Ok(())
+    } else {
+        Err(RenderTimeout { timeout_ns })
+    }
+}
+
+/// What to do with a window's swapchain before drawing a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ResizeDecision {
+    /// The swapchain still matches the window.
+    Keep,
+    /// The swapchain no longer matches the window, and must be recreated.
+    Recreate,
+    /// The window has no area, usually because it is minimized. Vulkan won't
+    /// create a swapchain that small, so the old one is kept and nothing is
+    /// drawn until the window has a size again.
+    Skip,
+}
+
+fn resize_decision(
+    window_extent: vk::Extent2D,
+    swapchain_extent: vk::Extent2D,
+    needs_resize: bool,
+) -> ResizeDecision {
+    if window_extent.width == 0 || window_extent.height == 0 {
+        ResizeDecision::Skip
+    } else if needs_resize || window_extent != swapchain_extent {
+        ResizeDecision::Recreate
+    } else {
+        ResizeDecision::Keep
+    }
+}
+
+/// The frame to draw after `frame_id`, cycling through all of the frames in
+/// flight.
+fn next_frame_id(frame_id: usize, frames_in_flight: usize) -> usize {
+    (frame_id + 1) % frames_in_flight
+}
+
+/// Whether images in `format` can be converted by [`read_pixels()`].
+fn can_read_pixels(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+    )
+}
+
+/// Converts the rows of an 8-bit RGBA or BGRA image in `format`, each
+/// `row_pitch` bytes apart, into an RGBA image.
+fn read_pixels(
+    data: &[u8],
+    extent: vk::Extent2D,
+    row_pitch: usize,
+    format: vk::Format,
+) -> Image<Rgba8> {
+    let is_bgra = matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+    );
+
+    let mut image = Image::new(Extent::new(
+        Px(extent.width as i16),
+        Px(extent.height as i16),
+    ));
+    let width = image.row_pitch();
+
+    for y in 0..image.height() {
+        let src = &data[y * row_pitch..y * row_pitch + width];
+        let dst = image.row_mut(y);
+        dst.copy_from_slice(src);
+
+        if is_bgra {
+            for pixel in dst.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+    }
+
+    image
+}
+
+/// Options that control how a [`RendererWindow`] renders.
+#[derive(Clone, Copy, Debug)]
+pub struct RendererOptions {
+    /// Extra ways in which the swapchain's images may be used, such as
+    /// `TRANSFER_SRC` for reading back what was presented. Creating the
+    /// renderer fails with `ERROR_FEATURE_NOT_PRESENT` if the surface does not
+    /// support all of them.
+    pub image_usage: vk::ImageUsageFlags,
+
+    /// Gives each swapchain image a depth buffer, so that overlapping layers
+    /// (see [`Canvas::set_layer()`](super::Canvas::set_layer)) are drawn by
+    /// depth instead of in draw order.
+    pub use_depth: bool,
+
+    /// The number of frames that can be drawn before waiting for the oldest
+    /// one to finish rendering. More frames can smooth out frame pacing at the
+    /// cost of latency. Must be at least 1.
+    pub frames_in_flight: usize,
+
+    /// The number of swapchain images to ask for, such as 3 for smoother
+    /// presentation with mailbox present mode. The surface may require more
+    /// or allow fewer, and at most [`MAX_SWAPCHAIN_DEPTH`] are used. Check
+    /// [`swapchain_info()`](RendererWindow::swapchain_info) for the number
+    /// that was granted.
+    pub swapchain_length: u32,
+
+    /// How the vertex colors are blended. Canvases drawn by the renderer must
+    /// use the same format (see
+    /// [`CanvasStorage::set_color_format()`](super::CanvasStorage::set_color_format)).
+    pub color_format: ColorFormat,
+
+    /// Lets whatever is behind the window show through wherever the frame's
+    /// alpha is less than 1, if the surface supports it. Check
+    /// [`swapchain_info()`](RendererWindow::swapchain_info) to see if it
+    /// does. The window itself must also be made transparent with
+    /// [`Control::set_transparent()`](crate::sys::Control::set_transparent).
+    /// Colors should be [`ColorFormat::Premultiplied`].
+    pub transparent: bool,
+
+    /// The color space to present in, for displays that support high dynamic
+    /// range. Falls back to sRGB if the display doesn't support it; check
+    /// [`swapchain_info()`](RendererWindow::swapchain_info) for what was
+    /// chosen.
+    pub color_space: ColorSpacePreference,
+}
+
+impl RendererOptions {
+    fn preferred_swapchain_length(&self) -> u32 {
+        self.swapchain_length.min(MAX_SWAPCHAIN_DEPTH as u32)
+    }
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self {
+            image_usage: vk::ImageUsageFlags::empty(),
+            use_depth: false,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            swapchain_length: DEFAULT_SWAPCHAIN_LENGTH,
+            color_format: ColorFormat::Straight,
+            transparent: false,
+            color_space: ColorSpacePreference::Srgb,
+        }
+    }
+}
+
 pub struct Frame {
     fence: vk::Fence,
     acquire: vk::Semaphore,
@@ -33,6 +567,12 @@ pub struct Frame {
     buffer: vk::Buffer,
     memory: vk::DeviceMemory,
     buffer_size: vk::DeviceSize,
+    /// Whether or not the frame's timestamp queries have been written since
+    /// they were last read.
+    timestamps_written: bool,
+    /// The generation of the canvas whose vertices and indices are in
+    /// `buffer`, and the offset of its indices.
+    uploaded: Option<(u64, vk::DeviceSize)>,
 }
 
 impl Frame {
@@ -45,8 +585,117 @@ impl Frame {
             buffer: vk::Buffer::null(),
             memory: vk::DeviceMemory::null(),
             buffer_size: 0,
+            timestamps_written: false,
+            uploaded: None,
+        }
+    }
+
+    /// Creates `count` frames, each with its own command buffer from
+    /// `command_pool`. There is always at least one frame.
+    fn create_all(command_pool: vk::CommandPool, count: usize) -> Vec<Self> {
+        let mut command_buffers = vec![vk::CommandBuffer::null(); count.max(1)];
+        VULKAN.allocate_command_buffers(command_pool, &mut command_buffers);
+        command_buffers.into_iter().map(Self::new).collect()
+    }
+
+    /// Frees everything but the frame's command buffer, which belongs to the
+    /// pool that it was allocated from. The frame must not be in use.
+    fn destroy(&self) {
+        VULKAN.free_fence(self.fence);
+        VULKAN.free_semaphore(self.acquire);
+        VULKAN.free_semaphore(self.present);
+        VULKAN.destroy_buffer(self.buffer);
+        VULKAN.free(self.memory);
+    }
+}
+
+/// What [`RendererWindow::record_frame()`] draws.
+enum FrameContent<'a> {
+    /// The whole window, clipped by each batch, or only the damaged regions
+    /// of it if there are any.
+    Batches(&'a [DrawBatch], Option<&'a [Rect]>),
+    /// Separate meshes, each in its own region of the window.
+    Viewports(&'a [ViewportDraw]),
+    /// The unit quad, once per instance.
+    Instances(&'a [Instance]),
+}
+
+impl<'a> FrameContent<'a> {
+    fn instances(&self) -> &'a [Instance] {
+        match self {
+            FrameContent::Instances(instances) => instances,
+            FrameContent::Batches(..) | FrameContent::Viewports(_) => &[],
+        }
+    }
+}
+
+/// The damage regions that a frame can be drawn with, or [`None`] if it must
+/// be drawn in full. Effects draw over the whole frame. If the swapchain's
+/// images are owned by one queue family at a time, they are released to the
+/// present queue after each frame and never acquired back, so their contents
+/// can't be loaded; redrawing the whole frame avoids reading them.
+fn usable_damage(
+    damage: Option<&[Rect]>,
+    has_effects: bool,
+    needs_ownership_transfer: bool,
+) -> Option<&[Rect]> {
+    if has_effects || needs_ownership_transfer {
+        None
+    } else {
+        damage
+    }
+}
+
+/// Adds `damage` to the regions that have changed since each swapchain image
+/// was last drawn, and takes the regions that `image` needs redrawn. [`None`]
+/// means that the whole image must be redrawn, either because it has never
+/// been drawn or because a frame since then was drawn without damage regions.
+fn take_damage(
+    stale: &mut [Option<Vec<Rect>>],
+    image: usize,
+    damage: Option<&[Rect]>,
+) -> Option<Vec<Rect>> {
+    for regions in stale.iter_mut() {
+        match damage {
+            Some(damage) => {
+                if let Some(regions) = regions {
+                    regions.extend_from_slice(damage);
+                }
+            }
+            None => *regions = None,
         }
     }
+
+    stale[image].replace(vec![])
+}
+
+/// Combines the meshes of each viewport passed to
+/// [`RendererWindow::draw_viewports()`] into a single vertex and index buffer.
+fn merge_viewports(
+    viewports: &[(vk::Rect2D, &[Vertex], &[u16])],
+) -> (Vec<Vertex>, Vec<u16>, Vec<ViewportDraw>) {
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut draws = Vec::with_capacity(viewports.len());
+
+    for (rect, viewport_vertices, viewport_indices) in viewports {
+        draws.push(ViewportDraw {
+            rect: Rect::new(
+                Px(rect.offset.x as i16),
+                Px(rect.offset.y as i16),
+                Px(rect.extent.width as i16),
+                Px(rect.extent.height as i16),
+            ),
+            first_index: indices.len() as u32,
+            num_indices: viewport_indices.len() as u32,
+            vertex_offset: vertices.len() as i32,
+        });
+
+        vertices.extend_from_slice(viewport_vertices);
+        indices.extend_from_slice(viewport_indices);
+    }
+
+    (vertices, indices, draws)
 }
 
 /// A [`RenderContext`] contains all render state needed for a window to
@@ -55,54 +704,392 @@ pub struct RendererWindow {
     surface: SurfaceData,
     swapchain: SwapchainData,
     render_pass: vk::RenderPass,
+    /// Created the first time a frame is drawn with damage regions.
+    preserving_render_pass: Option<vk::RenderPass>,
     pipeline: vk::Pipeline,
+    /// The [`shader_generation()`] that `pipeline` was created with.
+    pipeline_generation: u64,
+    /// Created the first time [`draw_instanced()`](Self::draw_instanced) is
+    /// called.
+    instanced_pipeline: Option<vk::Pipeline>,
     images: Vec<SwapchainImage>,
+    /// For each image in `images`, the regions that have been damaged since
+    /// it was last drawn. See [`take_damage()`].
+    stale: Vec<Option<Vec<Rect>>>,
+    /// One per image in `images` if the swapchain is exclusive to the
+    /// graphics queue's family, but presented from a different one.
+    present_transfers: Vec<PresentTransfer>,
+    /// Created the first time `present_transfers` is needed.
+    present_pool: vk::CommandPool,
     command_pool: vk::CommandPool,
-    frames: [Frame; FRAMES_IN_FLIGHT],
-    frame_id: u8,
+    frames: Vec<Frame>,
+    frame_id: usize,
+    /// The options that were asked for, which may include image usage flags
+    /// that the surface does not support.
+    options: RendererOptions,
+    /// The format of the depth images, if there are any.
+    depth_format: Option<vk::Format>,
+    capture_requested: bool,
+    /// Set when the swapchain should be recreated before the next frame,
+    /// even if the window's size hasn't changed.
+    needs_resize: bool,
+    readback: Option<Readback>,
+    /// Two timestamps per frame, marking the start and end of its commands.
+    /// Absent if the GPU can't write timestamps.
+    timestamps: Option<vk::QueryPool>,
+    last_frame_gpu_ms: f32,
+    stats: FrameStats,
+    view: ViewTransform,
+    /// Run after the UI has been drawn, in the order they were registered.
+    effects: EffectChain,
 }
 
 impl RendererWindow {
-    pub fn new(window: &Handle, window_size: Extent) -> Self {
-        let surface = VULKAN.create_surface(window);
-        let swapchain = VULKAN.create_or_resize_swapchain(&surface, to_extent(window_size), None);
-        let render_pass = create_render_pass(swapchain.format);
-        let pipeline = create_pipeline(*PIPELINE_LAYOUT, render_pass);
+    pub fn new(window: &Handle, window_size: Extent) -> Result<Self, Error> {
+        Self::with_options(window, window_size, RendererOptions::default())
+    }
+
+    /// Creates a renderer with non-default options. Check
+    /// [`has_depth()`](Self::has_depth) for whether a depth buffer was
+    /// actually enabled.
+    pub fn with_options(
+        window: &Handle,
+        window_size: Extent,
+        options: RendererOptions,
+    ) -> Result<Self, Error> {
+        let surface = VULKAN.create_surface(window)?;
+        let swapchain = match VULKAN.create_or_resize_swapchain(
+            &surface,
+            to_extent(window_size),
+            options.image_usage,
+            options.preferred_swapchain_length(),
+            options.transparent,
+            options.color_space,
+            None,
+        ) {
+            Ok(swapchain) => swapchain,
+            Err(error) => {
+                VULKAN.destroy_surface(surface);
+                return Err(error.into());
+            }
+        };
+
+        let depth_format = if options.use_depth {
+            VULKAN.find_depth_format()
+        } else {
+            None
+        };
+
+        let render_pass = create_render_pass(swapchain.format, depth_format);
+        let pipeline_generation = shader_generation();
+        let pipeline = create_pipeline(
+            *PIPELINE_LAYOUT,
+            render_pass,
+            depth_format.is_some(),
+            options.color_format,
+        );
         let mut images = vec![];
-        Self::init_images(&swapchain, render_pass, &mut images);
+        Self::init_images(&swapchain, render_pass, depth_format, &mut images);
         let command_pool = VULKAN.create_graphics_command_pool(true, true);
-        let mut command_buffers = [vk::CommandBuffer::null(), vk::CommandBuffer::null()];
-        VULKAN.allocate_command_buffers(command_pool, &mut command_buffers);
+        let frames_in_flight = options.frames_in_flight.max(1);
+        let timestamps = (VULKAN.timestamp_valid_bits() > 0)
+            .then(|| VULKAN.create_timestamp_query_pool(2 * frames_in_flight as u32));
 
-        Self {
+        let mut window = Self {
             surface,
             swapchain,
             render_pass,
+            preserving_render_pass: None,
             pipeline,
+            pipeline_generation,
+            instanced_pipeline: None,
+            stale: vec![None; images.len()],
             images,
+            present_transfers: vec![],
+            present_pool: vk::CommandPool::null(),
             command_pool,
-            frames: [
-                Frame::new(command_buffers[0]),
-                Frame::new(command_buffers[1]),
-            ],
+            frames: Frame::create_all(command_pool, frames_in_flight),
             frame_id: 0,
+            options,
+            depth_format,
+            capture_requested: false,
+            needs_resize: false,
+            readback: None,
+            timestamps,
+            last_frame_gpu_ms: 0.0,
+            stats: FrameStats::new(),
+            view: ViewTransform::IDENTITY,
+            effects: EffectChain::new(),
+        };
+        window.init_present_transfers();
+        Ok(window)
+    }
+
+    /// Creates a context that renders to a `size` image instead of a window,
+    /// using the same render pass and pipeline as a window would.
+    pub fn offscreen(size: Extent) -> OffscreenContext {
+        OffscreenContext::new(size)
+    }
+
+    /// Adds an effect to run every frame after the UI has been drawn, and
+    /// after any effects that were registered before it. See [`Effect`].
+    pub fn register_effect(&mut self, effect: Box<dyn Effect>) {
+        // The effects' images are recreated, and may still be in use.
+        let _ = self.wait_for_frames(u64::MAX);
+        self.effects.push(effect);
+        self.resize_effects();
+    }
+
+    /// The ways in which the swapchain's images may be used.
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        self.swapchain.image_usage
+    }
+
+    /// Whether or not the renderer draws with a depth buffer.
+    pub fn has_depth(&self) -> bool {
+        self.depth_format.is_some()
+    }
+
+    /// The time that the GPU spent on the most recent frame to finish
+    /// rendering, in milliseconds. Always 0 if the GPU doesn't support
+    /// timestamps.
+    pub fn last_frame_gpu_ms(&self) -> f32 {
+        self.last_frame_gpu_ms
+    }
+
+    /// Zooms and pans everything drawn from the next frame on. Points are
+    /// scaled by `zoom` around the window's top-left corner, then moved by
+    /// `pan` pixels, so a zoom of 1 and no pan draws the UI as-is. Clip rects
+    /// are transformed along with the UI, but viewports drawn with
+    /// [`draw_viewports()`](Self::draw_viewports) are not.
+    pub fn set_view_transform(&mut self, zoom: f32, pan: (f32, f32)) {
+        debug_assert!(zoom > 0.0, "zoom must be positive, not {}", zoom);
+        self.view = ViewTransform { zoom, pan };
+        // Everything has moved, not just the damaged regions.
+        self.stale.fill(None);
+    }
+
+    /// Timings of the CPU's side of the most recent frames. Frames that
+    /// weren't drawn, such as while the window is minimized, aren't counted.
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Tells the renderer that a frame was not drawn, such as because the
+    /// window is minimized, so that the time until the next frame isn't
+    /// counted in [`frame_stats()`](Self::frame_stats). Frames that the
+    /// renderer skips by itself are accounted for already.
+    pub fn skip_frame(&mut self) {
+        self.stats.skip();
+    }
+
+    /// Asks for the next frame that is drawn to be copied back to the CPU,
+    /// where it can be retrieved with [`capture()`](Self::capture).
+    ///
+    /// Returns `false` if the swapchain's images can't be copied from, which
+    /// requires `TRANSFER_SRC` in [`RendererOptions::image_usage`], or if they
+    /// aren't 8-bit RGBA or BGRA, as with HDR swapchains.
+    pub fn request_capture(&mut self) -> bool {
+        self.capture_requested = self
+            .swapchain
+            .image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            && can_read_pixels(self.swapchain.format);
+        self.capture_requested
+    }
+
+    /// Retrieves the frame that was copied after a call to
+    /// [`request_capture()`](Self::request_capture), waiting for it to finish
+    /// rendering if necessary. The frame must have been drawn, and its request
+    /// executed, before this is called.
+    ///
+    /// The image is in the orientation of the swapchain, which is rotated from
+    /// that of the window if the swapchain has a pre-transform.
+    pub fn capture(&mut self) -> Option<Image<Rgba8>> {
+        let readback = self.readback.take()?;
+        let _ = VULKAN.wait_for_fences(&[self.frames[readback.frame].fence], u64::MAX);
+        Some(readback.read())
+    }
+
+    /// Describes the window's swapchain as of the last call to `draw()`.
+    pub fn swapchain_info(&self) -> SwapchainInfo {
+        SwapchainInfo {
+            format: self.swapchain.format,
+            present_mode: self.swapchain.present_mode,
+            image_count: self.swapchain.image_count,
+            extent: self.swapchain.image_size,
+            transparent: self.swapchain.is_transparent(),
+            color_space: self.swapchain.color_space,
+            is_hdr: self.swapchain.is_hdr(),
         }
     }
 
+    /// The swapchain that frames drawn since the last resize are presented to.
+    pub(super) fn swapchain_handle(&self) -> vk::SwapchainKHR {
+        self.swapchain.handle
+    }
+
+    /// Draws `batches` over the whole window or, if `damage` is set, only the
+    /// regions of it (in window coordinates) that have changed since the last
+    /// frame. The rest of the window keeps what was drawn before.
+    ///
+    /// Damage regions rely on the swapchain's images keeping their contents
+    /// between presents. Vulkan doesn't promise this for parts of the window
+    /// that are covered by other windows, so a mostly-static window should
+    /// still redraw fully now and then, such as whenever it is uncovered.
+    /// Each swapchain image is drawn fully the first time that it is used.
+    /// Damage is ignored while there are effects, and when the graphics and
+    /// present queues are in different families and the swapchain uses
+    /// exclusive sharing, since the images' contents aren't kept then.
     pub fn draw(
         &mut self,
         window_size: Extent,
         vertices: &[Vertex],
-        indices: &[u16],
-    ) -> Option<Request> {
+        indices: Indices,
+        batches: &[DrawBatch],
+        damage: Option<&[Rect]>,
+    ) -> Result<Option<Request>, Error> {
+        self.record_frame(
+            window_size,
+            vertices,
+            indices,
+            FrameContent::Batches(batches, damage),
+            None,
+            u64::MAX,
+        )
+    }
+
+    /// Draws the contents of `canvas`. Vertices and indices are only copied to
+    /// the GPU if the canvas has changed since this frame's resources were
+    /// last used.
+    pub fn draw_canvas(&mut self, canvas: &Canvas) -> Result<Option<Request>, Error> {
+        self.record_frame(
+            canvas.size().0,
+            canvas.vertices(),
+            canvas.indices(),
+            FrameContent::Batches(canvas.batches(), None),
+            Some(canvas.generation()),
+            u64::MAX,
+        )
+    }
+
+    /// Like `draw()`, but gives up if the previous use of this frame's
+    /// resources has not completed within `timeout_ns` nanoseconds. The same
+    /// timeout applies to waiting for every frame in flight before the
+    /// swapchain is resized. Nothing is recorded if a wait times out, so the
+    /// call may be retried, or the device abandoned.
+    pub fn draw_with_timeout(
+        &mut self,
+        window_size: Extent,
+        vertices: &[Vertex],
+        indices: Indices,
+        batches: &[DrawBatch],
+        timeout_ns: u64,
+    ) -> Result<Option<Request>, Error> {
+        self.record_frame(
+            window_size,
+            vertices,
+            indices,
+            FrameContent::Batches(batches, None),
+            None,
+            timeout_ns,
+        )
+    }
+
+    /// Draws a separate mesh into each of several regions of the window, such
+    /// as the panes of a split view. Each viewport is given as its region in
+    /// window coordinates, and a mesh whose vertices are relative to the
+    /// region's top-left corner. Nothing is drawn outside of the regions.
+    pub fn draw_viewports(
+        &mut self,
+        window_size: Extent,
+        viewports: &[(vk::Rect2D, &[Vertex], &[u16])],
+    ) -> Result<Option<Request>, Error> {
+        let (vertices, indices, draws) = merge_viewports(viewports);
+        self.record_frame(
+            window_size,
+            &vertices,
+            Indices::U16(&indices),
+            FrameContent::Viewports(&draws),
+            None,
+            u64::MAX,
+        )
+    }
+
+    /// Draws `instances` of the same rect with a single draw call, which is
+    /// cheaper than drawing each one from its own vertices when there are
+    /// many of them, such as the rows of a long list. Colors are in the
+    /// renderer's [`ColorFormat`]. Instances are drawn in order, so later
+    /// ones are on top.
+    pub fn draw_instanced(
+        &mut self,
+        window_size: Extent,
+        instances: &[Instance],
+    ) -> Result<Option<Request>, Error> {
+        self.record_frame(
+            window_size,
+            &UNIT_QUAD,
+            Indices::U16(&Rect::INDICES),
+            FrameContent::Instances(instances),
+            None,
+            u64::MAX,
+        )
+    }
+
+    fn record_frame(
+        &mut self,
+        window_size: Extent,
+        vertices: &[Vertex],
+        indices: Indices,
+        content: FrameContent,
+        generation: Option<u64>,
+        timeout_ns: u64,
+    ) -> Result<Option<Request>, Error> {
+        let start = Instant::now();
         let window_extent = to_extent(window_size);
-        if window_extent != self.swapchain.image_size {
-            self.resize(window_extent);
+        match resize_decision(
+            window_extent,
+            self.swapchain.window_size(),
+            self.needs_resize,
+        ) {
+            ResizeDecision::Keep => {}
+            ResizeDecision::Recreate => {
+                self.resize(window_extent, timeout_ns)?;
+                self.needs_resize = false;
+            }
+            ResizeDecision::Skip => {
+                self.stats.skip();
+                return Ok(None);
+            }
         }
 
-        let frame_id = self.frame_id as usize;
+        if self.pipeline_generation != shader_generation() {
+            // The shaders have been reloaded since the pipeline was created.
+            self.wait_for_frames(timeout_ns)?;
+            self.rebuild_pipeline();
+        }
+
+        let frame_id = self.frame_id;
         let frame = &mut self.frames[frame_id];
-        let _ = VULKAN.wait_for_fences(&[frame.fence], u64::MAX);
+        check_wait(
+            VULKAN.wait_for_fences(&[frame.fence], timeout_ns),
+            timeout_ns,
+        )?;
+
+        let first_query = 2 * frame_id as u32;
+        if let Some(pool) = self.timestamps {
+            let mut ticks = [0; 2];
+            if std::mem::take(&mut frame.timestamps_written)
+                && VULKAN.get_query_results(pool, first_query, &mut ticks)
+            {
+                self.last_frame_gpu_ms = ticks_to_ms(
+                    ticks[0],
+                    ticks[1],
+                    VULKAN.timestamp_period(),
+                    VULKAN.timestamp_valid_bits(),
+                );
+            }
+        }
 
         VULKAN.reset_command_buffer(frame.command_buffer, false);
 
@@ -110,112 +1097,373 @@ impl RendererWindow {
         // and indices directly to mapped memory, especially on integrated GPUs.
         // You'd need the GPU version of a dynamic array though, and I have _no_
         // idea how performant that might be.
-        let index_buffer_offset = Self::copy_data_to_gpu(frame, vertices, indices);
+        let index_buffer_offset = match frame.uploaded {
+            Some((uploaded, offset)) if generation == Some(uploaded) => offset,
+            _ => {
+                let offset = Self::copy_data_to_gpu(frame, vertices, indices, content.instances());
+                frame.uploaded = generation.map(|generation| (generation, offset));
+                offset
+            }
+        };
 
         let viewport = vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
-            extent: window_extent,
+            extent: self.swapchain.image_size,
         };
 
-        let image_index = VULKAN.acquire_swapchain_image(&self.swapchain, frame.acquire)?;
+        let image_index = match VULKAN.acquire_swapchain_image(&self.swapchain, frame.acquire)? {
+            AcquiredImage::Ready { index, suboptimal } => {
+                // Present this frame anyway, recreating the swapchain for
+                // every suboptimal image would stall a drag-resize.
+                self.needs_resize |= suboptimal;
+                index
+            }
+            AcquiredImage::OutOfDate => {
+                self.needs_resize = true;
+                self.stats.skip();
+                return Ok(None);
+            }
+        };
 
         let cmd = VULKAN.record_command_buffer(frame.command_buffer);
-        record_command_buffer(
+        cmd.begin();
+
+        if let Some(pool) = self.timestamps {
+            cmd.reset_query_pool(pool, first_query, 2);
+            cmd.write_timestamp(vk::PipelineStageFlags::TOP_OF_PIPE, pool, first_query);
+        }
+        let clear_color = if self.swapchain.is_transparent() {
+            TRANSPARENT_CLEAR_COLOR
+        } else {
+            CLEAR_COLOR
+        };
+
+        let damage = match content {
+            FrameContent::Batches(_, damage) => usable_damage(
+                damage,
+                self.effects.has_effects(),
+                VULKAN
+                    .queue_families()
+                    .needs_ownership_transfer(self.swapchain.sharing_mode),
+            ),
+            _ => None,
+        };
+        let damage = take_damage(&mut self.stale, image_index as usize, damage);
+
+        // The UI is the first pass of the effect chain. With effects, it is
+        // drawn into an image for the first one to sample.
+        let draw_ui = |target| match content {
+            FrameContent::Batches(batches, _) => record_command_buffer(
+                &cmd,
+                viewport,
+                self.swapchain.pre_transform,
+                self.view,
+                self.pipeline,
+                if damage.is_some() {
+                    *self.preserving_render_pass.get_or_insert_with(|| {
+                        create_preserving_render_pass(self.swapchain.format, self.depth_format)
+                    })
+                } else {
+                    self.render_pass
+                },
+                *PIPELINE_LAYOUT,
+                target,
+                clear_color,
+                frame.buffer,
+                0,
+                frame.buffer,
+                index_buffer_offset,
+                indices.format(),
+                batches,
+                damage.as_deref(),
+            ),
+            FrameContent::Viewports(draws) => record_viewports(
+                &cmd,
+                viewport,
+                self.swapchain.pre_transform,
+                self.pipeline,
+                self.render_pass,
+                *PIPELINE_LAYOUT,
+                target,
+                clear_color,
+                frame.buffer,
+                index_buffer_offset,
+                draws,
+            ),
+            FrameContent::Instances(instances) => {
+                let pipeline = *self.instanced_pipeline.get_or_insert_with(|| {
+                    create_instanced_pipeline(
+                        *PIPELINE_LAYOUT,
+                        self.render_pass,
+                        self.depth_format.is_some(),
+                        self.options.color_format,
+                    )
+                });
+
+                record_instanced(
+                    &cmd,
+                    viewport,
+                    self.swapchain.pre_transform,
+                    self.view,
+                    pipeline,
+                    self.render_pass,
+                    *PIPELINE_LAYOUT,
+                    target,
+                    clear_color,
+                    frame.buffer,
+                    index_buffer_offset,
+                    instance_data_offset(index_buffer_offset as usize, indices.as_bytes().len())
+                        as vk::DeviceSize,
+                    instances.len() as u32,
+                )
+            }
+        };
+
+        self.effects.record(
             &cmd,
-            viewport,
-            self.pipeline,
-            self.render_pass,
-            *PIPELINE_LAYOUT,
+            image_index as usize,
             self.images[image_index as usize].frame_buffer,
-            frame.buffer,
-            0,
-            frame.buffer,
-            index_buffer_offset,
-            indices.len() as u32,
+            draw_ui,
         );
 
-        Some(Request::SubmitCommands {
+        if std::mem::take(&mut self.capture_requested) {
+            let readback =
+                Readback::new(frame_id, self.swapchain.image_size, self.swapchain.format);
+            readback.record(&cmd, self.images[image_index as usize].image);
+            self.readback = Some(readback);
+        }
+
+        // Release the image to the present queue, which acquires it with
+        // the matching command buffer in `present_transfers`.
+        let present_transfer = self.present_transfers.get(image_index as usize).copied();
+        if present_transfer.is_some() {
+            cmd.pipeline_barrier(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                &[present_transfer_barrier(
+                    self.images[image_index as usize].image,
+                    VULKAN.queue_families(),
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )],
+            );
+        }
+
+        if let Some(pool) = self.timestamps {
+            cmd.write_timestamp(
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                pool,
+                first_query + 1,
+            );
+            frame.timestamps_written = true;
+        }
+
+        cmd.end();
+
+        let request = Request::SubmitCommands {
             wait_semaphore: frame.acquire,
             signal_semaphore: frame.present,
             commands: cmd.buffer,
             fence: frame.fence,
             swapchain: self.swapchain.handle,
             image_id: image_index as u32,
-        })
+            present_transfer,
+        };
+
+        self.frame_id = next_frame_id(frame_id, self.frames.len());
+        self.stats.record(start, start.elapsed());
+        Ok(Some(request))
     }
 
-    fn resize(&mut self, window_extent: vk::Extent2D) {
-        // Wait for BOTH fences.
-        let fences = [self.frames[0].fence, self.frames[1].fence];
-        let _ = VULKAN.wait_for_fences(&fences, u64::MAX);
+    /// Recreates the swapchain and everything that depends on it. Nothing is
+    /// changed if the frames in flight don't finish within `timeout_ns`, or
+    /// if the new swapchain can't be created.
+    fn resize(&mut self, window_extent: vk::Extent2D, timeout_ns: u64) -> Result<(), Error> {
+        // Wait for ALL fences.
+        self.wait_for_frames(timeout_ns)?;
 
         let old_format = self.swapchain.format;
         self.swapchain = VULKAN.create_or_resize_swapchain(
             &self.surface,
             window_extent,
+            self.options.image_usage,
+            self.options.preferred_swapchain_length(),
+            self.options.transparent,
+            self.options.color_space,
             Some(self.swapchain.handle),
-        );
+        )?;
+        self.destroy_present_transfers();
 
         if old_format != self.swapchain.format {
-            VULKAN.destroy_pipeline(self.pipeline);
-            VULKAN.destroy_render_pass(self.render_pass);
-
-            self.render_pass = create_render_pass(self.swapchain.format);
-            self.pipeline = create_pipeline(*PIPELINE_LAYOUT, self.render_pass);
+            let old_render_pass = self.render_pass;
+            self.render_pass = create_render_pass(self.swapchain.format, self.depth_format);
+            self.rebuild_pipeline();
+            VULKAN.destroy_render_pass(old_render_pass);
+            if let Some(render_pass) = self.preserving_render_pass.take() {
+                VULKAN.destroy_render_pass(render_pass);
+            }
         }
 
+        // The depth images are recreated along with the rest so that they
+        // always match the new swapchain's size.
         self.images.clear();
-        Self::init_images(&self.swapchain, self.render_pass, &mut self.images);
+        Self::init_images(
+            &self.swapchain,
+            self.render_pass,
+            self.depth_format,
+            &mut self.images,
+        );
+        self.stale = vec![None; self.images.len()];
+        self.init_present_transfers();
+        self.resize_effects();
+        Ok(())
+    }
+
+    /// Recreates the effects' images to match the swapchain. The frames
+    /// that use them must have finished rendering.
+    fn resize_effects(&mut self) {
+        if !self.effects.has_effects() {
+            return;
+        }
+
+        let views = self
+            .images
+            .iter()
+            .map(|image| image.view)
+            .collect::<Vec<_>>();
+        self.effects.resize(
+            self.swapchain.format,
+            self.swapchain.image_size,
+            self.render_pass,
+            self.depth_format,
+            &views,
+        );
+    }
+
+    /// Recreates the pipeline for the current render pass and shaders. The
+    /// frames that use the old pipeline must have finished rendering.
+    fn rebuild_pipeline(&mut self) {
+        VULKAN.destroy_pipeline(self.pipeline);
+        if let Some(pipeline) = self.instanced_pipeline.take() {
+            VULKAN.destroy_pipeline(pipeline);
+        }
+        self.pipeline_generation = shader_generation();
+        self.pipeline = create_pipeline(
+            *PIPELINE_LAYOUT,
+            self.render_pass,
+            self.depth_format.is_some(),
+            self.options.color_format,
+        );
+        // The new shaders may draw everything differently.
+        self.stale.fill(None);
+    }
+
+    /// Records the command buffers that acquire each swapchain image on the
+    /// present queue, if the swapchain's sharing mode calls for them. They
+    /// never change, so they are recorded once per swapchain.
+    fn init_present_transfers(&mut self) {
+        let families = VULKAN.queue_families();
+        if !families.needs_ownership_transfer(self.swapchain.sharing_mode) {
+            return;
+        }
+
+        if self.present_pool == vk::CommandPool::null() {
+            self.present_pool = VULKAN.create_present_command_pool();
+        }
+
+        let mut command_buffers = vec![vk::CommandBuffer::null(); self.images.len()];
+        VULKAN.allocate_command_buffers(self.present_pool, &mut command_buffers);
+
+        for (image, commands) in self.images.iter().zip(command_buffers) {
+            let cmd = VULKAN.record_command_buffer(commands);
+            cmd.begin();
+            // The semaphore that the graphics queue signals orders this after
+            // the release, so there is nothing to wait for here.
+            cmd.pipeline_barrier(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                &[present_transfer_barrier(
+                    image.image,
+                    families,
+                    vk::AccessFlags::empty(),
+                )],
+            );
+            cmd.end();
+
+            self.present_transfers.push(PresentTransfer {
+                commands,
+                semaphore: VULKAN.create_semaphore(),
+            });
+        }
+    }
+
+    fn destroy_present_transfers(&mut self) {
+        if self.present_transfers.is_empty() {
+            return;
+        }
+
+        // The frame fences only cover the graphics queue.
+        VULKAN.wait_for_present_queue();
+
+        let command_buffers = self
+            .present_transfers
+            .drain(..)
+            .map(|transfer| {
+                VULKAN.free_semaphore(transfer.semaphore);
+                transfer.commands
+            })
+            .collect::<Vec<_>>();
+        VULKAN.free_command_buffers(self.present_pool, &command_buffers);
+    }
+
+    /// Waits up to `timeout_ns` for every frame in flight to finish
+    /// rendering.
+    fn wait_for_frames(&self, timeout_ns: u64) -> Result<(), RenderTimeout> {
+        let fences = self.frames.iter().map(|f| f.fence).collect::<Vec<_>>();
+        check_wait(VULKAN.wait_for_fences(&fences, timeout_ns), timeout_ns)
     }
 
     fn init_images(
         swapchain: &SwapchainData,
         render_pass: vk::RenderPass,
+        depth_format: Option<vk::Format>,
         buffer: &mut Vec<SwapchainImage>,
     ) {
+        assert!(
+            swapchain.image_count as usize <= MAX_SWAPCHAIN_DEPTH,
+            "The swapchain has {} images, but at most {} are supported",
+            swapchain.image_count,
+            MAX_SWAPCHAIN_DEPTH
+        );
         let images = VULKAN.get_swapchain_images::<MAX_SWAPCHAIN_DEPTH>(swapchain);
         buffer.reserve_exact(images.len());
 
         for handle in &images {
-            buffer.push({
-                let view = {
-                    let create_info = vk::ImageViewCreateInfo::builder()
-                        .image(*handle)
-                        .format(swapchain.format)
-                        .view_type(vk::ImageViewType::TYPE_2D)
-                        .subresource_range(vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        });
-
-                    VULKAN.create_image_view(&create_info)
-                };
-
-                let frame_buffer = {
-                    let attachment = [view];
-                    let create_info = vk::FramebufferCreateInfo::builder()
-                        .render_pass(render_pass)
-                        .attachments(&attachment)
-                        .width(swapchain.image_size.width)
-                        .height(swapchain.image_size.height)
-                        .layers(1);
-
-                    VULKAN.create_frame_buffer(&create_info)
-                };
-
-                SwapchainImage { view, frame_buffer }
-            });
+            buffer.push(SwapchainImage::new(
+                *handle,
+                swapchain.format,
+                depth_image_extent(swapchain),
+                render_pass,
+                depth_format,
+            ));
         }
     }
 
-    fn copy_data_to_gpu(frame: &mut Frame, vertices: &[Vertex], indices: &[u16]) -> vk::DeviceSize {
-        let alignment = VULKAN.non_coherent_atom_size() as usize;
-        let vertex_buffer_size =
-            ((std::mem::size_of_val(vertices) + alignment - 1) / alignment) * alignment;
-        let min_capacity = (vertex_buffer_size + std::mem::size_of_val(indices))
+    /// Copies the vertices, then the indices, then the instances (if any) to
+    /// the frame's buffer. Returns the offset of the indices; the instances
+    /// are at [`instance_data_offset()`].
+    fn copy_data_to_gpu(
+        frame: &mut Frame,
+        vertices: &[Vertex],
+        indices: Indices,
+        instances: &[Instance],
+    ) -> vk::DeviceSize {
+        let vertex_buffer_size = index_data_offset(
+            std::mem::size_of_val(vertices),
+            VULKAN.non_coherent_atom_size() as usize,
+            indices.format().size(),
+        );
+        let instance_offset = instance_data_offset(vertex_buffer_size, indices.as_bytes().len());
+        let min_capacity = (instance_offset + std::mem::size_of_val(instances))
             .max(DEFAULT_VERTEX_BUFFER_SIZE) as u64;
 
         if frame.buffer_size < min_capacity {
@@ -230,6 +1478,7 @@ impl RendererWindow {
             });
 
             let memory_requirements = VULKAN.buffer_memory_requirements(frame.buffer);
+            assert!(memory_requirements.size >= min_capacity);
             let memory_type_index = VULKAN
                 .find_memory_type(
                     memory_requirements.memory_type_bits,
@@ -244,7 +1493,9 @@ impl RendererWindow {
             };
 
             frame.memory = VULKAN.allocate(&alloc_info);
-            frame.buffer_size = memory_requirements.size;
+            // The allocation may be larger than the buffer, but anything past
+            // the end of the buffer can't be read from it.
+            frame.buffer_size = min_capacity;
             VULKAN.bind(frame.buffer, frame.memory, 0);
         }
 
@@ -255,11 +1506,16 @@ impl RendererWindow {
             let vertex_buffer = std::slice::from_raw_parts_mut(data.cast(), vertices.len());
             vertex_buffer.copy_from_slice(vertices);
 
+            let index_bytes = indices.as_bytes();
             let index_buffer = std::slice::from_raw_parts_mut(
                 data.add(vertex_buffer_size as usize).cast(),
-                indices.len(),
+                index_bytes.len(),
             );
-            index_buffer.copy_from_slice(indices);
+            index_buffer.copy_from_slice(index_bytes);
+
+            let instance_buffer =
+                std::slice::from_raw_parts_mut(data.add(instance_offset).cast(), instances.len());
+            instance_buffer.copy_from_slice(instances);
 
             // PERFORMANCE(David Z): This call is unecessary if the memory is
             // host-coherent
@@ -279,29 +1535,608 @@ impl RendererWindow {
 
 impl Drop for RendererWindow {
     fn drop(&mut self) {
-        let fences = [self.frames[0].fence, self.frames[1].fence];
-        let _ = VULKAN.wait_for_fences(&fences, u64::MAX);
+        let _ = self.wait_for_frames(u64::MAX);
 
         for frame in &self.frames {
-            VULKAN.free_fence(frame.fence);
-            VULKAN.free_semaphore(frame.acquire);
-            VULKAN.free_semaphore(frame.present);
-            VULKAN.destroy_buffer(frame.buffer);
-            VULKAN.free(frame.memory);
+            frame.destroy();
         }
 
+        self.destroy_present_transfers();
+        if self.present_pool != vk::CommandPool::null() {
+            VULKAN.destroy_command_pool(self.present_pool);
+        }
+
+        // The effects' framebuffers refer to the images' views.
+        self.effects = EffectChain::new();
         self.images.clear();
+        self.readback = None;
 
-        VULKAN.free_command_buffers(
-            self.command_pool,
-            &[self.frames[0].command_buffer, self.frames[1].command_buffer],
-        );
+        if let Some(pool) = self.timestamps {
+            VULKAN.destroy_query_pool(pool);
+        }
+
+        let command_buffers = self
+            .frames
+            .iter()
+            .map(|f| f.command_buffer)
+            .collect::<Vec<_>>();
+        VULKAN.free_command_buffers(self.command_pool, &command_buffers);
         VULKAN.destroy_command_pool(self.command_pool);
 
         VULKAN.destroy_pipeline(self.pipeline);
+        if let Some(pipeline) = self.instanced_pipeline {
+            VULKAN.destroy_pipeline(pipeline);
+        }
         VULKAN.destroy_render_pass(self.render_pass);
+        if let Some(render_pass) = self.preserving_render_pass {
+            VULKAN.destroy_render_pass(render_pass);
+        }
 
         VULKAN.destroy_swapchain(std::mem::take(&mut self.swapchain));
         VULKAN.destroy_surface(std::mem::take(&mut self.surface));
     }
 }
+
+/// Renders to an image of a fixed size without a window, for tests and
+/// thumbnails. Created with [`RendererWindow::offscreen()`].
+pub struct OffscreenContext {
+    extent: vk::Extent2D,
+    format: vk::Format,
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    /// The [`shader_generation()`] that `pipeline` was created with.
+    pipeline_generation: u64,
+    /// The color image is rendered to as if it were a swapchain image, and
+    /// so is left in `PRESENT_SRC_KHR` after each frame. Dropped manually
+    /// because it must be destroyed before its image.
+    target: ManuallyDrop<SwapchainImage>,
+    memory: vk::DeviceMemory,
+    command_pool: vk::CommandPool,
+    frame: Frame,
+    readback: Option<Readback>,
+    effects: EffectChain,
+}
+
+impl OffscreenContext {
+    const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+    fn new(size: Extent) -> Self {
+        let extent = to_extent(size);
+        let format = Self::FORMAT;
+
+        let render_pass = create_render_pass(format, None);
+        let pipeline_generation = shader_generation();
+        let pipeline = create_pipeline(*PIPELINE_LAYOUT, render_pass, false, ColorFormat::Straight);
+
+        let image_extent = vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+        let (image, memory) = create_color_image(
+            format,
+            image_extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+
+        let target = ManuallyDrop::new(SwapchainImage::new(
+            image,
+            format,
+            image_extent,
+            render_pass,
+            None,
+        ));
+
+        let command_pool = VULKAN.create_graphics_command_pool(true, true);
+        let mut command_buffers = [vk::CommandBuffer::null()];
+        VULKAN.allocate_command_buffers(command_pool, &mut command_buffers);
+
+        Self {
+            extent,
+            format,
+            render_pass,
+            pipeline,
+            pipeline_generation,
+            target,
+            memory,
+            command_pool,
+            frame: Frame::new(command_buffers[0]),
+            readback: None,
+            effects: EffectChain::new(),
+        }
+    }
+
+    /// Adds an effect to run after the UI has been drawn, as with
+    /// [`RendererWindow::register_effect()`].
+    pub fn register_effect(&mut self, effect: Box<dyn Effect>) {
+        let _ = VULKAN.wait_for_fences(&[self.frame.fence], u64::MAX);
+        self.effects.push(effect);
+        self.effects.resize(
+            self.format,
+            self.extent,
+            self.render_pass,
+            None,
+            &[self.target.view],
+        );
+    }
+
+    #[cfg(test)]
+    pub(super) fn effects(&self) -> &EffectChain {
+        &self.effects
+    }
+
+    /// Renders the vertices and submits the commands immediately. Call
+    /// [`capture()`](Self::capture) to retrieve the result.
+    pub fn draw(&mut self, vertices: &[Vertex], indices: Indices, batches: &[DrawBatch]) {
+        self.record_frame(vertices, indices, batches, None);
+    }
+
+    /// Like `draw()`, but skips copying the canvas's vertices to the GPU if
+    /// they haven't changed since the last frame.
+    pub fn draw_canvas(&mut self, canvas: &Canvas) {
+        self.record_frame(
+            canvas.vertices(),
+            canvas.indices(),
+            canvas.batches(),
+            Some(canvas.generation()),
+        );
+    }
+
+    /// Waits for the most recent frame to finish rendering and copies it back
+    /// to the CPU. Returns `None` if nothing has been drawn since the last
+    /// capture.
+    pub fn capture(&mut self) -> Option<Image<Rgba8>> {
+        let readback = self.readback.take()?;
+        let _ = VULKAN.wait_for_fences(&[self.frame.fence], u64::MAX);
+        Some(readback.read())
+    }
+
+    fn record_frame(
+        &mut self,
+        vertices: &[Vertex],
+        indices: Indices,
+        batches: &[DrawBatch],
+        generation: Option<u64>,
+    ) {
+        let frame = &mut self.frame;
+        let _ = VULKAN.wait_for_fences(&[frame.fence], u64::MAX);
+        VULKAN.reset_command_buffer(frame.command_buffer, false);
+
+        if self.pipeline_generation != shader_generation() {
+            VULKAN.destroy_pipeline(self.pipeline);
+            self.pipeline_generation = shader_generation();
+            self.pipeline = create_pipeline(
+                *PIPELINE_LAYOUT,
+                self.render_pass,
+                false,
+                ColorFormat::Straight,
+            );
+        }
+
+        let index_buffer_offset = match frame.uploaded {
+            Some((uploaded, offset)) if generation == Some(uploaded) => offset,
+            _ => {
+                let offset = RendererWindow::copy_data_to_gpu(frame, vertices, indices, &[]);
+                frame.uploaded = generation.map(|generation| (generation, offset));
+                offset
+            }
+        };
+
+        let viewport = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+
+        let cmd = VULKAN.record_command_buffer(frame.command_buffer);
+        cmd.begin();
+        self.effects
+            .record(&cmd, 0, self.target.frame_buffer, |target| {
+                record_command_buffer(
+                    &cmd,
+                    viewport,
+                    vk::SurfaceTransformFlagsKHR::IDENTITY,
+                    ViewTransform::IDENTITY,
+                    self.pipeline,
+                    self.render_pass,
+                    *PIPELINE_LAYOUT,
+                    target,
+                    CLEAR_COLOR,
+                    frame.buffer,
+                    0,
+                    frame.buffer,
+                    index_buffer_offset,
+                    indices.format(),
+                    batches,
+                    None,
+                )
+            });
+
+        let readback = Readback::new(0, self.extent, self.format);
+        readback.record(&cmd, self.target.image);
+        cmd.end();
+
+        let command_buffers = [cmd.buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build();
+
+        VULKAN.reset_fences(&[frame.fence]);
+        VULKAN.submit_to_graphics_queue(&[submit_info], frame.fence);
+
+        // Any previous readback can be dropped now that its frame has been
+        // waited for.
+        self.readback = Some(readback);
+    }
+}
+
+impl Drop for OffscreenContext {
+    fn drop(&mut self) {
+        let _ = VULKAN.wait_for_fences(&[self.frame.fence], u64::MAX);
+        self.readback = None;
+
+        self.frame.destroy();
+        VULKAN.free_command_buffers(self.command_pool, &[self.frame.command_buffer]);
+        VULKAN.destroy_command_pool(self.command_pool);
+
+        self.effects = EffectChain::new();
+
+        // The framebuffer and view are destroyed with `target`, but the image
+        // belongs to this context.
+        let image = self.target.image;
+        unsafe { ManuallyDrop::drop(&mut self.target) };
+        VULKAN.destroy_image(image);
+        VULKAN.free(self.memory);
+
+        VULKAN.destroy_pipeline(self.pipeline);
+        VULKAN.destroy_render_pass(self.render_pass);
+    }
+}
+
+/// The offset of the index data in a frame's buffer, which follows `vertex_size`
+/// bytes of vertex data. It must be a multiple of the index size to be bound as
+/// an index buffer, and of the non-coherent atom size so that flushing the
+/// vertices never touches the indices.
+fn index_data_offset(vertex_size: usize, atom_size: usize, index_size: usize) -> usize {
+    // Both are powers of two, so the larger is a multiple of the smaller.
+    debug_assert!(atom_size.is_power_of_two() && index_size.is_power_of_two());
+    let alignment = atom_size.max(index_size);
+    ((vertex_size + alignment - 1) / alignment) * alignment
+}
+
+/// Where the instances start in a frame's buffer, after the indices. Aligned
+/// for the instances' `f32`s.
+fn instance_data_offset(index_offset: usize, index_size: usize) -> usize {
+    let alignment = std::mem::align_of::<Instance>();
+    ((index_offset + index_size + alignment - 1) / alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gfx::{CanvasStorage, Color, DrawStyled},
+        px::Physical,
+        shapes::Rect,
+        sys::Library,
+    };
+
+    #[test]
+    fn viewports_draw_separately() {
+        let vertex = |x, y| Vertex {
+            position: (x, y),
+            color: Color::rgb(255, 255, 255),
+        };
+        let quad = [
+            vertex(0.0, 0.0),
+            vertex(0.0, 10.0),
+            vertex(10.0, 10.0),
+            vertex(10.0, 0.0),
+        ];
+        let rect2d = |x, y, width, height| vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        };
+
+        let left = rect2d(0, 0, 100, 100);
+        let right = rect2d(100, 20, 150, 100);
+        let (vertices, indices, draws) = merge_viewports(&[
+            (left, &quad, &Rect::INDICES),
+            (right, &quad[..3], &Rect::INDICES[..3]),
+        ]);
+
+        assert_eq!(vertices.len(), 7);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3, 0, 1, 2]);
+        assert_eq!(
+            draws,
+            [
+                ViewportDraw {
+                    rect: Rect::new(Px(0), Px(0), Px(100), Px(100)),
+                    first_index: 0,
+                    num_indices: 6,
+                    vertex_offset: 0,
+                },
+                ViewportDraw {
+                    rect: Rect::new(Px(100), Px(20), Px(150), Px(100)),
+                    first_index: 6,
+                    num_indices: 3,
+                    vertex_offset: 4,
+                },
+            ]
+        );
+
+        // The right viewport hangs off the edge of the window, which clips
+        // the scissor rect but not the viewport.
+        let window_size = Extent::new(Px(200), Px(100));
+        let identity = vk::SurfaceTransformFlagsKHR::IDENTITY;
+        let (viewport, scissor) = draws[0].area(window_size, identity);
+        assert_eq!(scissor, left);
+        assert_eq!((viewport.width, viewport.height), (100.0, 100.0));
+
+        let (viewport, scissor) = draws[1].area(window_size, identity);
+        assert_eq!(scissor, rect2d(100, 20, 100, 80));
+        assert_eq!((viewport.x, viewport.y), (100.0, 20.0));
+        assert_eq!((viewport.width, viewport.height), (150.0, 100.0));
+
+        // Rotated swapchains rotate the regions too.
+        let rotated = vk::SurfaceTransformFlagsKHR::ROTATE_90;
+        let (_, scissor) = draws[0].area(window_size, rotated);
+        assert_eq!(scissor, rect2d(0, 0, 100, 100));
+        let (_, scissor) = draws[1].area(window_size, rotated);
+        assert_eq!(scissor, rect2d(0, 100, 80, 100));
+    }
+
+    #[test]
+    fn index_data_alignment() {
+        let vertex_size = std::mem::size_of::<Vertex>();
+        for num_vertices in [0, 1, 3, 4, 7, 100, 1001] {
+            for atom_size in [1, 2, 64, 256] {
+                for index_size in [2, 4] {
+                    let vertices = num_vertices * vertex_size;
+                    let offset = index_data_offset(vertices, atom_size, index_size);
+
+                    assert!(offset >= vertices);
+                    assert_eq!(offset % atom_size, 0);
+                    assert_eq!(offset % index_size, 0);
+                    assert!(offset - vertices < atom_size.max(index_size));
+                }
+            }
+        }
+
+        // 3 vertices of 12 bytes each, with a 1-byte atom size, would leave
+        // 32-bit indices misaligned without padding.
+        assert_eq!(index_data_offset(3 * 12 + 2, 1, 4), 40);
+        assert_eq!(index_data_offset(38, 1, 2), 38);
+    }
+
+    #[test]
+    fn depth_image_follows_swapchain() {
+        let mut swapchain = SwapchainData {
+            image_size: vk::Extent2D {
+                width: 640,
+                height: 480,
+            },
+            ..Default::default()
+        };
+
+        let before = depth_image_extent(&swapchain);
+        assert_eq!((before.width, before.height, before.depth), (640, 480, 1));
+
+        swapchain.image_size = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        let after = depth_image_extent(&swapchain);
+        assert_eq!((after.width, after.height), (800, 600));
+
+        // Rotated swapchains have images in the display's orientation, which
+        // the depth image has to match.
+        swapchain.pre_transform = vk::SurfaceTransformFlagsKHR::ROTATE_90;
+        let rotated = depth_image_extent(&swapchain);
+        assert_eq!((rotated.width, rotated.height), (800, 600));
+        assert_eq!(swapchain.window_size().width, 600);
+    }
+
+    #[test]
+    fn timed_out_wait_is_an_error() {
+        assert_eq!(check_wait(true, 100), Ok(()));
+        assert_eq!(
+            check_wait(false, 100),
+            Err(RenderTimeout { timeout_ns: 100 })
+        );
+    }
+
+    #[test]
+    fn timestamp_conversion() {
+        // 2ms at 1ns per tick
+        assert_eq!(ticks_to_ms(1_000, 2_001_000, 1.0, 64), 2.0);
+
+        // 1.5ms at 83.333ns per tick
+        let ms = ticks_to_ms(0, 18_000, 83.333, 64);
+        assert!((ms - 1.5).abs() < 1e-4);
+
+        // A 32-bit counter that wrapped around
+        assert_eq!(ticks_to_ms(u32::MAX as u64 - 499, 500, 2.0, 32), 0.002);
+
+        // Bits past the valid ones are ignored.
+        assert_eq!(ticks_to_ms(1 << 40, (1 << 40) + 10, 100.0, 36), 0.001);
+    }
+
+    #[test]
+    fn zero_size_skips_resize() {
+        let extent = |width, height| vk::Extent2D { width, height };
+        let current = extent(640, 480);
+
+        assert_eq!(
+            resize_decision(extent(0, 0), current, false),
+            ResizeDecision::Skip
+        );
+        // Even if the swapchain is known to be out of date.
+        assert_eq!(
+            resize_decision(extent(0, 0), current, true),
+            ResizeDecision::Skip
+        );
+        assert_eq!(
+            resize_decision(extent(800, 0), current, false),
+            ResizeDecision::Skip
+        );
+
+        assert_eq!(
+            resize_decision(extent(800, 600), current, false),
+            ResizeDecision::Recreate
+        );
+        assert_eq!(
+            resize_decision(current, current, true),
+            ResizeDecision::Recreate
+        );
+        assert_eq!(
+            resize_decision(current, current, false),
+            ResizeDecision::Keep
+        );
+    }
+
+    #[test]
+    fn damage_accumulates_per_image() {
+        let a = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let b = Rect::new(Px(20), Px(0), Px(10), Px(10));
+        let mut stale = vec![None; 2];
+
+        // Neither image has been drawn yet.
+        assert_eq!(take_damage(&mut stale, 0, Some(&[a][..])), None);
+        assert_eq!(take_damage(&mut stale, 1, Some(&[b][..])), None);
+
+        // Image 0 missed the frame that damaged `b`.
+        assert_eq!(take_damage(&mut stale, 0, Some(&[a][..])), Some(vec![b, a]));
+        assert_eq!(take_damage(&mut stale, 1, Some(&[][..])), Some(vec![a]));
+        assert_eq!(take_damage(&mut stale, 0, Some(&[][..])), Some(vec![]));
+
+        // A full redraw leaves the other image out of date.
+        assert_eq!(take_damage(&mut stale, 1, None), None);
+        assert_eq!(take_damage(&mut stale, 0, Some(&[a][..])), None);
+
+        // Images that change hands between queue families are redrawn fully.
+        let damage = Some(&[a][..]);
+        assert_eq!(usable_damage(damage, false, false), damage);
+        assert_eq!(usable_damage(damage, true, false), None);
+        assert_eq!(usable_damage(damage, false, true), None);
+    }
+
+    #[test]
+    fn frames_cycle() {
+        let options = RendererOptions {
+            frames_in_flight: 3,
+            ..Default::default()
+        };
+
+        let mut frame_id = 0;
+        let mut ids = vec![frame_id];
+        for _ in 0..3 {
+            frame_id = next_frame_id(frame_id, options.frames_in_flight);
+            ids.push(frame_id);
+        }
+        assert_eq!(ids, [0, 1, 2, 0]);
+
+        assert_eq!(RendererOptions::default().frames_in_flight, 2);
+        assert_eq!(next_frame_id(0, 1), 0);
+    }
+
+    #[test]
+    fn frames_in_flight() {
+        // Needs a GPU, and so can't run everywhere.
+        if Library::load("vulkan-1").is_err() {
+            return;
+        }
+
+        let pool = VULKAN.create_graphics_command_pool(true, true);
+        let frames = Frame::create_all(pool, 3);
+        assert_eq!(frames.len(), 3);
+
+        // Each frame is recorded and waited for separately.
+        for (i, a) in frames.iter().enumerate() {
+            assert_ne!(a.command_buffer, vk::CommandBuffer::null());
+            for b in &frames[i + 1..] {
+                assert_ne!(a.fence, b.fence);
+                assert_ne!(a.command_buffer, b.command_buffer);
+            }
+        }
+
+        // New frames have nothing in flight, so waiting for all of them, as
+        // resizing and dropping do, returns at once.
+        let fences = frames.iter().map(|f| f.fence).collect::<Vec<_>>();
+        assert!(VULKAN.wait_for_fences(&fences, 0));
+        frames.iter().for_each(Frame::destroy);
+
+        // There is always at least one frame.
+        let frames = Frame::create_all(pool, 0);
+        assert_eq!(frames.len(), 1);
+        frames.iter().for_each(Frame::destroy);
+
+        VULKAN.destroy_command_pool(pool);
+    }
+
+    #[test]
+    fn read_back_clear_color() {
+        let extent = vk::Extent2D {
+            width: 3,
+            height: 2,
+        };
+
+        // A BGRA frame cleared to a single color, with rows padded out to 16
+        // bytes. The padding must not end up in the image.
+        let clear = Color::rgba(10, 20, 30, 255);
+        let row_pitch = 16;
+        let mut data = vec![0xAA; row_pitch * 2];
+        for row in data.chunks_exact_mut(row_pitch) {
+            for pixel in row[..12].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[clear.b, clear.g, clear.r, clear.a]);
+            }
+        }
+
+        let image = read_pixels(&data, extent, row_pitch, vk::Format::B8G8R8A8_SRGB);
+        assert_eq!((image.width(), image.height()), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(image.get(x, y), clear);
+            }
+        }
+
+        let image = read_pixels(&data, extent, row_pitch, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(image.get(0, 0), Color::rgba(30, 20, 10, 255));
+
+        // HDR formats have more than 4 bytes per pixel, or don't fit 8 bits
+        // per channel, so frames in them can't be captured.
+        assert!(can_read_pixels(vk::Format::B8G8R8A8_UNORM));
+        assert!(!can_read_pixels(vk::Format::R16G16B16A16_SFLOAT));
+        assert!(!can_read_pixels(vk::Format::A2B10G10R10_UNORM_PACK32));
+    }
+
+    #[test]
+    fn offscreen_rect() {
+        // Needs a GPU, and so can't run everywhere.
+        if Library::load("vulkan-1").is_err() {
+            return;
+        }
+
+        let size = Extent::new(Px(32), Px(24));
+        let color = Color::rgba(255, 0, 0, 255);
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(size), &mut storage);
+        canvas.draw_styled(&Rect::new(Px(8), Px(4), Px(10), Px(12)), color);
+
+        let mut context = RendererWindow::offscreen(size);
+        context.draw_canvas(&canvas);
+        let image = context.capture().unwrap();
+        assert_eq!((image.width(), image.height()), (32, 24));
+
+        for (x, y) in [(8, 4), (12, 10), (17, 15)] {
+            assert_eq!(image.get(x, y), color, "({}, {})", x, y);
+        }
+
+        let clear = Color::rgba(0, 0, 0, 255);
+        for (x, y) in [(0, 0), (7, 4), (18, 10), (12, 16), (31, 23)] {
+            assert_eq!(image.get(x, y), clear, "({}, {})", x, y);
+        }
+
+        // Nothing is left to capture until the next draw.
+        assert!(context.capture().is_none());
+    }
+}
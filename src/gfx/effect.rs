@@ -0,0 +1,519 @@
+//! Passes that run over the whole frame after the UI has been drawn, such as
+//! a blur or color grading. Each effect samples what the previous pass drew,
+//! and the last one draws to the window.
+
+use std::mem::ManuallyDrop;
+
+use ash::vk::{self, DependencyFlags};
+
+use super::{
+    context::{create_color_image, SwapchainImage},
+    recorder::Recorder,
+    shared::{to_viewport, write_texture_descriptor, TEXTURE_SET_LAYOUT, VULKAN},
+    vulkan::{Filter, WrapMode},
+};
+
+/// A full-frame pass that is registered with a renderer, and runs every frame
+/// after the UI has been drawn.
+pub trait Effect {
+    /// Creates the pipeline that the effect draws with into images of
+    /// `format`. `render_pass` has a single color attachment and no depth
+    /// attachment.
+    ///
+    /// This is called at most once for each format that the renderer draws
+    /// to, and the renderer destroys the pipeline when it is dropped.
+    fn create_pipeline(&mut self, render_pass: vk::RenderPass, format: vk::Format) -> vk::Pipeline;
+
+    /// Records the effect's commands. The render pass has already begun on
+    /// `pass.target`, with the viewport and scissor covering all of it, and
+    /// is ended once this returns.
+    ///
+    /// The target's previous contents are undefined, so every pixel must be
+    /// written.
+    fn record(&mut self, pass: &EffectPass);
+}
+
+/// What an [`Effect`] draws with during a frame.
+pub struct EffectPass<'a> {
+    pub cmd: &'a Recorder<'a>,
+    /// The pipeline that [`Effect::create_pipeline()`] returned for the
+    /// target's format.
+    pub pipeline: vk::Pipeline,
+    /// What the previous pass drew, as a descriptor set with a single
+    /// combined image sampler at binding 0 that is visible to the fragment
+    /// stage.
+    pub input: vk::DescriptorSet,
+    pub target: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+}
+
+/// Which image a pass draws into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Buffer(usize),
+    Target,
+}
+
+/// The buffer that the pass at `index` of `count` samples, and where it draws
+/// to. The UI pass comes first and samples nothing, drawing into buffer 0.
+/// Effects then alternate between the two buffers until the last pass draws
+/// to the target.
+fn pass_io(index: usize, count: usize) -> (Option<usize>, Output) {
+    let input = index.checked_sub(1).map(|previous| previous % 2);
+    if index + 1 == count {
+        (input, Output::Target)
+    } else {
+        (input, Output::Buffer(index % 2))
+    }
+}
+
+/// One of the passes that draws a frame.
+enum Pass {
+    /// The renderer's own pass, which draws the UI. It is always first, since
+    /// it draws with the renderer's render pass rather than sampling a
+    /// previous pass.
+    Ui,
+    Effect(Entry),
+}
+
+/// An effect, and the pipelines it has created so far.
+struct Entry {
+    effect: Box<dyn Effect>,
+    pipelines: Vec<(vk::Format, vk::Pipeline)>,
+}
+
+impl Entry {
+    /// The effect's pipeline for `format`, creating it if this is the first
+    /// time that it has been used. Pipelines are kept when the format
+    /// changes, since render passes with the same format are compatible.
+    fn pipeline(&mut self, render_pass: vk::RenderPass, format: vk::Format) -> vk::Pipeline {
+        match self.pipelines.iter().find(|(f, _)| *f == format) {
+            Some(&(_, pipeline)) => pipeline,
+            None => {
+                let pipeline = self.effect.create_pipeline(render_pass, format);
+                self.pipelines.push((format, pipeline));
+                pipeline
+            }
+        }
+    }
+}
+
+/// An image that the UI or an effect draws into, for the next effect to
+/// sample.
+struct Buffer {
+    /// Drawn to by the UI's render pass, as if it were a swapchain image.
+    /// Dropped manually because it must be destroyed before its image.
+    content: ManuallyDrop<SwapchainImage>,
+    memory: vk::DeviceMemory,
+    /// Drawn to by `EffectChain::buffer_pass`.
+    frame_buffer: vk::Framebuffer,
+    set: vk::DescriptorSet,
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        VULKAN.destroy_frame_buffer(self.frame_buffer);
+        let image = self.content.image;
+        unsafe { ManuallyDrop::drop(&mut self.content) };
+        VULKAN.destroy_image(image);
+        VULKAN.free(self.memory);
+    }
+}
+
+/// The passes that draw a renderer's frames: the UI, followed by the effects
+/// registered with it, and the images they draw with. The UI draws straight
+/// to the target until an effect is added.
+pub struct EffectChain {
+    passes: Vec<Pass>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    /// Draws into a buffer, leaving it ready to be sampled.
+    buffer_pass: vk::RenderPass,
+    /// Draws into a target image, leaving it ready to be presented.
+    target_pass: vk::RenderPass,
+    buffers: Vec<Buffer>,
+    /// One framebuffer for each of the renderer's images, for `target_pass`.
+    targets: Vec<vk::Framebuffer>,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self {
+            passes: vec![Pass::Ui],
+            format: vk::Format::UNDEFINED,
+            extent: vk::Extent2D::default(),
+            buffer_pass: vk::RenderPass::null(),
+            target_pass: vk::RenderPass::null(),
+            buffers: vec![],
+            targets: vec![],
+            descriptor_pool: vk::DescriptorPool::null(),
+        }
+    }
+
+    /// Whether any effects have been added after the UI pass.
+    pub fn has_effects(&self) -> bool {
+        self.passes.len() > 1
+    }
+
+    /// Adds an effect to the end of the chain. Call
+    /// [`resize()`](Self::resize) afterwards to create the images it needs.
+    pub fn push(&mut self, effect: Box<dyn Effect>) {
+        self.passes.push(Pass::Effect(Entry {
+            effect,
+            pipelines: vec![],
+        }));
+    }
+
+    /// Recreates the chain's images to match the renderer's. `content_pass`
+    /// is the render pass that the UI is drawn with, and `targets` are the
+    /// views of the images that the last effect draws to. None of the
+    /// chain's images may be in use by the GPU.
+    pub fn resize(
+        &mut self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        content_pass: vk::RenderPass,
+        depth_format: Option<vk::Format>,
+        targets: &[vk::ImageView],
+    ) {
+        self.destroy_images();
+        if !self.has_effects() {
+            return;
+        }
+
+        if format != self.format {
+            self.destroy_render_passes();
+            self.buffer_pass =
+                create_effect_render_pass(format, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            self.target_pass = create_effect_render_pass(format, vk::ImageLayout::PRESENT_SRC_KHR);
+            self.format = format;
+        }
+        self.extent = extent;
+
+        // A single effect draws straight from the UI to the target.
+        let num_buffers = (self.passes.len() - 1).min(2);
+        self.descriptor_pool = VULKAN.create_descriptor_pool(
+            num_buffers as u32,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: num_buffers as u32,
+            }],
+        );
+        let sets = VULKAN
+            .allocate_descriptor_sets(
+                self.descriptor_pool,
+                &vec![*TEXTURE_SET_LAYOUT; num_buffers],
+            )
+            .expect("the pool has room for every buffer");
+
+        let image_extent = vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+        let sampler = VULKAN.sampler(Filter::Linear, WrapMode::ClampToEdge);
+
+        for set in sets {
+            let (image, memory) = create_color_image(
+                format,
+                image_extent,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            );
+            let content =
+                SwapchainImage::new(image, format, image_extent, content_pass, depth_format);
+            let frame_buffer = create_frame_buffer(self.buffer_pass, content.view, extent);
+            write_texture_descriptor(set, content.view, sampler);
+
+            self.buffers.push(Buffer {
+                content: ManuallyDrop::new(content),
+                memory,
+                frame_buffer,
+                set,
+            });
+        }
+
+        self.targets = targets
+            .iter()
+            .map(|&view| create_frame_buffer(self.target_pass, view, extent))
+            .collect();
+    }
+
+    /// Records every pass in order. The UI pass is recorded by calling
+    /// `draw_ui` with the framebuffer to draw into, which is `ui_target` if
+    /// there are no effects. Otherwise, the last effect draws to the image at
+    /// `target_index` of the views passed to [`resize()`](Self::resize).
+    pub fn record(
+        &mut self,
+        cmd: &Recorder,
+        target_index: usize,
+        ui_target: vk::Framebuffer,
+        draw_ui: impl FnOnce(vk::Framebuffer),
+    ) {
+        let area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+
+        let mut draw_ui = Some(draw_ui);
+        let count = self.passes.len();
+        for (index, pass) in self.passes.iter_mut().enumerate() {
+            let (input, output) = pass_io(index, count);
+            let entry = match pass {
+                Pass::Ui => {
+                    let target = match output {
+                        Output::Buffer(buffer) => self.buffers[buffer].content.frame_buffer,
+                        Output::Target => ui_target,
+                    };
+                    if let Some(draw_ui) = draw_ui.take() {
+                        draw_ui(target);
+                    }
+
+                    // The UI's render pass leaves its target ready to be
+                    // presented.
+                    if let Output::Buffer(buffer) = output {
+                        cmd.transition_image_layout(
+                            self.buffers[buffer].content.image,
+                            vk::ImageLayout::PRESENT_SRC_KHR,
+                            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            vk::ImageSubresourceRange {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                base_mip_level: 0,
+                                level_count: 1,
+                                base_array_layer: 0,
+                                layer_count: 1,
+                            },
+                        );
+                    }
+                    continue;
+                }
+                Pass::Effect(entry) => entry,
+            };
+            let input = input.expect("only the UI pass samples nothing");
+
+            let (render_pass, target) = match output {
+                Output::Buffer(buffer) => (self.buffer_pass, self.buffers[buffer].frame_buffer),
+                Output::Target => (self.target_pass, self.targets[target_index]),
+            };
+
+            cmd.begin_render_pass(
+                &vk::RenderPassBeginInfo::builder()
+                    .render_pass(render_pass)
+                    .framebuffer(target)
+                    .render_area(area),
+                vk::SubpassContents::INLINE,
+            );
+            cmd.set_viewport(&[to_viewport(area)]);
+            cmd.set_scissor(&[area]);
+
+            let pipeline = entry.pipeline(self.buffer_pass, self.format);
+            entry.effect.record(&EffectPass {
+                cmd,
+                pipeline,
+                input: self.buffers[input].set,
+                target,
+                extent: self.extent,
+            });
+
+            cmd.end_render_pass();
+        }
+    }
+
+    fn destroy_images(&mut self) {
+        for frame_buffer in self.targets.drain(..) {
+            VULKAN.destroy_frame_buffer(frame_buffer);
+        }
+
+        self.buffers.clear();
+        if self.descriptor_pool != vk::DescriptorPool::null() {
+            VULKAN.destroy_descriptor_pool(std::mem::take(&mut self.descriptor_pool));
+        }
+    }
+
+    fn destroy_render_passes(&mut self) {
+        for render_pass in [&mut self.buffer_pass, &mut self.target_pass] {
+            if *render_pass != vk::RenderPass::null() {
+                VULKAN.destroy_render_pass(std::mem::take(render_pass));
+            }
+        }
+    }
+}
+
+impl Drop for EffectChain {
+    fn drop(&mut self) {
+        self.destroy_images();
+        self.destroy_render_passes();
+
+        for pass in &self.passes {
+            if let Pass::Effect(entry) = pass {
+                for &(_, pipeline) in &entry.pipelines {
+                    VULKAN.destroy_pipeline(pipeline);
+                }
+            }
+        }
+    }
+}
+
+fn create_frame_buffer(
+    render_pass: vk::RenderPass,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+) -> vk::Framebuffer {
+    let attachments = [view];
+    VULKAN.create_frame_buffer(
+        &vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1),
+    )
+}
+
+/// Creates a render pass that overwrites a color attachment with `format`,
+/// leaving it in `final_layout`. Render passes with the same format are
+/// compatible, so effects' pipelines work with either kind.
+fn create_effect_render_pass(format: vk::Format, final_layout: vk::ImageLayout) -> vk::RenderPass {
+    let attachments = [vk::AttachmentDescription {
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        format,
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::DONT_CARE,
+        store_op: vk::AttachmentStoreOp::STORE,
+        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        final_layout,
+    }];
+
+    let attachment_reference = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+
+    let subpasses = [vk::SubpassDescription {
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        p_color_attachments: attachment_reference.as_ptr(),
+        color_attachment_count: attachment_reference.len() as u32,
+        ..Default::default()
+    }];
+
+    let dependencies = [
+        // The image may still be being sampled by the pass before last.
+        vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dependency_flags: DependencyFlags::empty(),
+        },
+        // The next pass samples what this one drew.
+        vk::SubpassDependency {
+            src_subpass: 0,
+            dst_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            dependency_flags: DependencyFlags::empty(),
+        },
+    ];
+
+    let create_info = vk::RenderPassCreateInfo {
+        p_attachments: attachments.as_ptr(),
+        attachment_count: attachments.len() as u32,
+        p_subpasses: subpasses.as_ptr(),
+        subpass_count: subpasses.len() as u32,
+        p_dependencies: dependencies.as_ptr(),
+        dependency_count: dependencies.len() as u32,
+        ..Default::default()
+    };
+
+    VULKAN.create_render_pass(&create_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        gfx::{Canvas, CanvasStorage, Color, DrawStyled, RendererWindow},
+        px::{Physical, Px},
+        shapes::{Extent, Rect},
+        sys::Library,
+    };
+
+    #[test]
+    fn effects_alternate_buffers() {
+        // Without effects, the UI draws straight to the target.
+        assert_eq!(pass_io(0, 1), (None, Output::Target));
+        assert!(!EffectChain::new().has_effects());
+
+        let passes = (0..5).map(|index| pass_io(index, 5)).collect::<Vec<_>>();
+        assert_eq!(
+            passes,
+            [
+                (None, Output::Buffer(0)),
+                (Some(0), Output::Buffer(1)),
+                (Some(1), Output::Buffer(0)),
+                (Some(0), Output::Buffer(1)),
+                (Some(1), Output::Target),
+            ]
+        );
+    }
+
+    /// Draws nothing, but remembers where it was asked to draw.
+    struct PassThrough {
+        targets: Rc<RefCell<Vec<vk::Framebuffer>>>,
+        pipelines_created: Rc<RefCell<usize>>,
+    }
+
+    impl Effect for PassThrough {
+        fn create_pipeline(&mut self, _: vk::RenderPass, _: vk::Format) -> vk::Pipeline {
+            *self.pipelines_created.borrow_mut() += 1;
+            vk::Pipeline::null()
+        }
+
+        fn record(&mut self, pass: &EffectPass) {
+            assert_ne!(pass.input, vk::DescriptorSet::null());
+            self.targets.borrow_mut().push(pass.target);
+        }
+    }
+
+    #[test]
+    fn custom_effect_runs_every_frame() {
+        // Needs a GPU, and so can't run everywhere.
+        if Library::load("vulkan-1").is_err() {
+            return;
+        }
+
+        let size = Extent::new(Px(16), Px(16));
+        let mut storage = CanvasStorage::default();
+        let mut canvas = Canvas::new(Physical(size), &mut storage);
+        canvas.draw_styled(
+            &Rect::new(Px(2), Px(2), Px(4), Px(4)),
+            Color::rgba(255, 0, 0, 255),
+        );
+
+        let targets = Rc::new(RefCell::new(vec![]));
+        let pipelines_created = Rc::new(RefCell::new(0));
+        let mut context = RendererWindow::offscreen(size);
+        context.register_effect(Box::new(PassThrough {
+            targets: targets.clone(),
+            pipelines_created: pipelines_created.clone(),
+        }));
+
+        context.draw_canvas(&canvas);
+        context.draw_canvas(&canvas);
+
+        // The only effect draws to the offscreen image once per frame, and
+        // its pipeline is reused.
+        let expected = context.effects().targets[0];
+        assert_eq!(*targets.borrow(), [expected, expected]);
+        assert_eq!(*pipelines_created.borrow(), 1);
+    }
+}
@@ -6,7 +6,11 @@
 
 use ash::vk;
 
-use super::shared::{Request, Response, VULKAN};
+use super::{
+    shared::{PresentTransfer, Request, Response, VULKAN},
+    vulkan::{GpuInfo, QueueFamilies, SyncStats},
+    window_set::SubmitBatch,
+};
 
 pub struct Executor {}
 
@@ -17,6 +21,50 @@ impl Executor {
         Self {}
     }
 
+    /// Lists every GPU in the system. Set the `MAPLE_GPU` environment variable
+    /// to `high-performance`, `low-power`, or one of these GPUs' indices to
+    /// choose which one is rendered with.
+    pub fn enumerate_gpus(&self) -> Vec<GpuInfo> {
+        VULKAN.enumerate_gpus()
+    }
+
+    /// The GPU that is being rendered with.
+    pub fn gpu(&self) -> GpuInfo {
+        VULKAN.gpu()
+    }
+
+    /// Turns reporting of Vulkan validation messages on or off. This only has
+    /// an effect in debug builds, or when `MAPLE_CHECK_VULKAN` is set.
+    pub fn set_debug_messenger_enabled(&self, enabled: bool) {
+        VULKAN.set_debug_messenger_enabled(enabled);
+    }
+
+    /// The queue families that rendering and presentation happen on.
+    pub fn queue_families(&self) -> QueueFamilies {
+        VULKAN.queue_families()
+    }
+
+    /// Forces swapchains to use exclusive sharing with explicit ownership
+    /// transfers, even if rendering and presentation happen on different
+    /// queue families. For performance testing; see
+    /// [`Vulkan::set_force_exclusive_sharing()`](super::vulkan::Vulkan::set_force_exclusive_sharing).
+    pub fn set_force_exclusive_sharing(&self, force: bool) {
+        VULKAN.set_force_exclusive_sharing(force);
+    }
+
+    /// The number of fences and semaphores that currently exist, for finding
+    /// leaks.
+    pub fn sync_object_stats(&self) -> SyncStats {
+        VULKAN.sync_object_stats()
+    }
+
+    /// Waits for the GPU to go idle, then checks in debug builds that no
+    /// fences or semaphores were leaked. Call this after dropping every
+    /// renderer, such as just before the application exits.
+    pub fn shutdown(self) {
+        VULKAN.shutdown();
+    }
+
     pub fn execute(&mut self, request: &Request) -> Response {
         match *request {
             Request::SubmitCommands {
@@ -26,14 +74,23 @@ impl Executor {
                 commands,
                 swapchain,
                 image_id,
+                present_transfer,
             } => {
                 self.submit(commands, wait_semaphore, signal_semaphore, fence);
 
+                let present_wait = match present_transfer {
+                    Some(transfer) => {
+                        transfer_to_present_queue(&[(signal_semaphore, transfer)]);
+                        transfer.semaphore
+                    }
+                    None => signal_semaphore,
+                };
+
                 let present_info = vk::PresentInfoKHR {
                     s_type: vk::StructureType::PRESENT_INFO_KHR,
                     p_next: std::ptr::null(),
                     wait_semaphore_count: 1,
-                    p_wait_semaphores: &signal_semaphore,
+                    p_wait_semaphores: &present_wait,
                     swapchain_count: 1,
                     p_swapchains: &swapchain,
                     p_image_indices: &image_id,
@@ -46,6 +103,66 @@ impl Executor {
         }
     }
 
+    /// Submits the frames of several windows to the graphics queue at once,
+    /// then presents all of them together.
+    pub fn execute_batch(&mut self, batch: &SubmitBatch) {
+        let wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; batch.len()];
+        let submits = (0..batch.len())
+            .map(|i| vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                p_next: std::ptr::null(),
+                wait_semaphore_count: 1,
+                p_wait_semaphores: &batch.wait_semaphores[i],
+                p_wait_dst_stage_mask: &wait_stages[i],
+                signal_semaphore_count: 1,
+                p_signal_semaphores: &batch.signal_semaphores[i],
+                command_buffer_count: 1,
+                p_command_buffers: &batch.commands[i],
+            })
+            .collect::<Vec<_>>();
+
+        VULKAN.reset_fences(&batch.fences);
+
+        // A submission can only signal one fence, but every window waits on
+        // its own. Empty submissions signal the rest once the real one is
+        // done, since the queue completes work in submission order.
+        let (first, rest) = batch.fences.split_first().unwrap();
+        VULKAN.submit_to_graphics_queue(&submits, *first);
+        for fence in rest {
+            VULKAN.submit_to_graphics_queue(&[], *fence);
+        }
+
+        let transfers = batch
+            .signal_semaphores
+            .iter()
+            .zip(&batch.present_transfers)
+            .filter_map(|(signal, transfer)| Some((*signal, (*transfer)?)))
+            .collect::<Vec<_>>();
+        if !transfers.is_empty() {
+            transfer_to_present_queue(&transfers);
+        }
+
+        let present_waits = batch
+            .signal_semaphores
+            .iter()
+            .zip(&batch.present_transfers)
+            .map(|(signal, transfer)| transfer.map_or(*signal, |transfer| transfer.semaphore))
+            .collect::<Vec<_>>();
+
+        let present_info = vk::PresentInfoKHR {
+            s_type: vk::StructureType::PRESENT_INFO_KHR,
+            p_next: std::ptr::null(),
+            wait_semaphore_count: batch.len() as u32,
+            p_wait_semaphores: present_waits.as_ptr(),
+            swapchain_count: batch.len() as u32,
+            p_swapchains: batch.swapchains.as_ptr(),
+            p_image_indices: batch.image_ids.as_ptr(),
+            p_results: std::ptr::null_mut(),
+        };
+
+        VULKAN.present(&present_info);
+    }
+
     fn submit(
         &mut self,
         commands: vk::CommandBuffer,
@@ -69,3 +186,24 @@ impl Executor {
         VULKAN.submit_to_graphics_queue(&[submit_info], fence);
     }
 }
+
+/// Runs each transfer on the present queue once the graphics queue has
+/// signalled the semaphore paired with it.
+fn transfer_to_present_queue(transfers: &[(vk::Semaphore, PresentTransfer)]) {
+    let submits = transfers
+        .iter()
+        .map(|(wait, transfer)| vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            p_next: std::ptr::null(),
+            wait_semaphore_count: 1,
+            p_wait_semaphores: wait,
+            p_wait_dst_stage_mask: &vk::PipelineStageFlags::ALL_COMMANDS,
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &transfer.semaphore,
+            command_buffer_count: 1,
+            p_command_buffers: &transfer.commands,
+        })
+        .collect::<Vec<_>>();
+
+    VULKAN.submit_to_present_queue(&submits, vk::Fence::null());
+}
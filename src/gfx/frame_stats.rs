@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+/// The number of frames that [`FrameStats`] averages over.
+pub const FRAME_STATS_LENGTH: usize = 60;
+
+/// Rolling CPU-side timings of a window's most recent frames, for showing in
+/// a performance overlay. This only measures the time spent recording
+/// frames; see [`RendererWindow::last_frame_gpu_ms()`] for the GPU's side.
+///
+/// [`RendererWindow::last_frame_gpu_ms()`]: super::RendererWindow::last_frame_gpu_ms
+#[derive(Debug)]
+pub struct FrameStats {
+    /// How long each frame took to record.
+    cpu_times: Samples,
+    /// The time between the starts of consecutive frames.
+    intervals: Samples,
+    last_start: Option<Instant>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self {
+            cpu_times: Samples::default(),
+            intervals: Samples::default(),
+            last_start: None,
+        }
+    }
+
+    /// Records a frame that started at `start` and took `cpu_time` to
+    /// record. Only the last [`FRAME_STATS_LENGTH`] frames are kept.
+    pub fn record(&mut self, start: Instant, cpu_time: Duration) {
+        self.cpu_times.push(cpu_time);
+        if let Some(last_start) = self.last_start.replace(start) {
+            self.intervals
+                .push(start.saturating_duration_since(last_start));
+        }
+    }
+
+    /// Notes that a frame was skipped, so that the time until the next one
+    /// isn't counted as the interval between two frames.
+    pub fn skip(&mut self) {
+        self.last_start = None;
+    }
+
+    /// The average time spent recording a frame, in milliseconds.
+    pub fn frame_time_ms(&self) -> f32 {
+        self.cpu_times.average().as_secs_f32() * 1000.0
+    }
+
+    /// The number of frames drawn per second, averaged over the recent
+    /// frames. 0 until at least two frames have been drawn.
+    pub fn fps(&self) -> f32 {
+        let interval = self.intervals.average();
+        if interval.is_zero() {
+            0.0
+        } else {
+            1.0 / interval.as_secs_f32()
+        }
+    }
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A ring buffer of the last [`FRAME_STATS_LENGTH`] durations.
+#[derive(Debug)]
+struct Samples {
+    durations: [Duration; FRAME_STATS_LENGTH],
+    len: usize,
+    next: usize,
+}
+
+impl Samples {
+    fn push(&mut self, duration: Duration) {
+        self.durations[self.next] = duration;
+        self.next = (self.next + 1) % FRAME_STATS_LENGTH;
+        self.len = (self.len + 1).min(FRAME_STATS_LENGTH);
+    }
+
+    /// The average of the durations, or zero if there aren't any.
+    fn average(&self) -> Duration {
+        if self.len == 0 {
+            Duration::ZERO
+        } else {
+            // Unwritten durations are zero, so they don't affect the sum.
+            self.durations.iter().sum::<Duration>() / self.len as u32
+        }
+    }
+}
+
+impl Default for Samples {
+    fn default() -> Self {
+        Self {
+            durations: [Duration::ZERO; FRAME_STATS_LENGTH],
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_averages() {
+        let ms = Duration::from_millis;
+        let mut stats = FrameStats::new();
+        assert_eq!(stats.frame_time_ms(), 0.0);
+        assert_eq!(stats.fps(), 0.0);
+
+        // One frame is enough for a frame time, but not for a frame rate.
+        let start = Instant::now();
+        stats.record(start, ms(4));
+        assert_eq!(stats.frame_time_ms(), 4.0);
+        assert_eq!(stats.fps(), 0.0);
+
+        // Two more frames, 20ms apart, that took 2 and 6ms to record.
+        stats.record(start + ms(20), ms(2));
+        stats.record(start + ms(40), ms(6));
+        assert_eq!(stats.frame_time_ms(), 4.0);
+        assert!((stats.fps() - 50.0).abs() < 0.01);
+
+        // Older frames are forgotten.
+        for i in 1..=FRAME_STATS_LENGTH as u32 {
+            stats.record(start + ms(40) + ms(10) * i, ms(1));
+        }
+        assert_eq!(stats.frame_time_ms(), 1.0);
+        assert!((stats.fps() - 100.0).abs() < 0.01);
+
+        // A long gap after a skipped frame, such as while minimized, doesn't
+        // lower the frame rate.
+        let resumed = start + Duration::from_secs(10);
+        stats.skip();
+        stats.record(resumed, ms(1));
+        assert!((stats.fps() - 100.0).abs() < 0.01);
+        stats.record(resumed + ms(10), ms(1));
+        assert!((stats.fps() - 100.0).abs() < 0.01);
+    }
+}
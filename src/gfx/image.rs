@@ -0,0 +1,564 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use super::{color::Color, png};
+use crate::{
+    px::Px,
+    shapes::{Extent, Rect},
+};
+
+/// Describes how a single pixel is laid out in memory.
+pub trait PixelFormat {
+    fn bytes_per_pixel() -> usize;
+
+    /// Reads a pixel as straight (not premultiplied) RGBA.
+    fn to_rgba8(pixel: &[u8]) -> [u8; 4];
+
+    /// Writes a straight RGBA color into `pixel`.
+    fn from_rgba8(rgba: [u8; 4], pixel: &mut [u8]);
+}
+
+/// 8 bits per channel, in red, green, blue, alpha order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgba8;
+
+impl PixelFormat for Rgba8 {
+    fn bytes_per_pixel() -> usize {
+        4
+    }
+
+    fn to_rgba8(pixel: &[u8]) -> [u8; 4] {
+        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    }
+
+    fn from_rgba8(rgba: [u8; 4], pixel: &mut [u8]) {
+        pixel.copy_from_slice(&rgba);
+    }
+}
+
+/// 8 bits per channel, in blue, green, red, alpha order. This is the order
+/// that swapchain images usually use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bgra8;
+
+impl PixelFormat for Bgra8 {
+    fn bytes_per_pixel() -> usize {
+        4
+    }
+
+    fn to_rgba8(pixel: &[u8]) -> [u8; 4] {
+        [pixel[2], pixel[1], pixel[0], pixel[3]]
+    }
+
+    fn from_rgba8(rgba: [u8; 4], pixel: &mut [u8]) {
+        pixel.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+    }
+}
+
+/// Like [`Rgba8`], but with the color channels already multiplied by alpha.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PremultipliedRgba8;
+
+impl PixelFormat for PremultipliedRgba8 {
+    fn bytes_per_pixel() -> usize {
+        4
+    }
+
+    fn to_rgba8(pixel: &[u8]) -> [u8; 4] {
+        let a = pixel[3] as u32;
+        let unpremultiply = |c: u8| {
+            if a == 0 {
+                0
+            } else {
+                ((c as u32 * 255 + a / 2) / a).min(255) as u8
+            }
+        };
+
+        [
+            unpremultiply(pixel[0]),
+            unpremultiply(pixel[1]),
+            unpremultiply(pixel[2]),
+            pixel[3],
+        ]
+    }
+
+    fn from_rgba8(rgba: [u8; 4], pixel: &mut [u8]) {
+        let c = Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]).premultiplied();
+        pixel.copy_from_slice(&[c.r, c.g, c.b, c.a]);
+    }
+}
+
+/// How [`Image::resize()`] picks the color of each pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// Uses the color of the closest pixel. Keeps edges sharp, which suits
+    /// pixel art.
+    Nearest,
+    /// Blends the four closest pixels, weighted by distance.
+    Bilinear,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    #[error("An image of {extent:?} needs {expected} bytes of pixel data, but {actual} bytes were provided.")]
+    WrongLength {
+        extent: Extent,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// A CPU-side image, stored row by row with no padding between rows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image<F: PixelFormat> {
+    extent: Extent,
+    bytes: Vec<u8>,
+    format: PhantomData<F>,
+}
+
+impl<F: PixelFormat> Image<F> {
+    /// Creates an image with all bytes set to 0.
+    pub fn new(extent: Extent) -> Self {
+        let width = extent.width.0.max(0) as usize;
+        let height = extent.height.0.max(0) as usize;
+
+        Self {
+            extent,
+            bytes: vec![0; width * height * F::bytes_per_pixel()],
+            format: PhantomData,
+        }
+    }
+
+    /// Wraps `bytes`, which must be laid out row by row in the format `F`
+    /// with no padding between rows.
+    pub fn from_raw(extent: Extent, bytes: Vec<u8>) -> Result<Self, ImageError> {
+        let width = extent.width.0.max(0) as usize;
+        let height = extent.height.0.max(0) as usize;
+        let expected = width * height * F::bytes_per_pixel();
+
+        if bytes.len() == expected {
+            Ok(Self {
+                extent,
+                bytes,
+                format: PhantomData,
+            })
+        } else {
+            Err(ImageError::WrongLength {
+                extent,
+                expected,
+                actual: bytes.len(),
+            })
+        }
+    }
+
+    pub fn extent(&self) -> Extent {
+        self.extent
+    }
+
+    /// The image's pixel data, row by row.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn width(&self) -> usize {
+        self.extent.width.0.max(0) as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.extent.height.0.max(0) as usize
+    }
+
+    /// The number of bytes in a single row of the image.
+    pub fn row_pitch(&self) -> usize {
+        self.width() * F::bytes_per_pixel()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &[u8] {
+        let offset = self.offset_of(x, y);
+        &self.bytes[offset..offset + F::bytes_per_pixel()]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [u8] {
+        let offset = self.offset_of(x, y);
+        &mut self.bytes[offset..offset + F::bytes_per_pixel()]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [u8] {
+        let pitch = self.row_pitch();
+        &mut self.bytes[y * pitch..(y + 1) * pitch]
+    }
+
+    /// Creates a copy of the image in another pixel format. Converting to and
+    /// from a premultiplied format is lossy for translucent pixels.
+    pub fn convert<To: PixelFormat>(&self) -> Image<To> {
+        let mut converted = Image::<To>::new(self.extent);
+        for (from, to) in self
+            .bytes
+            .chunks_exact(F::bytes_per_pixel())
+            .zip(converted.bytes.chunks_exact_mut(To::bytes_per_pixel()))
+        {
+            To::from_rgba8(F::to_rgba8(from), to);
+        }
+        converted
+    }
+
+    /// Creates a copy of the image scaled to `new_size`. Channels are blended
+    /// as stored, so premultiplied formats blend correctly. An empty image
+    /// resizes to one with all bytes set to 0.
+    pub fn resize(&self, new_size: Extent, filter: ResizeFilter) -> Image<F> {
+        let mut resized = Image::new(new_size);
+        if self.width() == 0 || self.height() == 0 {
+            return resized;
+        }
+
+        let bpp = F::bytes_per_pixel();
+        let x_scale = self.width() as f32 / resized.width() as f32;
+        let y_scale = self.height() as f32 / resized.height() as f32;
+
+        for y in 0..resized.height() {
+            // The center of the new pixel, in this image's pixel coordinates.
+            let src_y = (y as f32 + 0.5) * y_scale - 0.5;
+            for x in 0..resized.width() {
+                let src_x = (x as f32 + 0.5) * x_scale - 0.5;
+                let offset = resized.offset_of(x, y);
+                let pixel = &mut resized.bytes[offset..offset + bpp];
+
+                match filter {
+                    ResizeFilter::Nearest => {
+                        let nearest = self.pixel(
+                            clamp_to((x as f32 + 0.5) * x_scale, self.width()),
+                            clamp_to((y as f32 + 0.5) * y_scale, self.height()),
+                        );
+                        pixel.copy_from_slice(nearest);
+                    }
+                    ResizeFilter::Bilinear => {
+                        let (x0, x1, tx) = neighbors(src_x, self.width());
+                        let (y0, y1, ty) = neighbors(src_y, self.height());
+                        let (p00, p10) = (self.pixel(x0, y0), self.pixel(x1, y0));
+                        let (p01, p11) = (self.pixel(x0, y1), self.pixel(x1, y1));
+
+                        for c in 0..bpp {
+                            let lerp = |a: u8, b: u8, t: f32| a as f32 + (b as f32 - a as f32) * t;
+                            let top = lerp(p00[c], p10[c], tx);
+                            let bottom = lerp(p01[c], p11[c], tx);
+                            pixel[c] = (top + (bottom - top) * ty).round() as u8;
+                        }
+                    }
+                }
+            }
+        }
+
+        resized
+    }
+
+    /// Copies `src` into this image with its top-left corner at `dst_origin`.
+    /// Any part of `src` that falls outside of this image is ignored.
+    pub fn blit(&mut self, src: &Image<F>, dst_origin: (u32, u32)) {
+        let (x, y) = (dst_origin.0 as usize, dst_origin.1 as usize);
+        let width = src.width().min(self.width().saturating_sub(x));
+        let height = src.height().min(self.height().saturating_sub(y));
+        self.copy_block(src, (0, 0), (x, y), (width, height));
+    }
+
+    /// Copies the part of the image covered by `rect` into a new image the
+    /// size of `rect`. Pixels of `rect` that lie outside of this image are
+    /// left as 0.
+    pub fn copy_region(&self, rect: Rect) -> Image<F> {
+        let mut region = Image::new(Extent::new(
+            rect.width().max(Px(0)),
+            rect.height().max(Px(0)),
+        ));
+
+        let bounds = Rect::from_extent(Px(0), Px(0), self.extent);
+        if let Some(visible) = rect.intersect(bounds) {
+            region.copy_block(
+                self,
+                (visible.x().0 as usize, visible.y().0 as usize),
+                (
+                    (visible.x() - rect.x()).0 as usize,
+                    (visible.y() - rect.y()).0 as usize,
+                ),
+                (visible.width().0 as usize, visible.height().0 as usize),
+            );
+        }
+
+        region
+    }
+
+    /// Copies a block of `size` pixels from `src_origin` in `src` to
+    /// `dst_origin` in this image. The block must fit within both images.
+    fn copy_block(
+        &mut self,
+        src: &Image<F>,
+        src_origin: (usize, usize),
+        dst_origin: (usize, usize),
+        size: (usize, usize),
+    ) {
+        if size.0 == 0 {
+            return;
+        }
+
+        let length = size.0 * F::bytes_per_pixel();
+        for row in 0..size.1 {
+            let from = src.offset_of(src_origin.0, src_origin.1 + row);
+            let to = self.offset_of(dst_origin.0, dst_origin.1 + row);
+            self.bytes[to..to + length].copy_from_slice(&src.bytes[from..from + length]);
+        }
+    }
+
+    fn offset_of(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width() && y < self.height());
+        (y * self.width() + x) * F::bytes_per_pixel()
+    }
+}
+
+/// Clamps a pixel coordinate to `0..len`.
+fn clamp_to(coord: f32, len: usize) -> usize {
+    (coord.max(0.0) as usize).min(len - 1)
+}
+
+/// The two pixels on either side of `coord`, clamped to `0..len`, and how far
+/// `coord` is from the first towards the second.
+fn neighbors(coord: f32, len: usize) -> (usize, usize, f32) {
+    let first = coord.floor();
+    let t = if coord < 0.0 { 0.0 } else { coord - first };
+    (clamp_to(first, len), clamp_to(first + 1.0, len), t)
+}
+
+impl Image<Rgba8> {
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        let p = self.pixel(x, y);
+        Color::rgba(p[0], p[1], p[2], p[3])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixel_mut(x, y)
+            .copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+
+    /// Sets every pixel in the image to `color`.
+    pub fn fill(&mut self, color: Color) {
+        for pixel in self.bytes.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    /// Saves the image to `path` as an uncompressed 8-bit RGBA PNG.
+    pub fn write_png(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        png::write_rgba8(
+            &mut file,
+            self.width() as u32,
+            self.height() as u32,
+            &self.bytes,
+        )?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_access() {
+        let mut image = Image::<Rgba8>::new(Extent::new(Px(3), Px(2)));
+        assert_eq!(image.row_pitch(), 12);
+        assert_eq!(image.get(2, 1), Color::rgba(0, 0, 0, 0));
+
+        image.set(2, 1, Color::rgb(1, 2, 3));
+        assert_eq!(image.get(2, 1), Color::rgb(1, 2, 3));
+        assert_eq!(image.get(1, 1), Color::rgba(0, 0, 0, 0));
+        assert_eq!(&image.row_mut(1)[8..], &[1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn from_raw_bytes() {
+        let extent = Extent::new(Px(2), Px(1));
+        let image = Image::<Bgra8>::from_raw(extent, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(image.pixel(1, 0), &[5, 6, 7, 8]);
+        assert_eq!(image.as_bytes(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert_eq!(
+            Image::<Bgra8>::from_raw(extent, vec![0; 7]),
+            Err(ImageError::WrongLength {
+                extent,
+                expected: 8,
+                actual: 7
+            })
+        );
+    }
+
+    #[test]
+    fn format_conversion() {
+        let mut image = Image::<Rgba8>::new(Extent::new(Px(2), Px(1)));
+        image.set(0, 0, Color::rgba(10, 20, 30, 255));
+        image.set(1, 0, Color::rgba(200, 100, 50, 128));
+
+        let bgra = image.convert::<Bgra8>();
+        assert_eq!(bgra.pixel(0, 0), &[30, 20, 10, 255]);
+        assert_eq!(bgra.pixel(1, 0), &[50, 100, 200, 128]);
+        assert_eq!(bgra.convert::<Rgba8>(), image);
+
+        let premultiplied = image.convert::<PremultipliedRgba8>();
+        assert_eq!(premultiplied.pixel(0, 0), &[10, 20, 30, 255]);
+        assert_eq!(premultiplied.pixel(1, 0), &[100, 50, 25, 128]);
+        // Translucent colors lose a little precision on the way back.
+        let straight = premultiplied.convert::<Rgba8>();
+        assert_eq!(straight.get(0, 0), image.get(0, 0));
+        for (a, b) in straight.pixel(1, 0).iter().zip(image.pixel(1, 0)) {
+            assert!((*a as i32 - *b as i32).abs() <= 1);
+        }
+
+        // Fully transparent pixels have no color once premultiplied.
+        image.set(0, 0, Color::rgba(10, 20, 30, 0));
+        let round_trip = image.convert::<PremultipliedRgba8>().convert::<Rgba8>();
+        assert_eq!(round_trip.get(0, 0), Color::rgba(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn blit_and_copy_region() {
+        let a = Color::rgb(1, 0, 0);
+        let b = Color::rgb(0, 2, 0);
+        let c = Color::rgb(0, 0, 3);
+        let d = Color::rgb(4, 4, 4);
+        let empty = Color::rgba(0, 0, 0, 0);
+
+        let mut src = Image::<Rgba8>::new(Extent::new(Px(2), Px(2)));
+        src.set(0, 0, a);
+        src.set(1, 0, b);
+        src.set(0, 1, c);
+        src.set(1, 1, d);
+
+        let mut dst = Image::<Rgba8>::new(Extent::new(Px(4), Px(4)));
+        dst.blit(&src, (1, 2));
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = match (x, y) {
+                    (1, 2) => a,
+                    (2, 2) => b,
+                    (1, 3) => c,
+                    (2, 3) => d,
+                    _ => empty,
+                };
+                assert_eq!(dst.get(x, y), expected, "({}, {})", x, y);
+            }
+        }
+
+        // Only the top-left pixel of `src` lands inside the image.
+        let mut dst = Image::<Rgba8>::new(Extent::new(Px(4), Px(4)));
+        dst.blit(&src, (3, 3));
+        assert_eq!(dst.get(3, 3), a);
+        assert_eq!(dst.get(2, 3), empty);
+        assert_eq!(dst.get(3, 2), empty);
+
+        // Entirely outside, or empty.
+        let before = dst.clone();
+        dst.blit(&src, (4, 0));
+        dst.blit(&src, (100, 100));
+        dst.blit(&Image::new(Extent::new(Px(0), Px(2))), (0, 0));
+        assert_eq!(dst, before);
+
+        let mut dst = Image::<Rgba8>::new(Extent::new(Px(4), Px(4)));
+        dst.blit(&src, (1, 2));
+        assert_eq!(dst.copy_region(Rect::new(Px(1), Px(2), Px(2), Px(2))), src);
+
+        // Overhanging the bottom-right corner.
+        let region = dst.copy_region(Rect::new(Px(2), Px(3), Px(3), Px(2)));
+        assert_eq!((region.width(), region.height()), (3, 2));
+        assert_eq!(region.get(0, 0), d);
+        assert_eq!(region.get(1, 0), empty);
+        assert_eq!(region.get(0, 1), empty);
+
+        // Overhanging the top-left corner.
+        let region = dst.copy_region(Rect::new(Px(-1), Px(1), Px(3), Px(2)));
+        assert_eq!(region.get(2, 1), a);
+        assert_eq!(region.get(0, 1), empty);
+
+        let region = dst.copy_region(Rect::new(Px(1), Px(1), Px(0), Px(3)));
+        assert_eq!((region.width(), region.height()), (0, 3));
+        let region = dst.copy_region(Rect::new(Px(5), Px(5), Px(2), Px(2)));
+        assert_eq!(region, Image::new(Extent::new(Px(2), Px(2))));
+    }
+
+    #[test]
+    fn resize() {
+        let red = Color::rgb(255, 0, 0);
+        let blue = Color::rgb(0, 0, 255);
+
+        // Red on the left, blue on the right.
+        let mut image = Image::<Rgba8>::new(Extent::new(Px(2), Px(2)));
+        image.set(0, 0, red);
+        image.set(0, 1, red);
+        image.set(1, 0, blue);
+        image.set(1, 1, blue);
+        let size = Extent::new(Px(4), Px(4));
+
+        let nearest = image.resize(size, ResizeFilter::Nearest);
+        for y in 0..4 {
+            assert_eq!(nearest.get(0, y), red);
+            assert_eq!(nearest.get(1, y), red);
+            assert_eq!(nearest.get(2, y), blue);
+            assert_eq!(nearest.get(3, y), blue);
+        }
+
+        let bilinear = image.resize(size, ResizeFilter::Bilinear);
+        for y in 0..4 {
+            // The edges are clamped, so the outer columns aren't blended.
+            assert_eq!(bilinear.get(0, y), red);
+            assert_eq!(bilinear.get(1, y), Color::rgb(191, 0, 64));
+            assert_eq!(bilinear.get(2, y), Color::rgb(64, 0, 191));
+            assert_eq!(bilinear.get(3, y), blue);
+        }
+        // Downscaling picks the pixel under the center of each new pixel.
+        let half = Extent::new(Px(2), Px(2));
+        assert_eq!(nearest.resize(half, ResizeFilter::Nearest), image);
+
+        // Shrinking to a single pixel blends all four.
+        let single = image.resize(Extent::new(Px(1), Px(1)), ResizeFilter::Bilinear);
+        assert_eq!(single.get(0, 0), Color::rgb(128, 0, 128));
+        let single = image.resize(Extent::new(Px(1), Px(1)), ResizeFilter::Nearest);
+        assert_eq!(
+            single.resize(size, ResizeFilter::Bilinear).get(3, 3),
+            single.get(0, 0)
+        );
+
+        let empty = Image::<Rgba8>::new(Extent::new(Px(0), Px(2)));
+        assert_eq!(empty.resize(size, ResizeFilter::Bilinear), Image::new(size));
+        assert_eq!(
+            image
+                .resize(Extent::default(), ResizeFilter::Nearest)
+                .as_bytes(),
+            &[0u8; 0]
+        );
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let black = Color::rgb(0, 0, 0);
+        let white = Color::rgb(255, 255, 255);
+
+        let mut image = Image::<Rgba8>::new(Extent::new(Px(2), Px(2)));
+        image.set(0, 0, black);
+        image.set(1, 0, white);
+        image.set(0, 1, white);
+        image.set(1, 1, black);
+
+        let path = std::env::temp_dir().join("maple_png_round_trip.png");
+        image.write_png(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (width, height, pixels) = png::tests::read_rgba8(&bytes);
+        assert_eq!((width, height), (2, 2));
+
+        let mut decoded = Image::<Rgba8>::new(Extent::new(Px(2), Px(2)));
+        decoded.row_mut(0).copy_from_slice(&pixels[..8]);
+        decoded.row_mut(1).copy_from_slice(&pixels[8..]);
+        assert_eq!(decoded, image);
+    }
+}
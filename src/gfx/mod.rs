@@ -1,18 +1,47 @@
 mod canvas;
-pub use canvas::{Canvas, CanvasStorage, Draw, DrawStyled};
+pub use canvas::{Canvas, CanvasStorage, Draw, DrawBatch, DrawStyled, IndexFormat, Indices};
 
 mod color;
-pub use color::Color;
+pub use color::{Color, ColorFormat};
+
+mod effect;
+pub use effect::{Effect, EffectPass};
+
+mod frame_stats;
+pub use frame_stats::FrameStats;
+
+mod image;
+pub use image::{Bgra8, Image, ImageError, PixelFormat, PremultipliedRgba8, ResizeFilter, Rgba8};
+
+mod png;
 
 mod shared;
-pub use shared::Vertex;
+#[cfg(feature = "hot-reload")]
+pub use shared::reload_shaders_from;
+pub use shared::{save_pipeline_cache, set_debug_callback, Vertex};
 
 mod context;
-pub use context::RendererWindow;
+pub use context::{
+    OffscreenContext, RenderTimeout, RendererOptions, RendererWindow, SwapchainInfo,
+};
 
 mod executor;
 pub use executor::Executor;
 
+mod raster;
+pub use raster::{DrawMode, ImageCanvas};
+
 mod recorder;
+pub use recorder::Recorder;
+
+mod render_thread;
+pub use render_thread::{RenderHandle, RenderThread, RenderThreadStopped, SubmitHandler};
+
+mod window_set;
+pub use window_set::RendererWindowSet;
 
 mod vulkan;
+pub use vulkan::{
+    ColorSpacePreference, DebugCallback, Filter, GpuInfo, GpuPreference, MessageType,
+    QueueFamilies, Severity, ShaderError, SyncStats, WrapMode,
+};
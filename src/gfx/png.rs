@@ -0,0 +1,159 @@
+//! A minimal PNG encoder. Image data is stored without compression, which
+//! keeps the encoder small at the cost of larger files.
+
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The largest amount of data that fits in a single stored deflate block.
+const MAX_STORED_BLOCK: usize = u16::MAX as usize;
+
+/// Writes an 8-bit RGBA image to `out`. `rows` must contain `height` rows of
+/// `width * 4` bytes each.
+pub fn write_rgba8(out: &mut impl Write, width: u32, height: u32, rows: &[u8]) -> io::Result<()> {
+    let row_size = width as usize * 4;
+    debug_assert_eq!(rows.len(), row_size * height as usize);
+
+    out.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), default compression and filtering, no
+    // interlacing.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    // Each row is preceded by its filter type, which is always 0 (none).
+    let mut filtered = Vec::with_capacity(rows.len() + height as usize);
+    if row_size > 0 {
+        for row in rows.chunks_exact(row_size) {
+            filtered.push(0);
+            filtered.extend_from_slice(row);
+        }
+    } else {
+        filtered.resize(height as usize, 0);
+    }
+
+    write_chunk(out, b"IDAT", &zlib_stored(&filtered))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = crc32(crc32(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream made up of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let num_blocks = data.len() / MAX_STORED_BLOCK + 1;
+    let mut out = Vec::with_capacity(data.len() + num_blocks * 5 + 6);
+
+    // Deflate with a 32K window, no preset dictionary. The check bits make the
+    // header a multiple of 31.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let is_last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(is_last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32(!0, b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    /// Reads back an image written by [`write_rgba8()`], returning its width,
+    /// height and rows. This only understands the subset of PNG that the
+    /// encoder produces.
+    pub fn read_rgba8(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(png[..8], SIGNATURE);
+
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind = &rest[4..8];
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(!crc32(crc32(!0, kind), data), crc);
+            chunks.push((kind, data));
+            rest = &rest[12 + len..];
+        }
+
+        let (kind, header) = chunks[0];
+        assert_eq!(kind, b"IHDR");
+        let width = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        assert_eq!(header[8..], [8, 6, 0, 0, 0]);
+
+        let (kind, zlib) = chunks[1];
+        assert_eq!(kind, b"IDAT");
+        assert_eq!(chunks[2].0, b"IEND");
+
+        let mut filtered = vec![];
+        let mut stream = &zlib[2..];
+        loop {
+            let is_last = stream[0] & 1 == 1;
+            assert_eq!(stream[0] >> 1, 0, "only stored blocks are supported");
+            let len = u16::from_le_bytes([stream[1], stream[2]]) as usize;
+            filtered.extend_from_slice(&stream[5..5 + len]);
+            stream = &stream[5 + len..];
+            if is_last {
+                break;
+            }
+        }
+        assert_eq!(stream, adler32(&filtered).to_be_bytes());
+
+        let mut rows = vec![];
+        for row in filtered.chunks_exact(width as usize * 4 + 1) {
+            assert_eq!(row[0], 0);
+            rows.extend_from_slice(&row[1..]);
+        }
+
+        (width, height, rows)
+    }
+}
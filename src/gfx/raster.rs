@@ -0,0 +1,479 @@
+//! Drawing into an [`Image`] on the CPU, for when there is no GPU to render
+//! with (such as when generating images in tests).
+
+use super::{
+    color::Color,
+    image::{Image, Rgba8},
+};
+use crate::{px::Px, shapes::Rect};
+
+/// How the inside of a shape is colored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawMode {
+    Fill(Color),
+    /// Blends between colors along the line from `start` to `end`. Each stop
+    /// is a position along the line from 0 to 1, in ascending order, and its
+    /// color. Points before the first stop or after the last one take on that
+    /// stop's color.
+    LinearGradient {
+        start: (f32, f32),
+        end: (f32, f32),
+        stops: Vec<(f32, Color)>,
+    },
+}
+
+impl DrawMode {
+    /// The color of the shape at `point`.
+    pub fn color_at(&self, point: (f32, f32)) -> Color {
+        match self {
+            DrawMode::Fill(color) => *color,
+            DrawMode::LinearGradient { start, end, stops } => {
+                let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                let length_squared = dx * dx + dy * dy;
+                let t = if length_squared == 0.0 {
+                    0.0
+                } else {
+                    ((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared
+                };
+
+                gradient_color(stops, t)
+            }
+        }
+    }
+}
+
+/// The color at position `t` of a gradient with the given stops.
+fn gradient_color(stops: &[(f32, Color)], t: f32) -> Color {
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Color::rgba(0, 0, 0, 0),
+    };
+
+    if t <= first.0 {
+        return first.1;
+    }
+
+    for pair in stops.windows(2) {
+        let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+        if t <= t1 {
+            let span = t1 - t0;
+            return if span <= 0.0 {
+                c1
+            } else {
+                c0.lerp(c1, (t - t0) / span)
+            };
+        }
+    }
+
+    last.1
+}
+
+/// Draws anti-aliased shapes directly into an image. Shapes that extend past
+/// the edges of the image, or of the current clip rect, are clipped.
+pub struct ImageCanvas<'a> {
+    image: &'a mut Image<Rgba8>,
+    /// Each pushed clip, intersected with the ones before it. [`None`] if the
+    /// intersection is empty.
+    clips: Vec<Option<Rect>>,
+}
+
+impl<'a> ImageCanvas<'a> {
+    pub fn new(image: &'a mut Image<Rgba8>) -> Self {
+        Self {
+            image,
+            clips: Vec::new(),
+        }
+    }
+
+    /// Restricts drawing to the part of `rect` that lies within the current
+    /// clip rect, until the matching call to [`pop_clip()`](Self::pop_clip).
+    /// Nothing is drawn while the two don't overlap.
+    pub fn push_clip(&mut self, rect: Rect) {
+        let clip = self.clip().and_then(|clip| clip.intersect(rect));
+        self.clips.push(clip);
+    }
+
+    /// Restores the clip rect from before the last call to
+    /// [`push_clip()`](Self::push_clip). Does nothing if no clips have been
+    /// pushed.
+    pub fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    /// The region that can be drawn to, or [`None`] if there isn't one.
+    fn clip(&self) -> Option<Rect> {
+        match self.clips.last() {
+            Some(clip) => *clip,
+            None => Some(Rect::from_extent(Px(0), Px(0), self.image.extent())),
+        }
+    }
+
+    /// Draws a straight line from `from` to `to`, `width` pixels wide, with
+    /// rounded ends. A zero-length line is drawn as a dot `width` pixels
+    /// across.
+    pub fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, color: Color) {
+        self.draw_polyline(&[from, to], width, color);
+    }
+
+    /// Draws line segments joining each point to the next, `width` pixels
+    /// wide. Segments are joined by rounding off the corners between them, and
+    /// overlapping segments are only blended once.
+    pub fn draw_polyline(&mut self, points: &[(f32, f32)], width: f32, color: Color) {
+        let half_width = width / 2.0;
+        if points.is_empty() || half_width <= 0.0 {
+            return;
+        }
+
+        let bounds = points.iter().fold(
+            (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |(left, top, right, bottom), &(x, y)| {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            },
+        );
+
+        let segments = points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain((points.len() == 1).then(|| (points[0], points[0])))
+            .collect::<Vec<_>>();
+
+        self.fill(
+            (
+                bounds.0 - half_width,
+                bounds.1 - half_width,
+                bounds.2 + half_width,
+                bounds.3 + half_width,
+            ),
+            color,
+            |p| {
+                let distance = segments
+                    .iter()
+                    .map(|&(a, b)| distance_to_segment(p, a, b))
+                    .fold(f32::MAX, f32::min);
+                half_width - distance
+            },
+        );
+    }
+
+    /// Fills a circle of `radius` pixels around `center`.
+    pub fn fill_circle(&mut self, center: (f32, f32), radius: f32, color: Color) {
+        self.fill_ellipse(center, radius, radius, color);
+    }
+
+    /// Fills an axis-aligned ellipse around `center`, with radii `rx` and `ry`
+    /// along the x and y axes respectively.
+    pub fn fill_ellipse(&mut self, center: (f32, f32), rx: f32, ry: f32, color: Color) {
+        if rx <= 0.0 || ry <= 0.0 {
+            return;
+        }
+
+        self.fill(
+            (center.0 - rx, center.1 - ry, center.0 + rx, center.1 + ry),
+            color,
+            |(x, y)| {
+                let (dx, dy) = (x - center.0, y - center.1);
+                // The ellipse is where f(x, y) = 0. Dividing f by the length
+                // of its gradient approximates the distance to the edge.
+                let f = (dx / rx).powi(2) + (dy / ry).powi(2) - 1.0;
+                let gradient = 2.0 * ((dx / (rx * rx)).powi(2) + (dy / (ry * ry)).powi(2)).sqrt();
+                if gradient == 0.0 {
+                    rx.min(ry)
+                } else {
+                    -f / gradient
+                }
+            },
+        );
+    }
+
+    /// Fills `rect`, coloring it according to `mode`.
+    pub fn fill_rect(&mut self, rect: Rect, mode: &DrawMode) {
+        let left = f32::from(rect.left());
+        let top = f32::from(rect.top());
+        let right = f32::from(rect.right());
+        let bottom = f32::from(rect.bottom());
+
+        self.fill_with(
+            (left, top, right, bottom),
+            |p| mode.color_at(p),
+            |(x, y)| (x - left).min(right - x).min(y - top).min(bottom - y),
+        );
+    }
+
+    /// Blends `color` into every pixel within `bounds` (left, top, right,
+    /// bottom), weighted by how much of the pixel is covered. `inside` returns
+    /// how far a point is inside the shape, and is negative outside of it.
+    fn fill(
+        &mut self,
+        bounds: (f32, f32, f32, f32),
+        color: Color,
+        inside: impl Fn((f32, f32)) -> f32,
+    ) {
+        self.fill_with(bounds, |_| color, inside);
+    }
+
+    /// Like [`fill()`](Self::fill), except that the color can vary from pixel
+    /// to pixel.
+    fn fill_with(
+        &mut self,
+        bounds: (f32, f32, f32, f32),
+        color: impl Fn((f32, f32)) -> Color,
+        inside: impl Fn((f32, f32)) -> f32,
+    ) {
+        let clip = match self.clip() {
+            Some(clip) => clip,
+            None => return,
+        };
+
+        let (min_x, min_y) = (clip.left().0.max(0) as f32, clip.top().0.max(0) as f32);
+        let max_x = (clip.right().0 as f32).max(min_x);
+        let max_y = (clip.bottom().0 as f32).max(min_y);
+        let left = bounds.0.floor().clamp(min_x, max_x) as usize;
+        let top = bounds.1.floor().clamp(min_y, max_y) as usize;
+        let right = bounds.2.ceil().clamp(min_x, max_x) as usize;
+        let bottom = bounds.3.ceil().clamp(min_y, max_y) as usize;
+
+        for y in top..bottom {
+            for x in left..right {
+                let center = (x as f32 + 0.5, y as f32 + 0.5);
+                // Approximates the covered area of the pixel by treating the
+                // edge as straight across it.
+                let coverage = (inside(center) + 0.5).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    self.blend(x, y, color(center), coverage);
+                }
+            }
+        }
+    }
+
+    /// Blends `color` over the pixel at (`x`, `y`), scaling its alpha by
+    /// `coverage`.
+    fn blend(&mut self, x: usize, y: usize, color: Color, coverage: f32) {
+        let src_alpha = color.a as f32 / 255.0 * coverage;
+        let dst = self.image.get(x, y);
+        let dst_alpha = dst.a as f32 / 255.0;
+
+        let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+        let mix = |src: u8, dst: u8| {
+            if out_alpha == 0.0 {
+                return 0.0;
+            }
+
+            (src as f32 * src_alpha + dst as f32 * dst_alpha * (1.0 - src_alpha))
+                / out_alpha
+                / 255.0
+        };
+
+        self.image.set(
+            x,
+            y,
+            Color::normalized(
+                mix(color.r, dst.r),
+                mix(color.g, dst.g),
+                mix(color.b, dst.b),
+                out_alpha,
+            ),
+        );
+    }
+}
+
+/// The distance from `p` to the closest point on the segment from `a` to `b`.
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+
+    let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Extent;
+
+    const RED: Color = Color::rgb(255, 0, 0);
+    const CLEAR: Color = Color::rgba(0, 0, 0, 0);
+
+    fn covered_rows(image: &Image<Rgba8>, x: usize) -> Vec<usize> {
+        (0..image.height())
+            .filter(|&y| image.get(x, y) != CLEAR)
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_line() {
+        let mut image = Image::new(Extent::new(Px(16), Px(16)));
+        ImageCanvas::new(&mut image).draw_line((2.0, 5.0), (12.0, 5.0), 2.0, RED);
+
+        assert_eq!(covered_rows(&image, 7), [4, 5]);
+        assert_eq!(image.get(7, 4), RED);
+        assert_eq!(image.get(7, 5), RED);
+
+        // Past the ends of the line
+        assert!(covered_rows(&image, 0).is_empty());
+        assert!(covered_rows(&image, 15).is_empty());
+    }
+
+    #[test]
+    fn lines_are_clipped() {
+        let mut image = Image::new(Extent::new(Px(8), Px(8)));
+        ImageCanvas::new(&mut image).draw_line((-20.0, 3.0), (40.0, 3.0), 2.0, RED);
+
+        assert_eq!(covered_rows(&image, 0), [2, 3]);
+        assert_eq!(covered_rows(&image, 7), [2, 3]);
+    }
+
+    #[test]
+    fn zero_length_line_is_a_dot() {
+        let mut image = Image::new(Extent::new(Px(8), Px(8)));
+        ImageCanvas::new(&mut image).draw_line((4.0, 4.0), (4.0, 4.0), 4.0, RED);
+
+        assert_eq!(image.get(3, 3), RED);
+        assert_eq!(image.get(4, 4), RED);
+        assert_eq!(image.get(0, 0), CLEAR);
+        assert_eq!(image.get(7, 4), CLEAR);
+    }
+
+    #[test]
+    fn polyline_joins_are_blended_once() {
+        let mut image = Image::new(Extent::new(Px(16), Px(16)));
+        let color = Color::rgba(255, 0, 0, 128);
+        ImageCanvas::new(&mut image).draw_polyline(
+            &[(2.0, 8.0), (8.0, 8.0), (8.0, 2.0)],
+            2.0,
+            color,
+        );
+
+        // The corner is covered by both segments, but is no more opaque than
+        // the rest of the line.
+        assert_eq!(image.get(7, 7), image.get(4, 7));
+        assert_eq!(image.get(7, 7).a, 128);
+    }
+
+    #[test]
+    fn two_stop_gradient() {
+        let mut image = Image::new(Extent::new(Px(11), Px(4)));
+        let black = Color::rgb(0, 0, 0);
+        let white = Color::rgb(255, 255, 255);
+
+        ImageCanvas::new(&mut image).fill_rect(
+            Rect::new(Px(0), Px(0), Px(11), Px(4)),
+            &DrawMode::LinearGradient {
+                start: (0.0, 0.0),
+                end: (11.0, 0.0),
+                stops: vec![(0.0, black), (1.0, white)],
+            },
+        );
+
+        for y in 0..4 {
+            let middle = image.get(5, y);
+            assert!((middle.r as f32 / 255.0 - 0.5).abs() < 0.01);
+            assert_eq!((middle.r, middle.a), (middle.b, 255));
+        }
+        assert!(image.get(0, 0).r < image.get(1, 0).r);
+        assert!(image.get(9, 0).r < image.get(10, 0).r);
+    }
+
+    #[test]
+    fn gradient_clamps_to_end_stops() {
+        let red = Color::rgb(255, 0, 0);
+        let blue = Color::rgb(0, 0, 255);
+        let mode = DrawMode::LinearGradient {
+            start: (10.0, 0.0),
+            end: (20.0, 0.0),
+            stops: vec![(0.0, red), (0.5, Color::rgb(0, 255, 0)), (1.0, blue)],
+        };
+
+        assert_eq!(mode.color_at((0.0, 0.0)), red);
+        assert_eq!(mode.color_at((15.0, 3.0)), Color::rgb(0, 255, 0));
+        assert_eq!(mode.color_at((30.0, 0.0)), blue);
+    }
+
+    #[test]
+    fn circle_coverage() {
+        let mut image = Image::new(Extent::new(Px(16), Px(16)));
+        ImageCanvas::new(&mut image).fill_circle((8.0, 8.0), 4.0, RED);
+
+        assert_eq!(image.get(8, 8), RED);
+        assert_eq!(image.get(7, 7), RED);
+        assert_eq!(image.get(1, 1), CLEAR);
+        assert_eq!(image.get(14, 8), CLEAR);
+
+        // The edge is partially covered.
+        let edge = image.get(10, 10);
+        assert!(edge.a > 0 && edge.a < 255);
+    }
+
+    #[test]
+    fn ellipse_coverage() {
+        let mut image = Image::new(Extent::new(Px(16), Px(16)));
+        ImageCanvas::new(&mut image).fill_ellipse((8.0, 8.0), 6.0, 2.0, RED);
+
+        assert_eq!(image.get(8, 8), RED);
+        assert_eq!(image.get(3, 7), RED);
+        assert_eq!(image.get(8, 12), CLEAR);
+        assert_eq!(image.get(15, 8), CLEAR);
+    }
+
+    #[test]
+    fn shapes_blend_and_clip() {
+        let mut image = Image::new(Extent::new(Px(8), Px(8)));
+        image.fill(Color::rgb(0, 0, 255));
+
+        // Mostly off the top left corner of the image.
+        let mut canvas = ImageCanvas::new(&mut image);
+        canvas.fill_circle((-2.0, -2.0), 6.0, Color::rgba(255, 0, 0, 128));
+
+        let blended = image.get(0, 0);
+        assert_eq!(blended.a, 255);
+        assert!(blended.r > 100 && blended.b > 100);
+        assert_eq!(image.get(7, 7), Color::rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn nested_clips() {
+        let full = Rect::new(Px(0), Px(0), Px(8), Px(8));
+        let covered = |image: &Image<Rgba8>| {
+            let mut pixels = vec![];
+            for y in 0..image.height() {
+                for x in 0..image.width() {
+                    if image.get(x, y) != CLEAR {
+                        pixels.push((x, y));
+                    }
+                }
+            }
+            pixels
+        };
+
+        let mut image = Image::new(Extent::new(Px(8), Px(8)));
+        let mut canvas = ImageCanvas::new(&mut image);
+        canvas.push_clip(Rect::new(Px(2), Px(2), Px(4), Px(3)));
+        canvas.fill_rect(full, &DrawMode::Fill(RED));
+        canvas.fill_circle((4.0, 4.0), 10.0, RED);
+        canvas.draw_line((0.0, 3.0), (8.0, 3.0), 8.0, RED);
+        let expected = (2..5)
+            .flat_map(|y| (2..6).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        assert_eq!(covered(&image), expected);
+
+        // Nested clips are intersected with the ones outside them, and
+        // popping one restores the last.
+        let mut image = Image::new(Extent::new(Px(8), Px(8)));
+        let mut canvas = ImageCanvas::new(&mut image);
+        canvas.push_clip(Rect::new(Px(2), Px(2), Px(4), Px(4)));
+        canvas.push_clip(Rect::new(Px(5), Px(-3), Px(10), Px(7)));
+        canvas.fill_rect(full, &DrawMode::Fill(RED));
+        canvas.pop_clip();
+        canvas.push_clip(Rect::new(Px(0), Px(0), Px(2), Px(2)));
+        canvas.fill_rect(full, &DrawMode::Fill(RED));
+        canvas.pop_clip();
+        canvas.pop_clip();
+        canvas.fill_rect(Rect::new(Px(0), Px(7), Px(1), Px(1)), &DrawMode::Fill(RED));
+        assert_eq!(covered(&image), [(5, 2), (5, 3), (0, 7)]);
+    }
+}
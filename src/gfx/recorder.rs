@@ -1,5 +1,79 @@
 use ash::{vk, Device};
 
+/// The accesses and pipeline stages on either side of an image layout
+/// transition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutScopes {
+    pub src_access: vk::AccessFlags,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_access: vk::AccessFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+}
+
+/// Picks the scopes for a transition from `old` to `new`. Returns [`None`] if
+/// either layout isn't one that we know how to transition.
+pub fn layout_scopes(old: vk::ImageLayout, new: vk::ImageLayout) -> Option<LayoutScopes> {
+    // What the image was last used for, which has to finish first.
+    let (src_access, src_stage) = match old {
+        vk::ImageLayout::UNDEFINED => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        // Images are put in the present layout by the render pass that draws
+        // to them.
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL | vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        _ => return None,
+    };
+
+    // What the image will be used for next, which has to wait.
+    let (dst_access, dst_stage) = match new {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        // Presentation is ordered by semaphores, not barriers.
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        _ => return None,
+    };
+
+    Some(LayoutScopes {
+        src_access,
+        src_stage,
+        dst_access,
+        dst_stage,
+    })
+}
+
 pub struct Recorder<'a> {
     device: &'a Device,
     pub buffer: vk::CommandBuffer,
@@ -65,6 +139,25 @@ impl<'a> Recorder<'a> {
         }
     }
 
+    pub fn bind_descriptor_sets(
+        &self,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+    ) {
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.buffer,
+                bind_point,
+                layout,
+                first_set,
+                sets,
+                &[],
+            );
+        }
+    }
+
     pub fn set_viewport(&self, viewports: &[vk::Viewport]) {
         unsafe {
             self.device.cmd_set_viewport(self.buffer, 0, viewports);
@@ -77,6 +170,14 @@ impl<'a> Recorder<'a> {
         }
     }
 
+    /// Clears regions of the current subpass's attachments.
+    pub fn clear_attachments(&self, attachments: &[vk::ClearAttachment], rects: &[vk::ClearRect]) {
+        unsafe {
+            self.device
+                .cmd_clear_attachments(self.buffer, attachments, rects);
+        }
+    }
+
     pub fn push_constants<T>(
         &self,
         layout: vk::PipelineLayout,
@@ -92,6 +193,105 @@ impl<'a> Recorder<'a> {
         }
     }
 
+    pub fn pipeline_barrier(
+        &self,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        image_barriers: &[vk::ImageMemoryBarrier],
+    ) {
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                image_barriers,
+            );
+        }
+    }
+
+    /// Moves `image` from `old_layout` to `new_layout`, waiting for whatever
+    /// work `old_layout` implies before starting on the work that
+    /// `new_layout` is for.
+    ///
+    /// Only common transitions are supported; see [`layout_scopes()`].
+    pub fn transition_image_layout(
+        &self,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        subresource_range: vk::ImageSubresourceRange,
+    ) {
+        let scopes = layout_scopes(old_layout, new_layout);
+        debug_assert!(
+            scopes.is_some(),
+            "unsupported layout transition from {:?} to {:?}",
+            old_layout,
+            new_layout
+        );
+
+        // Waiting on everything is slow, but always correct.
+        let scopes = scopes.unwrap_or(LayoutScopes {
+            src_access: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            src_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            dst_access: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            dst_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+        });
+
+        self.pipeline_barrier(
+            scopes.src_stage,
+            scopes.dst_stage,
+            &[vk::ImageMemoryBarrier {
+                src_access_mask: scopes.src_access,
+                dst_access_mask: scopes.dst_access,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range,
+                ..Default::default()
+            }],
+        );
+    }
+
+    pub fn reset_query_pool(&self, pool: vk::QueryPool, first: u32, count: u32) {
+        unsafe {
+            self.device
+                .cmd_reset_query_pool(self.buffer, pool, first, count);
+        }
+    }
+
+    /// Writes the time at which all previous commands reach `stage` to query
+    /// `query` in `pool`.
+    pub fn write_timestamp(&self, stage: vk::PipelineStageFlags, pool: vk::QueryPool, query: u32) {
+        unsafe {
+            self.device
+                .cmd_write_timestamp(self.buffer, stage, pool, query);
+        }
+    }
+
+    pub fn copy_buffer(&self, src: vk::Buffer, dst: vk::Buffer, regions: &[vk::BufferCopy]) {
+        unsafe {
+            self.device.cmd_copy_buffer(self.buffer, src, dst, regions);
+        }
+    }
+
+    pub fn copy_image_to_buffer(
+        &self,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        buffer: vk::Buffer,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        unsafe {
+            self.device
+                .cmd_copy_image_to_buffer(self.buffer, image, layout, buffer, regions);
+        }
+    }
+
     pub fn draw_indexed(
         &self,
         index_count: u32,
@@ -112,3 +312,68 @@ impl<'a> Recorder<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_layout_transitions() {
+        use vk::{AccessFlags as A, ImageLayout as L, PipelineStageFlags as S};
+
+        let table = [
+            (
+                L::UNDEFINED,
+                L::TRANSFER_DST_OPTIMAL,
+                (A::empty(), S::TOP_OF_PIPE),
+                (A::TRANSFER_WRITE, S::TRANSFER),
+            ),
+            (
+                L::TRANSFER_DST_OPTIMAL,
+                L::SHADER_READ_ONLY_OPTIMAL,
+                (A::TRANSFER_WRITE, S::TRANSFER),
+                (A::SHADER_READ, S::FRAGMENT_SHADER),
+            ),
+            (
+                L::PRESENT_SRC_KHR,
+                L::TRANSFER_SRC_OPTIMAL,
+                (A::COLOR_ATTACHMENT_WRITE, S::COLOR_ATTACHMENT_OUTPUT),
+                (A::TRANSFER_READ, S::TRANSFER),
+            ),
+            (
+                L::TRANSFER_SRC_OPTIMAL,
+                L::PRESENT_SRC_KHR,
+                (A::TRANSFER_READ, S::TRANSFER),
+                (A::empty(), S::BOTTOM_OF_PIPE),
+            ),
+            (
+                L::UNDEFINED,
+                L::COLOR_ATTACHMENT_OPTIMAL,
+                (A::empty(), S::TOP_OF_PIPE),
+                (
+                    A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+                    S::COLOR_ATTACHMENT_OUTPUT,
+                ),
+            ),
+        ];
+
+        for (old, new, (src_access, src_stage), (dst_access, dst_stage)) in table {
+            assert_eq!(
+                layout_scopes(old, new),
+                Some(LayoutScopes {
+                    src_access,
+                    src_stage,
+                    dst_access,
+                    dst_stage,
+                }),
+                "{:?} -> {:?}",
+                old,
+                new
+            );
+        }
+
+        // Nothing can be transitioned to the undefined layout.
+        assert_eq!(layout_scopes(L::TRANSFER_DST_OPTIMAL, L::UNDEFINED), None);
+        assert_eq!(layout_scopes(L::GENERAL, L::TRANSFER_SRC_OPTIMAL), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! A thread that submits and presents the frames recorded by window threads,
+//! so that windows can record their frames in parallel while their frames
+//! still reach the GPU in a single, predictable order.
+//!
+//! Window threads record command buffers as usual, then send the resulting
+//! [`Request`] to the render thread through a [`RenderHandle`]. The frame's
+//! semaphores and fence are carried in the request, so the render thread only
+//! has to submit and present in the order that requests arrive.
+//!
+//! The render thread is not the only user of the queues: window threads still
+//! submit one-off work such as uploads, and wait on the queues when resizing.
+//! Every use of a queue goes through the global Vulkan context, which holds a
+//! lock for its duration, so this is safe from any thread.
+
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::JoinHandle,
+};
+
+use super::{
+    executor::Executor,
+    shared::{Request, Response},
+};
+
+/// Something that can carry out [`Request`]s on the render thread. This is
+/// implemented by [`Executor`], and by stand-ins in tests.
+pub trait SubmitHandler {
+    fn execute(&mut self, request: &Request) -> Response;
+}
+
+impl SubmitHandler for Executor {
+    fn execute(&mut self, request: &Request) -> Response {
+        Executor::execute(self, request)
+    }
+}
+
+/// The render thread exited before the request could be carried out.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("The render thread has stopped.")]
+pub struct RenderThreadStopped;
+
+enum Message {
+    Execute {
+        request: Request,
+        reply: Sender<Response>,
+    },
+    Stop,
+}
+
+/// Runs a [`SubmitHandler`] on its own thread. The thread is stopped and
+/// joined when this is dropped, after every request sent before then has
+/// been carried out.
+pub struct RenderThread {
+    sender: Sender<Message>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    /// Starts a render thread that submits to the GPU with an [`Executor`].
+    /// The Vulkan context is initialized on the new thread if it hasn't been
+    /// already.
+    pub fn spawn() -> Self {
+        Self::with_handler(Executor::new)
+    }
+
+    /// Starts a render thread that carries out requests with the handler
+    /// returned by `create`, which is called on the new thread.
+    pub fn with_handler<H, F>(create: F) -> Self
+    where
+        H: SubmitHandler,
+        F: FnOnce() -> H + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let thread = std::thread::Builder::new()
+            .name("maple render".to_string())
+            .spawn(move || run(create(), receiver))
+            .expect("Could not spawn the render thread");
+
+        Self {
+            sender,
+            thread: Some(thread),
+        }
+    }
+
+    /// A handle that window threads can send requests through. Handles may
+    /// be cloned and sent to other threads.
+    pub fn handle(&self) -> RenderHandle {
+        RenderHandle {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        // Handles may outlive the thread, so the channel can't be relied on
+        // to close.
+        let _ = self.sender.send(Message::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sends [`Request`]s to a [`RenderThread`].
+#[derive(Clone)]
+pub struct RenderHandle {
+    sender: Sender<Message>,
+}
+
+impl RenderHandle {
+    /// Sends the request to the render thread and waits for it to be carried
+    /// out. Once this returns, the request's command buffer has been
+    /// submitted, and the frame's fence can be waited on before reusing it.
+    pub fn execute(&self, request: Request) -> Result<Response, RenderThreadStopped> {
+        let (reply, response) = channel();
+        self.sender
+            .send(Message::Execute { request, reply })
+            .map_err(|_| RenderThreadStopped)?;
+        response.recv().map_err(|_| RenderThreadStopped)
+    }
+}
+
+fn run(mut handler: impl SubmitHandler, receiver: Receiver<Message>) {
+    for message in receiver {
+        match message {
+            Message::Execute { request, reply } => {
+                let response = handler.execute(&request);
+                // The sender may have given up waiting, which is fine.
+                let _ = reply.send(response);
+            }
+            Message::Stop => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ash::vk::{self, Handle as _};
+
+    use super::*;
+
+    /// Records the image of every request it is given, and on which thread.
+    struct MockHandler {
+        executed: Arc<Mutex<Vec<(u32, std::thread::ThreadId)>>>,
+    }
+
+    impl SubmitHandler for MockHandler {
+        fn execute(&mut self, request: &Request) -> Response {
+            match *request {
+                Request::SubmitCommands { image_id, .. } => {
+                    let thread = std::thread::current().id();
+                    self.executed.lock().unwrap().push((image_id, thread));
+                    Response::CommandsSubmitted { image_id }
+                }
+            }
+        }
+    }
+
+    fn request(id: u64) -> Request {
+        Request::SubmitCommands {
+            wait_semaphore: vk::Semaphore::from_raw(id),
+            signal_semaphore: vk::Semaphore::from_raw(id + 100),
+            commands: vk::CommandBuffer::from_raw(id),
+            fence: vk::Fence::from_raw(id),
+            swapchain: vk::SwapchainKHR::from_raw(id),
+            image_id: id as u32,
+            present_transfer: None,
+        }
+    }
+
+    #[test]
+    fn requests_run_on_render_thread() {
+        let executed = Arc::new(Mutex::new(vec![]));
+        let thread = {
+            let executed = executed.clone();
+            RenderThread::with_handler(move || MockHandler { executed })
+        };
+
+        // Two window threads sharing the render thread.
+        let windows = (0..2)
+            .map(|window| {
+                let handle = thread.handle();
+                std::thread::spawn(move || {
+                    for frame in 0..3 {
+                        let id = 10 * window + frame;
+                        match handle.execute(request(id)).unwrap() {
+                            Response::CommandsSubmitted { image_id } => {
+                                assert_eq!(image_id, id as u32)
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for window in windows {
+            window.join().unwrap();
+        }
+
+        let handle = thread.handle();
+        drop(thread);
+        assert_eq!(
+            handle.execute(request(99)).unwrap_err(),
+            RenderThreadStopped
+        );
+
+        let executed = executed.lock().unwrap();
+        assert_eq!(executed.len(), 6);
+
+        // Everything ran on the same thread, which wasn't this one.
+        let render_thread = executed[0].1;
+        assert_ne!(render_thread, std::thread::current().id());
+        assert!(executed.iter().all(|(_, thread)| *thread == render_thread));
+
+        // Each window's frames were submitted in the order they were sent.
+        for window in [0, 10] {
+            let frames = executed
+                .iter()
+                .map(|(id, _)| *id)
+                .filter(|id| (window..window + 10).contains(id))
+                .collect::<Vec<_>>();
+            assert_eq!(frames, [window, window + 1, window + 2]);
+        }
+    }
+}
@@ -1,15 +1,25 @@
-use std::{ffi::CStr, process::abort};
+use std::{ffi::CStr, path::PathBuf, process::abort, sync::Mutex};
 
 use ash::vk::{self, DependencyFlags};
 use lazy_static::lazy_static;
 
-use super::{color::Color, recorder::Recorder, vulkan::Vulkan};
-use crate::{shapes::Extent, sys::Library};
+use super::{
+    canvas::{DrawBatch, IndexFormat},
+    color::{Color, ColorFormat},
+    recorder::Recorder,
+    vulkan::{is_quarter_turn, DebugCallback, GpuPreference, Shaders, Vulkan},
+};
+use crate::{
+    px::Px,
+    shapes::{Extent, Rect},
+    sys::Library,
+};
 
 pub const TRIANGLE_VERTEX_SHADER_SPIRV: &[u8] =
     include_bytes!("../../shaders/simple_vertex_vert.spv");
 pub const TRIANGLE_FRAGMENT_SHADER_SPIRV: &[u8] =
     include_bytes!("../../shaders/simple_vertex_frag.spv");
+pub const INSTANCED_VERTEX_SHADER_SPIRV: &[u8] = include_bytes!("../../shaders/instanced_vert.spv");
 
 lazy_static! {
     pub static ref VULKAN: Vulkan = {
@@ -25,39 +35,229 @@ lazy_static! {
             }
         }
 
-        let library = Library::load("vulkan-1").unwrap();
-        Vulkan::new(library, verify)
+        let gpu = match std::env::var("MAPLE_GPU").as_deref() {
+            Err(_) => GpuPreference::Any,
+            Ok("high-performance") => GpuPreference::HighPerformance,
+            Ok("low-power") => GpuPreference::LowPower,
+            Ok(val) => match val.parse() {
+                Ok(index) => GpuPreference::Index(index),
+                Err(_) => {
+                    println!(
+                        "MAPLE_GPU must be absent, high-performance, low-power, or a GPU index"
+                    );
+                    abort();
+                }
+            },
+        };
+
+        let library = match Library::load("vulkan-1") {
+            Ok(library) => library,
+            Err(err) => {
+                println!("{}. Is the Vulkan runtime installed?", err);
+                abort();
+            }
+        };
+        let pipeline_cache = pipeline_cache_path();
+        let debug_callback = DEBUG_CALLBACK.lock().unwrap().take();
+        Vulkan::new(library, verify, gpu, debug_callback, pipeline_cache.as_deref())
     };
-    pub static ref VERTEX_SHADER: vk::ShaderModule =
-        VULKAN.create_shader(TRIANGLE_VERTEX_SHADER_SPIRV);
-    pub static ref FRAGMENT_SHADER: vk::ShaderModule =
-        VULKAN.create_shader(TRIANGLE_FRAGMENT_SHADER_SPIRV);
+    /// The callback set with [`set_debug_callback()`], until the Vulkan
+    /// context takes it.
+    static ref DEBUG_CALLBACK: Mutex<PendingDebugCallback> = Mutex::new(PendingDebugCallback::default());
+    /// The compiled-in shaders, unless they have been replaced by
+    /// [`reload_shaders_from()`].
+    pub static ref SHADERS: Mutex<Shaders> = Mutex::new(Shaders {
+        vertex: VULKAN.create_shader(TRIANGLE_VERTEX_SHADER_SPIRV),
+        fragment: VULKAN.create_shader(TRIANGLE_FRAGMENT_SHADER_SPIRV),
+        instanced_vertex: VULKAN.create_shader(INSTANCED_VERTEX_SHADER_SPIRV),
+        generation: 0,
+    });
+    /// The layout of the descriptor set that textures are bound with, which
+    /// is set 0 of [`PIPELINE_LAYOUT`].
+    pub static ref TEXTURE_SET_LAYOUT: vk::DescriptorSetLayout =
+        VULKAN.create_descriptor_set_layout(&texture_set_layout_bindings());
     pub static ref PIPELINE_LAYOUT: vk::PipelineLayout = {
+        let set_layouts = [*TEXTURE_SET_LAYOUT];
         let push_constants = [vk::PushConstantRange {
             offset: 0,
-            size: std::mem::size_of::<Scale>() as u32,
+            size: std::mem::size_of::<PushConstants>() as u32,
             stage_flags: vk::ShaderStageFlags::VERTEX,
         }];
 
-        let create_info =
-            vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constants);
+        let create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constants);
         VULKAN.create_pipeline_layout(&create_info)
     };
 }
 
+/// The binding of the sampled texture in [`TEXTURE_SET_LAYOUT`].
+pub const TEXTURE_BINDING: u32 = 0;
+
+/// The bindings of the texture descriptor set, as declared by the fragment
+/// shader.
+pub fn texture_set_layout_bindings() -> [vk::DescriptorSetLayoutBinding; 1] {
+    [vk::DescriptorSetLayoutBinding {
+        binding: TEXTURE_BINDING,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: 1,
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        ..Default::default()
+    }]
+}
+
+/// Points the texture binding of `set`, which must have been allocated with
+/// [`TEXTURE_SET_LAYOUT`], at `view`. The image must be in the
+/// `SHADER_READ_ONLY_OPTIMAL` layout when the set is used, and the set must
+/// not be in use by the GPU when this is called.
+pub fn write_texture_descriptor(set: vk::DescriptorSet, view: vk::ImageView, sampler: vk::Sampler) {
+    let image_info = [vk::DescriptorImageInfo {
+        sampler,
+        image_view: view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(TEXTURE_BINDING)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&image_info)
+        .build();
+
+    VULKAN.update_descriptor_sets(&[write]);
+}
+
+/// A vertex of a UI triangle.
+///
+/// Positions are in pixels, with the origin at the top-left corner of the
+/// window and y pointing down. The vertex shader maps them to clip space with
+/// the `scale` push constant, so the same vertices can be drawn into any
+/// window or image regardless of its size.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vertex {
     pub position: (f32, f32),
     pub color: Color,
 }
 
-pub struct Scale {
+/// The winding order of triangles that are not culled. Because vertex
+/// positions map directly to framebuffer coordinates, this is counter-clockwise
+/// as seen on screen.
+pub const FRONT_FACE: vk::FrontFace = vk::FrontFace::COUNTER_CLOCKWISE;
+
+/// The signed area of a triangle in framebuffer coordinates, as the Vulkan
+/// spec defines it for culling. It is positive if the triangle is
+/// counter-clockwise.
+pub fn signed_area(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    -0.5 * ((a.0 * b.1 - b.0 * a.1) + (b.0 * c.1 - c.0 * b.1) + (c.0 * a.1 - a.0 * c.1))
+}
+
+/// Whether a triangle would be drawn with [`FRONT_FACE`] and back-face
+/// culling. Degenerate triangles don't cover any pixels, so they count as
+/// front-facing.
+pub fn is_front_facing(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+    let area = signed_area(a, b, c);
+    match FRONT_FACE {
+        vk::FrontFace::CLOCKWISE => area <= 0.0,
+        _ => area >= 0.0,
+    }
+}
+
+/// The color that images are cleared to before drawing.
+pub const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// The color that the images of transparent windows are cleared to, so that
+/// whatever is behind the window shows through where nothing is drawn.
+pub const TRANSPARENT_CLEAR_COLOR: [f32; 4] = [0.0; 4];
+
+/// The depth that the depth buffer is cleared to. Nothing is drawn behind it.
+pub const FAR_DEPTH: f32 = 1.0;
+
+/// Pans and zooms everything drawn to a window. Points are scaled by `zoom`
+/// around the window's top-left corner, then moved by `pan` pixels. To zoom
+/// around a point `c` instead, pan by `c - c * zoom`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewTransform {
+    /// Must be greater than 0.
+    pub zoom: f32,
+    pub pan: (f32, f32),
+}
+
+impl ViewTransform {
+    pub const IDENTITY: Self = Self {
+        zoom: 1.0,
+        pan: (0.0, 0.0),
+    };
+
+    /// Transforms a rect in window coordinates, rounding outwards to whole
+    /// pixels, and cuts it down to fit in a window of `window_size`. The
+    /// result may be empty.
+    pub fn apply_to_rect(&self, rect: Rect, window_size: Extent) -> Rect {
+        let map = |x: Px, pan: f32, max: Px| {
+            (f32::from(x.0) * self.zoom + pan).clamp(0.0, f32::from(max.0))
+        };
+        let left = map(rect.left(), self.pan.0, window_size.width).floor();
+        let top = map(rect.top(), self.pan.1, window_size.height).floor();
+        let right = map(rect.right(), self.pan.0, window_size.width).ceil();
+        let bottom = map(rect.bottom(), self.pan.1, window_size.height).ceil();
+
+        Rect::new(
+            Px(left as i16),
+            Px(top as i16),
+            Px((right - left) as i16),
+            Px((bottom - top) as i16),
+        )
+    }
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[repr(C)]
+pub struct PushConstants {
+    #[allow(dead_code)]
+    // Read by shader, so it's ok if this variable isn't read on the CPU
+    scale: [f32; 2],
     #[allow(dead_code)]
     // Read by shader, so it's ok if this variable isn't read on the CPU
-    horizontal: f32,
+    translate: [f32; 2],
     #[allow(dead_code)]
     // Read by shader, so it's ok if this variable isn't read on the CPU
-    vertical: f32,
+    rotation: [[f32; 2]; 2],
+    depth: f32,
+}
+
+impl PushConstants {
+    /// Maps window coordinates to clip space after applying `view`, rotating
+    /// them to match the swapchain's pre-transform.
+    fn new(
+        window_size: vk::Extent2D,
+        pre_transform: vk::SurfaceTransformFlagsKHR,
+        view: ViewTransform,
+    ) -> Self {
+        // Column-major, as GLSL expects.
+        let rotation = match pre_transform {
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 => [[0.0, 1.0], [-1.0, 0.0]],
+            vk::SurfaceTransformFlagsKHR::ROTATE_180 => [[-1.0, 0.0], [0.0, -1.0]],
+            vk::SurfaceTransformFlagsKHR::ROTATE_270 => [[0.0, -1.0], [1.0, 0.0]],
+            _ => [[1.0, 0.0], [0.0, 1.0]],
+        };
+
+        // Pixels to clip space, where the window spans -1 to 1.
+        let to_clip = [
+            2.0 / window_size.width as f32,
+            2.0 / window_size.height as f32,
+        ];
+
+        Self {
+            scale: [view.zoom * to_clip[0], view.zoom * to_clip[1]],
+            translate: [view.pan.0 * to_clip[0] - 1.0, view.pan.1 * to_clip[1] - 1.0],
+            rotation,
+            depth: FAR_DEPTH,
+        }
+    }
 }
 
 impl Vertex {
@@ -84,6 +284,88 @@ impl Vertex {
     ];
 }
 
+/// One of many identical rects drawn by
+/// [`RendererWindow::draw_instanced()`](super::RendererWindow::draw_instanced).
+/// Each instance scales and moves a unit quad, so only this needs to be
+/// uploaded per rect rather than four vertices.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    /// The top-left corner of the rect, in pixels.
+    pub offset: (f32, f32),
+    pub size: (f32, f32),
+    pub color: Color,
+}
+
+impl Instance {
+    pub const BINDING_DESCRIPTION: vk::VertexInputBindingDescription =
+        vk::VertexInputBindingDescription {
+            binding: 1,
+            stride: std::mem::size_of::<Instance>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        };
+
+    /// The instance's attributes follow the unit quad's position, which is
+    /// read from binding 0 as a [`Vertex`].
+    pub const ATTRIBUTE_DESCRIPTION: [vk::VertexInputAttributeDescription; 4] = [
+        vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 1,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 2,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: std::mem::size_of::<(f32, f32)>() as u32,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 3,
+            format: vk::Format::R8G8B8A8_UNORM,
+            offset: 2 * std::mem::size_of::<(f32, f32)>() as u32,
+        },
+    ];
+}
+
+/// The quad that every [`Instance`] is drawn from, with [`Rect::INDICES`].
+/// The color is ignored.
+pub const UNIT_QUAD: [Vertex; 4] = [
+    Vertex {
+        position: (0.0, 0.0),
+        color: Color::rgba(0, 0, 0, 0),
+    },
+    Vertex {
+        position: (0.0, 1.0),
+        color: Color::rgba(0, 0, 0, 0),
+    },
+    Vertex {
+        position: (1.0, 1.0),
+        color: Color::rgba(0, 0, 0, 0),
+    },
+    Vertex {
+        position: (1.0, 0.0),
+        color: Color::rgba(0, 0, 0, 0),
+    },
+];
+
+/// A command buffer for the present queue that takes ownership of a swapchain
+/// image from the graphics queue, and the semaphore that it signals once it
+/// has. Only needed when the swapchain uses exclusive sharing across two
+/// queue families.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentTransfer {
+    pub commands: vk::CommandBuffer,
+    pub semaphore: vk::Semaphore,
+}
+
 #[must_use]
 #[derive(Debug)]
 pub enum Request {
@@ -96,6 +378,9 @@ pub enum Request {
         fence: vk::Fence,
         swapchain: vk::SwapchainKHR,
         image_id: u32,
+        /// Submitted to the present queue after `commands`, if the image
+        /// must change queue families before it is presented.
+        present_transfer: Option<PresentTransfer>,
     },
 }
 
@@ -115,33 +400,370 @@ pub fn to_extent(size: Extent) -> vk::Extent2D {
     }
 }
 
+/// Rotates a rect in window coordinates to match the swapchain's
+/// pre-transform, the same way that [`PushConstants`] rotates vertices.
+pub fn rotate_rect(
+    rect: Rect,
+    window_size: Extent,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+) -> Rect {
+    let (width, height) = (window_size.width, window_size.height);
+    match pre_transform {
+        vk::SurfaceTransformFlagsKHR::ROTATE_90 => Rect::new(
+            height - rect.bottom(),
+            rect.left(),
+            rect.height(),
+            rect.width(),
+        ),
+        vk::SurfaceTransformFlagsKHR::ROTATE_180 => Rect::new(
+            width - rect.right(),
+            height - rect.bottom(),
+            rect.width(),
+            rect.height(),
+        ),
+        vk::SurfaceTransformFlagsKHR::ROTATE_270 => Rect::new(
+            rect.top(),
+            width - rect.right(),
+            rect.height(),
+            rect.width(),
+        ),
+        _ => rect,
+    }
+}
+
+/// Converts a rect to a Vulkan scissor rect. Vulkan requires scissor offsets
+/// to be non-negative, so anything left of or above the origin is cut off.
+pub fn to_rect2d(rect: Rect) -> vk::Rect2D {
+    let x = rect.left().0.max(0);
+    let y = rect.top().0.max(0);
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: x as i32,
+            y: y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (rect.right().0 - x).max(0) as u32,
+            height: (rect.bottom().0 - y).max(0) as u32,
+        },
+    }
+}
+
+/// The scissor rects that a batch clipped to `clip` is drawn with, in window
+/// coordinates: one for each of the `damage`d regions that it overlaps, or
+/// just `clip` if the whole window is being redrawn.
+pub fn damage_scissors(clip: Rect, damage: Option<&[Rect]>) -> impl Iterator<Item = Rect> + '_ {
+    let whole = if damage.is_none() { Some(clip) } else { None };
+    let regions = damage.unwrap_or(&[]);
+    whole.into_iter().chain(
+        regions
+            .iter()
+            .filter_map(move |region| clip.intersect(*region)),
+    )
+}
+
+/// Records a render pass that draws `batches`. If `damage` is set, the
+/// render pass must load the previous contents of `target`, and only the
+/// damaged regions (in window coordinates) are cleared and drawn to.
 #[allow(clippy::too_many_arguments)]
 pub fn record_command_buffer(
     cmd: &Recorder,
     viewport: vk::Rect2D,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+    view: ViewTransform,
     pipeline: vk::Pipeline,
     render_pass: vk::RenderPass,
     layout: vk::PipelineLayout,
     target: vk::Framebuffer,
+    clear_color: [f32; 4],
     vertex_buffer: vk::Buffer,
     vertex_buffer_offset: vk::DeviceSize,
     index_buffer: vk::Buffer,
     index_buffer_offset: vk::DeviceSize,
-    num_indices: u32,
+    index_format: IndexFormat,
+    batches: &[DrawBatch],
+    damage: Option<&[Rect]>,
+) {
+    begin_ui_pass(
+        cmd,
+        viewport,
+        pipeline,
+        render_pass,
+        target,
+        clear_color,
+        vertex_buffer,
+        vertex_buffer_offset,
+        index_buffer,
+        index_buffer_offset,
+        index_format,
+    );
+
+    cmd.set_viewport(&[to_viewport(viewport)]);
+
+    let window_size = if is_quarter_turn(pre_transform) {
+        vk::Extent2D {
+            width: viewport.extent.height,
+            height: viewport.extent.width,
+        }
+    } else {
+        viewport.extent
+    };
+
+    let mut constants = PushConstants::new(window_size, pre_transform, view);
+
+    let window_size = Extent::new(Px(window_size.width as i16), Px(window_size.height as i16));
+
+    if let Some(damage) = damage {
+        // The render pass only clears the depth buffer.
+        let clear_rects = damage
+            .iter()
+            .map(|region| vk::ClearRect {
+                rect: to_rect2d(rotate_rect(*region, window_size, pre_transform)),
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .filter(|clear| clear.rect.extent.width > 0 && clear.rect.extent.height > 0)
+            .collect::<Vec<_>>();
+
+        if !clear_rects.is_empty() {
+            cmd.clear_attachments(
+                &[vk::ClearAttachment {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    color_attachment: 0,
+                    clear_value: vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: clear_color,
+                        },
+                    },
+                }],
+                &clear_rects,
+            );
+        }
+    }
+
+    for batch in batches {
+        if batch.num_indices == 0 {
+            continue;
+        }
+
+        constants.depth = batch.depth;
+        cmd.push_constants(layout, vk::ShaderStageFlags::VERTEX, 0, &constants);
+
+        let clip = view.apply_to_rect(batch.clip, window_size);
+        for region in damage_scissors(clip, damage) {
+            let scissor = to_rect2d(rotate_rect(region, window_size, pre_transform));
+            if scissor.extent.width == 0 || scissor.extent.height == 0 {
+                continue;
+            }
+
+            cmd.set_scissor(&[scissor]);
+            cmd.draw_indexed(batch.num_indices, 1, batch.first_index, 0, 0);
+        }
+    }
+
+    cmd.end_render_pass();
+}
+
+/// A region of the window with its own mesh, drawn by [`record_viewports()`].
+/// The mesh's vertices are relative to the top-left corner of the region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportDraw {
+    /// The region of the window, in window coordinates.
+    pub rect: Rect,
+    pub first_index: u32,
+    pub num_indices: u32,
+    /// Added to each index, since the viewport's indices only count its own
+    /// vertices.
+    pub vertex_offset: i32,
+}
+
+impl ViewportDraw {
+    /// The Vulkan viewport and scissor rect for the draw, rotated to match the
+    /// swapchain's pre-transform. The scissor rect is cut down to fit in the
+    /// window, and may be empty.
+    pub fn area(
+        &self,
+        window_size: Extent,
+        pre_transform: vk::SurfaceTransformFlagsKHR,
+    ) -> (vk::Viewport, vk::Rect2D) {
+        let rotated = rotate_rect(self.rect, window_size, pre_transform);
+        let viewport = vk::Viewport {
+            x: f32::from(rotated.x().0),
+            y: f32::from(rotated.y().0),
+            width: f32::from(rotated.width().0),
+            height: f32::from(rotated.height().0),
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        let window = Rect::from_extent(Px(0), Px(0), window_size);
+        let scissor = match self.rect.intersect(window) {
+            Some(visible) => to_rect2d(rotate_rect(visible, window_size, pre_transform)),
+            None => vk::Rect2D::default(),
+        };
+
+        (viewport, scissor)
+    }
+}
+
+/// Records a render pass that draws each viewport's mesh into its own region
+/// of the window, clipped to that region.
+#[allow(clippy::too_many_arguments)]
+pub fn record_viewports(
+    cmd: &Recorder,
+    image_area: vk::Rect2D,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+    pipeline: vk::Pipeline,
+    render_pass: vk::RenderPass,
+    layout: vk::PipelineLayout,
+    target: vk::Framebuffer,
+    clear_color: [f32; 4],
+    buffer: vk::Buffer,
+    index_buffer_offset: vk::DeviceSize,
+    viewports: &[ViewportDraw],
+) {
+    begin_ui_pass(
+        cmd,
+        image_area,
+        pipeline,
+        render_pass,
+        target,
+        clear_color,
+        buffer,
+        0,
+        buffer,
+        index_buffer_offset,
+        IndexFormat::U16,
+    );
+
+    let window_size = if is_quarter_turn(pre_transform) {
+        Extent::new(
+            Px(image_area.extent.height as i16),
+            Px(image_area.extent.width as i16),
+        )
+    } else {
+        Extent::new(
+            Px(image_area.extent.width as i16),
+            Px(image_area.extent.height as i16),
+        )
+    };
+
+    for draw in viewports {
+        let (viewport, scissor) = draw.area(window_size, pre_transform);
+        if draw.num_indices == 0 || scissor.extent.width == 0 || scissor.extent.height == 0 {
+            continue;
+        }
+
+        // Each viewport has its own coordinate system, so the scale depends on
+        // its size rather than the window's.
+        let constants = PushConstants::new(
+            to_extent(draw.rect.extent),
+            pre_transform,
+            ViewTransform::IDENTITY,
+        );
+        cmd.push_constants(layout, vk::ShaderStageFlags::VERTEX, 0, &constants);
+        cmd.set_viewport(&[viewport]);
+        cmd.set_scissor(&[scissor]);
+        cmd.draw_indexed(draw.num_indices, 1, draw.first_index, draw.vertex_offset, 0);
+    }
+
+    cmd.end_render_pass();
+}
+
+/// Records commands to draw `instance_count` [`Instance`]s over the whole
+/// window. `buffer` holds the [`UNIT_QUAD`] at offset 0, its indices at
+/// `index_buffer_offset`, and the instances at `instance_offset`.
+#[allow(clippy::too_many_arguments)]
+pub fn record_instanced(
+    cmd: &Recorder,
+    viewport: vk::Rect2D,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+    view: ViewTransform,
+    pipeline: vk::Pipeline,
+    render_pass: vk::RenderPass,
+    layout: vk::PipelineLayout,
+    target: vk::Framebuffer,
+    clear_color: [f32; 4],
+    buffer: vk::Buffer,
+    index_buffer_offset: vk::DeviceSize,
+    instance_offset: vk::DeviceSize,
+    instance_count: u32,
+) {
+    begin_ui_pass(
+        cmd,
+        viewport,
+        pipeline,
+        render_pass,
+        target,
+        clear_color,
+        buffer,
+        0,
+        buffer,
+        index_buffer_offset,
+        IndexFormat::U16,
+    );
+
+    cmd.bind_vertex_buffers(
+        Instance::BINDING_DESCRIPTION.binding,
+        &[buffer],
+        &[instance_offset],
+    );
+
+    let window_size = if is_quarter_turn(pre_transform) {
+        vk::Extent2D {
+            width: viewport.extent.height,
+            height: viewport.extent.width,
+        }
+    } else {
+        viewport.extent
+    };
+
+    let constants = PushConstants::new(window_size, pre_transform, view);
+    cmd.push_constants(layout, vk::ShaderStageFlags::VERTEX, 0, &constants);
+    cmd.set_viewport(&[to_viewport(viewport)]);
+    cmd.set_scissor(&[viewport]);
+    if instance_count > 0 {
+        cmd.draw_indexed(Rect::INDICES.len() as u32, instance_count, 0, 0, 0);
+    }
+
+    cmd.end_render_pass();
+}
+
+/// Begins the render pass and binds the UI pipeline and buffers.
+#[allow(clippy::too_many_arguments)]
+fn begin_ui_pass(
+    cmd: &Recorder,
+    render_area: vk::Rect2D,
+    pipeline: vk::Pipeline,
+    render_pass: vk::RenderPass,
+    target: vk::Framebuffer,
+    clear_color: [f32; 4],
+    vertex_buffer: vk::Buffer,
+    vertex_buffer_offset: vk::DeviceSize,
+    index_buffer: vk::Buffer,
+    index_buffer_offset: vk::DeviceSize,
+    index_format: IndexFormat,
 ) {
-    cmd.begin();
     {
-        let clear_values = [vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
+        // The depth clear value is ignored if there is no depth attachment.
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color,
+                },
             },
-        }];
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: FAR_DEPTH,
+                    stencil: 0,
+                },
+            },
+        ];
 
         cmd.begin_render_pass(
             &vk::RenderPassBeginInfo::builder()
                 .render_pass(render_pass)
                 .framebuffer(target)
-                .render_area(viewport)
+                .render_area(render_area)
                 .clear_values(&clear_values),
             vk::SubpassContents::INLINE,
         );
@@ -152,67 +774,132 @@ pub fn record_command_buffer(
     let vertex_buffers = [vertex_buffer];
     let offsets = [vertex_buffer_offset];
     cmd.bind_vertex_buffers(0, &vertex_buffers, &offsets);
-    cmd.bind_index_buffer(index_buffer, index_buffer_offset, vk::IndexType::UINT16);
+    let index_type = match index_format {
+        IndexFormat::U16 => vk::IndexType::UINT16,
+        IndexFormat::U32 => vk::IndexType::UINT32,
+    };
+    cmd.bind_index_buffer(index_buffer, index_buffer_offset, index_type);
+}
 
-    cmd.set_viewport(&[vk::Viewport {
-        x: viewport.offset.x as f32,
-        y: viewport.offset.y as f32,
-        width: viewport.extent.width as f32,
-        height: viewport.extent.height as f32,
+pub fn to_viewport(rect: vk::Rect2D) -> vk::Viewport {
+    vk::Viewport {
+        x: rect.offset.x as f32,
+        y: rect.offset.y as f32,
+        width: rect.extent.width as f32,
+        height: rect.extent.height as f32,
         min_depth: 0.0,
-        max_depth: 0.0,
-    }]);
-    cmd.set_scissor(&[viewport]);
+        max_depth: 1.0,
+    }
+}
 
-    let scale = Scale {
-        vertical: 2.0 / viewport.extent.height as f32,
-        horizontal: 2.0 / viewport.extent.width as f32,
-    };
-    cmd.push_constants(layout, vk::ShaderStageFlags::VERTEX, 0, &scale);
+/// Creates a render pass that draws to a color attachment with `format`, and
+/// optionally a depth attachment with `depth_format`.
+pub fn create_render_pass(format: vk::Format, depth_format: Option<vk::Format>) -> vk::RenderPass {
+    build_render_pass(format, depth_format, false)
+}
 
-    cmd.draw_indexed(num_indices, 1, 0, 0, 0);
-    cmd.end_render_pass();
-    cmd.end();
+/// Like [`create_render_pass()`], but keeps what was last presented from the
+/// color attachment instead of clearing it, for drawing damaged regions. The
+/// two render passes are compatible, so they share pipelines and
+/// framebuffers. Only usable when the graphics queue still owns the image,
+/// since the render pass doesn't acquire it from the present queue.
+pub fn create_preserving_render_pass(
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+) -> vk::RenderPass {
+    build_render_pass(format, depth_format, true)
 }
 
-pub fn create_render_pass(format: vk::Format) -> vk::RenderPass {
-    let attachments = [vk::AttachmentDescription {
+fn build_render_pass(
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+    preserve: bool,
+) -> vk::RenderPass {
+    let (load_op, initial_layout) = if preserve {
+        (vk::AttachmentLoadOp::LOAD, vk::ImageLayout::PRESENT_SRC_KHR)
+    } else {
+        (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED)
+    };
+
+    let mut attachments = vec![vk::AttachmentDescription {
         flags: vk::AttachmentDescriptionFlags::empty(),
         format,
         samples: vk::SampleCountFlags::TYPE_1,
-        load_op: vk::AttachmentLoadOp::CLEAR,
+        load_op,
         store_op: vk::AttachmentStoreOp::STORE,
         stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
         stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-        initial_layout: vk::ImageLayout::UNDEFINED,
+        initial_layout,
         final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
     }];
 
+    if let Some(depth_format) = depth_format {
+        attachments.push(vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format: depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        });
+    }
+
     let attachment_reference = [vk::AttachmentReference {
         attachment: 0,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     }];
 
+    let depth_reference = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
     let subpasses = [vk::SubpassDescription {
         pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
         p_color_attachments: attachment_reference.as_ptr(),
         color_attachment_count: attachment_reference.len() as u32,
+        p_depth_stencil_attachment: if depth_format.is_some() {
+            &depth_reference
+        } else {
+            std::ptr::null()
+        },
         ..Default::default()
     }];
 
+    let (stage_mask, mut access_mask) = if depth_format.is_some() {
+        (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+    } else {
+        (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        )
+    };
+
+    if preserve {
+        access_mask |= vk::AccessFlags::COLOR_ATTACHMENT_READ;
+    }
+
     let dependencies = [vk::SubpassDependency {
         src_subpass: vk::SUBPASS_EXTERNAL,
         dst_subpass: 0,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        src_stage_mask: stage_mask,
         src_access_mask: vk::AccessFlags::empty(),
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: stage_mask,
+        dst_access_mask: access_mask,
         dependency_flags: DependencyFlags::empty(),
     }];
 
     let create_info = vk::RenderPassCreateInfo {
         p_attachments: attachments.as_ptr(),
-        attachment_count: 1,
+        attachment_count: attachments.len() as u32,
         p_subpasses: subpasses.as_ptr(),
         subpass_count: 1,
         p_dependencies: dependencies.as_ptr(),
@@ -223,22 +910,141 @@ pub fn create_render_pass(format: vk::Format) -> vk::RenderPass {
     VULKAN.create_render_pass(&create_info)
 }
 
-pub fn create_pipeline(layout: vk::PipelineLayout, render_pass: vk::RenderPass) -> vk::Pipeline {
+/// Where the pipeline cache is kept between runs, if anywhere. Set with the
+/// `MAPLE_PIPELINE_CACHE` environment variable.
+fn pipeline_cache_path() -> Option<PathBuf> {
+    std::env::var_os("MAPLE_PIPELINE_CACHE").map(PathBuf::from)
+}
+
+/// Saves the pipeline cache so that the next run can skip compiling the
+/// pipelines that this one did. Does nothing unless `MAPLE_PIPELINE_CACHE` is
+/// set.
+pub fn save_pipeline_cache() -> std::io::Result<()> {
+    match pipeline_cache_path() {
+        Some(path) => VULKAN.save_pipeline_cache(&path),
+        None => Ok(()),
+    }
+}
+
+/// The generation of the current [`SHADERS`]. Pipelines created while it had a
+/// different value are out of date.
+pub fn shader_generation() -> u64 {
+    SHADERS.lock().unwrap().generation
+}
+
+/// Sends the Vulkan debug messenger's messages to `callback`, instead of
+/// printing errors to stdout. Messages are only reported if validation is
+/// enabled, as it is in debug builds or with `MAPLE_CHECK_VULKAN=1`.
+///
+/// The callback is handed to the Vulkan context when it is initialized, which
+/// happens the first time that anything is rendered, so this must be called
+/// before then. Returns `false` if it is too late.
+pub fn set_debug_callback(callback: DebugCallback) -> bool {
+    DEBUG_CALLBACK.lock().unwrap().set(callback)
+}
+
+/// Holds a [`DebugCallback`] until the Vulkan context is initialized.
+#[derive(Default)]
+struct PendingDebugCallback {
+    callback: Option<DebugCallback>,
+    taken: bool,
+}
+
+impl PendingDebugCallback {
+    /// Replaces the callback, unless it has already been taken.
+    fn set(&mut self, callback: DebugCallback) -> bool {
+        if !self.taken {
+            self.callback = Some(callback);
+        }
+        !self.taken
+    }
+
+    /// Takes the callback, after which it can no longer be set.
+    fn take(&mut self) -> Option<DebugCallback> {
+        self.taken = true;
+        self.callback.take()
+    }
+}
+
+/// Replaces the UI shaders with SPIR-V from disk. Pipelines pick up the new
+/// shaders the next time they are used to draw.
+#[cfg(feature = "hot-reload")]
+pub fn reload_shaders_from(
+    vert_path: impl AsRef<std::path::Path>,
+    frag_path: impl AsRef<std::path::Path>,
+) -> Result<(), super::vulkan::ShaderError> {
+    VULKAN.reload_shaders_from(
+        &mut SHADERS.lock().unwrap(),
+        vert_path.as_ref(),
+        frag_path.as_ref(),
+    )
+}
+
+/// Creates the pipeline used to draw the UI. If `use_depth` is set,
+/// `render_pass` must have a depth attachment. Blending is only enabled for
+/// premultiplied colors.
+pub fn create_pipeline(
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    use_depth: bool,
+    color_format: ColorFormat,
+) -> vk::Pipeline {
+    let vertex_shader = SHADERS.lock().unwrap().vertex;
+    build_pipeline(
+        layout,
+        render_pass,
+        use_depth,
+        color_format,
+        vertex_shader,
+        &[Vertex::BINDING_DESCRIPTION],
+        &Vertex::ATTRIBUTE_DESCRIPTION,
+    )
+}
+
+/// Creates the pipeline used to draw [`Instance`]s, which is otherwise the
+/// same as the one from [`create_pipeline()`]. Its vertex shader is not
+/// reloaded along with the others.
+pub fn create_instanced_pipeline(
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    use_depth: bool,
+    color_format: ColorFormat,
+) -> vk::Pipeline {
+    let vertex_shader = SHADERS.lock().unwrap().instanced_vertex;
+    build_pipeline(
+        layout,
+        render_pass,
+        use_depth,
+        color_format,
+        vertex_shader,
+        &[Vertex::BINDING_DESCRIPTION, Instance::BINDING_DESCRIPTION],
+        &Instance::ATTRIBUTE_DESCRIPTION,
+    )
+}
+
+fn build_pipeline(
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    use_depth: bool,
+    color_format: ColorFormat,
+    vertex_shader: vk::ShaderModule,
+    vertex_binding_descriptions: &[vk::VertexInputBindingDescription],
+    attribute_binding_descriptions: &[vk::VertexInputAttributeDescription],
+) -> vk::Pipeline {
+    let shaders = SHADERS.lock().unwrap();
     let shader_stages = [
         vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::VERTEX)
-            .module(*VERTEX_SHADER)
+            .module(vertex_shader)
             .name(unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") })
             .build(),
         vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(*FRAGMENT_SHADER)
+            .module(shaders.fragment)
             .name(unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") })
             .build(),
     ];
 
-    let vertex_binding_descriptions = [Vertex::BINDING_DESCRIPTION];
-    let attribute_binding_descriptions = Vertex::ATTRIBUTE_DESCRIPTION;
     let vertex_input_state = vk::PipelineVertexInputStateCreateInfo {
         p_vertex_binding_descriptions: vertex_binding_descriptions.as_ptr(),
         vertex_binding_description_count: vertex_binding_descriptions.len() as u32,
@@ -265,7 +1071,7 @@ pub fn create_pipeline(layout: vk::PipelineLayout, render_pass: vk::RenderPass)
         polygon_mode: vk::PolygonMode::FILL,
         line_width: 1.0,
         cull_mode: vk::CullModeFlags::BACK,
-        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        front_face: FRONT_FACE,
         depth_bias_enable: vk::FALSE,
         ..Default::default()
     };
@@ -276,13 +1082,19 @@ pub fn create_pipeline(layout: vk::PipelineLayout, render_pass: vk::RenderPass)
         ..Default::default()
     };
 
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: use_depth.into(),
+        depth_write_enable: use_depth.into(),
+        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        ..Default::default()
+    };
+
     let color_blend_attachments = [vk::PipelineColorBlendAttachmentState {
         color_write_mask: vk::ColorComponentFlags::R
             | vk::ColorComponentFlags::G
             | vk::ColorComponentFlags::B
             | vk::ColorComponentFlags::A,
-        blend_enable: vk::FALSE,
-        ..Default::default()
+        ..blend_state(color_format)
     }];
 
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
@@ -308,6 +1120,7 @@ pub fn create_pipeline(layout: vk::PipelineLayout, render_pass: vk::RenderPass)
         p_viewport_state: &viewport_state,
         p_rasterization_state: &rasterization_state,
         p_multisample_state: &multisample_state,
+        p_depth_stencil_state: &depth_stencil_state,
         p_color_blend_state: &color_blend_state,
         p_dynamic_state: &dynamic_state,
         layout: layout,
@@ -318,3 +1131,341 @@ pub fn create_pipeline(layout: vk::PipelineLayout, render_pass: vk::RenderPass)
 
     VULKAN.create_graphics_pipeline(&create_info)
 }
+
+/// The blend factors for drawing colors in `format`. Both formats blend, so
+/// that translucent colors such as the edges of feathered shapes fade into
+/// what is behind them.
+fn blend_state(format: ColorFormat) -> vk::PipelineColorBlendAttachmentState {
+    match format {
+        ColorFormat::Straight => vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+            ..Default::default()
+        },
+        ColorFormat::Premultiplied => vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `(set, binding)` of every resource that a SPIR-V module declares.
+    fn spirv_bindings(spirv: &[u8]) -> Vec<(u32, u32)> {
+        const OP_DECORATE: u32 = 71;
+        const BINDING: u32 = 33;
+        const DESCRIPTOR_SET: u32 = 34;
+
+        let words = spirv
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(words[0], 0x0723_0203, "not a SPIR-V module");
+
+        let mut sets = std::collections::HashMap::new();
+        let mut bindings = vec![];
+        // Instructions start after the 5-word header. Each one's first word
+        // holds its length in words and its opcode.
+        let mut i = 5;
+        while i < words.len() {
+            let (length, opcode) = ((words[i] >> 16) as usize, words[i] & 0xFFFF);
+            if opcode == OP_DECORATE && length == 4 {
+                let (target, value) = (words[i + 1], words[i + 3]);
+                match words[i + 2] {
+                    DESCRIPTOR_SET => {
+                        sets.insert(target, value);
+                    }
+                    BINDING => bindings.push((target, value)),
+                    _ => {}
+                }
+            }
+            i += length.max(1);
+        }
+
+        bindings
+            .into_iter()
+            .map(|(target, binding)| (sets[&target], binding))
+            .collect()
+    }
+
+    #[test]
+    fn texture_bindings_match_fragment_shader() {
+        let layout = texture_set_layout_bindings();
+        let expected = layout
+            .iter()
+            .map(|binding| (0, binding.binding))
+            .collect::<Vec<_>>();
+        assert_eq!(spirv_bindings(TRIANGLE_FRAGMENT_SHADER_SPIRV), expected);
+
+        for binding in layout {
+            assert_eq!(
+                binding.descriptor_type,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            );
+            assert_eq!(binding.descriptor_count, 1);
+            assert_eq!(binding.stage_flags, vk::ShaderStageFlags::FRAGMENT);
+        }
+
+        // The vertex shaders don't use any textures.
+        assert!(spirv_bindings(TRIANGLE_VERTEX_SHADER_SPIRV).is_empty());
+        assert!(spirv_bindings(INSTANCED_VERTEX_SHADER_SPIRV).is_empty());
+    }
+
+    #[test]
+    fn rect_winding_matches_front_face() {
+        let rect = Rect::new(Px(10), Px(20), Px(30), Px(40));
+        let points = rect.points();
+        assert_eq!(points[0], rect.point, "the first point is the top-left");
+
+        for triangle in Rect::INDICES.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                let point = points[triangle[i] as usize];
+                (f32::from(point.x), f32::from(point.y))
+            });
+
+            assert!(signed_area(a, b, c) > 0.0);
+            assert!(is_front_facing(a, b, c));
+            assert!(!is_front_facing(a, c, b));
+        }
+    }
+
+    #[test]
+    fn instance_layout_matches_bindings() {
+        let binding = Instance::BINDING_DESCRIPTION;
+        assert_eq!(binding.stride as usize, std::mem::size_of::<Instance>());
+        assert_eq!(binding.input_rate, vk::VertexInputRate::INSTANCE);
+        assert_ne!(binding.binding, Vertex::BINDING_DESCRIPTION.binding);
+
+        let instance = Instance {
+            offset: (1.0, 2.0),
+            size: (3.0, 4.0),
+            color: Color::rgba(5, 6, 7, 8),
+        };
+        let base = &instance as *const Instance as usize;
+        let offset_of = |field: *const u8| (field as usize - base) as u32;
+        let fields = [
+            (offset_of(&instance.offset as *const _ as _), 8),
+            (offset_of(&instance.size as *const _ as _), 8),
+            (offset_of(&instance.color as *const _ as _), 4),
+        ];
+
+        // The unit quad's position comes from the vertex binding.
+        let (quad, attributes) = Instance::ATTRIBUTE_DESCRIPTION.split_first().unwrap();
+        assert_eq!(quad.binding, Vertex::BINDING_DESCRIPTION.binding);
+        let position = Vertex::ATTRIBUTE_DESCRIPTION[0];
+        assert_eq!(
+            (quad.format, quad.offset),
+            (position.format, position.offset)
+        );
+
+        for (attribute, (offset, size)) in attributes.iter().zip(fields) {
+            assert_eq!(attribute.binding, binding.binding);
+            assert_eq!(attribute.offset, offset);
+            assert!(attribute.offset + size <= binding.stride);
+        }
+
+        // The locations don't overlap.
+        let mut locations = Instance::ATTRIBUTE_DESCRIPTION.map(|a| a.location);
+        locations.sort_unstable();
+        assert_eq!(locations, [0, 1, 2, 3]);
+
+        // The unit quad faces the viewer, like any other rect.
+        for triangle in Rect::INDICES.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| UNIT_QUAD[triangle[i] as usize].position);
+            assert!(is_front_facing(a, b, c));
+        }
+    }
+
+    #[test]
+    fn view_transform_to_clip_space() {
+        let to_clip = |constants: &PushConstants, (x, y): (f32, f32)| {
+            (
+                x * constants.scale[0] + constants.translate[0],
+                y * constants.scale[1] + constants.translate[1],
+            )
+        };
+
+        let size = vk::Extent2D {
+            width: 200,
+            height: 100,
+        };
+        let identity = vk::SurfaceTransformFlagsKHR::IDENTITY;
+
+        // Without a transform, the window spans clip space.
+        let constants = PushConstants::new(size, identity, ViewTransform::IDENTITY);
+        assert_eq!(to_clip(&constants, (0.0, 0.0)), (-1.0, -1.0));
+        assert_eq!(to_clip(&constants, (100.0, 50.0)), (0.0, 0.0));
+        assert_eq!(to_clip(&constants, (200.0, 100.0)), (1.0, 1.0));
+
+        // Zoomed in around the top-left corner, then panned.
+        let view = ViewTransform {
+            zoom: 2.0,
+            pan: (10.0, 20.0),
+        };
+        let constants = PushConstants::new(size, identity, view);
+        // (0, 0) ends up at (10, 20), and (50, 25) at (110, 70).
+        assert_eq!(to_clip(&constants, (0.0, 0.0)), (-0.9, -0.6));
+        let (x, y) = to_clip(&constants, (50.0, 25.0));
+        assert!((x - 0.1).abs() < 1e-6 && (y - 0.4).abs() < 1e-6);
+
+        let window = Extent::new(Px(200), Px(100));
+        assert_eq!(
+            view.apply_to_rect(Rect::new(Px(5), Px(5), Px(10), Px(10)), window),
+            Rect::new(Px(20), Px(30), Px(20), Px(20))
+        );
+        let half = ViewTransform {
+            zoom: 0.5,
+            pan: (0.0, 0.0),
+        };
+        // Rounded outwards, so nothing that should be visible is clipped.
+        assert_eq!(
+            half.apply_to_rect(Rect::new(Px(1), Px(1), Px(2), Px(3)), window),
+            Rect::new(Px(0), Px(0), Px(2), Px(2))
+        );
+
+        // Zoomed in so far that the rect's edges are beyond what a Px can
+        // hold, it still covers the whole window.
+        let close = ViewTransform {
+            zoom: 800.0,
+            pan: (-40000.0, -40000.0),
+        };
+        assert_eq!(
+            close.apply_to_rect(Rect::new(Px(0), Px(0), Px(91), Px(100)), window),
+            Rect::new(Px(0), Px(0), Px(200), Px(100))
+        );
+        // Rects that end up entirely outside of the window are empty.
+        let clip = close.apply_to_rect(Rect::new(Px(0), Px(0), Px(10), Px(10)), window);
+        assert!(clip.width() == Px(0) || clip.height() == Px(0));
+    }
+
+    #[test]
+    fn scissors_cover_damage() {
+        let window = Rect::new(Px(0), Px(0), Px(100), Px(80));
+        let damage = [
+            Rect::new(Px(10), Px(10), Px(20), Px(5)),
+            Rect::new(Px(50), Px(40), Px(30), Px(30)),
+        ];
+
+        let scissors = damage_scissors(window, Some(&damage[..])).collect::<Vec<_>>();
+        assert_eq!(scissors, damage);
+
+        // Batches are still clipped, and skip regions that they miss.
+        let clip = Rect::new(Px(0), Px(0), Px(60), Px(50));
+        let scissors = damage_scissors(clip, Some(&damage[..])).collect::<Vec<_>>();
+        assert_eq!(
+            scissors,
+            [damage[0], Rect::new(Px(50), Px(40), Px(10), Px(10))]
+        );
+
+        assert_eq!(damage_scissors(clip, Some(&[][..])).count(), 0);
+        assert_eq!(damage_scissors(clip, None).collect::<Vec<_>>(), [clip]);
+    }
+
+    #[test]
+    fn rotated_rects_stay_in_image() {
+        let window = Extent::new(Px(100), Px(50));
+        let rect = Rect::new(Px(10), Px(5), Px(20), Px(10));
+
+        assert_eq!(
+            rotate_rect(rect, window, vk::SurfaceTransformFlagsKHR::IDENTITY),
+            rect
+        );
+        assert_eq!(
+            rotate_rect(rect, window, vk::SurfaceTransformFlagsKHR::ROTATE_90),
+            Rect::new(Px(35), Px(10), Px(10), Px(20))
+        );
+        assert_eq!(
+            rotate_rect(rect, window, vk::SurfaceTransformFlagsKHR::ROTATE_180),
+            Rect::new(Px(70), Px(35), Px(20), Px(10))
+        );
+        assert_eq!(
+            rotate_rect(rect, window, vk::SurfaceTransformFlagsKHR::ROTATE_270),
+            Rect::new(Px(5), Px(70), Px(10), Px(20))
+        );
+    }
+
+    /// Blends `src` over `dst` the way that a pipeline with `state` would,
+    /// with channels from 0 to 1.
+    fn blend(state: vk::PipelineColorBlendAttachmentState, src: Color, dst: Color) -> [f32; 4] {
+        let channels = |c: Color| [c.r, c.g, c.b, c.a].map(|v| v as f32 / 255.0);
+        let (src, dst) = (channels(src), channels(dst));
+        if state.blend_enable == vk::FALSE {
+            return src;
+        }
+
+        let factor = |factor: vk::BlendFactor| match factor {
+            vk::BlendFactor::ONE => 1.0,
+            vk::BlendFactor::SRC_ALPHA => src[3],
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA => 1.0 - src[3],
+            _ => panic!("unsupported blend factor {:?}", factor),
+        };
+        let (src_color, dst_color) = (
+            factor(state.src_color_blend_factor),
+            factor(state.dst_color_blend_factor),
+        );
+        let (src_alpha, dst_alpha) = (
+            factor(state.src_alpha_blend_factor),
+            factor(state.dst_alpha_blend_factor),
+        );
+        [
+            src[0] * src_color + dst[0] * dst_color,
+            src[1] * src_color + dst[1] * dst_color,
+            src[2] * src_color + dst[2] * dst_color,
+            src[3] * src_alpha + dst[3] * dst_alpha,
+        ]
+    }
+
+    #[test]
+    fn feathered_edges_fade_out() {
+        let background = Color::rgb(10, 20, 30);
+        let shape = Color::rgb(200, 100, 50);
+
+        for format in [ColorFormat::Straight, ColorFormat::Premultiplied] {
+            let state = blend_state(format);
+
+            // The outer edge of a feather ring leaves the background as it
+            // was, rather than drawing an opaque halo.
+            let edge = Color { a: 0, ..shape }.to_format(format);
+            let expected = blend(state, background, background);
+            assert_eq!(blend(state, edge, background), expected, "{:?}", format);
+
+            // Halfway through the ring, the shape and the background mix.
+            let middle = blend(
+                state,
+                Color { a: 128, ..shape }.to_format(format),
+                background,
+            );
+            assert!((middle[0] - (200.0 * 0.5 + 10.0 * 0.5) / 255.0).abs() < 0.01);
+            assert!((middle[2] - (50.0 * 0.5 + 30.0 * 0.5) / 255.0).abs() < 0.01);
+
+            // Opaque shapes cover the background entirely.
+            assert_eq!(blend(state, shape, background), blend(state, shape, shape));
+        }
+    }
+
+    #[test]
+    fn debug_callback_is_taken_once() {
+        let mut pending = PendingDebugCallback::default();
+        assert!(pending.set(Box::new(|_, _, _| {})));
+        assert!(pending.set(Box::new(|_, _, _| {})));
+        assert!(pending.take().is_some());
+
+        // The context has been created, so the callback would never be used.
+        assert!(!pending.set(Box::new(|_, _, _| {})));
+        assert!(pending.take().is_none());
+    }
+}
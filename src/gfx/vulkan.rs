@@ -1,9 +1,15 @@
 use std::{
     cmp::min,
+    collections::HashMap,
     convert::TryInto,
     ffi::{c_void, CStr},
     iter::FromIterator,
     os::raw::c_char,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
 };
 
 use ash::{
@@ -11,6 +17,7 @@ use ash::{
         ext::DebugUtils,
         khr::{Surface, Swapchain, Win32Surface},
     },
+    prelude::VkResult,
     vk, Device, EntryCustom, Instance,
 };
 
@@ -22,32 +29,293 @@ use crate::{
 
 const MAX_PHYSICAL_DEVICES: usize = 16;
 const MAX_QUEUE_FAMILIES: usize = 64;
-const PREFERRED_SWAPCHAIN_LENGTH: u32 = 2;
+/// The number of swapchain images to ask for unless told otherwise.
+pub const DEFAULT_SWAPCHAIN_LENGTH: u32 = 2;
 
 const VALIDATION_LAYER_NAME: *const c_char = "VK_LAYER_KHRONOS_validation\0".as_ptr().cast();
 const SURFACE_EXTENSION_NAME: *const c_char = "VK_KHR_surface\0".as_ptr().cast();
 const DEBUG_UTILS_EXTENSION_NAME: *const c_char = "VK_EXT_debug_utils\0\0".as_ptr().cast();
 const WIN32_SURFACE_EXTENSION_NAME: *const c_char = "VK_KHR_win32_surface\0".as_ptr().cast();
 const SWAPCHAIN_EXTENSION_NAME: *const c_char = "VK_KHR_swapchain\0".as_ptr().cast();
+const SWAPCHAIN_COLORSPACE_EXTENSION_NAME: &str = "VK_EXT_swapchain_colorspace\0";
+
+/// How serious a message from the Vulkan debug messenger is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Maps the most severe flag in `flags` to a [`Severity`].
+    fn from_flags(flags: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            Self::Error
+        } else if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            Self::Warning
+        } else if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            Self::Info
+        } else {
+            Self::Verbose
+        }
+    }
+}
+
+/// What a message from the Vulkan debug messenger is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    General,
+    /// The specification was violated.
+    Validation,
+    /// The API is being used in a way that may be slow.
+    Performance,
+}
+
+impl MessageType {
+    fn from_flags(flags: vk::DebugUtilsMessageTypeFlagsEXT) -> Self {
+        if flags.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            Self::Validation
+        } else if flags.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+            Self::Performance
+        } else {
+            Self::General
+        }
+    }
+}
 
+/// Receives messages from the Vulkan debug messenger.
+pub type DebugCallback = Box<dyn Fn(Severity, MessageType, &str) + Send + Sync>;
+
+/// The debug utils extension, and the messenger that reports validation
+/// messages if it is enabled.
 pub struct DebugInfo {
     api: DebugUtils,
-    callback: vk::DebugUtilsMessengerEXT,
+    messenger: Mutex<Option<vk::DebugUtilsMessengerEXT>>,
 }
 
 impl DebugInfo {
     fn new(
         entry: &EntryCustom<Library>,
         instance: &Instance,
-        create_info: &vk::DebugUtilsMessengerCreateInfoEXT,
+        enable_messenger: bool,
+        user_data: *mut c_void,
         allocation_callbacks: Option<&vk::AllocationCallbacks>,
     ) -> Self {
-        let api = DebugUtils::new(entry, instance);
-        let callback = unsafe {
-            api.create_debug_utils_messenger(create_info, allocation_callbacks)
-                .unwrap()
+        let this = Self {
+            api: DebugUtils::new(entry, instance),
+            messenger: Mutex::new(None),
         };
-        Self { api, callback }
+        this.set_enabled(enable_messenger, user_data, allocation_callbacks);
+        this
+    }
+
+    fn set_enabled(
+        &self,
+        enabled: bool,
+        user_data: *mut c_void,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) {
+        let mut messenger = self.messenger.lock().unwrap();
+        toggle_messenger(
+            &mut messenger,
+            enabled,
+            || unsafe {
+                self.api
+                    .create_debug_utils_messenger(
+                        &debug_messenger_create_info(user_data),
+                        allocation_callbacks,
+                    )
+                    .expect("Out of memory")
+            },
+            |messenger| unsafe {
+                self.api
+                    .destroy_debug_utils_messenger(messenger, allocation_callbacks)
+            },
+        );
+    }
+}
+
+/// Calls `create` or `destroy` so that `messenger` exists only if `enabled`.
+/// Does nothing if it is already in the requested state.
+fn toggle_messenger<M>(
+    messenger: &mut Option<M>,
+    enabled: bool,
+    create: impl FnOnce() -> M,
+    destroy: impl FnOnce(M),
+) {
+    if enabled && messenger.is_none() {
+        *messenger = Some(create());
+    } else if !enabled {
+        if let Some(messenger) = messenger.take() {
+            destroy(messenger);
+        }
+    }
+}
+
+/// `user_data` must point to an `Option<DebugCallback>` that outlives the
+/// messenger.
+fn debug_messenger_create_info(user_data: *mut c_void) -> vk::DebugUtilsMessengerCreateInfoEXT {
+    *vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+        .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+        .pfn_user_callback(Some(debug_callback))
+        .user_data(user_data)
+}
+
+/// The number of fences and semaphores that exist at a point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    /// Fences that have been created, but not freed.
+    pub live_fences: usize,
+    /// Every fence that has been returned with `free_fence()`.
+    pub pooled_fences: usize,
+    /// Semaphores that have been created, but not freed.
+    pub live_semaphores: usize,
+    /// Every semaphore that has been returned with `free_semaphore()`.
+    pub pooled_semaphores: usize,
+}
+
+/// Counts fences and semaphores as they are created and freed, so that leaks
+/// can be found.
+#[derive(Debug, Default)]
+struct SyncCounters {
+    fences_created: AtomicUsize,
+    fences_freed: AtomicUsize,
+    semaphores_created: AtomicUsize,
+    semaphores_freed: AtomicUsize,
+}
+
+impl SyncCounters {
+    fn stats(&self) -> SyncStats {
+        let fences_freed = self.fences_freed.load(Ordering::Relaxed);
+        let semaphores_freed = self.semaphores_freed.load(Ordering::Relaxed);
+
+        SyncStats {
+            live_fences: self.fences_created.load(Ordering::Relaxed) - fences_freed,
+            pooled_fences: fences_freed,
+            live_semaphores: self.semaphores_created.load(Ordering::Relaxed) - semaphores_freed,
+            pooled_semaphores: semaphores_freed,
+        }
+    }
+}
+
+/// The color space that a window's swapchain should present in. The renderer
+/// doesn't convert colors between spaces, so what is drawn must already be
+/// in the chosen space; see [`SwapchainData::color_space`] for which one that
+/// is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpacePreference {
+    /// Nonlinear sRGB with 8 bits per channel, which every display supports.
+    Srgb,
+    /// Linear scRGB in 16-bit floats, where 1.0 is SDR white and brighter
+    /// colors go above it.
+    ExtendedSrgbLinear,
+    /// HDR10 (the ST 2084 transfer function with BT.2020 primaries) in 10 bits
+    /// per channel, or 16-bit floats if that isn't supported.
+    Hdr10,
+}
+
+impl ColorSpacePreference {
+    /// The formats that the color space can be presented with, most preferred
+    /// first.
+    fn candidates(self) -> &'static [(vk::Format, vk::ColorSpaceKHR)] {
+        match self {
+            Self::Srgb => &[],
+            Self::ExtendedSrgbLinear => &[(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            )],
+            Self::Hdr10 => &[
+                (
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+                (
+                    vk::Format::A2R10G10B10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+                (
+                    vk::Format::R16G16B16A16_SFLOAT,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+            ],
+        }
+    }
+}
+
+impl Default for ColorSpacePreference {
+    fn default() -> Self {
+        Self::Srgb
+    }
+}
+
+/// How a texture is sampled between texels, and between mip levels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl Filter {
+    fn to_vk(self) -> (vk::Filter, vk::SamplerMipmapMode) {
+        match self {
+            Self::Nearest => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST),
+            Self::Linear => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+        }
+    }
+}
+
+/// What a texture is sampled as outside of its bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+}
+
+impl WrapMode {
+    fn to_vk(self) -> vk::SamplerAddressMode {
+        match self {
+            Self::Repeat => vk::SamplerAddressMode::REPEAT,
+            Self::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            Self::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        }
+    }
+}
+
+/// One sampler for each combination of [`Filter`] and [`WrapMode`], created
+/// the first time that it is asked for.
+#[derive(Debug, Default)]
+struct SamplerCache {
+    samplers: Mutex<HashMap<(Filter, WrapMode), vk::Sampler>>,
+}
+
+impl SamplerCache {
+    fn get_or_create(
+        &self,
+        filter: Filter,
+        wrap: WrapMode,
+        create: impl FnOnce() -> vk::Sampler,
+    ) -> vk::Sampler {
+        *self
+            .samplers
+            .lock()
+            .unwrap()
+            .entry((filter, wrap))
+            .or_insert_with(create)
+    }
+
+    /// Empties the cache, returning the samplers so that they can be
+    /// destroyed.
+    fn take_all(&mut self) -> Vec<vk::Sampler> {
+        self.samplers
+            .get_mut()
+            .unwrap()
+            .drain()
+            .map(|(_, sampler)| sampler)
+            .collect()
     }
 }
 
@@ -63,6 +331,10 @@ pub struct Vulkan {
 
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
+    /// Held while either queue is in use, since Vulkan requires access to a
+    /// queue to be externally synchronized. The queues may be one and the
+    /// same, so a single lock covers both.
+    queue_lock: Mutex<()>,
 
     surface_api: Surface,
     os_surface_api: Win32Surface,
@@ -71,11 +343,60 @@ pub struct Vulkan {
     pipeline_cache: vk::PipelineCache,
 
     debug: Option<DebugInfo>,
+    /// Boxed so that its address can be given to the debug messenger.
+    debug_callback: Box<Option<DebugCallback>>,
     allocation_callbacks: Option<vk::AllocationCallbacks>,
+
+    sync_counters: SyncCounters,
+    /// Use exclusive sharing for swapchains even when the graphics and
+    /// present queues are in different families.
+    force_exclusive_sharing: AtomicBool,
+    samplers: SamplerCache,
 }
 
 unsafe impl Sync for Vulkan {}
 
+/// The queue families that rendering and presentation happen on.
+///
+/// When they differ, swapchain images are created in concurrent mode so that
+/// both families can use them without transferring ownership, unless
+/// exclusive mode has been forced with
+/// [`Vulkan::set_force_exclusive_sharing()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    pub present: u32,
+    /// Whether `graphics` and `present` are the same family.
+    pub same: bool,
+}
+
+impl QueueFamilies {
+    pub fn new(graphics: u32, present: u32) -> Self {
+        Self {
+            graphics,
+            present,
+            same: graphics == present,
+        }
+    }
+
+    /// The sharing mode to create swapchain images with. Exclusive mode needs
+    /// explicit ownership transfers when the families differ, see
+    /// [`needs_ownership_transfer()`](Self::needs_ownership_transfer).
+    pub fn sharing_mode(&self, force_exclusive: bool) -> vk::SharingMode {
+        if self.same || force_exclusive {
+            vk::SharingMode::EXCLUSIVE
+        } else {
+            vk::SharingMode::CONCURRENT
+        }
+    }
+
+    /// Whether images created with `sharing_mode` must be released by the
+    /// graphics queue and acquired by the present queue before presenting.
+    pub fn needs_ownership_transfer(&self, sharing_mode: vk::SharingMode) -> bool {
+        !self.same && sharing_mode == vk::SharingMode::EXCLUSIVE
+    }
+}
+
 #[must_use]
 #[derive(Debug, Default)]
 pub struct SurfaceData {
@@ -84,6 +405,30 @@ pub struct SurfaceData {
     pub present_modes: Vec<vk::PresentModeKHR>,
 }
 
+/// The result of acquiring an image from a swapchain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcquiredImage {
+    /// The image can be drawn to and presented. If `suboptimal` is set, the
+    /// swapchain no longer matches the surface exactly, and should be
+    /// recreated once the image has been presented.
+    Ready { index: u32, suboptimal: bool },
+    /// The swapchain can no longer be presented to, and must be recreated
+    /// before anything can be drawn. No image was acquired.
+    OutOfDate,
+}
+
+impl AcquiredImage {
+    /// Out of date swapchains are expected, so only other errors, such as
+    /// device or surface loss, are passed on.
+    fn from_result(result: VkResult<(u32, bool)>) -> VkResult<Self> {
+        match result {
+            Ok((index, suboptimal)) => Ok(Self::Ready { index, suboptimal }),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(Self::OutOfDate),
+            Err(any) => Err(any),
+        }
+    }
+}
+
 #[must_use]
 #[derive(Debug, Default)]
 pub struct SwapchainData {
@@ -98,25 +443,164 @@ pub struct SwapchainData {
     /// A handle to the swapchain, managed by the Vulkan drivers.
     pub handle: vk::SwapchainKHR,
 
+    /// The size of the swapchain's images. This is the window's size with
+    /// `pre_transform` applied, so it may have its width and height swapped.
     pub image_size: vk::Extent2D,
+
+    /// The ways in which the swapchain's images may be used. Always includes
+    /// `COLOR_ATTACHMENT`.
+    pub image_usage: vk::ImageUsageFlags,
+
+    /// The transform that the presentation engine expects to have been
+    /// applied to the swapchain's images before they are presented.
+    pub pre_transform: vk::SurfaceTransformFlagsKHR,
+
+    /// The number of images in the swapchain. This may be more than were
+    /// asked for.
+    pub image_count: u32,
+
+    /// Whether the images are owned by one queue family at a time.
+    pub sharing_mode: vk::SharingMode,
+
+    /// How the images' alpha is used when compositing the window. Anything
+    /// other than `OPAQUE` or `INHERIT` means that the window is transparent
+    /// where the images are.
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+}
+
+impl SwapchainData {
+    /// Whether the images are presented in a high dynamic range color space.
+    /// Wide gamut color spaces such as Display P3 aren't HDR by themselves.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.color_space,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+                | vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT
+                | vk::ColorSpaceKHR::HDR10_ST2084_EXT
+                | vk::ColorSpaceKHR::HDR10_HLG_EXT
+                | vk::ColorSpaceKHR::DOLBYVISION_EXT
+        )
+    }
+
+    /// Whether the images' alpha channel shows through to what is behind the
+    /// window.
+    pub fn is_transparent(&self) -> bool {
+        self.composite_alpha == vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+            || self.composite_alpha == vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+    }
+
+    /// The size of the window that the swapchain was created for.
+    pub fn window_size(&self) -> vk::Extent2D {
+        if is_quarter_turn(self.pre_transform) {
+            vk::Extent2D {
+                width: self.image_size.height,
+                height: self.image_size.width,
+            }
+        } else {
+            self.image_size
+        }
+    }
+}
+
+/// Whether or not `transform` swaps the width and height of an image.
+pub fn is_quarter_turn(transform: vk::SurfaceTransformFlagsKHR) -> bool {
+    transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_90 | vk::SurfaceTransformFlagsKHR::ROTATE_270,
+    )
+}
+
+/// The reasons that SPIR-V could not be made into a shader module.
+#[derive(thiserror::Error, Debug)]
+pub enum ShaderError {
+    #[error("Shader source must be aligned to 4-byte words")]
+    Misaligned,
+    #[error("Could not read shader from {path}: {error}")]
+    Read { path: String, error: std::io::Error },
+}
+
+/// The shader modules that every pipeline is built from.
+pub struct Shaders {
+    pub vertex: vk::ShaderModule,
+    pub fragment: vk::ShaderModule,
+    /// Draws the unit quad once per instance, in place of `vertex`.
+    pub instanced_vertex: vk::ShaderModule,
+    /// Incremented whenever the modules are replaced, so that pipelines built
+    /// from older modules can be recreated.
+    pub generation: u64,
+}
+
+/// Which GPU to render with, when there is more than one to choose from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuPreference {
+    /// The first suitable GPU that the driver reports.
+    Any,
+    /// Prefers discrete GPUs, which are usually faster.
+    HighPerformance,
+    /// Prefers integrated GPUs, which usually use less power.
+    LowPower,
+    /// The GPU at this index in [`Vulkan::enumerate_gpus()`], if it is
+    /// suitable.
+    Index(usize),
+}
+
+impl Default for GpuPreference {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
+/// Describes one of the GPUs in the system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpuInfo {
+    /// The GPU's position in the list of physical devices.
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+}
+
+impl GpuInfo {
+    fn new(instance: &Instance, index: usize, device: vk::PhysicalDevice) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(device) };
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+
+        Self {
+            index,
+            name: name.to_string_lossy().into_owned(),
+            device_type: properties.device_type,
+        }
+    }
 }
 
 impl Vulkan {
     /// Initializes a new vulkan context.
     /// Note: The selected GPU is guaranteed to support surface creation.
-    pub fn new(os_library: Library, use_validation: bool) -> Self {
+    ///
+    /// Validation messages are passed to `debug_callback` if one is provided,
+    /// or else errors are printed to stdout.
+    ///
+    /// The pipeline cache is seeded from `pipeline_cache_path` if it names a
+    /// file written by [`save_pipeline_cache()`](Self::save_pipeline_cache)
+    /// for the same GPU and driver. Missing, mismatched or corrupt files are
+    /// ignored.
+    pub fn new(
+        os_library: Library,
+        use_validation: bool,
+        gpu_preference: GpuPreference,
+        debug_callback: Option<DebugCallback>,
+        pipeline_cache_path: Option<&Path>,
+    ) -> Self {
         let library = EntryCustom::new_custom(os_library, |lib, name| {
             lib.get_symbol(name).unwrap_or(std::ptr::null_mut())
         })
         .expect("Loaded library does not contain Vuklan loader");
 
-        let mut debug_callback_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(
-                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-            )
-            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
-            .pfn_user_callback(Some(debug_callback));
+        let debug_callback = Box::new(debug_callback);
+        let user_data = &*debug_callback as *const Option<DebugCallback> as *mut c_void;
+        let mut debug_callback_create_info = debug_messenger_create_info(user_data);
+
+        // The extension is always enabled in debug builds so that the
+        // messenger can be turned on later with `set_debug_messenger_enabled`.
+        let use_debug_utils = use_validation || cfg!(debug_assertions);
 
         let allocation_callbacks: Option<vk::AllocationCallbacks> = None;
 
@@ -126,15 +610,33 @@ impl Vulkan {
 
             let mut layers = ArrayVec::<*const c_char, 1>::new();
             let mut extensions =
-                ArrayVec::<_, 3>::from([SURFACE_EXTENSION_NAME, WIN32_SURFACE_EXTENSION_NAME]);
+                ArrayVec::<_, 4>::from([SURFACE_EXTENSION_NAME, WIN32_SURFACE_EXTENSION_NAME]);
+
+            // Surfaces only report color spaces other than sRGB with this
+            // enabled. It is optional, since sRGB works without it.
+            let colorspace_name = SWAPCHAIN_COLORSPACE_EXTENSION_NAME;
+            let has_colorspace_extension = library
+                .enumerate_instance_extension_properties()
+                .unwrap_or_default()
+                .iter()
+                .any(|properties| {
+                    let name = unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) };
+                    name.to_bytes_with_nul() == colorspace_name.as_bytes()
+                });
+            if has_colorspace_extension {
+                extensions.push(colorspace_name.as_ptr().cast());
+            }
 
             let enables = [vk::ValidationFeatureEnableEXT::BEST_PRACTICES];
             let mut validation_features =
                 vk::ValidationFeaturesEXT::builder().enabled_validation_features(&enables);
 
+            if use_debug_utils {
+                extensions.push(DEBUG_UTILS_EXTENSION_NAME);
+            }
+
             if use_validation {
                 layers.push(VALIDATION_LAYER_NAME);
-                extensions.push(DEBUG_UTILS_EXTENSION_NAME);
                 create_info = create_info.push_next(&mut debug_callback_create_info);
                 create_info = create_info.push_next(&mut validation_features);
             }
@@ -147,11 +649,12 @@ impl Vulkan {
                 .expect("Unexpected error")
         };
 
-        let debug = if use_validation {
+        let debug = if use_debug_utils {
             Some(DebugInfo::new(
                 &library,
                 &instance,
-                &debug_callback_create_info,
+                use_validation,
+                user_data,
                 allocation_callbacks.as_ref(),
             ))
         } else {
@@ -161,8 +664,8 @@ impl Vulkan {
         let surface_api = Surface::new(&library, &instance);
         let os_surface_api = Win32Surface::new(&library, &instance);
 
-        let gpu =
-            select_physical_device(&instance, &os_surface_api).expect("No supported GPU found");
+        let gpu = select_physical_device(&instance, &os_surface_api, gpu_preference)
+            .expect("No supported GPU found");
 
         let gpu_properties = unsafe { instance.get_physical_device_properties(gpu.handle) };
 
@@ -205,7 +708,19 @@ impl Vulkan {
         let graphics_queue = unsafe { device.get_device_queue(gpu.graphics_queue_index, 0) };
 
         let pipeline_cache = {
-            let create_info = vk::PipelineCacheCreateInfo::builder();
+            let initial_data = pipeline_cache_path
+                .and_then(|path| std::fs::read(path).ok())
+                .filter(|data| {
+                    is_pipeline_cache_compatible(
+                        data,
+                        gpu_properties.vendor_id,
+                        gpu_properties.device_id,
+                        &gpu_properties.pipeline_cache_uuid,
+                    )
+                })
+                .unwrap_or_default();
+
+            let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
             // Only fails on out of memory (Vulkan 1.2; Aug 7, 2021)
             unsafe { device.create_pipeline_cache(&create_info, allocation_callbacks.as_ref()) }
                 .expect("Out of memory")
@@ -220,12 +735,17 @@ impl Vulkan {
             device,
             graphics_queue,
             present_queue,
+            queue_lock: Mutex::new(()),
             surface_api,
             os_surface_api,
             swapchain_api,
             pipeline_cache,
             debug,
+            debug_callback,
             allocation_callbacks,
+            sync_counters: SyncCounters::default(),
+            force_exclusive_sharing: AtomicBool::new(false),
+            samplers: SamplerCache::default(),
         }
     }
 
@@ -240,10 +760,61 @@ impl Vulkan {
                             |___/
     */
 
+    /// Lists every GPU in the system, whether or not it can be rendered with.
+    pub fn enumerate_gpus(&self) -> Vec<GpuInfo> {
+        enumerate_physical_devices(&self.instance)
+            .iter()
+            .enumerate()
+            .map(|(index, device)| GpuInfo::new(&self.instance, index, *device))
+            .collect()
+    }
+
+    /// The GPU that is being rendered with.
+    pub fn gpu(&self) -> GpuInfo {
+        let index = enumerate_physical_devices(&self.instance)
+            .iter()
+            .position(|device| *device == self.gpu.handle)
+            .unwrap_or_default();
+        GpuInfo::new(&self.instance, index, self.gpu.handle)
+    }
+
+    /// The number of nanoseconds per timestamp tick.
+    pub fn timestamp_period(&self) -> f32 {
+        self.gpu_properties.limits.timestamp_period
+    }
+
+    /// The queue families that rendering and presentation happen on.
+    pub fn queue_families(&self) -> QueueFamilies {
+        QueueFamilies::new(self.gpu.graphics_queue_index, self.gpu.present_queue_index)
+    }
+
+    /// Creates swapchains in exclusive mode even when the graphics and present
+    /// queues are in different families, transferring ownership of each image
+    /// before it is presented. This is for comparing the performance of the
+    /// two modes; concurrent mode is used otherwise. Only affects swapchains
+    /// created or resized after the call.
+    pub fn set_force_exclusive_sharing(&self, force: bool) {
+        self.force_exclusive_sharing.store(force, Ordering::Relaxed);
+    }
+
+    /// The number of meaningful bits in timestamps written on the graphics
+    /// queue. Timestamps aren't supported if this is 0.
+    pub fn timestamp_valid_bits(&self) -> u32 {
+        self.gpu.timestamp_valid_bits
+    }
+
     pub fn non_coherent_atom_size(&self) -> vk::DeviceSize {
         self.gpu_properties.limits.non_coherent_atom_size
     }
 
+    /// The preferred alignment of each row of an image when it is copied to
+    /// or from a buffer.
+    pub fn optimal_buffer_copy_row_pitch_alignment(&self) -> vk::DeviceSize {
+        self.gpu_properties
+            .limits
+            .optimal_buffer_copy_row_pitch_alignment
+    }
+
     /*
     __      ___     _____             __               _  ___    _ _____
     \ \    / / |   / ____|           / _|             | |/ / |  | |  __ \
@@ -253,36 +824,50 @@ impl Vulkan {
         \/   |_|\_\_____/ \__,_|_|  |_| \__,_|\___\___|_|\_\_|  |_|_|  \_\
     */
 
-    pub fn create_surface(&self, window_handle: &Handle) -> SurfaceData {
+    pub fn create_surface(&self, window_handle: &Handle) -> VkResult<SurfaceData> {
         let ci = vk::Win32SurfaceCreateInfoKHR::builder()
             .hwnd(window_handle.hwnd.0 as _)
             .hinstance(window_handle.hinstance.0 as _);
 
-        unsafe {
-            let handle = self
-                .os_surface_api
-                .create_win32_surface(&ci, self.allocation_callbacks.as_ref())
-                .expect("Out of memory");
+        let handle = unsafe {
+            self.os_surface_api
+                .create_win32_surface(&ci, self.allocation_callbacks.as_ref())?
+        };
+
+        let query = || unsafe {
+            let supported = self.surface_api.get_physical_device_surface_support(
+                self.gpu.handle,
+                self.gpu.present_queue_index,
+                handle,
+            )?;
+
+            // The present queue was chosen for the GPU, not this window, so a
+            // surface on a display it can't reach is possible.
+            if !supported {
+                return Err(vk::Result::ERROR_INCOMPATIBLE_DISPLAY_KHR);
+            }
 
-            assert!(self
-                .surface_api
-                .get_physical_device_surface_support(
-                    self.gpu.handle,
-                    self.gpu.present_queue_index,
-                    handle,
-                )
-                .unwrap_or(false));
             let formats = self
                 .surface_api
-                .get_physical_device_surface_formats(self.gpu.handle, handle);
-            let modes = self
+                .get_physical_device_surface_formats(self.gpu.handle, handle)?;
+            let present_modes = self
                 .surface_api
-                .get_physical_device_surface_present_modes(self.gpu.handle, handle);
+                .get_physical_device_surface_present_modes(self.gpu.handle, handle)?;
+            Ok((formats, present_modes))
+        };
 
-            SurfaceData {
+        match query() {
+            Ok((formats, present_modes)) => Ok(SurfaceData {
                 handle,
-                formats: formats.unwrap(),
-                present_modes: modes.unwrap(),
+                formats,
+                present_modes,
+            }),
+            Err(error) => {
+                unsafe {
+                    self.surface_api
+                        .destroy_surface(handle, self.allocation_callbacks.as_ref());
+                }
+                Err(error)
             }
         }
     }
@@ -309,22 +894,18 @@ impl Vulkan {
         &self,
         surface: &SurfaceData,
         size: vk::Extent2D,
+        image_usage: vk::ImageUsageFlags,
+        preferred_images: u32,
+        transparent: bool,
+        color_space: ColorSpacePreference,
         old: Option<vk::SwapchainKHR>,
-    ) -> SwapchainData {
+    ) -> VkResult<SwapchainData> {
         let capabilities = unsafe {
             self.surface_api
-                .get_physical_device_surface_capabilities(self.gpu.handle, surface.handle)
-                .unwrap()
+                .get_physical_device_surface_capabilities(self.gpu.handle, surface.handle)?
         };
 
-        let format = *surface
-            .formats
-            .iter()
-            .find(|f| {
-                f.format == vk::Format::B8G8R8A8_SRGB
-                    && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .unwrap_or(&surface.formats[0]);
+        let format = select_surface_format(&surface.formats, color_space);
 
         let present_mode = *surface
             .present_modes
@@ -332,8 +913,31 @@ impl Vulkan {
             .find(|p| **p == vk::PresentModeKHR::MAILBOX)
             .unwrap_or(&vk::PresentModeKHR::FIFO);
 
+        // Let the presentation engine rotate the image if it can, since that
+        // leaves the display hardware free to do it for us. Otherwise, we have
+        // to render to the images in the display's native orientation.
+        let pre_transform = if capabilities
+            .supported_transforms
+            .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+        {
+            vk::SurfaceTransformFlagsKHR::IDENTITY
+        } else {
+            capabilities.current_transform
+        };
+
+        let swap_axes = |extent: vk::Extent2D| vk::Extent2D {
+            width: extent.height,
+            height: extent.width,
+        };
+
         let image_size = {
             if capabilities.current_extent.width == u32::MAX {
+                let size = if is_quarter_turn(pre_transform) {
+                    swap_axes(size)
+                } else {
+                    size
+                };
+
                 vk::Extent2D {
                     width: size.width.clamp(
                         capabilities.min_image_extent.width,
@@ -344,21 +948,22 @@ impl Vulkan {
                         capabilities.max_image_extent.height,
                     ),
                 }
+            } else if is_quarter_turn(capabilities.current_transform)
+                != is_quarter_turn(pre_transform)
+            {
+                // The current extent is reported in the orientation of the
+                // current transform, not the one we picked.
+                swap_axes(capabilities.current_extent)
             } else {
                 capabilities.current_extent
             }
         };
 
-        let min_images = if capabilities.max_image_count == 0 {
-            if PREFERRED_SWAPCHAIN_LENGTH > capabilities.min_image_count {
-                PREFERRED_SWAPCHAIN_LENGTH
-            } else {
-                capabilities.min_image_count
-            }
-        } else {
-            PREFERRED_SWAPCHAIN_LENGTH
-                .clamp(capabilities.min_image_count, capabilities.max_image_count)
-        };
+        let min_images = min_swapchain_images(&capabilities, preferred_images);
+        let composite_alpha =
+            select_composite_alpha(capabilities.supported_composite_alpha, transparent);
+
+        let image_usage = swapchain_image_usage(image_usage, capabilities.supported_usage_flags)?;
 
         let mut create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surface.handle)
@@ -367,28 +972,29 @@ impl Vulkan {
             .image_color_space(format.color_space)
             .image_extent(image_size)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .pre_transform(capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .image_usage(image_usage)
+            .pre_transform(pre_transform)
+            .composite_alpha(composite_alpha)
             .present_mode(present_mode)
             .clipped(true);
 
-        let queue_family_indices = [self.gpu.graphics_queue_index, self.gpu.present_queue_index];
-        if queue_family_indices[0] == queue_family_indices[1] {
-            create_info.image_sharing_mode = vk::SharingMode::EXCLUSIVE;
-        } else {
-            create_info.image_sharing_mode = vk::SharingMode::CONCURRENT;
+        let families = self.queue_families();
+        let queue_family_indices = [families.graphics, families.present];
+        let sharing_mode =
+            families.sharing_mode(self.force_exclusive_sharing.load(Ordering::Relaxed));
+        create_info.image_sharing_mode = sharing_mode;
+        if sharing_mode == vk::SharingMode::CONCURRENT {
             create_info.queue_family_index_count = 2;
             create_info.p_queue_family_indices = queue_family_indices.as_ptr();
         }
 
         create_info.old_swapchain = old.unwrap_or_default();
 
+        // If this fails, the old swapchain is left for the caller to destroy.
         let handle = unsafe {
             self.swapchain_api
-                .create_swapchain(&create_info, self.allocation_callbacks.as_ref())
-        }
-        .unwrap();
+                .create_swapchain(&create_info, self.allocation_callbacks.as_ref())?
+        };
 
         unsafe {
             self.swapchain_api.destroy_swapchain(
@@ -397,13 +1003,28 @@ impl Vulkan {
             );
         }
 
-        SwapchainData {
+        let mut image_count = 0;
+        let _ = unsafe {
+            self.swapchain_api.fp().get_swapchain_images_khr(
+                self.device.handle(),
+                handle,
+                &mut image_count,
+                std::ptr::null_mut(),
+            )
+        };
+
+        Ok(SwapchainData {
             handle,
             format: format.format,
             image_size,
             color_space: format.color_space,
             present_mode,
-        }
+            image_usage,
+            pre_transform,
+            image_count,
+            sharing_mode,
+            composite_alpha,
+        })
     }
 
     pub fn destroy_swapchain(&self, swapchain: SwapchainData) {
@@ -432,30 +1053,41 @@ impl Vulkan {
         &self,
         swapchain: &SwapchainData,
         acquire_semaphore: vk::Semaphore,
-    ) -> Option<u32> {
-        match unsafe {
+    ) -> VkResult<AcquiredImage> {
+        AcquiredImage::from_result(unsafe {
             self.swapchain_api.acquire_next_image(
                 swapchain.handle,
                 u64::MAX,
                 acquire_semaphore,
                 vk::Fence::null(),
             )
-        } {
-            Ok((index, is_suboptimal)) => {
-                if is_suboptimal {
-                    None
-                } else {
-                    Some(index)
-                }
-            }
-            Err(vkr) => match vkr {
-                vk::Result::ERROR_OUT_OF_DATE_KHR => None,
-                any => panic!("Unexpected error {:?}", any),
-            },
+        })
+    }
+
+    /// Submits work to the present queue, such as acquiring ownership of
+    /// swapchain images from the graphics queue.
+    pub fn submit_to_present_queue(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) {
+        let _queues = self.queue_lock.lock().unwrap();
+        unsafe {
+            self.device
+                .queue_submit(self.present_queue, submits, fence)
+                .expect("Unexpected error");
+        }
+    }
+
+    /// Waits until the present queue has finished all of the work submitted
+    /// to it.
+    pub fn wait_for_present_queue(&self) {
+        let _queues = self.queue_lock.lock().unwrap();
+        unsafe {
+            // Only fails on out of memory or device loss, either of which
+            // will be reported by the next submission.
+            let _ = self.device.queue_wait_idle(self.present_queue);
         }
     }
 
     pub fn present(&self, present_info: &vk::PresentInfoKHR) {
+        let _queues = self.queue_lock.lock().unwrap();
         unsafe {
             self.swapchain_api
                 .queue_present(self.present_queue, present_info)
@@ -487,6 +1119,90 @@ impl Vulkan {
         }
     }
 
+    /// Creates a sampler that the caller owns. Prefer [`sampler()`], which
+    /// shares one sampler between everyone asking for the same settings.
+    ///
+    /// [`sampler()`]: Self::sampler
+    pub fn create_sampler(&self, filter: Filter, wrap: WrapMode) -> vk::Sampler {
+        let (filter, mipmap_mode) = filter.to_vk();
+        let wrap = wrap.to_vk();
+        let create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(mipmap_mode)
+            .address_mode_u(wrap)
+            .address_mode_v(wrap)
+            .address_mode_w(wrap)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        unsafe {
+            self.device
+                .create_sampler(&create_info, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory")
+    }
+
+    pub fn destroy_sampler(&self, sampler: vk::Sampler) {
+        unsafe {
+            self.device
+                .destroy_sampler(sampler, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    /// A sampler with the given settings, which lives as long as the Vulkan
+    /// context. It must not be destroyed.
+    pub fn sampler(&self, filter: Filter, wrap: WrapMode) -> vk::Sampler {
+        self.samplers
+            .get_or_create(filter, wrap, || self.create_sampler(filter, wrap))
+    }
+
+    pub fn create_image(&self, create_info: &vk::ImageCreateInfo) -> vk::Image {
+        unsafe {
+            self.device
+                .create_image(create_info, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory")
+    }
+
+    pub fn destroy_image(&self, image: vk::Image) {
+        unsafe {
+            self.device
+                .destroy_image(image, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    pub fn image_memory_requirements(&self, image: vk::Image) -> vk::MemoryRequirements {
+        unsafe { self.device.get_image_memory_requirements(image) }
+    }
+
+    pub fn bind_image(&self, image: vk::Image, memory: vk::DeviceMemory, offset: u64) {
+        unsafe {
+            self.device
+                .bind_image_memory(image, memory, offset)
+                .expect("Out of memory");
+        }
+    }
+
+    /// Finds a depth format that the GPU can use as a depth attachment, in
+    /// order of preference.
+    pub fn find_depth_format(&self) -> Option<vk::Format> {
+        [
+            vk::Format::D32_SFLOAT,
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D16_UNORM,
+        ]
+        .into_iter()
+        .find(|format| {
+            let properties = unsafe {
+                self.instance
+                    .get_physical_device_format_properties(self.gpu.handle, *format)
+            };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+    }
+
     pub fn create_frame_buffer(&self, create_info: &vk::FramebufferCreateInfo) -> vk::Framebuffer {
         unsafe {
             self.device
@@ -590,6 +1306,90 @@ impl Vulkan {
         }
     }
 
+    /// Creates a buffer of `size` bytes, bound to a dedicated allocation from
+    /// memory with `properties`.
+    pub fn create_bound_buffer(
+        &self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        let buffer = self.create_buffer(&vk::BufferCreateInfo {
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        });
+
+        let memory_requirements = self.buffer_memory_requirements(buffer);
+        let memory_type_index = self
+            .find_memory_type(memory_requirements.memory_type_bits, properties)
+            .expect("No suitable memory type");
+
+        let memory = self.allocate(&vk::MemoryAllocateInfo {
+            allocation_size: memory_requirements.size,
+            memory_type_index,
+            ..Default::default()
+        });
+        self.bind(buffer, memory, 0);
+
+        (buffer, memory)
+    }
+
+    /// Copies `data` into a new `DEVICE_LOCAL` buffer, which is faster for the
+    /// GPU to read than host-visible memory. The data goes through a temporary
+    /// staging buffer, and this waits for the copy to finish before returning.
+    ///
+    /// The buffer can be used as a copy destination in addition to `usage`.
+    /// Vulkan buffers can't be empty, so null handles are returned if `data`
+    /// is; they can still be passed to `destroy_buffer()` and `free()`.
+    pub fn upload_to_device_local(
+        &self,
+        data: &[u8],
+        usage: vk::BufferUsageFlags,
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        if data.is_empty() {
+            return (vk::Buffer::null(), vk::DeviceMemory::null());
+        }
+
+        let size = data.len() as vk::DeviceSize;
+
+        let (staging, staging_memory) = self.create_bound_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        unsafe {
+            let mapped = self.map_memory(staging_memory, 0, size, vk::MemoryMapFlags::empty());
+            std::slice::from_raw_parts_mut(mapped.cast(), data.len()).copy_from_slice(data);
+            self.unmap_memory(staging_memory);
+        }
+
+        let (buffer, memory) = self.create_bound_buffer(
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        self.submit_and_wait(|cmd| {
+            cmd.copy_buffer(
+                staging,
+                buffer,
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size,
+                }],
+            );
+        });
+
+        self.destroy_buffer(staging);
+        self.free(staging_memory);
+
+        (buffer, memory)
+    }
+
     /*
      _____ _            _ _
     |  __ (_)          | (_)
@@ -604,23 +1404,78 @@ impl Vulkan {
     /// Creates a new shader from SPIR-V source. Note that the source must be
     /// 4-byte aligned to be accepted as valid.
     pub fn create_shader(&self, source: &[u8]) -> vk::ShaderModule {
-        if source.len() % 4 == 0 && ((source.as_ptr() as usize) % 4) == 0 {
-            let words =
-                unsafe { std::slice::from_raw_parts(source.as_ptr().cast(), source.len() / 4) };
-            let ci = vk::ShaderModuleCreateInfo::builder().code(words);
+        match self.try_create_shader(source) {
+            Ok(shader) => shader,
+            Err(error) => panic!("{}", error),
+        }
+    }
 
-            // Only fails on out of memory, or unused extension errors (Vulkan
-            // 1.2; Aug 7, 2021)
-            unsafe {
-                self.device
-                    .create_shader_module(&ci, self.allocation_callbacks.as_ref())
-            }
-            .expect("Out of memory")
-        } else {
-            panic!("Shader source must be aligned to 4-byte words")
+    fn try_create_shader(&self, source: &[u8]) -> Result<vk::ShaderModule, ShaderError> {
+        let ci = vk::ShaderModuleCreateInfo::builder().code(spirv_words(source)?);
+
+        // Only fails on out of memory, or unused extension errors (Vulkan
+        // 1.2; Aug 7, 2021)
+        Ok(unsafe {
+            self.device
+                .create_shader_module(&ci, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory"))
+    }
+
+    pub fn destroy_shader(&self, shader: vk::ShaderModule) {
+        unsafe {
+            self.device
+                .destroy_shader_module(shader, self.allocation_callbacks.as_ref());
         }
     }
 
+    /// Replaces `shaders` with SPIR-V read from disk, so that shaders can be
+    /// changed without rebuilding. Pipelines must be recreated to use the new
+    /// shaders, which callers can detect through `shaders.generation`.
+    ///
+    /// On error, `shaders` is left untouched.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders_from(
+        &self,
+        shaders: &mut Shaders,
+        vert_path: &std::path::Path,
+        frag_path: &std::path::Path,
+    ) -> Result<(), ShaderError> {
+        let read = |path: &std::path::Path| {
+            std::fs::read(path).map_err(|error| ShaderError::Read {
+                path: path.display().to_string(),
+                error,
+            })
+        };
+
+        let vertex_source = read(vert_path)?;
+        let fragment_source = read(frag_path)?;
+
+        let vertex = self.try_create_shader(&vertex_source)?;
+        let fragment = match self.try_create_shader(&fragment_source) {
+            Ok(fragment) => fragment,
+            Err(error) => {
+                self.destroy_shader(vertex);
+                return Err(error);
+            }
+        };
+
+        // Pipelines don't reference their shader modules once created, so the
+        // old ones can be destroyed right away.
+        self.destroy_shader(std::mem::replace(&mut shaders.vertex, vertex));
+        self.destroy_shader(std::mem::replace(&mut shaders.fragment, fragment));
+        shaders.generation += 1;
+        Ok(())
+    }
+
+    /// Writes the contents of the pipeline cache to `path`, so that it can be
+    /// passed to [`new()`](Self::new) the next time the program runs.
+    pub fn save_pipeline_cache(&self, path: &Path) -> std::io::Result<()> {
+        let data = unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache) }
+            .map_err(|result| std::io::Error::new(std::io::ErrorKind::Other, result))?;
+        std::fs::write(path, data)
+    }
+
     pub fn create_pipeline_layout(
         &self,
         create_info: &vk::PipelineLayoutCreateInfo,
@@ -665,6 +1520,74 @@ impl Vulkan {
         }
     }
 
+    pub fn create_descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> vk::DescriptorSetLayout {
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+        // Only fails on out of memory (Vulkan 1.2; Aug 7, 2021)
+        unsafe {
+            self.device
+                .create_descriptor_set_layout(&create_info, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory")
+    }
+
+    pub fn destroy_descriptor_set_layout(&self, layout: vk::DescriptorSetLayout) {
+        unsafe {
+            self.device
+                .destroy_descriptor_set_layout(layout, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    /// Creates a pool that up to `max_sets` descriptor sets can be allocated
+    /// from, with `pool_sizes` descriptors of each type between them. Sets
+    /// are only returned to the pool when it is destroyed.
+    pub fn create_descriptor_pool(
+        &self,
+        max_sets: u32,
+        pool_sizes: &[vk::DescriptorPoolSize],
+    ) -> vk::DescriptorPool {
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(max_sets)
+            .pool_sizes(pool_sizes);
+
+        unsafe {
+            self.device
+                .create_descriptor_pool(&create_info, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory")
+    }
+
+    pub fn destroy_descriptor_pool(&self, pool: vk::DescriptorPool) {
+        unsafe {
+            self.device
+                .destroy_descriptor_pool(pool, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    /// Allocates one descriptor set from `pool` for each of `layouts`. Fails
+    /// with `ERROR_OUT_OF_POOL_MEMORY` or `ERROR_FRAGMENTED_POOL` if the pool
+    /// doesn't have room for them.
+    pub fn allocate_descriptor_sets(
+        &self,
+        pool: vk::DescriptorPool,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<Vec<vk::DescriptorSet>, vk::Result> {
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(layouts);
+
+        unsafe { self.device.allocate_descriptor_sets(&alloc_info) }
+    }
+
+    pub fn update_descriptor_sets(&self, writes: &[vk::WriteDescriptorSet]) {
+        unsafe {
+            self.device.update_descriptor_sets(writes, &[]);
+        }
+    }
+
     /*
       _____                                          _
      / ____|                                        | |
@@ -678,9 +1601,24 @@ impl Vulkan {
         &self,
         transient: bool,
         reset_individual: bool,
+    ) -> vk::CommandPool {
+        self.create_command_pool(self.gpu.graphics_queue_index, transient, reset_individual)
+    }
+
+    /// Creates a pool for command buffers that are submitted to the present
+    /// queue.
+    pub fn create_present_command_pool(&self) -> vk::CommandPool {
+        self.create_command_pool(self.gpu.present_queue_index, false, false)
+    }
+
+    fn create_command_pool(
+        &self,
+        queue_family_index: u32,
+        transient: bool,
+        reset_individual: bool,
     ) -> vk::CommandPool {
         let mut create_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(self.gpu.graphics_queue_index)
+            .queue_family_index(queue_family_index)
             .build();
 
         if transient {
@@ -756,6 +1694,7 @@ impl Vulkan {
     }
 
     pub fn submit_to_graphics_queue(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) {
+        let _queues = self.queue_lock.lock().unwrap();
         unsafe {
             self.device
                 .queue_submit(self.graphics_queue, submits, fence)
@@ -763,6 +1702,34 @@ impl Vulkan {
         }
     }
 
+    /// Records a one-off command buffer with `record`, then runs it on the
+    /// graphics queue and waits for it to finish.
+    pub fn submit_and_wait(&self, record: impl FnOnce(&Recorder)) {
+        let pool = self.create_graphics_command_pool(true, false);
+        let mut buffers = [vk::CommandBuffer::null()];
+        self.allocate_command_buffers(pool, &mut buffers);
+
+        let cmd = self.record_command_buffer(buffers[0]);
+        cmd.begin();
+        record(&cmd);
+        cmd.end();
+
+        let fence = self.create_fence(false);
+        self.submit_to_graphics_queue(
+            &[vk::SubmitInfo {
+                command_buffer_count: 1,
+                p_command_buffers: buffers.as_ptr(),
+                ..Default::default()
+            }],
+            fence,
+        );
+        let _ = self.wait_for_fences(&[fence], u64::MAX);
+
+        self.free_fence(fence);
+        self.free_command_buffers(pool, &buffers);
+        self.destroy_command_pool(pool);
+    }
+
     pub fn create_render_pass(&self, create_info: &vk::RenderPassCreateInfo) -> vk::RenderPass {
         unsafe {
             self.device
@@ -789,6 +1756,48 @@ impl Vulkan {
             |___/
     */
 
+    pub fn create_timestamp_query_pool(&self, count: u32) -> vk::QueryPool {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(count);
+
+        unsafe {
+            self.device
+                .create_query_pool(&create_info, self.allocation_callbacks.as_ref())
+        }
+        .expect("Out of memory")
+    }
+
+    pub fn destroy_query_pool(&self, pool: vk::QueryPool) {
+        unsafe {
+            self.device
+                .destroy_query_pool(pool, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    /// Reads the 64-bit results of queries `first..first + results.len()`.
+    /// Returns `false` if they aren't all available yet.
+    pub fn get_query_results(&self, pool: vk::QueryPool, first: u32, results: &mut [u64]) -> bool {
+        let r = unsafe {
+            self.device.fp_v1_0().get_query_pool_results(
+                self.device.handle(),
+                pool,
+                first,
+                results.len() as u32,
+                std::mem::size_of_val(results),
+                results.as_mut_ptr().cast(),
+                std::mem::size_of::<u64>() as vk::DeviceSize,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+
+        match r {
+            vk::Result::SUCCESS => true,
+            vk::Result::NOT_READY => false,
+            any => panic!("Unexpected error: {:?}", any),
+        }
+    }
+
     pub fn create_fence(&self, signalled: bool) -> vk::Fence {
         let ci = vk::FenceCreateInfo {
             flags: if signalled {
@@ -799,11 +1808,16 @@ impl Vulkan {
             ..Default::default()
         };
 
-        unsafe {
+        let fence = unsafe {
             self.device
                 .create_fence(&ci, self.allocation_callbacks.as_ref())
                 .expect("Out of memory")
-        }
+        };
+
+        self.sync_counters
+            .fences_created
+            .fetch_add(1, Ordering::Relaxed);
+        fence
     }
 
     pub fn free_fence(&self, fence: vk::Fence) {
@@ -813,6 +1827,10 @@ impl Vulkan {
             self.device
                 .destroy_fence(fence, self.allocation_callbacks.as_ref());
         }
+
+        self.sync_counters
+            .fences_freed
+            .fetch_add(1, Ordering::Relaxed);
     }
 
     /// `true` of success, `false` for time out
@@ -842,11 +1860,16 @@ impl Vulkan {
 
     pub fn create_semaphore(&self) -> vk::Semaphore {
         let ci = vk::SemaphoreCreateInfo::builder();
-        unsafe {
+        let semaphore = unsafe {
             self.device
                 .create_semaphore(&ci, self.allocation_callbacks.as_ref())
         }
-        .expect("Out of memory")
+        .expect("Out of memory");
+
+        self.sync_counters
+            .semaphores_created
+            .fetch_add(1, Ordering::Relaxed);
+        semaphore
     }
 
     pub fn free_semaphore(&self, semaphore: vk::Semaphore) {
@@ -854,22 +1877,79 @@ impl Vulkan {
             self.device
                 .destroy_semaphore(semaphore, self.allocation_callbacks.as_ref());
         }
+
+        self.sync_counters
+            .semaphores_freed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of fences and semaphores that currently exist.
+    pub fn sync_object_stats(&self) -> SyncStats {
+        self.sync_counters.stats()
+    }
+
+    /// Waits for the GPU to finish everything submitted to it, then checks in
+    /// debug builds that every fence and semaphore has been freed.
+    ///
+    /// This is also checked when a context is dropped, but the global context
+    /// never is, so call this once everything that rendered with it is gone.
+    pub fn shutdown(&self) {
+        {
+            let _queues = self.queue_lock.lock().unwrap();
+            // We're shutting down, so ignore errors
+            let _ = unsafe { self.device.device_wait_idle() };
+        }
+        self.check_sync_leaks();
+    }
+
+    fn check_sync_leaks(&self) {
+        let stats = self.sync_object_stats();
+        debug_assert!(
+            stats.live_fences == 0 && stats.live_semaphores == 0,
+            "fences or semaphores were leaked: {:?}",
+            stats
+        );
+    }
+
+    fn debug_user_data(&self) -> *mut c_void {
+        &*self.debug_callback as *const Option<DebugCallback> as *mut c_void
+    }
+
+    /// Creates or destroys the messenger that reports validation messages.
+    /// This has no effect unless the debug utils extension was enabled when
+    /// the context was created, which is always the case in debug builds.
+    pub fn set_debug_messenger_enabled(&self, enabled: bool) {
+        if let Some(debug) = self.debug.as_ref() {
+            debug.set_enabled(
+                enabled,
+                self.debug_user_data(),
+                self.allocation_callbacks.as_ref(),
+            );
+        }
     }
 }
 
 impl Drop for Vulkan {
     fn drop(&mut self) {
+        self.check_sync_leaks();
+
         unsafe {
             // We're shutting down, so ignore errors
             let _ = self.device.device_wait_idle();
 
             if let Some(debug) = self.debug.as_ref() {
-                debug.api.destroy_debug_utils_messenger(
-                    debug.callback,
+                debug.set_enabled(
+                    false,
+                    self.debug_user_data(),
                     self.allocation_callbacks.as_ref(),
                 );
             }
 
+            for sampler in self.samplers.take_all() {
+                self.device
+                    .destroy_sampler(sampler, self.allocation_callbacks.as_ref());
+            }
+
             self.device
                 .destroy_pipeline_cache(self.pipeline_cache, self.allocation_callbacks.as_ref());
             self.device
@@ -882,42 +1962,173 @@ impl Drop for Vulkan {
 
 unsafe extern "system" fn debug_callback(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    _message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
-    if severity < vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
-        return vk::FALSE;
+    let message = CStr::from_ptr((*callback_data).p_message);
+
+    match (user_data as *const Option<DebugCallback>).as_ref() {
+        Some(Some(callback)) => callback(
+            Severity::from_flags(severity),
+            MessageType::from_flags(message_type),
+            &message.to_string_lossy(),
+        ),
+        _ => {
+            if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
+                println!("Vulkan: {:?}", message);
+            }
+        }
     }
 
-    println!("Vulkan: {:?}", CStr::from_ptr((*callback_data).p_message));
-
     vk::FALSE
 }
 
+/// Whether `data` starts with a pipeline cache header
+/// (`VkPipelineCacheHeaderVersionOne`) written by the given GPU. Drivers are
+/// meant to reject caches from other devices themselves, but not all of them
+/// do so reliably.
+fn is_pipeline_cache_compatible(
+    data: &[u8],
+    vendor_id: u32,
+    device_id: u32,
+    uuid: &[u8; vk::UUID_SIZE],
+) -> bool {
+    const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+
+    // Unlike the rest of Vulkan, the header is always little-endian.
+    let read_u32 = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    read_u32(0) as usize >= HEADER_SIZE
+        && read_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(8) == vendor_id
+        && read_u32(12) == device_id
+        && data[16..HEADER_SIZE] == uuid[..]
+}
+
+/// Reinterprets SPIR-V bytes as the words that Vulkan expects, without
+/// copying. Both the length and the address must be multiples of 4.
+fn spirv_words(source: &[u8]) -> Result<&[u32], ShaderError> {
+    if source.len() % 4 == 0 && ((source.as_ptr() as usize) % 4) == 0 {
+        Ok(unsafe { std::slice::from_raw_parts(source.as_ptr().cast(), source.len() / 4) })
+    } else {
+        Err(ShaderError::Misaligned)
+    }
+}
+
+/// The number of images to ask for when creating a swapchain, given that
+/// `preferred` would be ideal. A `max_image_count` of 0 means that there is no
+/// maximum.
+fn min_swapchain_images(capabilities: &vk::SurfaceCapabilitiesKHR, preferred: u32) -> u32 {
+    if capabilities.max_image_count == 0 {
+        preferred.max(capabilities.min_image_count)
+    } else {
+        preferred.clamp(capabilities.min_image_count, capabilities.max_image_count)
+    }
+}
+
+/// The usage for a swapchain's images, which are always color attachments
+/// and may be used in the `requested` ways as well. Fails with
+/// `ERROR_FEATURE_NOT_PRESENT` if the surface doesn't support all of them.
+fn swapchain_image_usage(
+    requested: vk::ImageUsageFlags,
+    supported: vk::ImageUsageFlags,
+) -> VkResult<vk::ImageUsageFlags> {
+    if supported.contains(requested) {
+        Ok(requested | vk::ImageUsageFlags::COLOR_ATTACHMENT)
+    } else {
+        Err(vk::Result::ERROR_FEATURE_NOT_PRESENT)
+    }
+}
+
+/// Chooses how a swapchain's images are composited, from the modes in
+/// `supported`. Transparent swapchains prefer premultiplied alpha, which is
+/// what blending premultiplied colors produces, then straight alpha. If
+/// neither is supported, or `transparent` isn't set, the window is opaque.
+fn select_composite_alpha(
+    supported: vk::CompositeAlphaFlagsKHR,
+    transparent: bool,
+) -> vk::CompositeAlphaFlagsKHR {
+    let transparent_modes = [
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+    ];
+    let opaque_modes = [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+    ];
+
+    let preferred = if transparent {
+        &transparent_modes[..]
+    } else {
+        &[]
+    };
+
+    preferred
+        .iter()
+        .chain(&opaque_modes)
+        .copied()
+        .find(|mode| supported.contains(*mode))
+        // Surfaces must support at least one mode, so this is only reached if
+        // they support nothing that we know of.
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+}
+
+/// Chooses the format of a swapchain's images from those that the surface
+/// supports. The color spaces of `preference` are tried in order, falling
+/// back to 8-bit sRGB, and then to whatever the surface lists first.
+fn select_surface_format(
+    supported: &[vk::SurfaceFormatKHR],
+    preference: ColorSpacePreference,
+) -> vk::SurfaceFormatKHR {
+    const SRGB: (vk::Format, vk::ColorSpaceKHR) =
+        (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+
+    preference
+        .candidates()
+        .iter()
+        .chain([&SRGB])
+        .find_map(|&(format, color_space)| {
+            supported
+                .iter()
+                .copied()
+                .find(|f| f.format == format && f.color_space == color_space)
+        })
+        .unwrap_or(supported[0])
+}
+
 pub(crate) struct Gpu {
     pub handle: vk::PhysicalDevice,
     pub graphics_queue_index: u32,
     pub present_queue_index: u32,
+    /// The number of meaningful bits in timestamps written on the graphics
+    /// queue, or 0 if it can't write timestamps.
+    pub timestamp_valid_bits: u32,
 }
 
-fn select_physical_device(instance: &Instance, surface_api: &Win32Surface) -> Option<Gpu> {
-    let physical_devices = load_vk_objects::<_, _, MAX_PHYSICAL_DEVICES>(|count, ptr| unsafe {
+fn enumerate_physical_devices(
+    instance: &Instance,
+) -> ArrayVec<vk::PhysicalDevice, MAX_PHYSICAL_DEVICES> {
+    load_vk_objects::<_, _, MAX_PHYSICAL_DEVICES>(|count, ptr| unsafe {
         instance
             .fp_v1_0()
             .enumerate_physical_devices(instance.handle(), count, ptr)
-    });
+    })
+    .unwrap_or_else(|_| ArrayVec::new())
+}
 
-    let physical_devices = if let Ok(devices) = physical_devices {
-        if devices.is_empty() {
-            return None;
-        }
-        devices
-    } else {
-        return None;
-    };
+fn select_physical_device(
+    instance: &Instance,
+    surface_api: &Win32Surface,
+    preference: GpuPreference,
+) -> Option<Gpu> {
+    let mut candidates = vec![];
 
-    for physical_device in &physical_devices {
+    for (index, physical_device) in enumerate_physical_devices(instance).iter().enumerate() {
         let queue_families = load_vk_objects::<_, _, MAX_QUEUE_FAMILIES>(|count, ptr| {
             unsafe {
                 instance
@@ -946,16 +2157,47 @@ fn select_physical_device(instance: &Instance, surface_api: &Win32Surface) -> Op
             }
 
             if let Some((graphics_i, present_i)) = graphics.zip(present) {
-                return Some(Gpu {
-                    handle: *physical_device,
-                    graphics_queue_index: graphics_i.try_into().unwrap(),
-                    present_queue_index: present_i.try_into().unwrap(),
-                });
+                candidates.push((
+                    GpuInfo::new(instance, index, *physical_device),
+                    Gpu {
+                        handle: *physical_device,
+                        graphics_queue_index: graphics_i.try_into().unwrap(),
+                        present_queue_index: present_i.try_into().unwrap(),
+                        timestamp_valid_bits: queue_families[graphics_i].timestamp_valid_bits,
+                    },
+                ));
+                break;
             }
         }
     }
 
-    None
+    let infos = candidates
+        .iter()
+        .map(|(info, _)| info.clone())
+        .collect::<Vec<_>>();
+    let chosen = choose_gpu(&infos, preference)?;
+    Some(candidates.swap_remove(chosen).1)
+}
+
+/// Picks one of the suitable GPUs in `candidates` according to `preference`,
+/// returning its position in the slice. Falls back to the first GPU if none of
+/// them match the preference.
+fn choose_gpu(candidates: &[GpuInfo], preference: GpuPreference) -> Option<usize> {
+    let find_type = |device_type| {
+        candidates
+            .iter()
+            .position(|info| info.device_type == device_type)
+    };
+
+    let preferred = match preference {
+        GpuPreference::Any => None,
+        GpuPreference::HighPerformance => find_type(vk::PhysicalDeviceType::DISCRETE_GPU)
+            .or_else(|| find_type(vk::PhysicalDeviceType::INTEGRATED_GPU)),
+        GpuPreference::LowPower => find_type(vk::PhysicalDeviceType::INTEGRATED_GPU),
+        GpuPreference::Index(index) => candidates.iter().position(|info| info.index == index),
+    };
+
+    preferred.or_else(|| if candidates.is_empty() { None } else { Some(0) })
 }
 
 pub(crate) fn load_vk_objects<T, F, const COUNT: usize>(
@@ -976,3 +2218,528 @@ where
 
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::shared::VULKAN;
+
+    #[test]
+    fn sampler_cache() {
+        use ash::vk::Handle as _;
+
+        let mut cache = SamplerCache::default();
+        let mut created = 0;
+        let mut get = |cache: &SamplerCache, filter, wrap| {
+            cache.get_or_create(filter, wrap, || {
+                created += 1;
+                vk::Sampler::from_raw(created)
+            })
+        };
+
+        let linear = get(&cache, Filter::Linear, WrapMode::ClampToEdge);
+        assert_eq!(get(&cache, Filter::Linear, WrapMode::ClampToEdge), linear);
+
+        let nearest = get(&cache, Filter::Nearest, WrapMode::ClampToEdge);
+        let repeat = get(&cache, Filter::Linear, WrapMode::Repeat);
+        let mirrored = get(&cache, Filter::Linear, WrapMode::MirroredRepeat);
+        assert_ne!(nearest, linear);
+        assert_ne!(repeat, linear);
+        assert_ne!(mirrored, repeat);
+        assert_eq!(get(&cache, Filter::Nearest, WrapMode::ClampToEdge), nearest);
+
+        let mut all = cache.take_all();
+        all.sort_by_key(|sampler| sampler.as_raw());
+        assert_eq!(all, [linear, nearest, repeat, mirrored]);
+        assert!(cache.take_all().is_empty());
+    }
+
+    #[test]
+    fn pipeline_cache_header() {
+        let uuid = [7; vk::UUID_SIZE];
+        let header = |length: u32, version: u32, vendor_id: u32, device_id: u32, uuid: [u8; 16]| {
+            let mut data = vec![];
+            data.extend(length.to_le_bytes());
+            data.extend(version.to_le_bytes());
+            data.extend(vendor_id.to_le_bytes());
+            data.extend(device_id.to_le_bytes());
+            data.extend(uuid);
+            // Some driver-specific data.
+            data.extend([1, 2, 3, 4]);
+            data
+        };
+
+        let data = header(32, 1, 0x10DE, 0x2204, uuid);
+        assert!(is_pipeline_cache_compatible(&data, 0x10DE, 0x2204, &uuid));
+
+        // Another GPU, or another driver version for the same GPU.
+        assert!(!is_pipeline_cache_compatible(&data, 0x1002, 0x2204, &uuid));
+        assert!(!is_pipeline_cache_compatible(&data, 0x10DE, 0x2206, &uuid));
+        let mut other_uuid = uuid;
+        other_uuid[15] = 8;
+        assert!(!is_pipeline_cache_compatible(
+            &data,
+            0x10DE,
+            0x2204,
+            &other_uuid
+        ));
+
+        // Corrupt headers.
+        assert!(!is_pipeline_cache_compatible(
+            &data[..31],
+            0x10DE,
+            0x2204,
+            &uuid
+        ));
+        assert!(!is_pipeline_cache_compatible(&[], 0x10DE, 0x2204, &uuid));
+        let data = header(16, 1, 0x10DE, 0x2204, uuid);
+        assert!(!is_pipeline_cache_compatible(&data, 0x10DE, 0x2204, &uuid));
+        let data = header(32, 2, 0x10DE, 0x2204, uuid);
+        assert!(!is_pipeline_cache_compatible(&data, 0x10DE, 0x2204, &uuid));
+    }
+
+    #[test]
+    fn misaligned_spirv() {
+        let words = [0x0723_0203_u32, 0, 0, 0];
+        let bytes =
+            unsafe { std::slice::from_raw_parts(words.as_ptr().cast::<u8>(), words.len() * 4) };
+
+        assert_eq!(spirv_words(bytes).unwrap(), &words[..]);
+        assert_eq!(spirv_words(&bytes[4..]).unwrap(), &words[1..]);
+        assert!(spirv_words(&bytes[..0]).unwrap().is_empty());
+
+        // A misaligned address, a length that isn't a whole number of words,
+        // or both.
+        for source in [&bytes[1..9], &bytes[..6], &bytes[3..6]] {
+            let error = spirv_words(source).unwrap_err();
+            assert!(matches!(error, ShaderError::Misaligned));
+            assert_eq!(
+                error.to_string(),
+                "Shader source must be aligned to 4-byte words"
+            );
+        }
+    }
+
+    #[test]
+    fn acquire_results() {
+        assert_eq!(
+            AcquiredImage::from_result(Ok((2, false))),
+            Ok(AcquiredImage::Ready {
+                index: 2,
+                suboptimal: false
+            })
+        );
+        // Suboptimal images are still drawn to and presented.
+        assert_eq!(
+            AcquiredImage::from_result(Ok((1, true))),
+            Ok(AcquiredImage::Ready {
+                index: 1,
+                suboptimal: true
+            })
+        );
+        assert_eq!(
+            AcquiredImage::from_result(Err(vk::Result::ERROR_OUT_OF_DATE_KHR)),
+            Ok(AcquiredImage::OutOfDate)
+        );
+        for error in [
+            vk::Result::ERROR_DEVICE_LOST,
+            vk::Result::ERROR_SURFACE_LOST_KHR,
+        ] {
+            assert_eq!(AcquiredImage::from_result(Err(error)), Err(error));
+        }
+    }
+
+    fn gpu(index: usize, device_type: vk::PhysicalDeviceType) -> GpuInfo {
+        GpuInfo {
+            index,
+            name: format!("GPU {}", index),
+            device_type,
+        }
+    }
+
+    #[test]
+    fn swapchain_image_count() {
+        let capabilities = |min_image_count, max_image_count| vk::SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        };
+
+        let default = DEFAULT_SWAPCHAIN_LENGTH;
+        assert_eq!(min_swapchain_images(&capabilities(1, 3), default), 2);
+        assert_eq!(min_swapchain_images(&capabilities(3, 8), default), 3);
+        assert_eq!(min_swapchain_images(&capabilities(1, 1), default), 1);
+        assert_eq!(min_swapchain_images(&capabilities(1, 0), default), 2);
+        assert_eq!(min_swapchain_images(&capabilities(4, 0), default), 4);
+    }
+
+    #[test]
+    fn swapchain_usage() {
+        type Usage = vk::ImageUsageFlags;
+        let supported = Usage::COLOR_ATTACHMENT | Usage::TRANSFER_SRC;
+
+        assert_eq!(
+            swapchain_image_usage(Usage::empty(), supported),
+            Ok(Usage::COLOR_ATTACHMENT)
+        );
+        assert_eq!(
+            swapchain_image_usage(Usage::TRANSFER_SRC, supported),
+            Ok(Usage::COLOR_ATTACHMENT | Usage::TRANSFER_SRC)
+        );
+
+        // Unsupported usage is an error rather than being dropped.
+        assert_eq!(
+            swapchain_image_usage(Usage::TRANSFER_SRC | Usage::STORAGE, supported),
+            Err(vk::Result::ERROR_FEATURE_NOT_PRESENT)
+        );
+    }
+
+    #[test]
+    fn preferred_swapchain_length() {
+        let capabilities = |min_image_count, max_image_count| vk::SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        };
+
+        // Triple buffering, where it's allowed.
+        assert_eq!(min_swapchain_images(&capabilities(2, 3), 3), 3);
+        assert_eq!(min_swapchain_images(&capabilities(1, 8), 3), 3);
+        assert_eq!(min_swapchain_images(&capabilities(1, 2), 3), 2);
+        assert_eq!(min_swapchain_images(&capabilities(4, 8), 3), 4);
+
+        // No maximum.
+        assert_eq!(min_swapchain_images(&capabilities(2, 0), 3), 3);
+        assert_eq!(min_swapchain_images(&capabilities(2, 0), 6), 6);
+        assert_eq!(min_swapchain_images(&capabilities(5, 0), 3), 5);
+
+        // Fewer than the minimum is never asked for.
+        assert_eq!(min_swapchain_images(&capabilities(2, 0), 1), 2);
+        assert_eq!(min_swapchain_images(&capabilities(2, 4), 0), 2);
+    }
+
+    #[test]
+    fn debug_message_flags() {
+        type S = vk::DebugUtilsMessageSeverityFlagsEXT;
+        assert_eq!(Severity::from_flags(S::VERBOSE), Severity::Verbose);
+        assert_eq!(Severity::from_flags(S::INFO), Severity::Info);
+        assert_eq!(Severity::from_flags(S::WARNING), Severity::Warning);
+        assert_eq!(Severity::from_flags(S::ERROR), Severity::Error);
+        assert_eq!(Severity::from_flags(S::INFO | S::ERROR), Severity::Error);
+        assert_eq!(Severity::from_flags(S::empty()), Severity::Verbose);
+
+        type T = vk::DebugUtilsMessageTypeFlagsEXT;
+        assert_eq!(MessageType::from_flags(T::GENERAL), MessageType::General);
+        assert_eq!(
+            MessageType::from_flags(T::VALIDATION),
+            MessageType::Validation
+        );
+        assert_eq!(
+            MessageType::from_flags(T::PERFORMANCE),
+            MessageType::Performance
+        );
+        assert_eq!(
+            MessageType::from_flags(T::PERFORMANCE | T::VALIDATION),
+            MessageType::Validation
+        );
+    }
+
+    #[test]
+    fn debug_messenger_toggle() {
+        let created = std::cell::Cell::new(0);
+        let destroyed = std::cell::Cell::new(0);
+        let mut messenger = None;
+
+        let mut toggle = |enabled| {
+            toggle_messenger(
+                &mut messenger,
+                enabled,
+                || {
+                    created.set(created.get() + 1);
+                    created.get()
+                },
+                |_| destroyed.set(destroyed.get() + 1),
+            );
+        };
+
+        toggle(true);
+        toggle(true);
+        assert_eq!((created.get(), destroyed.get()), (1, 0));
+
+        toggle(false);
+        toggle(false);
+        assert_eq!((created.get(), destroyed.get()), (1, 1));
+
+        toggle(true);
+        assert_eq!((created.get(), destroyed.get()), (2, 1));
+        assert_eq!(messenger, Some(2));
+    }
+
+    #[test]
+    fn sync_stats_balance() {
+        let library = match Library::load("vulkan-1") {
+            Ok(library) => library,
+            Err(_) => return,
+        };
+
+        // A context of its own, so that other tests' fences aren't counted.
+        let vulkan = Vulkan::new(library, false, GpuPreference::Any, None, None);
+        let fences = [vulkan.create_fence(false), vulkan.create_fence(true)];
+        let semaphore = vulkan.create_semaphore();
+        vulkan.free_fence(fences[0]);
+
+        assert_eq!(
+            vulkan.sync_object_stats(),
+            SyncStats {
+                live_fences: 1,
+                pooled_fences: 1,
+                live_semaphores: 1,
+                pooled_semaphores: 0,
+            }
+        );
+
+        vulkan.free_fence(fences[1]);
+        vulkan.free_semaphore(semaphore);
+
+        let stats = vulkan.sync_object_stats();
+        assert_eq!((stats.live_fences, stats.live_semaphores), (0, 0));
+        assert_eq!((stats.pooled_fences, stats.pooled_semaphores), (2, 1));
+
+        // Dropping the context checks for leaks, which would panic here.
+        vulkan.shutdown();
+    }
+
+    #[test]
+    fn gpu_preference() {
+        let gpus = [
+            gpu(0, vk::PhysicalDeviceType::INTEGRATED_GPU),
+            gpu(1, vk::PhysicalDeviceType::CPU),
+            gpu(2, vk::PhysicalDeviceType::DISCRETE_GPU),
+        ];
+
+        assert_eq!(choose_gpu(&gpus, GpuPreference::Any), Some(0));
+        assert_eq!(choose_gpu(&gpus, GpuPreference::HighPerformance), Some(2));
+        assert_eq!(choose_gpu(&gpus, GpuPreference::LowPower), Some(0));
+        assert_eq!(choose_gpu(&gpus, GpuPreference::Index(1)), Some(1));
+
+        // Preferences that can't be met fall back to the first GPU.
+        assert_eq!(choose_gpu(&gpus[1..], GpuPreference::LowPower), Some(0));
+        assert_eq!(choose_gpu(&gpus, GpuPreference::Index(7)), Some(0));
+        assert_eq!(choose_gpu(&[], GpuPreference::HighPerformance), None);
+    }
+
+    #[test]
+    fn upload_round_trip() {
+        // Needs a GPU, and so can't run everywhere.
+        if Library::load("vulkan-1").is_err() {
+            return;
+        }
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        let size = data.len() as vk::DeviceSize;
+        let (buffer, memory) = VULKAN.upload_to_device_local(
+            &data,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+
+        let (readback, readback_memory) = VULKAN.create_bound_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        VULKAN.submit_and_wait(|cmd| {
+            cmd.copy_buffer(
+                buffer,
+                readback,
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size,
+                }],
+            );
+        });
+
+        let read = unsafe {
+            let mapped = VULKAN.map_memory(readback_memory, 0, size, vk::MemoryMapFlags::empty());
+            let read = std::slice::from_raw_parts(mapped.cast::<u8>(), data.len()).to_vec();
+            VULKAN.unmap_memory(readback_memory);
+            read
+        };
+        assert_eq!(read, data);
+
+        VULKAN.destroy_buffer(readback);
+        VULKAN.free(readback_memory);
+        VULKAN.destroy_buffer(buffer);
+        VULKAN.free(memory);
+
+        // Nothing is created for empty data.
+        let (buffer, memory) =
+            VULKAN.upload_to_device_local(&[], vk::BufferUsageFlags::VERTEX_BUFFER);
+        assert_eq!(buffer, vk::Buffer::null());
+        assert_eq!(memory, vk::DeviceMemory::null());
+        VULKAN.destroy_buffer(buffer);
+        VULKAN.free(memory);
+    }
+
+    #[test]
+    fn surface_format_selection() {
+        let format = |format, color_space| vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        };
+        let bgra_srgb = format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        let bgra_unorm = format(
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        let scrgb = format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        );
+        let hdr10_float = format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let hdr10 = format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+
+        let hdr_display = [bgra_unorm, bgra_srgb, scrgb, hdr10_float, hdr10];
+        let is_hdr = |color_space| {
+            SwapchainData {
+                color_space,
+                ..SwapchainData::default()
+            }
+            .is_hdr()
+        };
+        assert!(!is_hdr(vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT));
+        assert!(!is_hdr(vk::ColorSpaceKHR::BT709_LINEAR_EXT));
+        assert!(is_hdr(vk::ColorSpaceKHR::HDR10_HLG_EXT));
+
+        let select = |supported: &[_], preference| {
+            let chosen = select_surface_format(supported, preference);
+            let data = SwapchainData {
+                color_space: chosen.color_space,
+                ..SwapchainData::default()
+            };
+            (chosen, data.is_hdr())
+        };
+
+        assert_eq!(
+            select(&hdr_display, ColorSpacePreference::Srgb),
+            (bgra_srgb, false)
+        );
+        assert_eq!(
+            select(&hdr_display, ColorSpacePreference::ExtendedSrgbLinear),
+            (scrgb, true)
+        );
+        // 10-bit formats are preferred to floats for HDR10.
+        assert_eq!(
+            select(&hdr_display, ColorSpacePreference::Hdr10),
+            (hdr10, true)
+        );
+        assert_eq!(
+            select(&[bgra_srgb, hdr10_float], ColorSpacePreference::Hdr10),
+            (hdr10_float, true)
+        );
+
+        // SDR displays fall back to sRGB, or whatever they support.
+        let sdr_display = [bgra_unorm, bgra_srgb];
+        assert_eq!(
+            select(&sdr_display, ColorSpacePreference::Hdr10),
+            (bgra_srgb, false)
+        );
+        assert_eq!(
+            select(&[bgra_unorm], ColorSpacePreference::ExtendedSrgbLinear),
+            (bgra_unorm, false)
+        );
+    }
+
+    #[test]
+    fn composite_alpha_selection() {
+        type Alpha = vk::CompositeAlphaFlagsKHR;
+
+        // Opaque windows stay opaque, whatever else is supported.
+        assert_eq!(select_composite_alpha(Alpha::all(), false), Alpha::OPAQUE);
+        assert_eq!(
+            select_composite_alpha(Alpha::INHERIT | Alpha::PRE_MULTIPLIED, false),
+            Alpha::INHERIT
+        );
+
+        // Premultiplied alpha is preferred, then straight alpha.
+        assert_eq!(
+            select_composite_alpha(Alpha::all(), true),
+            Alpha::PRE_MULTIPLIED
+        );
+        assert_eq!(
+            select_composite_alpha(Alpha::OPAQUE | Alpha::POST_MULTIPLIED, true),
+            Alpha::POST_MULTIPLIED
+        );
+
+        // Without either, transparent windows fall back to opaque.
+        assert_eq!(select_composite_alpha(Alpha::OPAQUE, true), Alpha::OPAQUE);
+        assert_eq!(select_composite_alpha(Alpha::INHERIT, true), Alpha::INHERIT);
+        assert_eq!(select_composite_alpha(Alpha::empty(), true), Alpha::OPAQUE);
+    }
+
+    #[test]
+    fn queue_family_sharing_mode() {
+        let same = QueueFamilies::new(0, 0);
+        assert!(same.same);
+        assert_eq!(same.sharing_mode(false), vk::SharingMode::EXCLUSIVE);
+        assert_eq!(same.sharing_mode(true), vk::SharingMode::EXCLUSIVE);
+        assert!(!same.needs_ownership_transfer(vk::SharingMode::EXCLUSIVE));
+
+        let split = QueueFamilies::new(0, 2);
+        assert!(!split.same);
+        assert_eq!(split.sharing_mode(false), vk::SharingMode::CONCURRENT);
+        assert!(!split.needs_ownership_transfer(vk::SharingMode::CONCURRENT));
+
+        // Forcing exclusive mode makes ownership transfers necessary.
+        assert_eq!(split.sharing_mode(true), vk::SharingMode::EXCLUSIVE);
+        assert!(split.needs_ownership_transfer(vk::SharingMode::EXCLUSIVE));
+    }
+
+    #[test]
+    fn debug_messages_reach_callback() {
+        use std::sync::{Arc, Mutex};
+
+        let received = Arc::new(Mutex::new(vec![]));
+        let callback: Option<DebugCallback> = Some(Box::new({
+            let received = received.clone();
+            move |severity, message_type, message: &str| {
+                received
+                    .lock()
+                    .unwrap()
+                    .push((severity, message_type, message.to_string()))
+            }
+        }));
+
+        let message = CStr::from_bytes_with_nul(b"bad things\0").unwrap();
+        let data = vk::DebugUtilsMessengerCallbackDataEXT {
+            p_message: message.as_ptr(),
+            ..Default::default()
+        };
+        // Called the way the debug messenger would call it.
+        let result = unsafe {
+            debug_callback(
+                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                &data,
+                &callback as *const Option<DebugCallback> as *mut c_void,
+            )
+        };
+
+        assert_eq!(result, vk::FALSE);
+        assert_eq!(
+            *received.lock().unwrap(),
+            [(
+                Severity::Warning,
+                MessageType::Validation,
+                "bad things".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Rendering for several windows at once. Each window is drawn independently,
+//! but their command buffers are submitted to the graphics queue together and
+//! presented with a single call.
+
+use std::{collections::HashMap, hash::Hash};
+
+use ash::vk;
+
+use super::{
+    canvas::{DrawBatch, Indices},
+    context::RendererWindow,
+    executor::Executor,
+    shared::{PresentTransfer, Request, Vertex},
+};
+use crate::{error::Error, shapes::Extent, sys::WindowId};
+
+/// A set of [`RendererWindow`]s that share one submission to the graphics
+/// queue per frame.
+pub struct RendererWindowSet<K = WindowId> {
+    windows: HashMap<K, RendererWindow>,
+    batch: SubmitBatch,
+}
+
+impl<K: Hash + Eq> RendererWindowSet<K> {
+    pub fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+            batch: SubmitBatch::default(),
+        }
+    }
+
+    /// Adds a window to the set, returning the window it replaced, if any.
+    pub fn insert(&mut self, key: K, window: RendererWindow) -> Option<RendererWindow> {
+        self.windows.insert(key, window)
+    }
+
+    /// Removes a window from the set. Any frame that it drew but that has not
+    /// been submitted yet is discarded, while those of the other windows are
+    /// kept for the next [`submit()`](Self::submit).
+    pub fn remove(&mut self, key: &K) -> Option<RendererWindow> {
+        let window = self.windows.remove(key)?;
+        self.batch.remove_swapchain(window.swapchain_handle());
+        Some(window)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut RendererWindow> {
+        self.windows.get_mut(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Records a frame for the window identified by `key`, resizing it first
+    /// if necessary. The frame is not submitted until [`submit()`] is called.
+    ///
+    /// Returns `false` if there is no such window, or if it could not be drawn
+    /// to this frame.
+    ///
+    /// [`submit()`]: Self::submit
+    pub fn draw(
+        &mut self,
+        key: &K,
+        window_size: Extent,
+        vertices: &[Vertex],
+        indices: Indices,
+        batches: &[DrawBatch],
+    ) -> Result<bool, Error> {
+        let window = match self.windows.get_mut(key) {
+            Some(window) => window,
+            None => return Ok(false),
+        };
+
+        match window.draw(window_size, vertices, indices, batches, None)? {
+            Some(request) => {
+                self.batch.push(&request);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Submits every frame drawn since the last call, and presents them.
+    pub fn submit(&mut self, executor: &mut Executor) {
+        if !self.batch.is_empty() {
+            executor.execute_batch(&self.batch);
+            self.batch.clear();
+        }
+    }
+}
+
+impl<K: Hash + Eq> Default for RendererWindowSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The frames from several windows, collected so that they can be submitted
+/// and presented together.
+#[derive(Debug, Default)]
+pub struct SubmitBatch {
+    pub wait_semaphores: Vec<vk::Semaphore>,
+    pub signal_semaphores: Vec<vk::Semaphore>,
+    pub commands: Vec<vk::CommandBuffer>,
+    pub fences: Vec<vk::Fence>,
+    pub swapchains: Vec<vk::SwapchainKHR>,
+    pub image_ids: Vec<u32>,
+    pub present_transfers: Vec<Option<PresentTransfer>>,
+}
+
+impl SubmitBatch {
+    pub fn push(&mut self, request: &Request) {
+        match *request {
+            Request::SubmitCommands {
+                wait_semaphore,
+                signal_semaphore,
+                commands,
+                fence,
+                swapchain,
+                image_id,
+                present_transfer,
+            } => {
+                self.wait_semaphores.push(wait_semaphore);
+                self.signal_semaphores.push(signal_semaphore);
+                self.commands.push(commands);
+                self.fences.push(fence);
+                self.swapchains.push(swapchain);
+                self.image_ids.push(image_id);
+                self.present_transfers.push(present_transfer);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Removes the frames that would be presented to `swapchain`.
+    pub fn remove_swapchain(&mut self, swapchain: vk::SwapchainKHR) {
+        for i in (0..self.len()).rev() {
+            if self.swapchains[i] == swapchain {
+                self.wait_semaphores.remove(i);
+                self.signal_semaphores.remove(i);
+                self.commands.remove(i);
+                self.fences.remove(i);
+                self.swapchains.remove(i);
+                self.image_ids.remove(i);
+                self.present_transfers.remove(i);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.wait_semaphores.clear();
+        self.signal_semaphores.clear();
+        self.commands.clear();
+        self.fences.clear();
+        self.swapchains.clear();
+        self.image_ids.clear();
+        self.present_transfers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle as _;
+
+    use super::*;
+
+    fn request(id: u64) -> Request {
+        Request::SubmitCommands {
+            wait_semaphore: vk::Semaphore::from_raw(id),
+            signal_semaphore: vk::Semaphore::from_raw(id + 100),
+            commands: vk::CommandBuffer::from_raw(id),
+            fence: vk::Fence::from_raw(id),
+            swapchain: vk::SwapchainKHR::from_raw(id),
+            image_id: id as u32,
+            present_transfer: None,
+        }
+    }
+
+    #[test]
+    fn batch_collects_every_window() {
+        // The windows themselves need a GPU, so stand in for them with the
+        // requests they would have produced.
+        let mut batch = SubmitBatch::default();
+        batch.push(&request(1));
+        batch.push(&request(2));
+        batch.push(&request(3));
+
+        assert_eq!(batch.len(), 3);
+        assert_eq!(
+            batch.signal_semaphores,
+            [
+                vk::Semaphore::from_raw(101),
+                vk::Semaphore::from_raw(102),
+                vk::Semaphore::from_raw(103)
+            ]
+        );
+
+        // Removing one window keeps the frames that the others have already
+        // acquired images for.
+        batch.remove_swapchain(vk::SwapchainKHR::from_raw(2));
+        assert_eq!(
+            batch.commands,
+            [
+                vk::CommandBuffer::from_raw(1),
+                vk::CommandBuffer::from_raw(3)
+            ]
+        );
+        assert_eq!(
+            batch.wait_semaphores,
+            [vk::Semaphore::from_raw(1), vk::Semaphore::from_raw(3)]
+        );
+        assert_eq!(
+            batch.swapchains,
+            [vk::SwapchainKHR::from_raw(1), vk::SwapchainKHR::from_raw(3)]
+        );
+        assert_eq!(batch.image_ids, [1, 3]);
+        assert_eq!(batch.fences.len(), 2);
+        assert_eq!(batch.present_transfers.len(), 2);
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+}
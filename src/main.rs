@@ -1,4 +1,5 @@
 mod array_vec;
+mod error;
 mod gfx;
 mod px;
 mod registry;
@@ -10,11 +11,11 @@ mod ui;
 use std::time::Instant;
 
 use gfx::{Canvas, CanvasStorage, DrawStyled, RendererWindow};
-use px::Px;
+use px::{Logical, Physical, Px};
 use registry::named::StrOps;
 use shapes::Extent;
-use sys::{ButtonState, EventLoopControl, InputEvent, MouseButton, WindowEvent};
-use ui::Layout;
+use sys::{ButtonState, EventLoopControl, InputEvent, Key, MouseButton, WindowEvent};
+use ui::{Layout, TextInput};
 
 #[derive(Debug)]
 struct CliOptions {}
@@ -34,15 +35,24 @@ fn run() {
     let mut registry = registry::named::Registry::new();
     let mut ui_context = ui::Context::default();
     let mut ui_command_buffer = vec![];
+    let mut text = String::new();
 
     registry.set("slider", 0.5_f32).unwrap();
-    spawn_window("Title 1", |inputs, canvas| {
+    let result = spawn_window("Title 1", |inputs, canvas, control| {
+        // The UI is laid out in logical pixels, and converted to the window's
+        // physical pixels when it is drawn.
+        let scale = control.scale_factor();
+        let size = canvas.size().to_logical(scale);
+
         for input in inputs {
-            let input_handler = ui_context.begin(canvas.size(), &mut ui_command_buffer);
+            let input_handler = ui_context.begin(size, &mut ui_command_buffer);
 
             let mut ui = match input {
                 InputEvent::None => input_handler.no_input(),
-                InputEvent::CursorMove { position } => input_handler.move_cursor(*position),
+                InputEvent::CursorMove { position } => {
+                    input_handler.move_cursor(Physical(*position).to_logical(scale).0)
+                }
+                InputEvent::ScrollWheel { x, y, .. } => input_handler.scroll(*x, *y),
                 InputEvent::MouseButton { button, state } => {
                     if *button == MouseButton::Left {
                         input_handler.lmb_pressed(*state == ButtonState::Pressed)
@@ -50,52 +60,124 @@ fn run() {
                         continue;
                     }
                 }
+                InputEvent::Key {
+                    key: Key::Tab,
+                    state: ButtonState::Pressed,
+                    modifiers,
+                } => {
+                    if modifiers.shift {
+                        input_handler.focus_previous()
+                    } else {
+                        input_handler.focus_next()
+                    }
+                }
+                InputEvent::Key {
+                    key,
+                    state: ButtonState::Pressed,
+                    modifiers,
+                } => input_handler.text_input(match key {
+                    Key::Backspace => TextInput::Backspace,
+                    Key::Delete => TextInput::Delete,
+                    Key::Left => TextInput::Left,
+                    Key::Right => TextInput::Right,
+                    Key::Home => TextInput::Home,
+                    Key::End => TextInput::End,
+                    Key::Letter('V') if modifiers.ctrl => match sys::clipboard_get_text() {
+                        Some(text) => TextInput::Paste(text),
+                        None => continue,
+                    },
+                    _ => continue,
+                }),
+                InputEvent::Char { codepoint } => {
+                    input_handler.text_input(TextInput::Char(*codepoint))
+                }
+                InputEvent::ImeCommit { text } => {
+                    input_handler.text_input(TextInput::Paste(text.clone()))
+                }
                 _ => continue,
             };
 
-            {
-                let mut rows = ui.top_to_bottom(Px(10));
-                rows.button("a");
-                {
-                    let mut columns = rows.layout_columns(2, Px(20));
-                    columns.button("b");
-                    columns.button("c");
-                }
-                {
-                    let mut columns = rows.layout_columns(3, Px(20));
-                    columns.button("d");
-                    {
-                        let mut rows = columns.layout_rows(Px(10));
-                        if rows.button("e").is_active() {
-                            rows.button("f");
-                            rows.button("g");
-                        }
-                    }
-                    columns.smooth_slider("h", registry.get_mut("slider").unwrap())
-                }
-                rows.button("i");
-            }
+            build_ui(&mut ui, registry.get_mut("slider").unwrap(), &mut text);
 
             if *input == InputEvent::None {
-                canvas.clear();
-                for command in ui.build() {
+                let commands = ui.build();
+                // The same commands draw differently if the scale changed.
+                if canvas.reuse_if_unchanged(&(scale.to_bits(), &commands[..])) {
+                    continue;
+                }
+
+                let to_physical = |rect: &shapes::Rect| Logical(*rect).to_physical(scale).0;
+                for command in commands.iter() {
                     match command {
                         ui::DrawCommand::ColoredRect { rect, color } => {
-                            canvas.draw_styled(rect, *color)
+                            canvas.draw_styled(&to_physical(rect), *color)
                         }
+                        ui::DrawCommand::RoundedRect {
+                            rect,
+                            radius,
+                            color,
+                        } => canvas.draw_rounded(&to_physical(rect), *radius * scale, *color),
+                        ui::DrawCommand::PushClip { rect } => canvas.push_clip(to_physical(rect)),
+                        ui::DrawCommand::PopClip => canvas.pop_clip(),
                     }
                 }
             }
         }
+
+        // Keep receiving mouse input during a drag, even if the cursor leaves
+        // the window.
+        control.set_mouse_capture(ui_context.wants_mouse_capture());
     });
     registry.remove("slider").unwrap();
+
+    if let Err(error) = result {
+        println!("{}", error);
+    }
+
+    if let Err(error) = gfx::save_pipeline_cache() {
+        println!("Could not save the pipeline cache: {}", error);
+    }
+}
+
+fn build_ui(ui: &mut ui::Builder, slider: &mut f32, text: &mut String) {
+    let mut rows = ui.top_to_bottom(Px(10));
+    rows.button("a");
+    {
+        let mut columns = rows.layout_columns(2, Px(20));
+        columns.button("b");
+        columns.button("c");
+    }
+    {
+        let mut columns = rows.layout_columns(3, Px(20));
+        columns.button("d");
+        {
+            let mut rows = columns.layout_rows(Px(10));
+            if rows.button("e").is_active() {
+                rows.button("f");
+                rows.button("g");
+            }
+        }
+        columns.smooth_slider("h", slider)
+    }
+    rows.rounded_button("i", 6.0);
+    rows.text_edit("n", text);
+    {
+        let mut area = rows.scroll_area("j", Px(50), Px(10));
+        area.button("k");
+        area.button("l");
+        area.button("m");
+    }
 }
 
 /// Always calls ui_callback with at least one event. If no inputs were received
 /// since the last call, the [`InputEvent::None`](sys::input::Event) event is
 /// used.
-pub fn spawn_window(title: &str, mut ui_callback: impl FnMut(&[InputEvent], &mut Canvas)) {
+pub fn spawn_window(
+    title: &str,
+    mut ui_callback: impl FnMut(&[InputEvent], &mut Canvas, &mut dyn sys::Control),
+) -> Result<(), error::Error> {
     let mut context = None;
+    let mut render_error = None;
     let mut renderer = gfx::Executor::new();
     let mut inputs = vec![];
 
@@ -104,38 +186,55 @@ pub fn spawn_window(title: &str, mut ui_callback: impl FnMut(&[InputEvent], &mut
     sys::window(title, |control, event| {
         match event {
             WindowEvent::Created { size } => {
-                control.set_min_size(Extent::new(Px(100), Px(100)));
-                context = Some(RendererWindow::new(control.handle(), size));
+                let min_size = Logical(Extent::new(Px(100), Px(100)));
+                control.set_min_size(min_size.to_physical(control.scale_factor()));
+                match RendererWindow::new(control.handle(), size) {
+                    Ok(window) => context = Some(window),
+                    Err(error) => {
+                        render_error = Some(error);
+                        return EventLoopControl::Stop;
+                    }
+                }
             }
-            WindowEvent::Destroyed {} => {}
-            WindowEvent::CloseRequested {} => {
+            WindowEvent::Destroyed {}
+            | WindowEvent::FocusGained {}
+            | WindowEvent::FocusLost {}
+            | WindowEvent::FilesDropped { .. }
+            | WindowEvent::Timer { .. } => {}
+            WindowEvent::CloseRequested {} | WindowEvent::QuitRequested {} => {
                 return EventLoopControl::Stop;
             }
             WindowEvent::Input(event) => {
                 inputs.push(event);
+                control.request_redraw();
             }
-            WindowEvent::Update { size, resized } => {
-                if size == Extent::default() {
+            WindowEvent::Update { size, resized, .. } => {
+                if size.0 == Extent::default() {
                     println!("oops");
+                    if let Some(context) = context.as_mut() {
+                        context.skip_frame();
+                    }
                 }
-                if size != Extent::default() {
+                if size.0 != Extent::default() {
                     let update_start = Instant::now();
                     inputs.push(InputEvent::None);
 
                     let mut canvas = Canvas::new(size, &mut canvas_storage);
-                    ui_callback(&inputs, &mut canvas);
+                    ui_callback(&inputs, &mut canvas, control);
                     inputs.clear();
 
                     let ui_time = Instant::now() - update_start;
 
                     let draw_start = Instant::now();
-                    if let Some(request) =
-                        context
-                            .as_mut()
-                            .unwrap()
-                            .draw(size, canvas.vertices(), canvas.indices())
-                    {
-                        let _ = renderer.execute(&request);
+                    match context.as_mut().unwrap().draw_canvas(&canvas) {
+                        Ok(Some(request)) => {
+                            let _ = renderer.execute(&request);
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            render_error = Some(error);
+                            return EventLoopControl::Stop;
+                        }
                     }
 
                     let draw_time = Instant::now() - draw_start;
@@ -154,5 +253,54 @@ pub fn spawn_window(title: &str, mut ui_callback: impl FnMut(&[InputEvent], &mut
             }
         }
         EventLoopControl::Continue
-    });
+    })?;
+
+    std::mem::drop(context);
+    renderer.shutdown();
+
+    match render_error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ui_commands_are_self_contained() {
+        let size = Extent::new(Px(400), Px(300));
+        let bounds = shapes::Rect::from_extent(Px(0), Px(0), size);
+
+        let mut context = ui::Context::default();
+        let mut commands = vec![];
+        let mut ui = context.begin(Logical(size), &mut commands).no_input();
+        build_ui(&mut ui, &mut 0.5, &mut String::from("text"));
+        let commands = ui.build().clone();
+
+        let rects = commands
+            .iter()
+            .filter_map(|command| match command {
+                ui::DrawCommand::ColoredRect { rect, .. }
+                | ui::DrawCommand::RoundedRect { rect, .. } => Some(*rect),
+                ui::DrawCommand::PushClip { .. } | ui::DrawCommand::PopClip => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!rects.is_empty());
+        assert!(rects.iter().all(|rect| bounds.contains_rect(*rect)));
+
+        // Buttons are 20px tall. The slider's track and the text's glyphs
+        // overlap the widgets they belong to, but are shorter.
+        let buttons = rects
+            .iter()
+            .filter(|rect| rect.height() == Px(20))
+            .collect::<Vec<_>>();
+        assert!(buttons.len() >= 8);
+        for (i, a) in buttons.iter().enumerate() {
+            for b in &buttons[i + 1..] {
+                assert_eq!(a.intersect(**b), None, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
 }
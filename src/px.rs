@@ -1,61 +1,93 @@
-//! This module defines the [`Px`] device-independent pixel unit type for UI
-//! layout and sizing.
+//! This module defines the [`Px`] pixel unit type for UI layout and sizing,
+//! and the [`Logical`] and [`Physical`] wrappers that tell device-independent
+//! and physical pixels apart.
 //!
 
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Rem, RemAssign, Sub, SubAssign};
 
-/// A device-independent pixel.
+/// A pixel. Layout code works in device-independent pixels; wrap values in
+/// [`Logical`] or [`Physical`] where both kinds meet.
 ///
 /// Note: Multiplication of a pixel by another pixel is not defined.
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Px(pub i16);
 
 impl Px {
     pub const MAX: Self = Px(i16::MAX);
+    pub const MIN: Self = Px(i16::MIN);
+
+    /// Adds two pixel values, clamping the result to [`Px::MIN`] and
+    /// [`Px::MAX`] instead of overflowing.
+    pub const fn saturating_add(self, rhs: Self) -> Self {
+        Px(self.0.saturating_add(rhs.0))
+    }
+
+    pub const fn saturating_sub(self, rhs: Self) -> Self {
+        Px(self.0.saturating_sub(rhs.0))
+    }
+
+    pub const fn max(self, other: Self) -> Self {
+        if self.0 >= other.0 {
+            self
+        } else {
+            other
+        }
+    }
+
+    pub const fn min(self, other: Self) -> Self {
+        if self.0 <= other.0 {
+            self
+        } else {
+            other
+        }
+    }
 }
 
+// Addition, subtraction, multiplication, and division saturate at the bounds
+// of the backing integer, since an overflowing layout is better than a panic
+// or a wrapped-around negative size.
 macro_rules! impl_bin_op {
-    ($trait:ident, $lhs: ty, $rhs:ty, $func:ident, $extract_left:expr, $extract_right:expr) => {
+    ($trait:ident, $lhs: ty, $rhs:ty, $func:ident, $op:ident, $extract_left:expr, $extract_right:expr) => {
         impl $trait<$rhs> for $lhs {
             type Output = Px;
 
             fn $func(self, rhs: $rhs) -> Self::Output {
-                Px($extract_left(self).$func($extract_right(rhs)))
+                Px($extract_left(self).$op($extract_right(rhs)))
             }
         }
     };
 }
 
-impl_bin_op!(Add, Px, Self, add, |v: Px| v.0, |v: Px| v.0);
-impl_bin_op!(Sub, Px, Self, sub, |v: Px| v.0, |v: Px| v.0);
-impl_bin_op!(Div, Px, Self, div, |v: Px| v.0, |v: Px| v.0);
-impl_bin_op!(Rem, Px, Self, rem, |v: Px| v.0, |v: Px| v.0);
+impl_bin_op!(Add, Px, Self, add, saturating_add, |v: Px| v.0, |v: Px| v.0);
+impl_bin_op!(Sub, Px, Self, sub, saturating_sub, |v: Px| v.0, |v: Px| v.0);
+impl_bin_op!(Div, Px, Self, div, saturating_div, |v: Px| v.0, |v: Px| v.0);
+impl_bin_op!(Rem, Px, Self, rem, rem, |v: Px| v.0, |v: Px| v.0);
 
-impl_bin_op!(Mul, Px, i16, mul, |v: Px| v.0, |v: i16| v);
-impl_bin_op!(Div, Px, i16, div, |v: Px| v.0, |v: i16| v);
-impl_bin_op!(Rem, Px, i16, rem, |v: Px| v.0, |v: i16| v);
-impl_bin_op!(Mul, i16, Px, mul, |v: i16| v, |v: Px| v.0);
-impl_bin_op!(Div, i16, Px, div, |v: i16| v, |v: Px| v.0);
-impl_bin_op!(Rem, i16, Px, rem, |v: i16| v, |v: Px| v.0);
+impl_bin_op!(Mul, Px, i16, mul, saturating_mul, |v: Px| v.0, |v: i16| v);
+impl_bin_op!(Div, Px, i16, div, saturating_div, |v: Px| v.0, |v: i16| v);
+impl_bin_op!(Rem, Px, i16, rem, rem, |v: Px| v.0, |v: i16| v);
+impl_bin_op!(Mul, i16, Px, mul, saturating_mul, |v: i16| v, |v: Px| v.0);
+impl_bin_op!(Div, i16, Px, div, saturating_div, |v: i16| v, |v: Px| v.0);
+impl_bin_op!(Rem, i16, Px, rem, rem, |v: i16| v, |v: Px| v.0);
 
 macro_rules! impl_bin_op_assign {
-    ($trait:ident, $rhs:ty, $func:ident, $extract_right:expr) => {
+    ($trait:ident, $rhs:ty, $func:ident, $op:path) => {
         impl $trait<$rhs> for Px {
             fn $func(&mut self, rhs: $rhs) {
-                self.0.$func($extract_right(rhs));
+                *self = $op(*self, rhs);
             }
         }
     };
 }
 
-impl_bin_op_assign!(AddAssign, Self, add_assign, |v: Px| v.0);
-impl_bin_op_assign!(SubAssign, Self, sub_assign, |v: Px| v.0);
-impl_bin_op_assign!(DivAssign, Self, div_assign, |v: Px| v.0);
-impl_bin_op_assign!(RemAssign, Self, rem_assign, |v: Px| v.0);
+impl_bin_op_assign!(AddAssign, Self, add_assign, Add::add);
+impl_bin_op_assign!(SubAssign, Self, sub_assign, Sub::sub);
+impl_bin_op_assign!(DivAssign, Self, div_assign, Div::div);
+impl_bin_op_assign!(RemAssign, Self, rem_assign, Rem::rem);
 
-impl_bin_op_assign!(MulAssign, i16, mul_assign, |v: i16| v);
-impl_bin_op_assign!(DivAssign, i16, div_assign, |v: i16| v);
-impl_bin_op_assign!(RemAssign, i16, rem_assign, |v: i16| v);
+impl_bin_op_assign!(MulAssign, i16, mul_assign, Mul::mul);
+impl_bin_op_assign!(DivAssign, i16, div_assign, Div::div);
+impl_bin_op_assign!(RemAssign, i16, rem_assign, Rem::rem);
 
 impl PartialEq<i16> for Px {
     fn eq(&self, other: &i16) -> bool {
@@ -87,8 +119,117 @@ impl From<Px> for f32 {
     }
 }
 
+impl From<Px> for i32 {
+    fn from(p: Px) -> Self {
+        p.0.into()
+    }
+}
+
 impl From<i16> for Px {
     fn from(v: i16) -> Self {
         Self(v)
     }
 }
+
+/// Converts a value between device-independent and physical pixels.
+///
+/// Implemented for [`Px`] and the shapes built from it. Results are rounded to
+/// the nearest pixel and saturate at [`Px::MIN`] and [`Px::MAX`].
+pub trait Scale: Sized {
+    /// Multiplies every coordinate of the value by `factor`.
+    fn scale(self, factor: f32) -> Self;
+}
+
+impl Scale for Px {
+    fn scale(self, factor: f32) -> Self {
+        // Float to int casts saturate, and NaN becomes 0.
+        Px((f32::from(self) * factor).round() as i16)
+    }
+}
+
+/// A value measured in device-independent pixels, where 96 DPI is 1:1.
+///
+/// UI layout happens in logical pixels so that widgets keep the same apparent
+/// size across displays. Use [`to_physical()`](Logical::to_physical) to get
+/// the pixels that are actually drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Logical<T>(pub T);
+
+impl<T: Scale> Logical<T> {
+    /// Converts the value to physical pixels for a display with the given
+    /// scale factor (e.g. 1.5 at 144 DPI).
+    pub fn to_physical(self, scale: f32) -> Physical<T> {
+        Physical(self.0.scale(scale))
+    }
+}
+
+/// A value measured in physical pixels, as reported by the window and used by
+/// the renderer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Physical<T>(pub T);
+
+impl<T: Scale> Physical<T> {
+    /// Converts the value to logical pixels for a display with the given
+    /// scale factor (e.g. 1.5 at 144 DPI).
+    pub fn to_logical(self, scale: f32) -> Logical<T> {
+        Logical(self.0.scale(scale.recip()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators() {
+        assert_eq!(Px(3) + Px(4), Px(7));
+        assert_eq!(Px(3) - Px(4), Px(-1));
+        assert_eq!(Px(3) * 4, Px(12));
+        assert_eq!(4 * Px(3), Px(12));
+        assert_eq!(Px(13) / 4, Px(3));
+        assert_eq!(Px(13) / Px(4), Px(3));
+        assert_eq!(Px(13) % 4, Px(1));
+
+        let mut p = Px(10);
+        p += Px(5);
+        p -= Px(3);
+        p *= 2;
+        p /= 3;
+        assert_eq!(p, Px(8));
+
+        assert_eq!(Px(3).max(Px(-4)), Px(3));
+        assert_eq!(Px(3).min(Px(-4)), Px(-4));
+        assert_eq!(i32::from(Px(-7)), -7);
+        assert_eq!(f32::from(Px(-7)), -7.0);
+    }
+
+    #[test]
+    fn saturating_at_bounds() {
+        assert_eq!(Px::MAX + Px(1), Px::MAX);
+        assert_eq!(Px::MIN - Px(1), Px::MIN);
+        assert_eq!(Px::MAX.saturating_add(Px::MAX), Px::MAX);
+        assert_eq!(Px::MIN.saturating_sub(Px::MAX), Px::MIN);
+        assert_eq!(Px::MAX * 2, Px::MAX);
+        assert_eq!(Px::MIN * 2, Px::MIN);
+        assert_eq!(Px::MIN / -1, Px::MAX);
+
+        let mut p = Px::MAX;
+        p += Px(100);
+        assert_eq!(p, Px::MAX);
+    }
+
+    #[test]
+    fn logical_physical_round_trip() {
+        for scale in [1.0, 1.5, 2.0] {
+            for v in [-301, -7, -1, 0, 1, 2, 3, 7, 100, 301] {
+                let logical = Logical(Px(v));
+                assert_eq!(logical.to_physical(scale).to_logical(scale), logical);
+            }
+        }
+
+        assert_eq!(Logical(Px(3)).to_physical(1.5), Physical(Px(5)));
+        assert_eq!(Logical(Px(10)).to_physical(2.0), Physical(Px(20)));
+        assert_eq!(Physical(Px(15)).to_logical(1.5), Logical(Px(10)));
+        assert_eq!(Logical(Px::MAX).to_physical(2.0), Physical(Px::MAX));
+    }
+}
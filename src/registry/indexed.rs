@@ -32,14 +32,31 @@ pub enum Type {
 
 #[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
-    #[error("The provided ID is invalid, the item having either been deleted or never existed.")]
+    #[error("The provided ID is invalid, the item having never existed.")]
     InvalidId,
+    #[error("The provided ID is stale, the item having been deleted. The slot is at generation {current_generation}, but the ID is for generation {provided_generation}.")]
+    StaleId {
+        current_generation: u16,
+        provided_generation: u16,
+    },
     #[error("Too many objects have been allocated. No Ids are available.")]
     TooManyObjects,
     #[error("The type of the stored item ({actual:?}) is not hte same as the expected type ({expected:?})")]
     TypeMismatch { expected: Type, actual: Type },
 }
 
+impl Error {
+    fn missing(id: Id, reason: slot::Missing) -> Self {
+        match reason {
+            slot::Missing::Invalid => Self::InvalidId,
+            slot::Missing::Stale { current_version } => Self::StaleId {
+                current_generation: current_version,
+                provided_generation: id.version(),
+            },
+        }
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, PartialEq, Eq)]
 pub struct TypedId<T>(Id, PhantomData<T>);
@@ -78,6 +95,39 @@ union Object32 {
     f32: f32,
 }
 
+/// The primitive-typed values of a [`Registry`] at some point in time,
+/// returned by [`Registry::snapshot()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    entries: Vec<(Id, Value)>,
+}
+
+impl Snapshot {
+    /// The number of values captured by the snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A copy of a value in a [`Snapshot`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    U128(u128),
+    I128(i128),
+    StaticStr(&'static str),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Char(char),
+}
+
 pub struct Registry {
     slots: slot::Storage<(Type, object::Index)>,
     objects_128: object::Storage<Object128>,
@@ -95,6 +145,49 @@ impl Registry {
         }
     }
 
+    /// Creates a registry that can hold at least `capacity` values of any
+    /// type without reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut registry = Self::new();
+        registry.reserve(capacity);
+        registry
+    }
+
+    /// The number of values that the registry is guaranteed to be able to
+    /// hold without reallocating, whatever their types.
+    pub fn capacity(&self) -> usize {
+        self.slots
+            .capacity()
+            .min(self.objects_128.capacity())
+            .min(self.objects_64.capacity())
+            .min(self.objects_32.capacity())
+    }
+
+    /// Makes room for at least `additional` more values of any type. Values
+    /// never move in the registry, so their IDs stay valid.
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+        self.objects_128.reserve(additional);
+        self.objects_64.reserve(additional);
+        self.objects_32.reserve(additional);
+    }
+
+    /// Releases memory that isn't needed for the values currently in the
+    /// registry, such as after removing many of them. IDs stay valid.
+    pub fn shrink_to_fit(&mut self) {
+        self.slots.shrink_to_fit();
+        self.objects_128.shrink_to_fit();
+        self.objects_64.shrink_to_fit();
+        self.objects_32.shrink_to_fit();
+    }
+
+    /// The ID that the next value inserted into the registry will get, for
+    /// checking the order that slots are reused in.
+    #[cfg(test)]
+    pub fn peek_next_id(&self) -> Option<Id> {
+        self.slots.peek_next_id()
+    }
+
     /// Returns `true` if the `id` refers to a value.
     pub fn is_valid(&self, id: Id) -> bool {
         self.slots.is_valid(id)
@@ -105,12 +198,13 @@ impl Registry {
     pub fn type_of(&self, id: Id) -> Result<Type, Error> {
         self.slots
             .get(id)
-            .map_or(Err(Error::InvalidId), |(object_type, _)| Ok(object_type))
+            .map(|(object_type, _)| object_type)
+            .map_err(|reason| Error::missing(id, reason))
     }
 
     /// Removes the value referred to by `id`.
     pub fn remove(&mut self, id: Id) -> Result<(), Error> {
-        let (object_type, object_index) = self.slots.take(id).ok_or(Error::InvalidId)?;
+        let (object_type, object_index) = self.take_slot(id)?;
         match object_type {
             Type::U128 | Type::I128 | Type::StaticStr => unsafe {
                 self.objects_128.delete(object_index, |_| {});
@@ -130,6 +224,93 @@ impl Registry {
 
         Ok(())
     }
+
+    /// Copies every value in the registry so that they can be put back with
+    /// [`restore()`](Self::restore), such as to undo changes.
+    ///
+    /// `Box<dyn Any>` values cannot be copied, so they are not included.
+    pub fn snapshot(&self) -> Snapshot {
+        let entries = self
+            .slots
+            .iter()
+            .filter_map(|(id, (object_type, index))| {
+                let value = unsafe {
+                    match object_type {
+                        Type::U128 => Value::U128(self.objects_128.get(index).u128),
+                        Type::I128 => Value::I128(self.objects_128.get(index).i128),
+                        Type::StaticStr => Value::StaticStr(self.objects_128.get(index).static_str),
+                        Type::U64 => Value::U64(self.objects_64.get(index).u64),
+                        Type::I64 => Value::I64(self.objects_64.get(index).i64),
+                        Type::F64 => Value::F64(self.objects_64.get(index).f64),
+                        Type::U32 => Value::U32(self.objects_32.get(index).u32),
+                        Type::I32 => Value::I32(self.objects_32.get(index).i32),
+                        Type::F32 => Value::F32(self.objects_32.get(index).f32),
+                        Type::Char => Value::Char(self.objects_32.get(index).char),
+                        Type::Any => return None,
+                        Type::Unknown => unreachable!(),
+                    }
+                };
+                Some((id, value))
+            })
+            .collect();
+
+        Snapshot { entries }
+    }
+
+    /// Replaces the contents of the registry with those of `snapshot`. Every
+    /// value is given back the ID that it had when the snapshot was taken, so
+    /// IDs from before then are valid again if their value was captured.
+    ///
+    /// Since snapshots don't include `Box<dyn Any>` values, any that are in
+    /// the registry are dropped. IDs handed out after the snapshot was taken
+    /// may be reused, and must not be used once it has been restored.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        let ids = self.slots.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        for id in ids {
+            self.remove(id).expect("the ID came from the registry");
+        }
+
+        let entries = snapshot
+            .entries
+            .into_iter()
+            .map(|(id, value)| (id, self.store(value)))
+            .collect::<Vec<_>>();
+        self.slots.restore(entries);
+    }
+
+    /// Stores a value from a [`Snapshot`], without allocating a slot for it.
+    fn store(&mut self, value: Value) -> (Type, object::Index) {
+        let (object_type, index) = match value {
+            Value::U128(u128) => (Type::U128, self.objects_128.store(Object128 { u128 })),
+            Value::I128(i128) => (Type::I128, self.objects_128.store(Object128 { i128 })),
+            Value::StaticStr(static_str) => (
+                Type::StaticStr,
+                self.objects_128.store(Object128 { static_str }),
+            ),
+            Value::U64(u64) => (Type::U64, self.objects_64.store(Object64 { u64 })),
+            Value::I64(i64) => (Type::I64, self.objects_64.store(Object64 { i64 })),
+            Value::F64(f64) => (Type::F64, self.objects_64.store(Object64 { f64 })),
+            Value::U32(u32) => (Type::U32, self.objects_32.store(Object32 { u32 })),
+            Value::I32(i32) => (Type::I32, self.objects_32.store(Object32 { i32 })),
+            Value::F32(f32) => (Type::F32, self.objects_32.store(Object32 { f32 })),
+            Value::Char(char) => (Type::Char, self.objects_32.store(Object32 { char })),
+        };
+
+        // The registry held at least this many objects when the snapshot was
+        // taken, and is now empty.
+        (
+            object_type,
+            index.expect("the snapshot fits in the registry"),
+        )
+    }
+
+    /// Frees the slot referred to by `id`, returning what it held.
+    fn take_slot(&mut self, id: Id) -> Result<(Type, object::Index), Error> {
+        match self.slots.get(id) {
+            Ok(_) => Ok(self.slots.take(id).unwrap()),
+            Err(reason) => Err(Error::missing(id, reason)),
+        }
+    }
 }
 
 impl Drop for Registry {
@@ -159,6 +340,11 @@ pub trait Ops<T> {
 
     /// Inserts a new value into the [`Registry`], returning an ID that can be
     /// used to retrieve it at a later time.
+    ///
+    /// The value is put in the most recently freed slot if there is one, so
+    /// the ID has the same index as the removed value's, but a newer
+    /// generation. IDs for removed values are not valid again, unless a
+    /// [`Snapshot`] taken while they were live is restored.
     fn insert(&mut self, value: T) -> Result<TypedId<T>, Error>;
 
     /// Destroys the value identified by `id` if `is_valid(id)`.
@@ -169,7 +355,10 @@ macro_rules! impl_ops {
     ($api_type:ty, $api_name:ident, $kind:expr, $storage:ident, $object_type:ident, $ctor:expr, $dtor:expr) => {
         impl Ops<$api_type> for Registry {
             fn get(&self, id: Id) -> Result<&$api_type, Error> {
-                let (object_type, object_index) = self.slots.get(id).ok_or(Error::InvalidId)?;
+                let (object_type, object_index) = self
+                    .slots
+                    .get(id)
+                    .map_err(|reason| Error::missing(id, reason))?;
 
                 if object_type == $kind {
                     Ok(unsafe { &self.$storage.get(object_index).$api_name })
@@ -182,7 +371,10 @@ macro_rules! impl_ops {
             }
 
             fn get_mut(&mut self, id: Id) -> Result<&mut $api_type, Error> {
-                let (object_type, object_index) = self.slots.get(id).ok_or(Error::InvalidId)?;
+                let (object_type, object_index) = self
+                    .slots
+                    .get(id)
+                    .map_err(|reason| Error::missing(id, reason))?;
 
                 if object_type == $kind {
                     Ok(unsafe { &mut self.$storage.get_mut(object_index).$api_name })
@@ -195,12 +387,18 @@ macro_rules! impl_ops {
             }
 
             fn get_typed(&self, id: TypedId<$api_type>) -> Result<&$api_type, Error> {
-                let (_, object_index) = self.slots.get(id.0).ok_or(Error::InvalidId)?;
+                let (_, object_index) = self
+                    .slots
+                    .get(id.0)
+                    .map_err(|reason| Error::missing(id.0, reason))?;
                 Ok(unsafe { &self.$storage.get(object_index).$api_name })
             }
 
             fn get_typed_mut(&mut self, id: TypedId<$api_type>) -> Result<&mut $api_type, Error> {
-                let (_, object_index) = self.slots.get(id.0).ok_or(Error::InvalidId)?;
+                let (_, object_index) = self
+                    .slots
+                    .get(id.0)
+                    .map_err(|reason| Error::missing(id.0, reason))?;
                 Ok(unsafe { &mut self.$storage.get_mut(object_index).$api_name })
             }
 
@@ -218,7 +416,7 @@ macro_rules! impl_ops {
             }
 
             fn remove_typed(&mut self, id: TypedId<$api_type>) -> Result<(), Error> {
-                let (_, index) = self.slots.take(id.0).ok_or(Error::InvalidId)?;
+                let (_, index) = self.take_slot(id.0)?;
                 unsafe { self.$storage.delete(index, $dtor) };
                 Ok(())
             }
@@ -317,7 +515,7 @@ mod tests {
                         let raw: Result<&$t, Error> = registry.get(id.0);
 
                         assert_eq!(typed, raw);
-                        assert_eq!(raw, Err(Error::InvalidId));
+                        assert!(matches!(raw, Err(Error::StaleId { .. })));
                     }
                 }
             }
@@ -381,6 +579,124 @@ mod tests {
         }
     }
 
+    #[test]
+    fn freed_slots_are_reused_with_new_generation() {
+        let mut registry = Registry::new();
+        let a = registry.insert(1u64).unwrap();
+        let b = registry.insert(2u64).unwrap();
+
+        let next = registry.peek_next_id().unwrap();
+        let c = registry.insert(3u64).unwrap();
+        assert_eq!(c.get(), next);
+
+        // The most recently freed slot is reused first.
+        registry.remove_typed(a).unwrap();
+        registry.remove_typed(b).unwrap();
+        let next = registry.peek_next_id().unwrap();
+        let d = registry.insert(4u64).unwrap();
+        assert_eq!(d.get(), next);
+        assert_eq!(d.get().index(), b.get().index());
+        assert_eq!(d.get().version(), b.get().version() + 1);
+
+        // The stale ID is rejected rather than reading the new value.
+        assert!(matches!(registry.get_typed(b), Err(Error::StaleId { .. })));
+        assert_eq!(registry.get_typed(d), Ok(&4));
+
+        let e = registry.insert(5u64).unwrap();
+        assert_eq!(e.get().index(), a.get().index());
+        assert_ne!(e.get(), a.get());
+        assert!(matches!(registry.get_typed(a), Err(Error::StaleId { .. })));
+
+        for id in [c, d, e] {
+            registry.remove_typed(id).unwrap();
+        }
+    }
+
+    #[test]
+    fn stale_ids_are_distinguished() {
+        let mut registry = Registry::new();
+        let a = registry.insert(1u32).unwrap();
+        registry.remove_typed(a).unwrap();
+        let b = registry.insert(2u32).unwrap();
+        assert_eq!(b.get().index(), a.get().index());
+
+        let stale = Error::StaleId {
+            current_generation: b.get().version(),
+            provided_generation: a.get().version(),
+        };
+        assert_eq!(registry.get_typed(a), Err(stale));
+        assert_eq!(registry.type_of(a.get()), Err(stale));
+        assert_eq!(registry.remove_typed(a), Err(stale));
+
+        // An ID that the registry hasn't handed out yet.
+        let next = registry.peek_next_id().unwrap();
+        assert_eq!(registry.type_of(next), Err(Error::InvalidId));
+
+        registry.remove_typed(b).unwrap();
+    }
+
+    #[test]
+    fn reserved_capacity() {
+        let mut registry = Registry::with_capacity(1000);
+        let capacity = registry.capacity();
+        assert!(capacity >= 1000);
+
+        let mut ids = vec![];
+        for i in 0..1000u32 {
+            ids.push(registry.insert(i).unwrap());
+        }
+        assert_eq!(registry.capacity(), capacity, "inserting reallocated");
+
+        registry.reserve(5000);
+        assert!(registry.capacity() >= 5000);
+        assert_eq!(registry.get_typed(ids[10]), Ok(&10));
+
+        for id in ids.drain(10..) {
+            registry.remove_typed(id).unwrap();
+        }
+        registry.shrink_to_fit();
+        assert_eq!(registry.objects_32.capacity(), 10);
+        for (i, id) in ids.into_iter().enumerate() {
+            assert_eq!(registry.get_typed(id), Ok(&(i as u32)));
+            registry.remove_typed(id).unwrap();
+        }
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut registry = Registry::new();
+        let a = registry.insert(1u32).unwrap();
+        let b = registry.insert(2.5f64).unwrap();
+        let c = registry.insert("three").unwrap();
+        let any = registry.insert(Box::new(4u8) as Box<dyn Any>).unwrap();
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 3, "Any values aren't captured");
+
+        *registry.get_typed_mut(a).unwrap() = 10;
+        registry.remove_typed(b).unwrap();
+        let d = registry.insert('d').unwrap();
+        assert!(!registry.is_valid(b.0));
+
+        registry.restore(snapshot.clone());
+
+        // The original IDs are valid again, with their original values.
+        assert_eq!(registry.get_typed(a), Ok(&1));
+        assert_eq!(registry.get_typed(b), Ok(&2.5));
+        assert_eq!(registry.get_typed(c), Ok(&"three"));
+        let raw: Result<&f64, Error> = registry.get(b.0);
+        assert_eq!(raw, Ok(&2.5));
+
+        // Values added since the snapshot, and Any values, are gone.
+        assert!(!registry.is_valid(d.0));
+        assert!(!registry.is_valid(any.0));
+        assert_eq!(registry.snapshot(), snapshot);
+
+        registry.remove_typed(a).unwrap();
+        registry.remove_typed(b).unwrap();
+        registry.remove_typed(c).unwrap();
+    }
+
     #[test]
     #[should_panic(expected = "All allocations must be freed before destroying the registry.")]
     fn bad_cleanup() {
@@ -17,8 +17,13 @@ use super::{
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error<'a> {
-    #[error("The provided ID is invalid, the item having either been deleted or never existed.")]
+    #[error("The provided ID is invalid, the item having never existed.")]
     InvalidId,
+    #[error("The provided ID is stale, the item having been deleted. The slot is at generation {current_generation}, but the ID is for generation {provided_generation}.")]
+    StaleId {
+        current_generation: u16,
+        provided_generation: u16,
+    },
     #[error("Too many objects have been allocated. No Ids are available.")]
     TooManyObjects,
     #[error("The provided item name does not refer to an item in the registry.")]
@@ -34,6 +39,13 @@ impl From<IndexedError> for Error<'static> {
         match err {
             IndexedError::TooManyObjects => Self::TooManyObjects,
             IndexedError::InvalidId => Self::InvalidId,
+            IndexedError::StaleId {
+                current_generation,
+                provided_generation,
+            } => Self::StaleId {
+                current_generation,
+                provided_generation,
+            },
             IndexedError::TypeMismatch { expected, actual } => {
                 Self::TypeMismatch { expected, actual }
             }
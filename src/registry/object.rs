@@ -43,6 +43,47 @@ impl<T> Storage<T> {
         }
     }
 
+    /// The number of objects that can be stored without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Makes room for at least `additional` more objects than are currently
+    /// allocated, freed or not.
+    pub fn reserve(&mut self, additional: usize) {
+        self.values.reserve(additional);
+    }
+
+    /// Releases freed objects at the end of the storage, and any unused
+    /// capacity. Objects that are still live don't move.
+    pub fn shrink_to_fit(&mut self) {
+        let mut is_free = vec![false; self.values.len()];
+        let mut next = self.free_list;
+        while let Some(index) = next {
+            is_free[index.0 as usize] = true;
+            next = unsafe { self.values[index.0 as usize].next_free };
+        }
+
+        let len = is_free
+            .iter()
+            .rposition(|free| !free)
+            .map_or(0, |last| last + 1);
+        if len < self.values.len() {
+            // The objects are unions, so truncating doesn't drop anything.
+            self.values.truncate(len);
+
+            self.free_list = None;
+            self.num_free_objects = 0;
+            for index in (0..len).rev().filter(|index| is_free[*index]) {
+                self.values[index].next_free = self.free_list;
+                self.free_list = Some(Index(index as u16));
+                self.num_free_objects += 1;
+            }
+        }
+
+        self.values.shrink_to_fit();
+    }
+
     /// # Safety
     ///
     /// Make sure that `index` points to a live object. Pointing to an
@@ -133,4 +174,33 @@ mod tests {
             assert_eq!(storage.values.len(), 0);
         }
     }
+
+    #[test]
+    fn shrink_after_removal() {
+        let mut storage = Storage::new();
+        storage.reserve(100);
+        assert!(storage.capacity() >= 100);
+
+        let indices = (0..10u32)
+            .map(|i| storage.store(i).unwrap())
+            .collect::<Vec<_>>();
+        unsafe {
+            // Free the tail, except for the last object, and one in the middle.
+            for index in &indices[5..9] {
+                storage.delete(*index, |_| {});
+            }
+            storage.delete(indices[2], |_| {});
+            storage.delete(indices[9], |_| {});
+
+            storage.shrink_to_fit();
+            assert_eq!(storage.values.len(), 5);
+            assert_eq!(storage.capacity(), 5);
+            assert_eq!(storage.num_free_objects, 1);
+            assert_eq!(*storage.get(indices[4]), 4);
+
+            // Only the hole in the middle is reused.
+            assert_eq!(storage.store(20), Some(indices[2]));
+            assert_eq!(storage.store(21), Some(Index(5)));
+        }
+    }
 }
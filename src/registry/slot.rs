@@ -1,5 +1,9 @@
 #![allow(dead_code)]
 
+/// A generational index into a [`Storage`]. Each slot's version is bumped
+/// when its value is freed, so an `Id` for a freed value stays invalid after
+/// the slot is reused. A slot whose version can't be bumped any further is
+/// retired instead of being reused.
 #[repr(align(4))]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Id {
@@ -7,6 +11,28 @@ pub struct Id {
     version: Version,
 }
 
+impl Id {
+    /// The slot that the ID refers to.
+    pub fn index(&self) -> u16 {
+        self.index.0
+    }
+
+    /// How many times the slot had been freed when the ID was allocated.
+    pub fn version(&self) -> u16 {
+        self.version.0
+    }
+}
+
+/// The reason that a [`Storage`] has no value for an [`Id`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Missing {
+    /// The ID's slot has never held a value with the ID's version.
+    Invalid,
+    /// The ID's value was freed, and the slot has moved on to
+    /// `current_version`.
+    Stale { current_version: u16 },
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Version(pub u16);
 
@@ -45,17 +71,32 @@ impl<T: Copy> Storage<T> {
     }
 
     /// Retrieves the [`ItemType`] and [`Index`] associated with `id`. If the
-    /// `id` is invalid or the resource it pointed to was destroyed, this
-    /// function will return `None`.
-    pub fn get(&self, id: Id) -> Option<T> {
-        self.slots.get(id.index.0 as usize).and_then(|slot| {
-            if let Payload::Active(data) = &slot.payload {
-                if slot.version == id.version {
-                    return Some(*data);
-                }
+    /// resource it pointed to was destroyed, this function will return
+    /// [`Missing::Stale`], and [`Missing::Invalid`] if `id` was never handed
+    /// out by this storage.
+    pub fn get(&self, id: Id) -> Result<T, Missing> {
+        let slot = self
+            .slots
+            .get(id.index.0 as usize)
+            .ok_or(Missing::Invalid)?;
+
+        match slot.payload {
+            Payload::Active(data) if slot.version == id.version => Ok(data),
+            // Versions only go up between restores, so an older version was
+            // freed. A retired slot keeps the version of the last value it
+            // held. `restore()` can wind a slot's version back, after which
+            // IDs handed out since the snapshot look newer than the slot and
+            // are reported as invalid.
+            Payload::Active(_) | Payload::Free { .. } if id.version.0 < slot.version.0 => {
+                Err(Missing::Stale {
+                    current_version: slot.version.0,
+                })
             }
-            None
-        })
+            Payload::Dead if id.version.0 <= slot.version.0 => Err(Missing::Stale {
+                current_version: slot.version.0,
+            }),
+            _ => Err(Missing::Invalid),
+        }
     }
 
     pub fn is_valid(&self, id: Id) -> bool {
@@ -67,9 +108,103 @@ impl<T: Copy> Storage<T> {
         self.num_allocated
     }
 
+    /// The number of slots that can be active at once without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Makes room for at least `additional` more slots than there are now,
+    /// active or not.
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+    }
+
+    /// Releases unused capacity. Free slots are kept so that IDs for them
+    /// stay invalid.
+    pub fn shrink_to_fit(&mut self) {
+        self.slots.shrink_to_fit();
+    }
+
+    /// Iterates over the IDs and values of every active slot.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, T)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot.payload {
+                Payload::Active(data) => Some((
+                    Id {
+                        index: Index(index as u16),
+                        version: slot.version,
+                    },
+                    data,
+                )),
+                _ => None,
+            })
+    }
+
+    /// Puts each value back in the slot it was allocated to, with the version
+    /// it had, so that `get()` returns it for the same [`Id`] once again.
+    ///
+    /// IDs allocated since `entries` were collected may be handed out again,
+    /// so they must be discarded.
+    ///
+    /// # Panics
+    /// This function will panic if any slots are active.
+    pub fn restore(&mut self, entries: impl IntoIterator<Item = (Id, T)>) {
+        assert_eq!(self.num_allocated, 0, "cannot restore over active slots");
+
+        for (id, data) in entries {
+            let index = id.index.0 as usize;
+            if index >= self.slots.len() {
+                self.slots.resize_with(index + 1, || Slot {
+                    version: Version(0),
+                    payload: Payload::Free { next_free: None },
+                });
+            }
+
+            self.slots[index] = Slot {
+                version: id.version,
+                payload: Payload::Active(data),
+            };
+            self.num_allocated += 1;
+        }
+
+        // Rebuild the free list from the slots that are still free, lowest
+        // index first.
+        self.freelist_head = None;
+        for (index, slot) in self.slots.iter_mut().enumerate().rev() {
+            if let Payload::Free { next_free } = &mut slot.payload {
+                *next_free = self.freelist_head;
+                self.freelist_head = Some(Index(index as u16));
+            }
+        }
+    }
+
+    /// The [`Id`] that the next call to [`alloc()`](Self::alloc) will
+    /// return, if there is room for it.
+    pub fn peek_next_id(&self) -> Option<Id> {
+        if let Some(index) = self.freelist_head {
+            Some(Id {
+                index,
+                version: self.slots[index.0 as usize].version,
+            })
+        } else if self.slots.len() < (u16::MAX as usize) {
+            Some(Id {
+                index: Index(self.slots.len() as u16),
+                version: Version(0),
+            })
+        } else {
+            None
+        }
+    }
+
     /// Allocates a slot to store `item_type` and `value_index`, returning an
     /// [`ItemId`] on success. The `item_type` and `value_index` cannot be
     /// modified except to be freed.
+    ///
+    /// The most recently freed slot is reused first, with the version it was
+    /// given when it was freed. New slots are only added once every freed
+    /// slot is in use.
     pub fn alloc(&mut self, data: T) -> Option<Id> {
         if let Some(index) = self.freelist_head {
             let slot = unsafe { self.slots.get_unchecked_mut(index.0 as usize) };
@@ -167,7 +302,7 @@ mod tests {
                     version: Version(1)
                 }
             );
-            assert_eq!(slots.get(slot1), Some(10));
+            assert_eq!(slots.get(slot1), Ok(10));
             assert_eq!(slots.slots.len(), 1);
             assert_eq!(slots.freelist_head, None);
 
@@ -179,8 +314,8 @@ mod tests {
 
             let slot2 = slots.alloc(11).unwrap();
             assert_eq!(slots.is_valid(slot2), true);
-            assert_eq!(slots.get(slot1), None);
-            assert_eq!(slots.get(slot2), Some(11));
+            assert_eq!(slots.get(slot1), Err(Missing::Stale { current_version: 2 }));
+            assert_eq!(slots.get(slot2), Ok(11));
             assert_eq!(slots.slots.len(), 1);
             assert_eq!(slots.freelist_head, None);
 
@@ -188,6 +323,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn slot_restore() {
+        let mut slots = Storage::new();
+        let a = slots.alloc('a').unwrap();
+        let b = slots.alloc('b').unwrap();
+        let c = slots.alloc('c').unwrap();
+        let saved = slots.iter().filter(|(id, _)| *id != b).collect::<Vec<_>>();
+        assert_eq!(saved, [(a, 'a'), (c, 'c')]);
+
+        for id in [a, b, c] {
+            slots.take(id);
+        }
+        slots.restore(saved);
+
+        assert_eq!(slots.num_active(), 2);
+        assert_eq!(slots.get(a), Ok('a'));
+        assert_eq!(slots.get(b), Err(Missing::Stale { current_version: 1 }));
+        assert_eq!(slots.get(c), Ok('c'));
+
+        // Only b's slot is free.
+        assert_eq!(slots.freelist_head, Some(b.index));
+        let d = slots.alloc('d').unwrap();
+        assert_eq!(d.index, b.index);
+        assert!(!slots.is_valid(b));
+        assert_eq!(slots.freelist_head, None);
+    }
+
     #[test]
     fn slot_allocator_dead_slot() {
         let mut slots = Storage::new();
@@ -1,8 +1,8 @@
-use super::px::Px;
+use super::px::{Px, Scale};
 
 use std::ops::Add;
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Point {
     pub x: Px,
     pub y: Px,
@@ -12,6 +12,20 @@ impl Point {
     pub fn new(x: Px, y: Px) -> Self {
         Self { x, y }
     }
+
+    /// Moves the point by `dx` horizontally and `dy` vertically.
+    pub fn offset(self, dx: Px, dy: Px) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+}
+
+impl Scale for Point {
+    fn scale(self, factor: f32) -> Self {
+        Self::new(self.x.scale(factor), self.y.scale(factor))
+    }
 }
 
 impl Add<Extent> for Point {
@@ -25,13 +39,13 @@ impl Add<Extent> for Point {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Offset {
     pub x: Px,
     pub y: Px,
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Extent {
     pub width: Px,
     pub height: Px,
@@ -45,7 +59,13 @@ impl Extent {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+impl Scale for Extent {
+    fn scale(self, factor: f32) -> Self {
+        Self::new(self.width.scale(factor), self.height.scale(factor))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Rect {
     pub point: Point,
     pub extent: Extent,
@@ -100,7 +120,18 @@ impl Rect {
         self.point.y + self.extent.height
     }
 
+    /// The corners of the rect, counter-clockwise on screen starting from the
+    /// top-left. Together with [`Rect::INDICES`], this produces triangles that
+    /// face the viewer.
     pub fn points(&self) -> [Point; 4] {
+        // The origin is the top-left corner, so a negative extent would flip
+        // the winding and the rect would be culled.
+        debug_assert!(
+            self.width() >= Px(0) && self.height() >= Px(0),
+            "rect has a negative extent: {:?}",
+            self
+        );
+
         [
             self.point,
             Point {
@@ -118,11 +149,14 @@ impl Rect {
         ]
     }
 
+    /// Checks if the point lies within the rect, including the left and top
+    /// edges but excluding the right and bottom edges. A rect with no area
+    /// contains no points.
     pub fn contains_point(&self, point: Point) -> bool {
         (self.left() <= point.x)
-            & (self.right() >= point.x)
+            & (point.x < self.right())
             & (self.top() <= point.y)
-            & (self.bottom() >= point.y)
+            & (point.y < self.bottom())
     }
 
     pub fn contains_rect(&self, rect: Self) -> bool {
@@ -131,6 +165,57 @@ impl Rect {
             & (self.top() <= rect.top())
             & (self.bottom() >= rect.bottom())
     }
+
+    /// Computes the region shared by both rects, or [`None`] if they do not
+    /// overlap.
+    pub fn intersect(&self, other: Self) -> Option<Self> {
+        let left = self.left().max(other.left());
+        let top = self.top().max(other.top());
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if (left < right) & (top < bottom) {
+            Some(Self::new(left, top, right - left, bottom - top))
+        } else {
+            None
+        }
+    }
+
+    /// Computes the smallest rect that covers both rects. Rects with no area
+    /// do not contribute to the result.
+    pub fn union(&self, other: Self) -> Self {
+        if other.is_empty() {
+            return *self;
+        }
+
+        if self.is_empty() {
+            return other;
+        }
+
+        let left = self.left().min(other.left());
+        let top = self.top().min(other.top());
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Self::new(left, top, right - left, bottom - top)
+    }
+
+    /// Checks if the rect has no area.
+    pub fn is_empty(&self) -> bool {
+        (self.width() <= Px(0)) | (self.height() <= Px(0))
+    }
+}
+
+impl Scale for Rect {
+    /// Scales the edges of the rect rather than its extent, so that rects
+    /// which touch before scaling still touch afterwards.
+    fn scale(self, factor: f32) -> Self {
+        let left = self.left().scale(factor);
+        let top = self.top().scale(factor);
+        let right = self.right().scale(factor);
+        let bottom = self.bottom().scale(factor);
+        Self::new(left, top, right - left, bottom - top)
+    }
 }
 
 impl std::fmt::Debug for Rect {
@@ -143,3 +228,70 @@ impl std::fmt::Debug for Rect {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_edges() {
+        let rect = Rect::new(Px(10), Px(20), Px(5), Px(5));
+
+        assert!(rect.contains_point(Point::new(Px(10), Px(20))));
+        assert!(rect.contains_point(Point::new(Px(14), Px(24))));
+        assert!(!rect.contains_point(Point::new(Px(15), Px(20))));
+        assert!(!rect.contains_point(Point::new(Px(10), Px(25))));
+        assert!(!rect.contains_point(Point::new(Px(9), Px(20))));
+
+        let empty = Rect::new(Px(10), Px(20), Px(0), Px(5));
+        assert!(!empty.contains_point(Point::new(Px(10), Px(20))));
+    }
+
+    #[test]
+    fn intersect_and_union() {
+        let a = Rect::new(Px(0), Px(0), Px(10), Px(10));
+        let b = Rect::new(Px(5), Px(-5), Px(10), Px(10));
+        let c = Rect::new(Px(10), Px(0), Px(10), Px(10));
+
+        assert_eq!(a.intersect(b), Some(Rect::new(Px(5), Px(0), Px(5), Px(5))));
+        assert_eq!(a.intersect(c), None);
+        assert_eq!(a.union(b), Rect::new(Px(0), Px(-5), Px(15), Px(15)));
+        assert_eq!(a.union(c), Rect::new(Px(0), Px(0), Px(20), Px(10)));
+
+        let empty = Rect::new(Px(100), Px(100), Px(0), Px(0));
+        assert_eq!(a.union(empty), a);
+        assert_eq!(empty.union(a), a);
+        assert_eq!(a.intersect(empty), None);
+    }
+
+    #[test]
+    fn scaled_rects_stay_adjacent() {
+        use crate::px::Logical;
+
+        let a = Rect::new(Px(0), Px(0), Px(3), Px(3));
+        let b = Rect::new(Px(3), Px(0), Px(3), Px(3));
+
+        for scale in [1.0, 1.5, 2.0] {
+            let pa = Logical(a).to_physical(scale).0;
+            let pb = Logical(b).to_physical(scale).0;
+            assert_eq!(pa.right(), pb.left());
+            assert_eq!(Logical(a).to_physical(scale).to_logical(scale), Logical(a));
+            assert_eq!(Logical(b).to_physical(scale).to_logical(scale), Logical(b));
+        }
+
+        let point = Logical(Point::new(Px(-3), Px(7)));
+        assert_eq!(point.to_physical(1.5).0, Point::new(Px(-5), Px(11)));
+        assert_eq!(point.to_physical(1.5).to_logical(1.5), point);
+
+        let extent = Logical(Extent::new(Px(100), Px(33)));
+        assert_eq!(extent.to_physical(2.0).0, Extent::new(Px(200), Px(66)));
+        assert_eq!(extent.to_physical(1.5).to_logical(1.5), extent);
+    }
+
+    #[test]
+    fn point_offset() {
+        let point = Point::new(Px(3), Px(4));
+        assert_eq!(point.offset(Px(-5), Px(2)), Point::new(Px(-2), Px(6)));
+        assert_eq!(point.offset(Px(0), Px(0)), point);
+    }
+}
@@ -0,0 +1,148 @@
+//! Plain-text access to the system clipboard.
+//!
+//! The clipboard is a shared resource, so every operation opens it, does its
+//! work, and closes it again before returning.
+
+use std::{thread, time::Duration};
+
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::{
+        DataExchange::{
+            CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable,
+            OpenClipboard, SetClipboardData,
+        },
+        Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE},
+        SystemServices::CF_UNICODETEXT,
+    },
+};
+
+/// The number of times we try to open the clipboard before concluding that
+/// another process is holding on to it.
+const OPEN_ATTEMPTS: u32 = 5;
+
+/// The delay between attempts to open the clipboard. `OpenClipboard()` fails
+/// immediately if the clipboard is held, so this bounds the total wait to
+/// about 10ms.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(2);
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    #[error("The clipboard is being held by another application.")]
+    Busy,
+    #[error("Could not allocate memory for the clipboard contents.")]
+    OutOfMemory,
+    #[error("The clipboard rejected the new contents.")]
+    SetFailed,
+}
+
+/// Retrieves the text on the clipboard, if there is any. Returns [`None`] if
+/// the clipboard does not contain text, or if it could not be opened.
+pub fn clipboard_get_text() -> Option<String> {
+    let _clipboard = OpenClipboardGuard::open()?;
+
+    if !unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT.0) }.as_bool() {
+        return None;
+    }
+
+    let handle = unsafe { GetClipboardData(CF_UNICODETEXT.0) };
+    if handle == HANDLE::default() {
+        return None;
+    }
+
+    // The clipboard owns `handle`, so we must not free it.
+    let text = unsafe {
+        let data = GlobalLock(handle.0) as *const u16;
+        if data.is_null() {
+            return None;
+        }
+
+        // Don't trust the data to be null-terminated, another application
+        // could have put anything in there.
+        let max_len = GlobalSize(handle.0) / std::mem::size_of::<u16>();
+        let wide = std::slice::from_raw_parts(data, max_len);
+        let len = wide.iter().position(|c| *c == 0).unwrap_or(max_len);
+        let text = String::from_utf16_lossy(&wide[..len]);
+
+        GlobalUnlock(handle.0);
+        text
+    };
+
+    Some(text)
+}
+
+/// Replaces the contents of the clipboard with `text`.
+pub fn clipboard_set_text(text: &str) -> Result<(), Error> {
+    let wide = text
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect::<Vec<_>>();
+    let num_bytes = wide.len() * std::mem::size_of::<u16>();
+
+    let _clipboard = OpenClipboardGuard::open().ok_or(Error::Busy)?;
+
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, num_bytes);
+        if memory == 0 {
+            return Err(Error::OutOfMemory);
+        }
+
+        let data = GlobalLock(memory) as *mut u16;
+        if data.is_null() {
+            GlobalFree(memory);
+            return Err(Error::OutOfMemory);
+        }
+
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+        GlobalUnlock(memory);
+
+        EmptyClipboard();
+
+        // On success, ownership of the memory passes to the clipboard.
+        if SetClipboardData(CF_UNICODETEXT.0, HANDLE(memory)) == HANDLE::default() {
+            GlobalFree(memory);
+            return Err(Error::SetFailed);
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the clipboard open for as long as it is alive.
+struct OpenClipboardGuard {}
+
+impl OpenClipboardGuard {
+    fn open() -> Option<Self> {
+        for attempt in 0..OPEN_ATTEMPTS {
+            if unsafe { OpenClipboard(None) }.as_bool() {
+                return Some(Self {});
+            }
+
+            if attempt + 1 < OPEN_ATTEMPTS {
+                thread::sleep(OPEN_RETRY_DELAY);
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for OpenClipboardGuard {
+    fn drop(&mut self) {
+        unsafe { CloseClipboard() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Touches the real system clipboard, so it only runs when asked for.
+    #[test]
+    #[cfg_attr(not(feature = "clipboard-tests"), ignore)]
+    fn clipboard_round_trip() {
+        let text = "Hello, clipboard! Grüße, 你好, 🍁";
+        clipboard_set_text(text).unwrap();
+        assert_eq!(clipboard_get_text().as_deref(), Some(text));
+    }
+}
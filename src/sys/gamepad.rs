@@ -0,0 +1,311 @@
+//! Polled input from up to four XInput controllers.
+//!
+//! XInput has no events, so [`Gamepads::poll`] compares each controller's
+//! state with the one from the previous poll and reports the difference.
+
+use std::time::{Duration, Instant};
+
+use windows::Win32::UI::Input::XboxController::{XInputGetState, XINPUT_GAMEPAD, XINPUT_STATE};
+
+use super::input::{ButtonState, Event as InputEvent};
+
+/// The number of controllers that XInput supports.
+pub const MAX_GAMEPADS: usize = 4;
+
+/// How long to wait before checking if a disconnected controller has been
+/// plugged in again. Querying a disconnected controller is expensive, so this
+/// isn't done on every poll.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The distance from the center that the sticks must be pushed before they
+/// register, out of 32767. These are the values recommended by XInput.
+const LEFT_STICK_DEADZONE: i16 = 7849;
+const RIGHT_STICK_DEADZONE: i16 = 8689;
+
+/// How far a trigger must be pulled before it registers, out of 255.
+const TRIGGER_THRESHOLD: u8 = 30;
+
+const ERROR_SUCCESS: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadButton {
+    A,
+    B,
+    X,
+    Y,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Start,
+    Back,
+    LeftThumb,
+    RightThumb,
+    LeftShoulder,
+    RightShoulder,
+}
+
+/// An analog input on a gamepad. Sticks range from -1.0 to 1.0, with up being
+/// positive on the Y axes, and triggers from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// The bit in `XINPUT_GAMEPAD::wButtons` for each button.
+const BUTTONS: [(u16, GamepadButton); 14] = [
+    (0x0001, GamepadButton::DPadUp),
+    (0x0002, GamepadButton::DPadDown),
+    (0x0004, GamepadButton::DPadLeft),
+    (0x0008, GamepadButton::DPadRight),
+    (0x0010, GamepadButton::Start),
+    (0x0020, GamepadButton::Back),
+    (0x0040, GamepadButton::LeftThumb),
+    (0x0080, GamepadButton::RightThumb),
+    (0x0100, GamepadButton::LeftShoulder),
+    (0x0200, GamepadButton::RightShoulder),
+    (0x1000, GamepadButton::A),
+    (0x2000, GamepadButton::B),
+    (0x4000, GamepadButton::X),
+    (0x8000, GamepadButton::Y),
+];
+
+/// The state of a controller after dead zones have been applied.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct PadState {
+    buttons: u16,
+    axes: [f32; 6],
+}
+
+impl PadState {
+    const AXES: [GamepadAxis; 6] = [
+        GamepadAxis::LeftX,
+        GamepadAxis::LeftY,
+        GamepadAxis::RightX,
+        GamepadAxis::RightY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+
+    fn new(gamepad: &XINPUT_GAMEPAD) -> Self {
+        let (left_x, left_y) =
+            normalize_stick(gamepad.sThumbLX, gamepad.sThumbLY, LEFT_STICK_DEADZONE);
+        let (right_x, right_y) =
+            normalize_stick(gamepad.sThumbRX, gamepad.sThumbRY, RIGHT_STICK_DEADZONE);
+
+        Self {
+            buttons: gamepad.wButtons,
+            axes: [
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+                normalize_trigger(gamepad.bLeftTrigger),
+                normalize_trigger(gamepad.bRightTrigger),
+            ],
+        }
+    }
+
+    /// Reports every change between `self` and `next`.
+    fn diff(&self, pad: u8, next: &Self, events: &mut Vec<InputEvent>) {
+        for (mask, button) in BUTTONS {
+            let was_pressed = self.buttons & mask != 0;
+            let is_pressed = next.buttons & mask != 0;
+
+            if was_pressed != is_pressed {
+                events.push(InputEvent::GamepadButton {
+                    pad,
+                    button,
+                    state: if is_pressed {
+                        ButtonState::Pressed
+                    } else {
+                        ButtonState::Released
+                    },
+                });
+            }
+        }
+
+        for ((old, new), axis) in self.axes.iter().zip(next.axes).zip(Self::AXES) {
+            if *old != new {
+                events.push(InputEvent::GamepadAxis {
+                    pad,
+                    axis,
+                    value: new,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pad {
+    /// The last state read, or `None` if the controller is disconnected.
+    state: Option<PadState>,
+    /// The packet number of the last state read, which only changes when the
+    /// state does.
+    packet: u32,
+    /// When to next check for the controller if it is disconnected.
+    next_check: Option<Instant>,
+}
+
+/// Tracks the state of every connected controller.
+#[derive(Debug, Default)]
+pub struct Gamepads {
+    pads: [Pad; MAX_GAMEPADS],
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether any controller was connected at the last poll.
+    pub fn is_any_connected(&self) -> bool {
+        self.pads.iter().any(|pad| pad.state.is_some())
+    }
+
+    /// Reads the state of every controller and returns what changed since the
+    /// last poll. A controller that is disconnected releases its buttons and
+    /// centers its axes.
+    pub fn poll(&mut self, now: Instant) -> Vec<InputEvent> {
+        let mut events = vec![];
+
+        for (index, pad) in self.pads.iter_mut().enumerate() {
+            if pad.state.is_none() && pad.next_check.map_or(false, |next| now < next) {
+                continue;
+            }
+
+            let mut state = XINPUT_STATE::default();
+            let connected = unsafe { XInputGetState(index as u32, &mut state) } == ERROR_SUCCESS;
+
+            let previous = pad.state.unwrap_or_default();
+            let next = if connected {
+                if pad.state.is_some() && state.dwPacketNumber == pad.packet {
+                    continue;
+                }
+
+                pad.packet = state.dwPacketNumber;
+                pad.next_check = None;
+                PadState::new(&state.Gamepad)
+            } else {
+                pad.next_check = Some(now + RECONNECT_INTERVAL);
+                PadState::default()
+            };
+
+            previous.diff(index as u8, &next, &mut events);
+            pad.state = if connected { Some(next) } else { None };
+        }
+
+        events
+    }
+}
+
+/// Maps a stick's position to the range -1.0 to 1.0 on each axis. Positions
+/// within `deadzone` of the center are reported as 0.0, and the rest of the
+/// range is rescaled so that the output starts from 0.0 at the edge of the
+/// dead zone. The dead zone is circular, so the direction is preserved.
+fn normalize_stick(x: i16, y: i16, deadzone: i16) -> (f32, f32) {
+    let (x, y) = (f32::from(x), f32::from(y));
+    let magnitude = (x * x + y * y).sqrt();
+    let deadzone = f32::from(deadzone);
+
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+
+    // Corners can reach a magnitude of about 46000.
+    let max = f32::from(i16::MAX);
+    let scaled = ((magnitude - deadzone) / (max - deadzone)).min(1.0);
+    let x = (x / magnitude * scaled).clamp(-1.0, 1.0);
+    let y = (y / magnitude * scaled).clamp(-1.0, 1.0);
+    (x, y)
+}
+
+/// Maps a trigger's position to the range 0.0 to 1.0, ignoring light pulls.
+fn normalize_trigger(value: u8) -> f32 {
+    if value <= TRIGGER_THRESHOLD {
+        0.0
+    } else {
+        f32::from(value - TRIGGER_THRESHOLD) / f32::from(u8::MAX - TRIGGER_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stick_deadzone() {
+        let deadzone = LEFT_STICK_DEADZONE;
+
+        assert_eq!(normalize_stick(0, 0, deadzone), (0.0, 0.0));
+        assert_eq!(normalize_stick(deadzone, 0, deadzone), (0.0, 0.0));
+        assert_eq!(normalize_stick(-5000, 5000, deadzone), (0.0, 0.0));
+
+        // The edge of the range is 1.0 in every direction.
+        assert_eq!(normalize_stick(i16::MAX, 0, deadzone), (1.0, 0.0));
+        assert_eq!(normalize_stick(0, i16::MAX, deadzone), (0.0, 1.0));
+        let (x, y) = normalize_stick(i16::MIN, 0, deadzone);
+        assert!((x + 1.0).abs() < 1e-6 && y == 0.0);
+
+        // Just outside the dead zone is close to 0.0, not to the dead zone.
+        let (x, _) = normalize_stick(deadzone + 100, 0, deadzone);
+        assert!(x > 0.0 && x < 0.01, "{}", x);
+
+        // Halfway between the dead zone and the edge.
+        let half = (i32::from(deadzone) + i32::from(i16::MAX)) / 2;
+        let (x, _) = normalize_stick(half as i16, 0, deadzone);
+        assert!((x - 0.5).abs() < 1e-3, "{}", x);
+
+        // The direction is kept on diagonals, and corners are clamped.
+        let (x, y) = normalize_stick(20000, -20000, deadzone);
+        assert!((x + y).abs() < 1e-6 && x > 0.0);
+        let (x, y) = normalize_stick(i16::MAX, i16::MAX, deadzone);
+        assert!((x - y).abs() < 1e-6);
+        assert!((x * x + y * y).sqrt() <= 1.0 + 1e-6);
+    }
+
+    #[test]
+    fn trigger_threshold() {
+        assert_eq!(normalize_trigger(0), 0.0);
+        assert_eq!(normalize_trigger(TRIGGER_THRESHOLD), 0.0);
+        assert!(normalize_trigger(TRIGGER_THRESHOLD + 1) > 0.0);
+        assert_eq!(normalize_trigger(u8::MAX), 1.0);
+    }
+
+    #[test]
+    fn pad_state_changes() {
+        let previous = PadState::default();
+        let next = PadState {
+            buttons: 0x1000,
+            axes: [0.0, 0.5, 0.0, 0.0, 0.0, 0.0],
+        };
+
+        let mut events = vec![];
+        previous.diff(2, &next, &mut events);
+        assert_eq!(
+            events,
+            [
+                InputEvent::GamepadButton {
+                    pad: 2,
+                    button: GamepadButton::A,
+                    state: ButtonState::Pressed,
+                },
+                InputEvent::GamepadAxis {
+                    pad: 2,
+                    axis: GamepadAxis::LeftY,
+                    value: 0.5,
+                },
+            ]
+        );
+
+        events.clear();
+        next.diff(2, &next, &mut events);
+        assert!(events.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use super::gamepad::{GamepadAxis, GamepadButton};
 use crate::shapes::Point;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,21 +21,96 @@ pub enum MouseButton {
     Right  = 2,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// A key on the keyboard, independent of the keyboard layout for the keys
+/// that have names here. Letters are always reported in upper case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Tab,
+    Enter,
+    Escape,
+    Space,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Letter(char),
+    /// Any other key, identified by its platform-specific key code.
+    Other(u16),
+}
+
+/// The modifier keys that were held when a key event was generated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Event {
     None,
     CursorMove {
         position: Point,
     },
+    /// Relative mouse motion in device units, without pointer acceleration
+    /// and unaffected by the cursor reaching the edge of the screen. Only sent
+    /// while enabled with [`Control::set_raw_mouse`](super::Control::set_raw_mouse).
+    RawMouseMotion {
+        dx: f32,
+        dy: f32,
+    },
     MouseButton {
         button: MouseButton,
         state: ButtonState,
     },
+    /// `x` and `y` are in wheel notches, which may be fractional for
+    /// high-resolution wheels and trackpads. `lines` is the same distance in
+    /// lines (or characters, when scrolling horizontally) according to the
+    /// user's lines-per-notch setting.
     ScrollWheel {
         x: f32,
         y: f32,
+        lines: f32,
     },
     Char {
         codepoint: char,
     },
+    /// Text that an input method editor (IME) is composing, such as
+    /// Japanese kana that have yet to be converted to kanji. It should be
+    /// shown at the caret, in place of the previous preedit text, until it is
+    /// committed. `cursor` is the byte offset of the IME's cursor within
+    /// `text`. An empty `text` means that there is no preedit text, and is
+    /// sent when composition starts and ends.
+    ImePreedit {
+        text: String,
+        cursor: usize,
+    },
+    /// The IME has finished composing `text`, which should be inserted at the
+    /// caret as if it had been typed.
+    ImeCommit {
+        text: String,
+    },
+    Key {
+        key: Key,
+        state: ButtonState,
+        modifiers: Modifiers,
+    },
+    /// A button on one of the connected gamepads, numbered from 0. Gamepad
+    /// input is only sent to the focused window.
+    GamepadButton {
+        pad: u8,
+        button: GamepadButton,
+        state: ButtonState,
+    },
+    /// A stick or trigger on one of the connected gamepads moved. See
+    /// [`GamepadAxis`] for the range of `value`.
+    GamepadAxis {
+        pad: u8,
+        axis: GamepadAxis,
+        value: f32,
+    },
 }
@@ -1,37 +1,106 @@
 use std::ffi::{c_void, CStr};
 
 use windows::Win32::{
-    Foundation::{HINSTANCE, PSTR},
+    Foundation::{GetLastError, HINSTANCE, PSTR, PWSTR},
     System::{
-        Diagnostics::Debug::{SetErrorMode, SEM_FAILCRITICALERRORS},
+        Diagnostics::Debug::{
+            FormatMessageW, SetErrorMode, FORMAT_MESSAGE_FROM_SYSTEM,
+            FORMAT_MESSAGE_IGNORE_INSERTS, SEM_FAILCRITICALERRORS,
+        },
         LibraryLoader::{GetProcAddress, LoadLibraryW},
     },
 };
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum LibraryError {
+    #[error("Could not load the library \"{name}\": {}", system_message(*.code))]
+    Load { name: String, code: u32 },
+    #[error("Could not find the symbol \"{name}\": {}", system_message(*.code))]
+    Symbol { name: String, code: u32 },
+}
+
 #[derive(Debug)]
 pub struct Library {
     library: HINSTANCE,
 }
 
 impl Library {
-    pub fn load(path: &str) -> Option<Self> {
+    pub fn load(path: &str) -> Result<Self, LibraryError> {
         // INFO(davidzhang, Aug 1, 2021): There is an automatic conversion from
         // &str to PSTR that involves a memory allocation. However, I don't
         // expect that the application will be loading libraries willy-nilly, so
         // we should be ok.
         let library = unsafe { LoadLibraryW(path) };
         if library == HINSTANCE::default() {
-            None
+            Err(LibraryError::Load {
+                name: path.to_owned(),
+                code: unsafe { GetLastError() }.0,
+            })
         } else {
             unsafe { SetErrorMode(SEM_FAILCRITICALERRORS) };
-            Some(Self { library })
+            Ok(Self { library })
         }
     }
 
-    pub fn get_symbol(&self, path: &CStr) -> Option<*mut c_void> {
+    pub fn get_symbol(&self, path: &CStr) -> Result<*mut c_void, LibraryError> {
         let symbol =
             unsafe { GetProcAddress(self.library, PSTR(path.to_bytes_with_nul().as_ptr() as _)) };
 
-        symbol.map(|s| s as _)
+        symbol.map(|s| s as _).ok_or_else(|| LibraryError::Symbol {
+            name: path.to_string_lossy().into_owned(),
+            code: unsafe { GetLastError() }.0,
+        })
+    }
+}
+
+/// Looks up the system's description of a Win32 error code.
+pub(super) fn system_message(code: u32) -> String {
+    let mut buffer = [0u16; 512];
+    let length = unsafe {
+        FormatMessageW(
+            FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS,
+            std::ptr::null(),
+            code,
+            0,
+            PWSTR(buffer.as_mut_ptr()),
+            buffer.len() as u32,
+            std::ptr::null(),
+        )
+    };
+
+    if length == 0 {
+        format!("Unknown error ({})", code)
+    } else {
+        let message = String::from_utf16_lossy(&buffer[..length as usize]);
+        format!("{} ({})", message.trim_end(), code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_library() {
+        let library = Library::load("kernel32").unwrap();
+        assert!(library
+            .get_symbol(CStr::from_bytes_with_nul(b"GetLastError\0").unwrap())
+            .is_ok());
+
+        match library.get_symbol(CStr::from_bytes_with_nul(b"NotARealSymbol\0").unwrap()) {
+            Err(LibraryError::Symbol { name, .. }) => assert_eq!(name, "NotARealSymbol"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match Library::load("maple-not-a-real-library") {
+            Err(error @ LibraryError::Load { .. }) => {
+                assert!(error.to_string().contains("maple-not-a-real-library"));
+                if let LibraryError::Load { name, code } = error {
+                    assert_eq!(name, "maple-not-a-real-library");
+                    assert_ne!(code, 0);
+                }
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 }
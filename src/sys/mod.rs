@@ -1,8 +1,17 @@
+mod clipboard;
+pub use clipboard::{clipboard_get_text, clipboard_set_text, Error as ClipboardError};
+
+mod gamepad;
+pub use gamepad::{GamepadAxis, GamepadButton, MAX_GAMEPADS};
+
 mod input;
-pub use input::{ButtonState, Event as InputEvent, MouseButton};
+pub use input::{ButtonState, Event as InputEvent, Key, Modifiers, MouseButton};
 
 mod library;
-pub use library::Library;
+pub use library::{Library, LibraryError};
 
 mod window;
-pub use window::{window, Control, Event as WindowEvent, EventLoopControl, Handle};
+pub use window::{
+    window, Control, CursorIcon, Event as WindowEvent, EventLoopControl, Handle, IconError,
+    TimerId, WindowError, WindowId, WindowManager,
+};
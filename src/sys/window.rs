@@ -1,25 +1,82 @@
-use std::{cell::RefCell, convert::TryInto, sync::Once};
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryInto,
+    ffi::OsString,
+    os::windows::ffi::OsStringExt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicIsize, Ordering},
+        Once,
+    },
+    time::{Duration, Instant},
+};
 
 use windows::Win32::{
-    Foundation::{GetLastError, HINSTANCE, HWND, LPARAM, LRESULT, POINT, PWSTR, RECT, WPARAM},
-    System::LibraryLoader::GetModuleHandleW,
-    UI::WindowsAndMessaging::{
-        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
-        GetWindowLongPtrW, GetWindowRect, LoadCursorW, PeekMessageW, PostQuitMessage,
-        RegisterClassW, SetWindowLongPtrW, SetWindowTextW, ShowWindow, TranslateMessage,
-        CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, GWLP_USERDATA, IDC_ARROW, MINMAXINFO,
-        MSG, PM_REMOVE, SWP_NOCOPYBITS, SW_SHOW, WHEEL_DELTA, WINDOWPOS, WINDOW_EX_STYLE, WM_CHAR,
-        WM_CLOSE, WM_CREATE, WM_ERASEBKGND, WM_GETMINMAXINFO, WM_LBUTTONDOWN, WM_LBUTTONUP,
-        WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_PAINT,
-        WM_QUIT, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SIZE, WM_WINDOWPOSCHANGING, WNDCLASSW,
-        WS_OVERLAPPEDWINDOW,
+    Foundation::{
+        GetLastError, BOOL, HINSTANCE, HWND, LPARAM, LRESULT, POINT, PWSTR, RECT, WPARAM,
+    },
+    Graphics::{
+        Dwm::{DwmEnableBlurBehindWindow, DWM_BB_BLURREGION, DWM_BB_ENABLE, DWM_BLURBEHIND},
+        Gdi::{CreateBitmap, CreateRectRgn, DeleteObject, InvalidateRect, ValidateRect, HBITMAP},
+    },
+    System::{
+        Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT},
+        LibraryLoader::GetModuleHandleW,
+    },
+    UI::{
+        HiDpi::GetDpiForWindow,
+        Input::{
+            GetRawInputData,
+            Ime::{
+                ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext, ImmSetCandidateWindow,
+                ImmSetCompositionWindow, CANDIDATEFORM, CFS_CANDIDATEPOS, CFS_POINT,
+                COMPOSITIONFORM, GCS_COMPSTR, GCS_CURSORPOS, GCS_RESULTSTR, HIMC,
+                IME_COMPOSITION_STRING,
+            },
+            KeyboardAndMouse::{
+                GetCapture, GetKeyState, ReleaseCapture, SetCapture, TrackMouseEvent, TME_LEAVE,
+                TRACKMOUSEEVENT, VIRTUAL_KEY, VK_BACK, VK_CONTROL, VK_DELETE, VK_DOWN, VK_END,
+                VK_ESCAPE, VK_HOME, VK_LEFT, VK_MENU, VK_RETURN, VK_RIGHT, VK_SHIFT, VK_SPACE,
+                VK_TAB, VK_UP,
+            },
+            RegisterRawInputDevices, HRAWINPUT, RAWINPUT, RAWINPUTDEVICE, RAWINPUTDEVICE_FLAGS,
+            RAWINPUTHEADER, RAWMOUSE, RIDEV_REMOVE, RID_INPUT,
+        },
+        Shell::{DragAcceptFiles, DragFinish, DragQueryFileW, DragQueryPoint, HDROP},
+        WindowsAndMessaging::{
+            CreateIconIndirect, CreateWindowExW, DefWindowProcW, DestroyIcon, DestroyWindow,
+            DispatchMessageW, GetWindowLongPtrW, GetWindowRect, KillTimer, LoadCursorW,
+            MsgWaitForMultipleObjects, PeekMessageW, PostMessageW, PostQuitMessage, RegisterClassW,
+            SendMessageW, SetCursor, SetLayeredWindowAttributes, SetTimer, SetWindowLongPtrW,
+            SetWindowPos, SetWindowTextW, ShowWindow, SystemParametersInfoW, TranslateMessage,
+            CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, GWLP_USERDATA, GWL_EXSTYLE, HICON, HTCLIENT,
+            ICONINFO, IDC_ARROW, IDC_HAND, IDC_IBEAM, IDC_SIZEALL, IDC_SIZENS, IDC_SIZEWE,
+            LWA_ALPHA, MINMAXINFO, MSG, PM_REMOVE, QS_ALLINPUT, SPI_GETWHEELSCROLLCHARS,
+            SPI_GETWHEELSCROLLLINES, SWP_NOACTIVATE, SWP_NOCOPYBITS, SWP_NOMOVE, SWP_NOSIZE,
+            SW_SHOW, SYSTEM_PARAMETERS_INFO_ACTION, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+            WA_INACTIVE, WHEEL_DELTA, WINDOWPOS, WINDOW_EX_STYLE, WM_ACTIVATE, WM_ACTIVATEAPP,
+            WM_APP, WM_CHAR, WM_CLOSE, WM_DROPFILES, WM_ENDSESSION, WM_ERASEBKGND,
+            WM_GETMINMAXINFO, WM_IME_COMPOSITION, WM_IME_ENDCOMPOSITION, WM_IME_STARTCOMPOSITION,
+            WM_INPUT, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN,
+            WM_MBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_PAINT,
+            WM_QUERYENDSESSION, WM_QUIT, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SETCURSOR, WM_SETICON,
+            WM_SIZE, WM_SYSKEYDOWN, WM_SYSKEYUP, WM_TIMER, WM_WINDOWPOSCHANGING, WNDCLASSW,
+            WS_EX_LAYERED, WS_EX_TOPMOST, WS_OVERLAPPEDWINDOW,
+        },
     },
 };
 
-use super::input::{ButtonState, Event as InputEvent, MouseButton};
+use super::{
+    gamepad::Gamepads,
+    input::{ButtonState, Event as InputEvent, Key, Modifiers, MouseButton},
+    library::system_message,
+};
 use crate::{
     array_vec::ArrayVec,
-    px::Px,
+    gfx::{Image, Rgba8},
+    px::{Physical, Px},
     shapes::{Extent, Point},
 };
 
@@ -31,15 +88,121 @@ const WNDCLASS_NAME: &str = "maple_wndclass";
 /// That is to say: at most 255 bytes, plus the '\0' character.
 pub const MAX_TITLE_LENGTH: usize = 256;
 
+/// The maximum amount of time the application has to respond to a
+/// [`Event::QuitRequested`] caused by the console being closed, or the user
+/// logging off, before the OS terminates the process.
+///
+/// Windows kills the process after about 5 seconds regardless, so this is
+/// kept a little shorter than that.
+pub const QUIT_GRACE_PERIOD: Duration = Duration::from_secs(4);
+
+/// The longest time reported between two updates, see [`Event::Update`].
+pub const MAX_UPDATE_DELTA: Duration = Duration::from_millis(250);
+
+/// The tick used for the first update when the window has neither an update
+/// rate nor a frame rate limit.
+const DEFAULT_UPDATE_TICK: Duration = Duration::from_nanos(16_666_667);
+
+/// How often the gamepads are polled while one of the windows has focus.
+const GAMEPAD_POLL_INTERVAL: Duration = Duration::from_millis(8);
+
+/// Posted to the window by the console control handler, which runs on its own
+/// thread and cannot dispatch events directly.
+const WM_QUIT_REQUESTED: u32 = WM_APP;
+
+/// Sent when the cursor leaves the window, if requested with
+/// `TrackMouseEvent`. This is defined in `Win32::UI::Controls`, which we
+/// don't otherwise need.
+const WM_MOUSELEAVE: u32 = 0x02A3;
+
+/// The `WPARAM` of `WM_SETICON` for the title bar and taskbar icons.
+const ICON_SMALL: usize = 0;
+const ICON_BIG: usize = 1;
+
+/// The HID usage page and usage that identify mice, for raw input.
+const HID_USAGE_PAGE_GENERIC: u16 = 0x01;
+const HID_USAGE_GENERIC_MOUSE: u16 = 0x02;
+
+/// `RAWINPUTHEADER::dwType` for mouse input.
+const RIM_TYPEMOUSE: u32 = 0;
+
+/// Set in `RAWMOUSE::usFlags` when the mouse reports absolute positions
+/// (e.g. tablets and remote desktop sessions) instead of relative motion.
+const MOUSE_MOVE_ABSOLUTE: u16 = 0x01;
+
+/// Passed to `MsgWaitForMultipleObjects` to wait until a message arrives.
+const INFINITE: u32 = u32::MAX;
+
+/// The shortest and longest intervals that `SetTimer` accepts, in
+/// milliseconds.
+const USER_TIMER_MINIMUM: u32 = 0x0000_000A;
+const USER_TIMER_MAXIMUM: u32 = 0x7FFF_FFFF;
+
+/// Passed to `SetWindowPos` to move a window into or out of the topmost band
+/// of windows.
+const HWND_TOPMOST: HWND = HWND(-1);
+const HWND_NOTOPMOST: HWND = HWND(-2);
+
 static REGISTER_CLASS: Once = Once::new();
+static REGISTER_CTRL_HANDLER: Once = Once::new();
 
-#[derive(Debug, Clone, Copy)]
+/// The window that console control events (Ctrl+C, closing the console
+/// window, etc.) are forwarded to. This is reset to 0 once the window's event
+/// loop has exited.
+static QUIT_TARGET: AtomicIsize = AtomicIsize::new(0);
+
+/// The window that raw mouse input is registered for, or 0 if there isn't
+/// one. Registrations are per process, so enabling raw input for a window
+/// takes it away from any other.
+static RAW_MOUSE_TARGET: AtomicIsize = AtomicIsize::new(0);
+
+#[derive(Debug, Clone)]
 pub enum Event {
-    Created { size: Extent },
+    Created {
+        size: Extent,
+    },
     Destroyed {},
+    /// The user has asked to close the window, such as with its close button
+    /// or Alt+F4. Return [`EventLoopControl::Stop`] to close it, or
+    /// [`EventLoopControl::Continue`] to keep it open, for example to ask
+    /// whether unsaved changes should be saved first. A window that is kept
+    /// open can be closed later by returning `Stop` from any other event.
     CloseRequested {},
-    Update { size: Extent, resized: bool },
+    /// The application is being asked to quit by the OS, either because the
+    /// user is logging off or shutting down, or because of a Ctrl+C in the
+    /// console. Save any state that needs to be persisted _now_; return
+    /// [`EventLoopControl::Stop`] to exit gracefully.
+    QuitRequested {},
+    /// The window has become the active window.
+    FocusGained {},
+    /// Another window, possibly from another application, has become the
+    /// active window. Background work can be paused until
+    /// [`Event::FocusGained`] is received.
+    FocusLost {},
+    Update {
+        size: Physical<Extent>,
+        /// The window has been resized since the previous update. However
+        /// many times that happened, only the latest size is reported.
+        resized: bool,
+        /// The time since the previous update, for driving animations. This
+        /// is the nominal tick for the first update, and is capped at
+        /// [`MAX_UPDATE_DELTA`] so that a stall doesn't cause a jump.
+        delta: Duration,
+        /// The sum of every `delta` sent to the window so far.
+        total: Duration,
+    },
     Input(super::input::Event),
+    /// Files were dropped onto the window, which must have been set to accept
+    /// them with [`Control::accept_dropped_files`]. `position` is where they
+    /// were dropped, relative to the window's client area.
+    FilesDropped {
+        paths: Vec<PathBuf>,
+        position: Point,
+    },
+    /// A timer started with [`Control::set_timer`] has elapsed.
+    Timer {
+        id: TimerId,
+    },
 }
 
 #[derive(Debug, PartialEq)]
@@ -55,340 +218,2394 @@ pub struct Handle {
     pub hinstance: HINSTANCE,
 }
 
+/// Lets the window be used with other crates, such as other graphics APIs.
+/// The handle is only valid until the window is destroyed.
+#[cfg(feature = "raw-window-handle")]
+unsafe impl raw_window_handle::HasRawWindowHandle for Handle {
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        let mut handle = raw_window_handle::Win32WindowHandle::empty();
+        handle.hwnd = self.hwnd.0 as *mut _;
+        handle.hinstance = self.hinstance.0 as *mut _;
+        raw_window_handle::RawWindowHandle::Win32(handle)
+    }
+}
+
+#[cfg(feature = "raw-window-handle")]
+unsafe impl raw_window_handle::HasRawDisplayHandle for Handle {
+    fn raw_display_handle(&self) -> raw_window_handle::RawDisplayHandle {
+        raw_window_handle::RawDisplayHandle::Windows(
+            raw_window_handle::WindowsDisplayHandle::empty(),
+        )
+    }
+}
+
 pub trait Control {
     fn handle(&self) -> &Handle;
 
-    fn min_size(&self) -> Extent;
+    fn min_size(&self) -> Physical<Extent>;
 
-    fn set_min_size(&mut self, size: Extent);
+    fn set_min_size(&mut self, size: Physical<Extent>);
+
+    /// The largest size the window may be resized to. A width or height of 0
+    /// means that the window is not limited in that direction.
+    fn max_size(&self) -> Physical<Extent>;
+
+    /// Sets the maximum size of the window. The minimum size is reduced to
+    /// fit if necessary.
+    fn set_max_size(&mut self, size: Physical<Extent>);
+
+    /// The ratio of physical pixels to logical pixels for the display the
+    /// window is on, e.g. 1.5 at 144 DPI. Sizes and positions reported by the
+    /// window are in physical pixels.
+    fn scale_factor(&self) -> f32;
 
     fn set_title(&mut self, s: &str);
+
+    /// Sets the icon shown in the title bar and taskbar. The image must be
+    /// square; the OS scales it to the sizes that it needs.
+    fn set_icon(&mut self, image: &Image<Rgba8>) -> Result<(), IconError>;
+
+    /// Sets the cursor shown while the mouse is over the window. This is
+    /// reset to [`CursorIcon::Arrow`] when the mouse leaves the window.
+    fn set_cursor(&mut self, cursor: CursorIcon);
+
+    /// Captures the mouse so that the window keeps receiving mouse input
+    /// while the cursor is outside of it, such as during a drag. The capture
+    /// is released when `capture` is false, or when the OS takes it away.
+    fn set_mouse_capture(&mut self, capture: bool);
+
+    /// Enables [`InputEvent::RawMouseMotion`] events, which report how far the
+    /// mouse moved before pointer acceleration or the edge of the screen are
+    /// applied. [`InputEvent::CursorMove`] events are still sent. Raw input
+    /// is only delivered while the window has focus, and only one window can
+    /// receive it at a time.
+    fn set_raw_mouse(&mut self, enabled: bool);
+
+    /// Lets whatever is behind the window show through wherever the
+    /// renderer's output is transparent. The renderer must also be created
+    /// with [`RendererOptions::transparent`](crate::gfx::RendererOptions::transparent)
+    /// set, or the window stays opaque.
+    fn set_transparent(&mut self, transparent: bool);
+
+    /// Allows files to be dragged onto the window from Explorer and other
+    /// applications, producing [`Event::FilesDropped`] events. Windows don't
+    /// accept dropped files unless this is enabled.
+    fn accept_dropped_files(&mut self, enabled: bool);
+
+    /// Returns `true` if the window is kept above all windows that aren't.
+    fn is_always_on_top(&self) -> bool;
+
+    /// Keeps the window above all windows that aren't always on top, even
+    /// when it loses focus. The window's position and size are unchanged.
+    fn set_always_on_top(&mut self, on: bool);
+
+    /// Marks the window as needing to be redrawn, so that it is sent an
+    /// [`Event::Update`] even if it has no update rate. Windows that don't
+    /// request a redraw are only updated when they are resized or their
+    /// update timer fires.
+    fn request_redraw(&mut self);
+
+    /// Moves the IME's candidate window, which lists the text that the input
+    /// can be converted to, so that it is next to `caret`. This should be
+    /// called whenever the caret moves while text is being edited.
+    fn set_ime_position(&mut self, caret: Point);
+
+    /// Sends the window an [`Event::Timer`] every `interval` until the timer
+    /// is stopped with [`kill_timer()`](Control::kill_timer) or the window is
+    /// destroyed. Setting a timer that is already running restarts it with
+    /// the new interval. Intervals are rounded up to whole milliseconds, and
+    /// are at least 10ms long.
+    fn set_timer(&mut self, id: u32, interval: Duration) -> TimerId;
+
+    /// Stops a timer started with [`set_timer()`](Control::set_timer). Does
+    /// nothing if the timer isn't running.
+    fn kill_timer(&mut self, id: TimerId);
+
+    /// Attaches application state to the window, such as the document that
+    /// it shows, replacing any that was attached before. The state is
+    /// dropped when the window is destroyed. Use `user_data()` on a
+    /// `dyn Control` to get it back.
+    fn set_user_data(&mut self, data: Box<dyn Any>);
+
+    /// The state attached with [`set_user_data()`](Control::set_user_data),
+    /// if there is any.
+    fn user_data_any(&self) -> Option<&dyn Any>;
+
+    fn user_data_any_mut(&mut self) -> Option<&mut dyn Any>;
+}
+
+impl dyn Control + '_ {
+    /// The state attached with [`set_user_data()`](Control::set_user_data),
+    /// or [`None`] if there isn't any or it isn't a `T`.
+    pub fn user_data<T: 'static>(&self) -> Option<&T> {
+        self.user_data_any()?.downcast_ref()
+    }
+
+    pub fn user_data_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.user_data_any_mut()?.downcast_mut()
+    }
+}
+
+/// Identifies a timer started with [`Control::set_timer`]. Timer IDs are
+/// chosen by the application, and only need to be unique per window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(pub u32);
+
+/// The system cursors that can be shown over a window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorIcon {
+    Arrow,
+    /// A pointing hand, for links.
+    Hand,
+    /// A text cursor.
+    IBeam,
+    /// Resizing horizontally.
+    ResizeWestEast,
+    /// Resizing vertically.
+    ResizeNorthSouth,
+    /// Moving in any direction.
+    ResizeAll,
+}
+
+impl Default for CursorIcon {
+    fn default() -> Self {
+        Self::Arrow
+    }
+}
+
+/// The system cursor resource for `cursor`.
+fn cursor_resource(cursor: CursorIcon) -> PWSTR {
+    match cursor {
+        CursorIcon::Arrow => IDC_ARROW,
+        CursorIcon::Hand => IDC_HAND,
+        CursorIcon::IBeam => IDC_IBEAM,
+        CursorIcon::ResizeWestEast => IDC_SIZEWE,
+        CursorIcon::ResizeNorthSouth => IDC_SIZENS,
+        CursorIcon::ResizeAll => IDC_SIZEALL,
+    }
+}
+
+fn show_cursor(cursor: CursorIcon) {
+    unsafe { SetCursor(LoadCursorW(None, cursor_resource(cursor))) };
+}
+
+/// The OS failed to create a window.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Could not create a window: {}", system_message(*.code))]
+pub struct WindowError {
+    pub code: u32,
 }
 
-pub fn window<Callback>(title: &str, callback: Callback)
+/// An image could not be used as a window icon.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconError {
+    #[error("Window icons must be square, but the image is {width}x{height}.")]
+    NotSquare { width: usize, height: usize },
+    #[error("Window icons cannot be empty.")]
+    Empty,
+    #[error("Could not create the icon: {}", system_message(*.code))]
+    CreateFailed { code: u32 },
+}
+
+/// Identifies one of the windows created by a [`WindowManager`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WindowId(isize);
+
+/// Creates a single window and runs its event loop until it is closed.
+pub fn window<Callback>(title: &str, mut callback: Callback) -> Result<(), WindowError>
 where
     Callback: FnMut(&mut dyn Control, Event) -> EventLoopControl,
 {
-    let mut class_name = to_wstr::<16>(WNDCLASS_NAME);
+    let mut manager =
+        WindowManager::new(move |_, control: &mut dyn Control, event| callback(control, event));
+    manager.create_window(title)?;
+    manager.run();
+    Ok(())
+}
+
+/// Owns the event loop shared by any number of top-level windows. Each event
+/// is passed to the callback along with the [`WindowId`] of the window it was
+/// sent to.
+///
+/// Returning [`EventLoopControl::Stop`] from the callback closes the window
+/// that the event was for, or every window in response to
+/// [`Event::QuitRequested`]. The event loop runs until the last window has
+/// been closed.
+pub struct WindowManager<Callback>
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    // Boxed so that its address can be stored in each window's user data.
+    shared: Box<RefCell<Windows<Callback>>>,
+}
 
-    let hinstance = unsafe { GetModuleHandleW(None) };
-    assert_ne!(hinstance, HINSTANCE::default());
+impl<Callback> WindowManager<Callback>
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    pub fn new(callback: Callback) -> Self {
+        Self {
+            shared: Box::new(RefCell::new(Windows::new(callback))),
+        }
+    }
 
-    REGISTER_CLASS.call_once(|| {
-        let cursor = unsafe { LoadCursorW(None, &IDC_ARROW) };
+    /// Sends [`Event::Update`] to each window `updates_per_second` times a
+    /// second while it has focus, and `background_updates_per_second` times a
+    /// second while it doesn't. A rate of 0 means that the window is only
+    /// updated when it is resized or [`Control::request_redraw`] is called,
+    /// which is the default.
+    ///
+    /// Gamepads are polled by the event loop while a window has focus,
+    /// whatever the update rate.
+    pub fn set_update_rate(&mut self, updates_per_second: u32, background_updates_per_second: u32) {
+        let mut shared = self.shared.borrow_mut();
+        shared.updates_per_second = updates_per_second;
+        shared.background_updates_per_second = background_updates_per_second;
 
-        let class = WNDCLASSW {
-            style: CS_VREDRAW | CS_HREDRAW, /*| CS_DBLCLKS // for double clicks */
-            hInstance: hinstance,
-            lpfnWndProc: Some(wndproc_trampoline::<Callback>),
-            lpszClassName: PWSTR(class_name.as_mut_ptr()),
-            hCursor: cursor,
-            ..WNDCLASSW::default()
-        };
+        let now = Instant::now();
+        let ids = shared.states.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            shared.schedule_ticks(id, now);
+        }
+    }
 
-        let _ = unsafe { RegisterClassW(&class) };
-    });
+    /// Limits how often each window is sent [`Event::Update`], waiting out
+    /// the rest of the frame if an update arrives early. `None` means that
+    /// updates are only limited by how quickly the swapchain presents.
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        let mut shared = self.shared.borrow_mut();
+        shared.max_fps = max_fps;
 
-    let hwnd = {
-        let mut w_title = to_wstr::<MAX_TITLE_LENGTH>(title);
-        unsafe {
-            CreateWindowExW(
-                WINDOW_EX_STYLE::default(),
-                PWSTR(class_name.as_ptr() as *mut _),
-                PWSTR(w_title.as_mut_ptr()),
-                WS_OVERLAPPEDWINDOW,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                None,
-                None,
-                GetModuleHandleW(None),
-                std::ptr::null_mut(),
-            )
+        for state in shared.states.values_mut() {
+            state.frame_limiter = FrameLimiter::new(max_fps);
         }
-    };
+    }
+
+    /// The number of windows that are open.
+    pub fn num_windows(&self) -> usize {
+        self.shared.borrow().states.len()
+    }
+
+    pub fn create_window(&mut self, title: &str) -> Result<WindowId, WindowError> {
+        let mut class_name = to_wstr::<16>(WNDCLASS_NAME);
 
-    let window = RefCell::new(Window {
-        callback,
-        state: WindowState {
+        let hinstance = unsafe { GetModuleHandleW(None) };
+        assert_ne!(hinstance, HINSTANCE::default());
+
+        REGISTER_CLASS.call_once(|| {
+            let cursor = unsafe { LoadCursorW(None, &IDC_ARROW) };
+
+            let class = WNDCLASSW {
+                style: CS_VREDRAW | CS_HREDRAW, /*| CS_DBLCLKS // for double clicks */
+                hInstance: hinstance,
+                lpfnWndProc: Some(wndproc_trampoline::<Callback>),
+                lpszClassName: PWSTR(class_name.as_mut_ptr()),
+                hCursor: cursor,
+                ..WNDCLASSW::default()
+            };
+
+            let _ = unsafe { RegisterClassW(&class) };
+        });
+
+        let hwnd = {
+            let mut w_title = to_wstr::<MAX_TITLE_LENGTH>(title);
+            unsafe {
+                CreateWindowExW(
+                    WINDOW_EX_STYLE::default(),
+                    PWSTR(class_name.as_ptr() as *mut _),
+                    PWSTR(w_title.as_mut_ptr()),
+                    WS_OVERLAPPEDWINDOW,
+                    CW_USEDEFAULT,
+                    CW_USEDEFAULT,
+                    CW_USEDEFAULT,
+                    CW_USEDEFAULT,
+                    None,
+                    None,
+                    GetModuleHandleW(None),
+                    std::ptr::null_mut(),
+                )
+            }
+        };
+
+        if hwnd == HWND::default() {
+            return Err(WindowError {
+                code: unsafe { GetLastError() }.0,
+            });
+        }
+
+        let frame_limiter = FrameLimiter::new(self.shared.borrow().max_fps);
+        let id = self.shared.borrow_mut().insert(WindowState {
             high_surrogate: 0,
             handle: Handle { hwnd, hinstance },
             min_size: Extent::default(),
+            max_size: Extent::default(),
             size: Extent::default(),
-        },
-    });
+            cursor: CursorIcon::Arrow,
+            is_tracking_mouse: false,
+            is_transparent: false,
+            is_focused: false,
+            frame_limiter,
+            update_clock: UpdateClock::default(),
+            icon: None,
+            needs_redraw: false,
+            pending_resize: false,
+            next_tick: None,
+            user_data: None,
+            timers: Timers::default(),
+        });
 
-    {
-        let mut rect = RECT::default();
-        unsafe { GetWindowRect(hwnd, &mut rect) };
-
-        let width = (rect.right - rect.left)
-            .try_into()
-            .expect("Window width is negative or > 65535");
-        let height = (rect.bottom - rect.top)
-            .try_into()
-            .expect("Window heigth is negative or > 65535");
-        window.borrow_mut().dispatch(Event::Created {
-            size: Extent {
-                width: Px(width),
-                height: Px(height),
-            },
+        {
+            let mut rect = RECT::default();
+            unsafe { GetWindowRect(hwnd, &mut rect) };
+
+            let width = (rect.right - rect.left)
+                .try_into()
+                .expect("Window width is negative or > 65535");
+            let height = (rect.bottom - rect.top)
+                .try_into()
+                .expect("Window heigth is negative or > 65535");
+            self.shared.borrow_mut().dispatch(
+                id,
+                Event::Created {
+                    size: Extent {
+                        width: Px(width),
+                        height: Px(height),
+                    },
+                },
+            );
+        }
+
+        let _ = QUIT_TARGET.compare_exchange(0, hwnd.0, Ordering::AcqRel, Ordering::Acquire);
+        REGISTER_CTRL_HANDLER.call_once(|| unsafe {
+            SetConsoleCtrlHandler(Some(console_ctrl_handler), true);
         });
+
+        unsafe {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, &*self.shared as *const _ as _);
+            ShowWindow(hwnd, SW_SHOW);
+        }
+
+        self.shared.borrow_mut().schedule_ticks(id, Instant::now());
+
+        // The window may have been closed in response to being created.
+        finish_closing(&self.shared);
+        Ok(id)
     }
 
-    let mut msg = MSG::default();
+    /// Runs the event loop until every window has been closed.
+    ///
+    /// The thread sleeps until a message arrives or a window is due an
+    /// update, so an idle application uses no CPU time. Queued messages are
+    /// always handled before updates so that input is never held up by them.
+    ///
+    /// While a window is being moved or resized, Windows runs its own message
+    /// loop and updates at the window's tick rate are paused. Resizing still
+    /// updates the window, but only once for however many sizes it went
+    /// through while the previous update was being handled.
+    pub fn run(self) {
+        if self.num_windows() == 0 {
+            return;
+        }
+
+        let mut msg = MSG::default();
 
-    unsafe {
-        SetWindowLongPtrW(hwnd, GWLP_USERDATA, &window as *const _ as _);
-        ShowWindow(hwnd, SW_SHOW);
         loop {
-            let ret = GetMessageW(&mut msg, None, 0, 0).0;
-            if ret == -1 {
-                panic!("GetMessage failed. Error: {:?}", GetLastError());
-            } else if ret == 0 {
-                break;
-            } else {
-                TranslateMessage(&msg);
-                DispatchMessageW(&msg);
+            unsafe {
+                while PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).as_bool() {
+                    if msg.message == WM_QUIT {
+                        return;
+                    }
+
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
             }
 
-            while PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).into() {
-                if msg.message == WM_QUIT {
-                    DestroyWindow(hwnd);
-                    return;
+            let now = Instant::now();
+            let gamepad_input = self.shared.borrow_mut().poll_gamepads(now);
+            if let Some((id, events)) = gamepad_input {
+                for event in events {
+                    self.shared.borrow_mut().dispatch(id, Event::Input(event));
                 }
-                TranslateMessage(&msg);
-                DispatchMessageW(&msg);
+                finish_closing(&self.shared);
             }
-        }
 
-        DestroyWindow(window.borrow().state.handle.hwnd);
-        PostQuitMessage(0);
-    }
+            let due = self.shared.borrow_mut().take_due_ticks(now);
+            for id in due {
+                update_window(&self.shared, id);
+                finish_closing(&self.shared);
+            }
 
-    window.borrow_mut().dispatch(Event::Destroyed {});
+            let now = Instant::now();
+            let wake = self.shared.borrow().next_wake();
+            let timeout = wait_timeout_ms(now, wake);
+            if timeout > 0 {
+                // Returns early when any message is posted, sent or input.
+                unsafe {
+                    MsgWaitForMultipleObjects(0, std::ptr::null(), false, timeout, QS_ALLINPUT)
+                };
+            }
+        }
+    }
 }
 
-struct Window<Callback>
+impl<Callback> Drop for WindowManager<Callback>
 where
-    Callback: FnMut(&mut dyn Control, Event) -> EventLoopControl,
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
 {
-    callback: Callback,
-    state: WindowState,
+    fn drop(&mut self) {
+        self.shared.borrow_mut().close_all();
+        finish_closing(&self.shared);
+        QUIT_TARGET.store(0, Ordering::Release);
+    }
 }
 
-struct WindowState {
-    handle: Handle,
-    high_surrogate: u16,
-    min_size: Extent,
-    size: Extent,
+/// The state shared between the [`WindowManager`] and its windows' window
+/// procedures.
+struct Windows<Callback>
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    callback: Callback,
+    states: HashMap<WindowId, WindowState>,
+    /// Windows that the callback has asked to close. They are destroyed once
+    /// the callback has returned to avoid re-entering the window procedure.
+    closing: Vec<WindowId>,
+    updates_per_second: u32,
+    background_updates_per_second: u32,
+    max_fps: Option<u32>,
+    gamepads: Gamepads,
+    /// When the gamepads may next be polled, or `None` if they haven't been.
+    next_gamepad_poll: Option<Instant>,
 }
 
-impl Control for WindowState {
-    fn handle(&self) -> &Handle {
-        &self.handle
+impl<Callback> Windows<Callback>
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    fn new(callback: Callback) -> Self {
+        Self {
+            callback,
+            states: HashMap::new(),
+            closing: Vec::new(),
+            updates_per_second: 0,
+            background_updates_per_second: 0,
+            max_fps: None,
+            gamepads: Gamepads::new(),
+            next_gamepad_poll: None,
+        }
     }
 
-    fn min_size(&self) -> Extent {
-        self.min_size
+    fn insert(&mut self, state: WindowState) -> WindowId {
+        let id = WindowId(state.handle.hwnd.0);
+        self.states.insert(id, state);
+        id
     }
 
-    fn set_min_size(&mut self, size: Extent) {
-        self.min_size = size;
+    fn dispatch(&mut self, id: WindowId, event: Event) {
+        let state = match self.states.get_mut(&id) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let is_quit = matches!(event, Event::QuitRequested {});
+        if (self.callback)(id, state, event) == EventLoopControl::Stop {
+            if is_quit {
+                self.close_all();
+            } else if !self.closing.contains(&id) {
+                self.closing.push(id);
+            }
+        }
     }
 
-    fn set_title(&mut self, s: &str) {
-        let mut text = to_wstr::<MAX_TITLE_LENGTH>(s);
-        unsafe {
-            SetWindowTextW(self.handle.hwnd, PWSTR(text.as_mut_ptr()));
+    /// Records whether the window is the active window, and dispatches
+    /// [`Event::FocusGained`] or [`Event::FocusLost`] if that changed. Returns
+    /// `true` if it did.
+    fn set_focused(&mut self, id: WindowId, is_focused: bool) -> bool {
+        match self.states.get_mut(&id) {
+            Some(state) if state.is_focused != is_focused => state.is_focused = is_focused,
+            _ => return false,
         }
+
+        self.dispatch(
+            id,
+            if is_focused {
+                Event::FocusGained {}
+            } else {
+                Event::FocusLost {}
+            },
+        );
+        true
     }
-}
 
-impl<Callback> Window<Callback>
-where
-    Callback: FnMut(&mut dyn Control, Event) -> EventLoopControl,
-{
-    fn dispatch(&mut self, event: Event) {
-        let op = (self.callback)(&mut self.state, event);
+    /// How often the window should be sent [`Event::Update`], if at all.
+    fn update_interval(&self, id: WindowId) -> Option<Duration> {
+        let is_focused = self.states.get(&id)?.is_focused;
+        tick_interval(
+            is_focused,
+            self.updates_per_second,
+            self.background_updates_per_second,
+        )
+    }
 
-        if op == EventLoopControl::Stop {
-            unsafe { PostQuitMessage(0) };
+    /// Starts sending [`Event::Update`] to the window at its tick rate, from
+    /// `now`. Called whenever the rate changes.
+    fn schedule_ticks(&mut self, id: WindowId, now: Instant) {
+        let interval = self.update_interval(id);
+        if let Some(state) = self.states.get_mut(&id) {
+            state.next_tick = interval.map(|interval| now + interval);
         }
     }
-}
 
-unsafe extern "system" fn wndproc_trampoline<Callback>(
-    hwnd: HWND,
-    msg: u32,
-    wparam: WPARAM,
-    lparam: LPARAM,
-) -> LRESULT
-where
-    Callback: FnMut(&mut dyn Control, Event) -> EventLoopControl,
-{
-    let window_ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const RefCell<Window<Callback>>;
+    /// Returns the windows that are due an update at their tick rate, and
+    /// schedules their next one. Ticks that were missed are skipped rather
+    /// than caught up on.
+    fn take_due_ticks(&mut self, now: Instant) -> Vec<WindowId> {
+        let (rate, background_rate) = (self.updates_per_second, self.background_updates_per_second);
+        let mut due = vec![];
 
-    if window_ptr.is_null() {
-        DefWindowProcW(hwnd, msg, wparam, lparam)
-    } else {
-        let window = &(*window_ptr);
+        for (&id, state) in &mut self.states {
+            let tick = match state.next_tick {
+                Some(tick) if tick <= now => tick,
+                _ => continue,
+            };
 
-        match msg {
-            WM_CREATE => {
-                let createstruct = &(*(lparam.0 as *const CREATESTRUCTW));
-                let width = createstruct
-                    .cx
-                    .try_into()
-                    .expect("Window width out of bounds!");
-                let height = createstruct
-                    .cy
-                    .try_into()
-                    .expect("Window height out of bounds!");
-                window.borrow_mut().dispatch(Event::Created {
-                    size: Extent {
-                        width: Px(width),
-                        height: Px(height),
-                    },
+            due.push(id);
+            state.next_tick =
+                tick_interval(state.is_focused, rate, background_rate).map(|interval| {
+                    if tick + interval > now {
+                        tick + interval
+                    } else {
+                        now + interval
+                    }
                 });
-            }
-            WM_CLOSE => {
-                window.borrow_mut().dispatch(Event::CloseRequested {});
-            }
-            WM_GETMINMAXINFO => {
-                let pointer = lparam.0 as *mut MINMAXINFO;
-                let min = window.borrow().state.min_size;
-                (*pointer).ptMinTrackSize = POINT {
-                    x: min.width.0.into(),
-                    y: min.height.0.into(),
-                };
-            }
-            // WM_DESTROY is not handled. We send out the Event::Destroyed
-            // message once we exit the event loop instead to avoid a re-entrant
-            // call to window.borrow_mut();
-            WM_SIZE => {
-                // LOWORD and HIWORD (i16s for historical reasons, I guess)
-                let width = (lparam.0 as i16)
-                    .try_into()
-                    .expect("Window width is negative or > 65535");
-                let height = ((lparam.0 >> i16::BITS) as i16)
-                    .try_into()
-                    .expect("Window height is negative or > 65535");
+        }
 
-                let mut window_mut = window.borrow_mut();
-                window_mut.state.size = Extent { width, height };
-                window_mut.dispatch(Event::Update {
-                    size: Extent { width, height },
-                    resized: true,
-                });
-            }
-            WM_ERASEBKGND => {
-                /* No op, as recommended here:
-                  https://stackoverflow.com/questions/53000291/how-to-smooth-ugly-jitter-flicker-jumping-when-resizing-windows-especially-drag
-                */
-                return LRESULT(1);
-            }
-            WM_WINDOWPOSCHANGING => {
-                let pos = lparam.0 as *mut WINDOWPOS;
-                (*pos).flags |= SWP_NOCOPYBITS;
-            }
-            WM_MOUSEMOVE => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::CursorMove {
-                    position: Point::new(Px(lparam.0 as i16), Px((lparam.0 >> 16) as i16)),
-                })),
-            WM_LBUTTONDOWN => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Left,
-                    state: ButtonState::Pressed,
-                })),
-            WM_LBUTTONUP => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Left,
-                    state: ButtonState::Released,
-                })),
-            WM_MBUTTONDOWN => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Middle,
-                    state: ButtonState::Pressed,
-                })),
-            WM_MBUTTONUP => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Middle,
-                    state: ButtonState::Released,
-                })),
-            WM_RBUTTONDOWN => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Right,
-                    state: ButtonState::Pressed,
-                })),
-            WM_RBUTTONUP => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::MouseButton {
-                    button: MouseButton::Right,
-                    state: ButtonState::Released,
-                })),
-            WM_MOUSEWHEEL => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::ScrollWheel {
-                    x: 0.0,
-                    y: (wparam.0 >> 16) as i16 as f32 / (WHEEL_DELTA as f32),
-                })),
-            WM_MOUSEHWHEEL => window
-                .borrow_mut()
-                .dispatch(Event::Input(InputEvent::ScrollWheel {
-                    x: (wparam.0 >> 16) as i16 as f32 / (WHEEL_DELTA as f32),
-                    y: 0.0,
-                })),
-            WM_CHAR => {
-                let mut window_mut = window.borrow_mut();
-                if (wparam.0 & 0xD800) == 0xD800 {
-                    window_mut.state.high_surrogate = wparam.0 as u16;
-                } else {
-                    let codepoint = char::from_u32(if (wparam.0 & 0xDC00) == 0xDC00 {
-                        (((window_mut.state.high_surrogate as u32 - 0xD800) << 10)
-                            | (wparam.0 as u32 - 0xDC00))
-                            + 0x10000
-                    } else {
-                        wparam.0 as u32
-                    })
-                    .unwrap();
+        due
+    }
 
-                    window_mut.dispatch(Event::Input(InputEvent::Char { codepoint }));
-                }
-            }
-            WM_PAINT => {
-                let mut window_mut = window.borrow_mut();
-                let size = window_mut.state.size;
-                window_mut.dispatch(Event::Update {
-                    size,
-                    resized: false,
-                });
+    /// The window that has focus, which receives gamepad input.
+    fn focused(&self) -> Option<WindowId> {
+        self.states
+            .iter()
+            .find(|(_, state)| state.is_focused)
+            .map(|(&id, _)| id)
+    }
+
+    /// Polls the gamepads if a window has focus and they haven't been polled
+    /// in the last [`GAMEPAD_POLL_INTERVAL`], returning what changed along
+    /// with the window to send it to.
+    fn poll_gamepads(&mut self, now: Instant) -> Option<(WindowId, Vec<InputEvent>)> {
+        let id = self.focused()?;
+        if self.next_gamepad_poll.map_or(false, |next| now < next) {
+            return None;
+        }
+
+        self.next_gamepad_poll = Some(now + GAMEPAD_POLL_INTERVAL);
+        Some((id, self.gamepads.poll(now)))
+    }
+
+    /// When the event loop next has to wake up without a message to update a
+    /// window or poll the gamepads. Windows that need to be redrawn don't
+    /// count, since they have been invalidated and will receive `WM_PAINT`,
+    /// which wakes the loop. Nor do disconnected gamepads, since plugging one
+    /// in sends `WM_DEVICECHANGE`.
+    fn next_wake(&self) -> Option<Instant> {
+        let gamepads = self
+            .focused()
+            .filter(|_| self.gamepads.is_any_connected())
+            .and(self.next_gamepad_poll);
+        next_wake(
+            self.states
+                .values()
+                .map(|state| state.next_tick)
+                .chain(std::iter::once(gamepads)),
+        )
+    }
+
+    /// Records the window's new size, and marks it as needing a redraw so
+    /// that it is updated with the size. The size isn't applied until then,
+    /// so resizing several times before the window is updated results in a
+    /// single update.
+    ///
+    /// A minimized window is resized to 0x0, but is never painted, so it isn't
+    /// marked as needing a redraw. The size is reported once it is restored.
+    fn resize(&mut self, id: WindowId, size: Extent) {
+        if let Some(state) = self.states.get_mut(&id) {
+            state.size = size;
+            state.pending_resize = true;
+            if size != Extent::default() {
+                state.request_redraw();
             }
-            _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
         }
+    }
 
-        LRESULT::default()
+    /// Prepares to send [`Event::Update`] to the window. The window is no
+    /// longer marked as needing a redraw or as having been resized.
+    fn begin_update(&mut self, id: WindowId, now: Instant) -> Option<PendingUpdate> {
+        let update_interval = self.update_interval(id);
+        let state = self.states.get_mut(&id)?;
+        state.needs_redraw = false;
+        let resized = std::mem::take(&mut state.pending_resize);
+
+        Some(PendingUpdate {
+            size: state.size,
+            resized,
+            delay: state.frame_limiter.schedule(now),
+            tick: update_interval
+                .or(state.frame_limiter.interval)
+                .unwrap_or(DEFAULT_UPDATE_TICK),
+        })
     }
-}
 
-fn to_wstr<const MAX_LENGTH: usize>(s: &str) -> ArrayVec<u16, MAX_LENGTH> {
-    assert!(MAX_LENGTH > 0);
+    fn close_all(&mut self) {
+        self.closing.clear();
+        self.closing.extend(self.states.keys().copied());
+    }
 
-    let mut buffer = s.encode_utf16().collect::<ArrayVec<_, MAX_LENGTH>>();
-    let len = buffer.len();
+    /// Removes the window and dispatches [`Event::Destroyed`] for it. Returns
+    /// `true` if it was the last window.
+    fn remove(&mut self, id: WindowId) -> bool {
+        if let Some(mut state) = self.states.remove(&id) {
+            let _ = (self.callback)(id, &mut state, Event::Destroyed {});
+        }
 
-    if len == buffer.capacity() {
-        buffer[len - 1] = 0;
-    } else {
-        buffer.push(0);
+        self.states.is_empty()
     }
+}
 
-    buffer
+/// What [`Windows::begin_update`] found out about a window that is about to be
+/// updated.
+struct PendingUpdate {
+    size: Extent,
+    /// Whether the window has been resized since its last update.
+    resized: bool,
+    /// How long to wait before sending the update, to limit the frame rate.
+    delay: Duration,
+    /// The nominal time between updates, used for the first update's delta.
+    tick: Duration,
+}
+
+/// Sends [`Event::Update`] to the window.
+fn update_window<Callback>(shared: &RefCell<Windows<Callback>>, id: WindowId)
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    let pending = shared.borrow_mut().begin_update(id, Instant::now());
+
+    if let Some(PendingUpdate {
+        size,
+        resized,
+        delay,
+        tick,
+    }) = pending
+    {
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+
+        let (delta, total) = match shared.borrow_mut().states.get_mut(&id) {
+            Some(state) => state.update_clock.tick(Instant::now(), tick),
+            None => return,
+        };
+
+        shared.borrow_mut().dispatch(
+            id,
+            Event::Update {
+                size: Physical(size),
+                resized,
+                delta,
+                total,
+            },
+        );
+    }
+}
+
+/// Destroys the windows that the callback has asked to close, and ends the
+/// event loop if none are left.
+fn finish_closing<Callback>(shared: &RefCell<Windows<Callback>>)
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    loop {
+        let id = match shared.borrow_mut().closing.pop() {
+            Some(id) => id,
+            None => break,
+        };
+
+        if !shared.borrow().states.contains_key(&id) {
+            continue;
+        }
+
+        // This sends WM_DESTROY, so the window must not be borrowed here.
+        unsafe { DestroyWindow(HWND(id.0)) };
+
+        let is_last = shared.borrow_mut().remove(id);
+
+        let next_target = shared.borrow().states.keys().next().map_or(0, |id| id.0);
+        let _ =
+            QUIT_TARGET.compare_exchange(id.0, next_target, Ordering::AcqRel, Ordering::Acquire);
+
+        if is_last {
+            unsafe { PostQuitMessage(0) };
+        }
+    }
+}
+
+struct WindowState {
+    handle: Handle,
+    high_surrogate: u16,
+    min_size: Extent,
+    max_size: Extent,
+    size: Extent,
+    cursor: CursorIcon,
+    /// Set while the mouse is over the window and `WM_MOUSELEAVE` has been
+    /// requested.
+    is_tracking_mouse: bool,
+    is_transparent: bool,
+    is_focused: bool,
+    frame_limiter: FrameLimiter,
+    update_clock: UpdateClock,
+    /// The icon set with [`Control::set_icon`], which is destroyed along with
+    /// the window.
+    icon: Option<HICON>,
+    /// Set by [`Control::request_redraw`], and cleared by the next update.
+    needs_redraw: bool,
+    /// Set when the window's size changes, and cleared by the next update.
+    pending_resize: bool,
+    /// When the window is next due an update at its tick rate, if it has one.
+    next_tick: Option<Instant>,
+    /// Set by [`Control::set_user_data`], and dropped with the window.
+    user_data: Option<Box<dyn Any>>,
+    timers: Timers,
+}
+
+impl Drop for WindowState {
+    fn drop(&mut self) {
+        // The registration would otherwise outlive the window.
+        self.set_raw_mouse(false);
+
+        if let Some(icon) = self.icon.take() {
+            unsafe {
+                DestroyIcon(icon);
+            }
+        }
+
+        for id in self.timers.take_all() {
+            unsafe { KillTimer(self.handle.hwnd, id.0 as usize) };
+        }
+    }
+}
+
+/// The timers that are running for a window.
+#[derive(Debug, Default)]
+struct Timers {
+    running: Vec<TimerId>,
+}
+
+impl Timers {
+    fn start(&mut self, id: TimerId) {
+        if !self.running.contains(&id) {
+            self.running.push(id);
+        }
+    }
+
+    /// Returns `true` if the timer was running.
+    fn stop(&mut self, id: TimerId) -> bool {
+        let len = self.running.len();
+        self.running.retain(|running| *running != id);
+        self.running.len() != len
+    }
+
+    fn take_all(&mut self) -> Vec<TimerId> {
+        std::mem::take(&mut self.running)
+    }
+}
+
+/// Spaces out updates so that there are at most `max_fps` of them a second.
+#[derive(Debug, Default)]
+struct FrameLimiter {
+    interval: Option<Duration>,
+    next_frame: Option<Instant>,
+}
+
+impl FrameLimiter {
+    fn new(max_fps: Option<u32>) -> Self {
+        Self {
+            interval: max_fps
+                .filter(|&fps| fps > 0)
+                .map(|fps| Duration::from_secs(1) / fps),
+            next_frame: None,
+        }
+    }
+
+    /// Schedules a frame for `now` or later, and returns how long to wait
+    /// before it begins.
+    fn schedule(&mut self, now: Instant) -> Duration {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return Duration::ZERO,
+        };
+
+        let frame = match self.next_frame {
+            // Don't try to catch up after falling more than a frame behind
+            // (or waking from sleep), start again from now instead.
+            Some(next) if next + interval > now => next.max(now),
+            _ => now,
+        };
+
+        self.next_frame = Some(frame + interval);
+        frame - now
+    }
+}
+
+/// Measures the time between updates for [`Event::Update`].
+#[derive(Debug, Default)]
+struct UpdateClock {
+    last_update: Option<Instant>,
+    total: Duration,
+}
+
+impl UpdateClock {
+    /// Records an update at `now`, and returns the time since the last one
+    /// along with the running total. The first update is `tick` long.
+    fn tick(&mut self, now: Instant, tick: Duration) -> (Duration, Duration) {
+        let delta = match self.last_update {
+            Some(last) => now.saturating_duration_since(last),
+            None => tick,
+        }
+        .min(MAX_UPDATE_DELTA);
+
+        self.last_update = Some(now);
+        self.total += delta;
+        (delta, self.total)
+    }
+}
+
+impl Control for WindowState {
+    fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    fn min_size(&self) -> Physical<Extent> {
+        Physical(self.min_size)
+    }
+
+    fn set_min_size(&mut self, size: Physical<Extent>) {
+        self.min_size = clamp_min_size(size.0, self.max_size);
+    }
+
+    fn max_size(&self) -> Physical<Extent> {
+        Physical(self.max_size)
+    }
+
+    fn set_max_size(&mut self, size: Physical<Extent>) {
+        self.max_size = size.0;
+        self.min_size = clamp_min_size(self.min_size, size.0);
+    }
+
+    fn scale_factor(&self) -> f32 {
+        dpi_to_scale(unsafe { GetDpiForWindow(self.handle.hwnd) })
+    }
+
+    fn set_title(&mut self, s: &str) {
+        let mut text = to_wstr::<MAX_TITLE_LENGTH>(s);
+        unsafe {
+            SetWindowTextW(self.handle.hwnd, PWSTR(text.as_mut_ptr()));
+        }
+    }
+
+    fn set_icon(&mut self, image: &Image<Rgba8>) -> Result<(), IconError> {
+        let (color, mask) = icon_bitmaps(image)?;
+        let size = image.width() as i32;
+
+        let icon = unsafe {
+            let info = ICONINFO {
+                fIcon: BOOL(1),
+                xHotspot: 0,
+                yHotspot: 0,
+                hbmMask: CreateBitmap(size, size, 1, 1, mask.as_ptr() as _),
+                hbmColor: CreateBitmap(size, size, 1, 32, color.as_ptr() as _),
+            };
+
+            let icon = CreateIconIndirect(&info);
+            let code = GetLastError().0;
+
+            // The icon has its own copy of the bitmaps.
+            for bitmap in [info.hbmMask, info.hbmColor] {
+                if bitmap != HBITMAP::default() {
+                    DeleteObject(bitmap);
+                }
+            }
+
+            if icon == HICON::default() {
+                return Err(IconError::CreateFailed { code });
+            }
+
+            icon
+        };
+
+        unsafe {
+            let hwnd = self.handle.hwnd;
+            SendMessageW(hwnd, WM_SETICON, WPARAM(ICON_SMALL), LPARAM(icon.0));
+            SendMessageW(hwnd, WM_SETICON, WPARAM(ICON_BIG), LPARAM(icon.0));
+
+            if let Some(previous) = self.icon.replace(icon) {
+                DestroyIcon(previous);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request_redraw(&mut self) {
+        if !self.needs_redraw {
+            self.needs_redraw = true;
+            unsafe {
+                InvalidateRect(self.handle.hwnd, std::ptr::null(), false);
+            }
+        }
+    }
+
+    fn set_ime_position(&mut self, caret: Point) {
+        let position = POINT {
+            x: caret.x.into(),
+            y: caret.y.into(),
+        };
+
+        unsafe {
+            let context = ImmGetContext(self.handle.hwnd);
+            if context == HIMC::default() {
+                return;
+            }
+
+            let composition = COMPOSITIONFORM {
+                dwStyle: CFS_POINT,
+                ptCurrentPos: position,
+                rcArea: RECT::default(),
+            };
+            ImmSetCompositionWindow(context, &composition);
+
+            let candidate = CANDIDATEFORM {
+                dwIndex: 0,
+                dwStyle: CFS_CANDIDATEPOS,
+                ptCurrentPos: position,
+                rcArea: RECT::default(),
+            };
+            ImmSetCandidateWindow(context, &candidate);
+
+            ImmReleaseContext(self.handle.hwnd, context);
+        }
+    }
+
+    fn set_timer(&mut self, id: u32, interval: Duration) -> TimerId {
+        let id = TimerId(id);
+        unsafe {
+            SetTimer(
+                self.handle.hwnd,
+                id.0 as usize,
+                timer_interval_ms(interval),
+                None,
+            );
+        }
+        self.timers.start(id);
+        id
+    }
+
+    fn kill_timer(&mut self, id: TimerId) {
+        if self.timers.stop(id) {
+            unsafe { KillTimer(self.handle.hwnd, id.0 as usize) };
+        }
+    }
+
+    fn set_user_data(&mut self, data: Box<dyn Any>) {
+        self.user_data = Some(data);
+    }
+
+    fn user_data_any(&self) -> Option<&dyn Any> {
+        self.user_data.as_deref()
+    }
+
+    fn user_data_any_mut(&mut self) -> Option<&mut dyn Any> {
+        self.user_data.as_deref_mut()
+    }
+
+    fn set_cursor(&mut self, cursor: CursorIcon) {
+        // WM_SETCURSOR arrives before the mouse move that prompted the change,
+        // so apply it now if the mouse is over the window.
+        if self.cursor != cursor && self.is_tracking_mouse {
+            show_cursor(cursor);
+        }
+        self.cursor = cursor;
+    }
+
+    fn set_mouse_capture(&mut self, capture: bool) {
+        // The capture can be taken away at any time, including while the
+        // window's state is borrowed and can't be told, so ask who has it.
+        let has_capture = unsafe { GetCapture() }.0 == self.handle.hwnd.0;
+        if capture == has_capture {
+            return;
+        }
+
+        unsafe {
+            if capture {
+                SetCapture(self.handle.hwnd);
+            } else {
+                ReleaseCapture();
+            }
+        }
+    }
+
+    fn set_transparent(&mut self, transparent: bool) {
+        if transparent == self.is_transparent {
+            return;
+        }
+
+        self.is_transparent = transparent;
+        let hwnd = self.handle.hwnd;
+        unsafe {
+            let style = GetWindowLongPtrW(hwnd, GWL_EXSTYLE);
+            let layered = WS_EX_LAYERED.0 as isize;
+            if transparent {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, style | layered);
+                // A layered window isn't drawn at all until its attributes
+                // are set. Fully opaque, since the alpha comes from DWM.
+                SetLayeredWindowAttributes(hwnd, 0, u8::MAX, LWA_ALPHA);
+            } else {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, style & !layered);
+            }
+
+            // Blurring behind an empty region makes DWM composite the window
+            // with its per-pixel alpha, without actually blurring anything.
+            let region = CreateRectRgn(0, 0, -1, -1);
+            let blur = DWM_BLURBEHIND {
+                dwFlags: DWM_BB_ENABLE | DWM_BB_BLURREGION,
+                fEnable: transparent.into(),
+                hRgnBlur: region,
+                fTransitionOnMaximized: false.into(),
+            };
+            let _ = DwmEnableBlurBehindWindow(hwnd, &blur);
+            DeleteObject(region);
+        }
+    }
+
+    fn accept_dropped_files(&mut self, enabled: bool) {
+        unsafe { DragAcceptFiles(self.handle.hwnd, enabled) };
+    }
+
+    fn is_always_on_top(&self) -> bool {
+        is_topmost(unsafe { GetWindowLongPtrW(self.handle.hwnd, GWL_EXSTYLE) })
+    }
+
+    fn set_always_on_top(&mut self, on: bool) {
+        if on == self.is_always_on_top() {
+            return;
+        }
+
+        // The OS sets WS_EX_TOPMOST itself when the window changes bands.
+        unsafe {
+            SetWindowPos(
+                self.handle.hwnd,
+                if on { HWND_TOPMOST } else { HWND_NOTOPMOST },
+                0,
+                0,
+                0,
+                0,
+                SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE,
+            );
+        }
+    }
+
+    fn set_raw_mouse(&mut self, enabled: bool) {
+        let target = RAW_MOUSE_TARGET.load(Ordering::Acquire);
+        if !needs_raw_mouse_change(enabled, self.handle.hwnd.0, target) {
+            return;
+        }
+
+        let device = RAWINPUTDEVICE {
+            usUsagePage: HID_USAGE_PAGE_GENERIC,
+            usUsage: HID_USAGE_GENERIC_MOUSE,
+            dwFlags: if enabled {
+                RAWINPUTDEVICE_FLAGS(0)
+            } else {
+                RIDEV_REMOVE
+            },
+            // Must be null when removing the device.
+            hwndTarget: if enabled {
+                self.handle.hwnd
+            } else {
+                HWND::default()
+            },
+        };
+
+        let registered = unsafe {
+            RegisterRawInputDevices(&device, 1, std::mem::size_of::<RAWINPUTDEVICE>() as u32)
+        };
+
+        if registered.as_bool() {
+            RAW_MOUSE_TARGET.store(
+                if enabled { self.handle.hwnd.0 } else { 0 },
+                Ordering::Release,
+            );
+        }
+    }
+}
+
+/// Forwards console control events to the window as
+/// [`Event::QuitRequested`].
+///
+/// This is called on a separate thread created by the OS. For events other
+/// than Ctrl+C and Ctrl+Break, the process is terminated as soon as this
+/// function returns, so we wait (for a bounded amount of time) for the event
+/// loop to exit first.
+unsafe extern "system" fn console_ctrl_handler(ctrl_type: u32) -> BOOL {
+    let hwnd = HWND(QUIT_TARGET.load(Ordering::Acquire));
+    if hwnd == HWND::default() {
+        // No window to notify, let the default handler terminate the process.
+        return BOOL(0);
+    }
+
+    PostMessageW(hwnd, WM_QUIT_REQUESTED, WPARAM(0), LPARAM(0));
+
+    if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT {
+        let deadline = Instant::now() + QUIT_GRACE_PERIOD;
+        while QUIT_TARGET.load(Ordering::Acquire) != 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    BOOL(1)
+}
+
+unsafe extern "system" fn wndproc_trampoline<Callback>(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT
+where
+    Callback: FnMut(WindowId, &mut dyn Control, Event) -> EventLoopControl,
+{
+    let shared_ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const RefCell<Windows<Callback>>;
+
+    if shared_ptr.is_null() {
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    } else {
+        let shared = &(*shared_ptr);
+        let id = WindowId(hwnd.0);
+        let dispatch = |event| shared.borrow_mut().dispatch(id, event);
+        let update = || update_window(shared, id);
+
+        match msg {
+            WM_CLOSE => {
+                // Not passed on to DefWindowProcW, which would destroy the
+                // window even if the callback wants to keep it open. It is
+                // destroyed by finish_closing() if the callback agreed.
+                dispatch(Event::CloseRequested {});
+            }
+            WM_DROPFILES => {
+                let drop = HDROP(wparam.0 as isize);
+                let paths = dropped_paths(|index, buffer| {
+                    let (pointer, len) = match buffer {
+                        Some(buffer) => (buffer.as_mut_ptr(), buffer.len() as u32),
+                        None => (std::ptr::null_mut(), 0),
+                    };
+                    DragQueryFileW(drop, index, PWSTR(pointer), len)
+                });
+
+                let mut point = POINT::default();
+                DragQueryPoint(drop, &mut point);
+                DragFinish(drop);
+
+                dispatch(Event::FilesDropped {
+                    paths,
+                    position: Point::new(Px(point.x as i16), Px(point.y as i16)),
+                });
+            }
+            WM_TIMER => {
+                dispatch(Event::Timer {
+                    id: TimerId(wparam.0 as u32),
+                });
+            }
+            WM_QUERYENDSESSION => {
+                dispatch(Event::QuitRequested {});
+                // Never block the session from ending, the app has been told.
+                return LRESULT(1);
+            }
+            WM_ENDSESSION => {
+                // The process is terminated as soon as we return if the session
+                // is actually ending, so this is the last chance to clean up.
+                if wparam.0 != 0 {
+                    shared.borrow_mut().close_all();
+                }
+            }
+            WM_QUIT_REQUESTED => {
+                dispatch(Event::QuitRequested {});
+            }
+            WM_ACTIVATE | WM_ACTIVATEAPP => {
+                let is_focused = if msg == WM_ACTIVATE {
+                    (wparam.0 & 0xFFFF) as u32 != WA_INACTIVE
+                } else {
+                    wparam.0 != 0
+                };
+
+                // WM_ACTIVATEAPP is sent to every window of the application
+                // when it is activated, but only one of them becomes active.
+                if msg == WM_ACTIVATE || !is_focused {
+                    let changed = shared.borrow_mut().set_focused(id, is_focused);
+                    if changed {
+                        shared.borrow_mut().schedule_ticks(id, Instant::now());
+                    }
+                }
+
+                // The default handler sets the keyboard focus.
+                finish_closing(shared);
+                return DefWindowProcW(hwnd, msg, wparam, lparam);
+            }
+            WM_GETMINMAXINFO => {
+                let pointer = lparam.0 as *mut MINMAXINFO;
+                let limits = shared
+                    .borrow()
+                    .states
+                    .get(&id)
+                    .map(|state| (state.min_size, state.max_size));
+                let (min, max) = match limits {
+                    Some(limits) => limits,
+                    None => return DefWindowProcW(hwnd, msg, wparam, lparam),
+                };
+
+                (*pointer).ptMinTrackSize = POINT {
+                    x: min.width.into(),
+                    y: min.height.into(),
+                };
+
+                let default = &mut (*pointer).ptMaxTrackSize;
+                let (x, y) = max_track_size(max, (default.x, default.y));
+                *default = POINT { x, y };
+            }
+            // WM_DESTROY is not handled. Windows are only destroyed once the
+            // callback has returned, and Event::Destroyed is sent from there
+            // instead to avoid a re-entrant call to shared.borrow_mut();
+            WM_SIZE => {
+                // LOWORD and HIWORD (i16s for historical reasons, I guess)
+                let width = (lparam.0 as i16)
+                    .try_into()
+                    .expect("Window width is negative or > 65535");
+                let height = ((lparam.0 >> i16::BITS) as i16)
+                    .try_into()
+                    .expect("Window height is negative or > 65535");
+
+                // Updating here would recreate the swapchain for every size
+                // the window passes through. The window is updated when it is
+                // next painted instead, after any other WM_SIZEs have been
+                // handled.
+                shared.borrow_mut().resize(id, Extent { width, height });
+            }
+            WM_ERASEBKGND => {
+                /* No op, as recommended here:
+                  https://stackoverflow.com/questions/53000291/how-to-smooth-ugly-jitter-flicker-jumping-when-resizing-windows-especially-drag
+                */
+                return LRESULT(1);
+            }
+            WM_WINDOWPOSCHANGING => {
+                let pos = lparam.0 as *mut WINDOWPOS;
+                (*pos).flags |= SWP_NOCOPYBITS;
+            }
+            WM_MOUSEMOVE => {
+                if let Some(state) = shared.borrow_mut().states.get_mut(&id) {
+                    if !state.is_tracking_mouse {
+                        let mut track = TRACKMOUSEEVENT {
+                            cbSize: std::mem::size_of::<TRACKMOUSEEVENT>() as u32,
+                            dwFlags: TME_LEAVE,
+                            hwndTrack: hwnd,
+                            dwHoverTime: 0,
+                        };
+                        state.is_tracking_mouse = TrackMouseEvent(&mut track).as_bool();
+                    }
+                }
+
+                dispatch(Event::Input(InputEvent::CursorMove {
+                    position: Point::new(Px(lparam.0 as i16), Px((lparam.0 >> 16) as i16)),
+                }));
+            }
+            WM_INPUT => {
+                let mut input = RAWINPUT::default();
+                let mut size = std::mem::size_of::<RAWINPUT>() as u32;
+                let read = GetRawInputData(
+                    HRAWINPUT(lparam.0),
+                    RID_INPUT,
+                    &mut input as *mut RAWINPUT as _,
+                    &mut size,
+                    std::mem::size_of::<RAWINPUTHEADER>() as u32,
+                );
+
+                // Reads that fail return u32::MAX, and only mouse input (which
+                // fits in a RAWINPUT) is registered for.
+                if read != u32::MAX {
+                    if let Some(event) = raw_mouse_motion(&input) {
+                        dispatch(Event::Input(event));
+                    }
+                }
+
+                // Lets the system clean up after the input.
+                finish_closing(shared);
+                return DefWindowProcW(hwnd, msg, wparam, lparam);
+            }
+            WM_MOUSELEAVE => {
+                if let Some(state) = shared.borrow_mut().states.get_mut(&id) {
+                    state.is_tracking_mouse = false;
+                    state.cursor = CursorIcon::Arrow;
+                }
+            }
+            WM_SETCURSOR if (lparam.0 & 0xFFFF) as u32 == HTCLIENT => {
+                let cursor = shared.borrow().states.get(&id).map(|state| state.cursor);
+                show_cursor(cursor.unwrap_or_default());
+                return LRESULT(1);
+            }
+            WM_LBUTTONDOWN => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Left,
+                state: ButtonState::Pressed,
+            })),
+            WM_LBUTTONUP => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Left,
+                state: ButtonState::Released,
+            })),
+            WM_MBUTTONDOWN => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Middle,
+                state: ButtonState::Pressed,
+            })),
+            WM_MBUTTONUP => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Middle,
+                state: ButtonState::Released,
+            })),
+            WM_RBUTTONDOWN => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Right,
+                state: ButtonState::Pressed,
+            })),
+            WM_RBUTTONUP => dispatch(Event::Input(InputEvent::MouseButton {
+                button: MouseButton::Right,
+                state: ButtonState::Released,
+            })),
+            WM_MOUSEWHEEL => {
+                let notches = (wparam.0 >> 16) as i16 as f32 / (WHEEL_DELTA as f32);
+                dispatch(Event::Input(InputEvent::ScrollWheel {
+                    x: 0.0,
+                    y: notches,
+                    lines: notches_to_lines(notches, scroll_setting(SPI_GETWHEELSCROLLLINES)),
+                }))
+            }
+            WM_MOUSEHWHEEL => {
+                let notches = (wparam.0 >> 16) as i16 as f32 / (WHEEL_DELTA as f32);
+                dispatch(Event::Input(InputEvent::ScrollWheel {
+                    x: notches,
+                    y: 0.0,
+                    lines: notches_to_lines(notches, scroll_setting(SPI_GETWHEELSCROLLCHARS)),
+                }))
+            }
+            WM_CHAR => {
+                let codepoint = match shared.borrow_mut().states.get_mut(&id) {
+                    Some(state) if (wparam.0 & 0xD800) == 0xD800 => {
+                        state.high_surrogate = wparam.0 as u16;
+                        None
+                    }
+                    Some(state) => char::from_u32(if (wparam.0 & 0xDC00) == 0xDC00 {
+                        (((state.high_surrogate as u32 - 0xD800) << 10)
+                            | (wparam.0 as u32 - 0xDC00))
+                            + 0x10000
+                    } else {
+                        wparam.0 as u32
+                    }),
+                    None => None,
+                };
+
+                if let Some(codepoint) = codepoint {
+                    dispatch(Event::Input(InputEvent::Char { codepoint }));
+                }
+            }
+            // The composition string is sent as preedit events for the
+            // application to draw, so the IME's own composition window isn't
+            // shown. Its candidate window still is.
+            WM_IME_STARTCOMPOSITION | WM_IME_ENDCOMPOSITION => {
+                dispatch(Event::Input(InputEvent::ImePreedit {
+                    text: String::new(),
+                    cursor: 0,
+                }));
+            }
+            WM_IME_COMPOSITION => {
+                let context = ImmGetContext(hwnd);
+                if context == HIMC::default() {
+                    return DefWindowProcW(hwnd, msg, wparam, lparam);
+                }
+
+                // Both can be set at once, when the IME commits some of the
+                // text and carries on composing the rest.
+                let changed = lparam.0 as u32;
+                let result = (changed & GCS_RESULTSTR.0 != 0)
+                    .then(|| read_composition_string(context, GCS_RESULTSTR));
+                let preedit = (changed & GCS_COMPSTR.0 != 0).then(|| {
+                    let text = read_composition_string(context, GCS_COMPSTR);
+                    let cursor =
+                        ImmGetCompositionStringW(context, GCS_CURSORPOS, std::ptr::null_mut(), 0);
+                    composition_text(&text, cursor.max(0) as usize)
+                });
+                ImmReleaseContext(hwnd, context);
+
+                if let Some(result) = result {
+                    let (text, _) = composition_text(&result, 0);
+                    dispatch(Event::Input(InputEvent::ImeCommit { text }));
+                }
+                if let Some((text, cursor)) = preedit {
+                    dispatch(Event::Input(InputEvent::ImePreedit { text, cursor }));
+                }
+
+                // The default handler would also send the result as WM_CHARs.
+            }
+            WM_KEYDOWN | WM_KEYUP => {
+                dispatch(key_event(msg, wparam));
+            }
+            WM_SYSKEYDOWN | WM_SYSKEYUP => {
+                // Let the system see these too so that Alt+F4 and friends
+                // keep working.
+                dispatch(key_event(msg, wparam));
+                finish_closing(shared);
+                return DefWindowProcW(hwnd, msg, wparam, lparam);
+            }
+            WM_PAINT => {
+                // Drawing is done with Vulkan, so the update region only needs
+                // to be cleared to stop WM_PAINT from being sent again.
+                ValidateRect(hwnd, std::ptr::null());
+
+                let needs_redraw = shared
+                    .borrow()
+                    .states
+                    .get(&id)
+                    .map_or(false, |state| state.needs_redraw);
+
+                if needs_redraw {
+                    update();
+                }
+            }
+            _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+
+        finish_closing(shared);
+        LRESULT::default()
+    }
+}
+
+fn key_event(msg: u32, wparam: WPARAM) -> Event {
+    let key = match VIRTUAL_KEY(wparam.0 as u16) {
+        VK_TAB => Key::Tab,
+        VK_RETURN => Key::Enter,
+        VK_ESCAPE => Key::Escape,
+        VK_SPACE => Key::Space,
+        VK_BACK => Key::Backspace,
+        VK_DELETE => Key::Delete,
+        VK_LEFT => Key::Left,
+        VK_RIGHT => Key::Right,
+        VK_UP => Key::Up,
+        VK_DOWN => Key::Down,
+        VK_HOME => Key::Home,
+        VK_END => Key::End,
+        // Virtual key codes for letters are their ASCII upper case values.
+        VIRTUAL_KEY(code @ 0x41..=0x5A) => Key::Letter(code as u8 as char),
+        VIRTUAL_KEY(code) => Key::Other(code),
+    };
+
+    // The high bit is set if the key is held down.
+    let is_held = |key: VIRTUAL_KEY| unsafe { GetKeyState(key.0 as i32) } < 0;
+
+    Event::Input(InputEvent::Key {
+        key,
+        state: if (msg == WM_KEYDOWN) | (msg == WM_SYSKEYDOWN) {
+            ButtonState::Pressed
+        } else {
+            ButtonState::Released
+        },
+        modifiers: Modifiers {
+            shift: is_held(VK_SHIFT),
+            ctrl: is_held(VK_CONTROL),
+            alt: is_held(VK_MENU),
+        },
+    })
+}
+
+/// Reads the paths of dropped files with `query`, which behaves like
+/// `DragQueryFileW()`. Given an index of `u32::MAX`, it returns the number of
+/// files. Otherwise, it returns the length of the file's path in UTF-16 code
+/// units, excluding the null terminator, and copies as much of the path as
+/// fits (with a terminator) into the buffer if there is one.
+fn dropped_paths(mut query: impl FnMut(u32, Option<&mut [u16]>) -> u32) -> Vec<PathBuf> {
+    let count = query(u32::MAX, None);
+    (0..count)
+        .map(|index| {
+            let mut buffer = vec![0; query(index, None) as usize + 1];
+            let len = query(index, Some(&mut buffer)) as usize;
+            buffer.truncate(len);
+            PathBuf::from(OsString::from_wide(&buffer))
+        })
+        .collect()
+}
+
+/// Converts a square RGBA image into the color and mask bitmaps for
+/// `CreateIconIndirect()`: 32-bit BGRA pixels, and a 1-bit mask with rows
+/// padded to 16 bits in which set bits mark fully transparent pixels.
+fn icon_bitmaps(image: &Image<Rgba8>) -> Result<(Vec<u8>, Vec<u8>), IconError> {
+    let (width, height) = (image.width(), image.height());
+    if width != height {
+        return Err(IconError::NotSquare { width, height });
+    }
+
+    if width == 0 {
+        return Err(IconError::Empty);
+    }
+
+    let mask_pitch = (width + 15) / 16 * 2;
+    let mut color = Vec::with_capacity(width * height * 4);
+    let mut mask = vec![0; mask_pitch * height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let rgba = image.pixel(x, y);
+            color.extend_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+
+            if rgba[3] == 0 {
+                mask[y * mask_pitch + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    Ok((color, mask))
+}
+
+/// Converts raw mouse input into [`InputEvent::RawMouseMotion`]. Returns
+/// `None` for other devices, for mice that report absolute positions, and if
+/// the mouse didn't move.
+fn raw_mouse_motion(input: &RAWINPUT) -> Option<InputEvent> {
+    if input.header.dwType != RIM_TYPEMOUSE {
+        return None;
+    }
+
+    let mouse = unsafe { &input.data.mouse };
+    if (mouse.usFlags & MOUSE_MOVE_ABSOLUTE) != 0 || (mouse.lLastX == 0 && mouse.lLastY == 0) {
+        return None;
+    }
+
+    Some(InputEvent::RawMouseMotion {
+        dx: mouse.lLastX as f32,
+        dy: mouse.lLastY as f32,
+    })
+}
+
+/// The time between updates for a window, or `None` if it should only be
+/// updated when it needs to be repainted.
+fn tick_interval(
+    is_focused: bool,
+    updates_per_second: u32,
+    background_updates_per_second: u32,
+) -> Option<Duration> {
+    let rate = if is_focused {
+        updates_per_second
+    } else {
+        background_updates_per_second
+    };
+
+    (rate > 0).then(|| Duration::from_secs(1) / rate)
+}
+
+/// When the event loop must wake up to update a window, given each window's
+/// next tick. `None` means that nothing is scheduled, and the event loop can
+/// sleep until a message arrives.
+fn next_wake(ticks: impl IntoIterator<Item = Option<Instant>>) -> Option<Instant> {
+    ticks.into_iter().flatten().min()
+}
+
+/// How long to wait for a message before waking up at `wake`, in
+/// milliseconds. Rounded up so that the event loop doesn't wake up just
+/// before a tick is due and have to wait again.
+fn wait_timeout_ms(now: Instant, wake: Option<Instant>) -> u32 {
+    match wake {
+        Some(wake) => {
+            let nanos = wake.saturating_duration_since(now).as_nanos();
+            // INFINITE is reserved, so stop one short of it.
+            ((nanos + 999_999) / 1_000_000).min(u128::from(INFINITE - 1)) as u32
+        }
+        None => INFINITE,
+    }
+}
+
+/// The number of lines (or characters) to scroll per wheel notch when the
+/// user's setting can't be used.
+const DEFAULT_SCROLL_LINES: u32 = 3;
+
+/// The lines-per-notch setting that means the wheel scrolls a page at a time.
+const WHEEL_PAGESCROLL: u32 = u32::MAX;
+
+/// Reads one of the user's lines-per-notch settings, `SPI_GETWHEELSCROLLLINES`
+/// or `SPI_GETWHEELSCROLLCHARS`. Read on every event, since the user may
+/// change it at any time.
+fn scroll_setting(action: SYSTEM_PARAMETERS_INFO_ACTION) -> u32 {
+    let mut lines = DEFAULT_SCROLL_LINES;
+    let ok = unsafe {
+        SystemParametersInfoW(
+            action,
+            0,
+            (&mut lines as *mut u32).cast(),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+    };
+
+    if ok.as_bool() {
+        lines
+    } else {
+        DEFAULT_SCROLL_LINES
+    }
+}
+
+/// Converts a (possibly fractional) number of wheel notches to lines, using
+/// the user's lines-per-notch setting. There is no notion of a page here, so
+/// page scrolling falls back to the default number of lines.
+fn notches_to_lines(notches: f32, lines_per_notch: u32) -> f32 {
+    let lines_per_notch = if lines_per_notch == WHEEL_PAGESCROLL {
+        DEFAULT_SCROLL_LINES
+    } else {
+        lines_per_notch
+    };
+
+    notches * lines_per_notch as f32
+}
+
+/// Reads one of the IME's strings, in UTF-16.
+unsafe fn read_composition_string(context: HIMC, index: IME_COMPOSITION_STRING) -> Vec<u16> {
+    // The lengths are in bytes, and negative on failure.
+    let len = ImmGetCompositionStringW(context, index, std::ptr::null_mut(), 0);
+    let mut text = vec![0u16; len.max(0) as usize / 2];
+    let read = ImmGetCompositionStringW(
+        context,
+        index,
+        text.as_mut_ptr().cast(),
+        (text.len() * 2) as u32,
+    );
+    text.truncate(read.max(0) as usize / 2);
+    text
+}
+
+/// Converts a composition string from UTF-16 to UTF-8, along with a cursor
+/// position within it. `cursor` is in UTF-16 code units, and the returned
+/// cursor is in bytes. Unpaired surrogates are replaced with U+FFFD.
+fn composition_text(utf16: &[u16], cursor: usize) -> (String, usize) {
+    let cursor = cursor.min(utf16.len());
+    let before = String::from_utf16_lossy(&utf16[..cursor]);
+    let after = String::from_utf16_lossy(&utf16[cursor..]);
+    let cursor = before.len();
+    (before + &after, cursor)
+}
+
+/// Converts a timer interval to the milliseconds that `SetTimer()` expects,
+/// rounding up and clamping it to the range that `SetTimer()` accepts.
+fn timer_interval_ms(interval: Duration) -> u32 {
+    let ms = (interval.as_nanos() + 999_999) / 1_000_000;
+    ms.clamp(USER_TIMER_MINIMUM as u128, USER_TIMER_MAXIMUM as u128) as u32
+}
+
+/// Returns `true` if the extended window style `ex_style` places the window in
+/// the topmost band.
+fn is_topmost(ex_style: isize) -> bool {
+    ex_style & WS_EX_TOPMOST.0 as isize != 0
+}
+
+/// Whether raw mouse input has to be registered or removed for `window` to
+/// match `enabled`, given the window that it is registered for. Removing it
+/// applies to the whole process, so only the window receiving it may do so.
+fn needs_raw_mouse_change(enabled: bool, window: isize, target: isize) -> bool {
+    enabled != (target != 0 && target == window)
+}
+
+/// Reduces `min` so that it is no larger than `max`, ignoring dimensions of
+/// `max` that are 0.
+fn clamp_min_size(min: Extent, max: Extent) -> Extent {
+    let clamp = |min: Px, max: Px| if max > Px(0) { min.min(max) } else { min };
+    Extent::new(clamp(min.width, max.width), clamp(min.height, max.height))
+}
+
+/// Converts a DPI value to a scale factor relative to the default of 96 DPI.
+/// Returns 1.0 if the DPI is unknown.
+fn dpi_to_scale(dpi: u32) -> f32 {
+    if dpi == 0 {
+        1.0
+    } else {
+        dpi as f32 / 96.0
+    }
+}
+
+/// Computes the maximum tracking size for `WM_GETMINMAXINFO`, keeping the
+/// system's `default` for dimensions of `max` that are 0.
+fn max_track_size(max: Extent, default: (i32, i32)) -> (i32, i32) {
+    let pick = |max: Px, default: i32| {
+        if max > Px(0) {
+            max.into()
+        } else {
+            default
+        }
+    };
+    (pick(max.width, default.0), pick(max.height, default.1))
+}
+
+fn to_wstr<const MAX_LENGTH: usize>(s: &str) -> ArrayVec<u16, MAX_LENGTH> {
+    assert!(MAX_LENGTH > 0);
+
+    let mut buffer = s.encode_utf16().collect::<ArrayVec<_, MAX_LENGTH>>();
+    let len = buffer.len();
+
+    if len == buffer.capacity() {
+        buffer[len - 1] = 0;
+    } else {
+        buffer.push(0);
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::Color;
+    use std::{cell::Cell, rc::Rc};
+
+    fn window_state(hwnd: isize) -> WindowState {
+        WindowState {
+            handle: Handle {
+                hwnd: HWND(hwnd),
+                hinstance: HINSTANCE::default(),
+            },
+            high_surrogate: 0,
+            min_size: Extent::default(),
+            max_size: Extent::default(),
+            size: Extent::default(),
+            cursor: CursorIcon::Arrow,
+            is_tracking_mouse: false,
+            is_transparent: false,
+            is_focused: false,
+            frame_limiter: FrameLimiter::default(),
+            update_clock: UpdateClock::default(),
+            icon: None,
+            needs_redraw: false,
+            pending_resize: false,
+            next_tick: None,
+            user_data: None,
+            timers: Timers::default(),
+        }
+    }
+
+    #[test]
+    fn cursor_resources() {
+        let cases = [
+            (CursorIcon::Arrow, IDC_ARROW),
+            (CursorIcon::Hand, IDC_HAND),
+            (CursorIcon::IBeam, IDC_IBEAM),
+            (CursorIcon::ResizeWestEast, IDC_SIZEWE),
+            (CursorIcon::ResizeNorthSouth, IDC_SIZENS),
+            (CursorIcon::ResizeAll, IDC_SIZEALL),
+        ];
+
+        for (cursor, resource) in cases {
+            assert_eq!(cursor_resource(cursor).0, resource.0, "{:?}", cursor);
+        }
+    }
+
+    #[cfg(feature = "raw-window-handle")]
+    #[test]
+    fn raw_window_handle() {
+        use raw_window_handle::{
+            HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+        };
+
+        let handle = Handle {
+            hwnd: HWND(0x1234),
+            hinstance: HINSTANCE(0x5678),
+        };
+
+        match handle.raw_window_handle() {
+            RawWindowHandle::Win32(win32) => {
+                assert_eq!(win32.hwnd as isize, handle.hwnd.0);
+                assert_eq!(win32.hinstance as isize, handle.hinstance.0);
+            }
+            other => panic!("expected a Win32 handle, got {:?}", other),
+        }
+
+        assert!(matches!(
+            handle.raw_display_handle(),
+            RawDisplayHandle::Windows(_)
+        ));
+    }
+
+    #[test]
+    fn icon_conversion() {
+        let mut image = Image::<Rgba8>::new(Extent::new(Px(17), Px(17)));
+        image.set(0, 0, Color::rgba(10, 20, 30, 255));
+        image.set(16, 1, Color::rgba(40, 50, 60, 128));
+
+        let (color, mask) = icon_bitmaps(&image).unwrap();
+        assert_eq!(color.len(), 17 * 17 * 4);
+        assert_eq!(&color[..4], &[30, 20, 10, 255]);
+        assert_eq!(&color[(17 + 16) * 4..(17 + 17) * 4], &[60, 50, 40, 128]);
+
+        // Rows of 17 pixels are padded to 32 bits.
+        assert_eq!(mask.len(), 4 * 17);
+        assert_eq!(&mask[..4], &[0x7F, 0xFF, 0x80, 0x00]);
+        assert_eq!(&mask[4..8], &[0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(&mask[8..12], &[0xFF, 0xFF, 0x80, 0x00]);
+
+        assert_eq!(
+            icon_bitmaps(&Image::new(Extent::new(Px(16), Px(32)))),
+            Err(IconError::NotSquare {
+                width: 16,
+                height: 32
+            })
+        );
+        assert_eq!(
+            icon_bitmaps(&Image::new(Extent::default())),
+            Err(IconError::Empty)
+        );
+    }
+
+    #[test]
+    fn raw_mouse_input() {
+        let input = |dw_type, flags, x, y| {
+            let mut input = RAWINPUT::default();
+            input.header.dwType = dw_type;
+            input.header.dwSize = std::mem::size_of::<RAWINPUT>() as u32;
+            input.data.mouse = RAWMOUSE {
+                usFlags: flags,
+                lLastX: x,
+                lLastY: y,
+                ..Default::default()
+            };
+            input
+        };
+
+        assert_eq!(
+            raw_mouse_motion(&input(RIM_TYPEMOUSE, 0, -3, 12)),
+            Some(InputEvent::RawMouseMotion { dx: -3.0, dy: 12.0 })
+        );
+
+        // Absolute positions, no motion, and keyboard input.
+        assert_eq!(
+            raw_mouse_motion(&input(RIM_TYPEMOUSE, MOUSE_MOVE_ABSOLUTE, 100, 200)),
+            None
+        );
+        assert_eq!(raw_mouse_motion(&input(RIM_TYPEMOUSE, 0, 0, 0)), None);
+        assert_eq!(raw_mouse_motion(&input(1, 0, 5, 0)), None);
+    }
+
+    #[test]
+    fn closing_one_of_many_windows() {
+        let mut destroyed = vec![];
+        let mut windows = Windows::new(|id, _: &mut dyn Control, event| {
+            match event {
+                Event::Destroyed {} => destroyed.push(id),
+                Event::CloseRequested {} => return EventLoopControl::Stop,
+                _ => {}
+            }
+            EventLoopControl::Continue
+        });
+
+        let a = windows.insert(window_state(1));
+        let b = windows.insert(window_state(2));
+        assert_eq!(windows.states.len(), 2);
+
+        windows.dispatch(a, Event::CloseRequested {});
+        assert_eq!(windows.closing, [a]);
+
+        // The loop keeps running while a window is left.
+        let closing = windows.closing.pop().unwrap();
+        assert!(!windows.remove(closing));
+        assert_eq!(windows.states.len(), 1);
+
+        windows.dispatch(b, Event::QuitRequested {});
+        assert!(windows.closing.is_empty());
+        windows.dispatch(b, Event::CloseRequested {});
+        let closing = windows.closing.pop().unwrap();
+        assert!(windows.remove(closing));
+
+        drop(windows);
+        assert_eq!(destroyed, [a, b]);
+    }
+
+    #[test]
+    fn vetoed_close() {
+        let allow_close = std::cell::Cell::new(false);
+        let mut windows = Windows::new(|_, _: &mut dyn Control, event| match event {
+            Event::CloseRequested {} if allow_close.get() => EventLoopControl::Stop,
+            _ => EventLoopControl::Continue,
+        });
+
+        let a = windows.insert(window_state(1));
+
+        // "Save changes?" was cancelled.
+        windows.dispatch(a, Event::CloseRequested {});
+        assert!(windows.closing.is_empty());
+        assert!(windows.states.contains_key(&a));
+
+        allow_close.set(true);
+        windows.dispatch(a, Event::CloseRequested {});
+        assert_eq!(windows.closing, [a]);
+        assert!(windows.states.contains_key(&a));
+    }
+
+    #[test]
+    fn size_limits() {
+        let max = Extent::new(Px(300), Px(200));
+        assert_eq!(
+            clamp_min_size(Extent::new(Px(400), Px(100)), max),
+            Extent::new(Px(300), Px(100))
+        );
+        assert_eq!(
+            clamp_min_size(Extent::new(Px(400), Px(100)), Extent::default()),
+            Extent::new(Px(400), Px(100))
+        );
+        assert_eq!(
+            clamp_min_size(Extent::new(Px(400), Px(400)), Extent::new(Px(0), Px(200))),
+            Extent::new(Px(400), Px(200))
+        );
+
+        assert_eq!(max_track_size(max, (1920, 1080)), (300, 200));
+        assert_eq!(
+            max_track_size(Extent::default(), (1920, 1080)),
+            (1920, 1080)
+        );
+        assert_eq!(
+            max_track_size(Extent::new(Px(0), Px(500)), (1920, 1080)),
+            (1920, 500)
+        );
+    }
+
+    #[test]
+    fn dpi_scale_factors() {
+        assert_eq!(dpi_to_scale(96), 1.0);
+        assert_eq!(dpi_to_scale(144), 1.5);
+        assert_eq!(dpi_to_scale(192), 2.0);
+        assert_eq!(dpi_to_scale(120), 1.25);
+        // GetDpiForWindow returns 0 for invalid windows.
+        assert_eq!(dpi_to_scale(0), 1.0);
+    }
+
+    #[test]
+    fn focus_selects_tick_interval() {
+        assert_eq!(
+            tick_interval(true, 60, 4),
+            Some(Duration::from_secs(1) / 60)
+        );
+        assert_eq!(
+            tick_interval(false, 60, 4),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(tick_interval(false, 60, 0), None);
+        assert_eq!(tick_interval(true, 0, 4), None);
+
+        let mut events = vec![];
+        let mut windows = Windows::new(|_, _: &mut dyn Control, event| {
+            events.push(matches!(event, Event::FocusGained {}));
+            EventLoopControl::Continue
+        });
+        windows.updates_per_second = 60;
+        windows.background_updates_per_second = 4;
+
+        let a = windows.insert(window_state(1));
+        assert_eq!(windows.update_interval(a), Some(Duration::from_millis(250)));
+
+        assert!(windows.set_focused(a, true));
+        assert!(!windows.set_focused(a, true));
+        assert_eq!(
+            windows.update_interval(a),
+            Some(Duration::from_secs(1) / 60)
+        );
+
+        assert!(windows.set_focused(a, false));
+        assert_eq!(windows.update_interval(a), Some(Duration::from_millis(250)));
+
+        drop(windows);
+        assert_eq!(events, [true, false]);
+    }
+
+    #[test]
+    fn redraw_requests() {
+        let mut windows = Windows::new(|_, control: &mut dyn Control, event| {
+            if let Event::Input(InputEvent::Char { .. }) = event {
+                control.request_redraw();
+            }
+            EventLoopControl::Continue
+        });
+
+        let a = windows.insert(window_state(1));
+        assert!(!windows.states[&a].needs_redraw);
+
+        windows.dispatch(a, Event::Input(InputEvent::None));
+        assert!(!windows.states[&a].needs_redraw);
+
+        windows.dispatch(a, Event::Input(InputEvent::Char { codepoint: 'a' }));
+        assert!(windows.states[&a].needs_redraw);
+
+        windows.begin_update(a, Instant::now()).unwrap();
+        assert!(!windows.states[&a].needs_redraw);
+    }
+
+    #[test]
+    fn user_data() {
+        struct Document {
+            name: &'static str,
+            is_dropped: Rc<Cell<bool>>,
+        }
+
+        impl Drop for Document {
+            fn drop(&mut self) {
+                self.is_dropped.set(true);
+            }
+        }
+
+        let mut windows = Windows::new(|_, control: &mut dyn Control, event| {
+            if let Event::Input(InputEvent::Char { .. }) = event {
+                control.user_data_mut::<Document>().unwrap().name = "renamed";
+            }
+            EventLoopControl::Continue
+        });
+
+        let a = windows.insert(window_state(1));
+        let control: &mut dyn Control = windows.states.get_mut(&a).unwrap();
+        assert!(control.user_data::<Document>().is_none());
+
+        let is_dropped = Rc::new(Cell::new(false));
+        control.set_user_data(Box::new(Document {
+            name: "untitled",
+            is_dropped: is_dropped.clone(),
+        }));
+        assert_eq!(control.user_data::<Document>().unwrap().name, "untitled");
+        assert!(control.user_data::<String>().is_none());
+
+        windows.dispatch(a, Event::Input(InputEvent::Char { codepoint: 'a' }));
+        let control: &dyn Control = &windows.states[&a];
+        assert_eq!(control.user_data::<Document>().unwrap().name, "renamed");
+
+        assert!(!is_dropped.get());
+        windows.remove(a);
+        assert!(is_dropped.get());
+    }
+
+    #[test]
+    fn resizes_are_coalesced() {
+        let mut windows = Windows::new(|_, _: &mut dyn Control, _| EventLoopControl::Continue);
+        let a = windows.insert(window_state(1));
+
+        let update = windows.begin_update(a, Instant::now()).unwrap();
+        assert!(!update.resized);
+
+        // A drag-resize sends several sizes before the window is painted.
+        for width in [100, 120, 150] {
+            windows.resize(a, Extent::new(Px(width), Px(80)));
+        }
+        assert!(windows.states[&a].needs_redraw);
+
+        let update = windows.begin_update(a, Instant::now()).unwrap();
+        assert!(update.resized);
+        assert_eq!(update.size, Extent::new(Px(150), Px(80)));
+
+        // The resize has been reported, and isn't reported again.
+        let update = windows.begin_update(a, Instant::now()).unwrap();
+        assert!(!update.resized);
+        assert_eq!(update.size, Extent::new(Px(150), Px(80)));
+
+        // Minimizing doesn't wait for a paint that will never come.
+        windows.resize(a, Extent::default());
+        assert!(!windows.states[&a].needs_redraw);
+        windows.resize(a, Extent::new(Px(150), Px(80)));
+        assert!(windows.states[&a].needs_redraw);
+        assert!(windows.begin_update(a, Instant::now()).unwrap().resized);
+    }
+
+    #[test]
+    fn next_wake_time() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+
+        // Nothing to do, so sleep until a message arrives.
+        assert_eq!(next_wake([]), None);
+        assert_eq!(next_wake([None, None]), None);
+        assert_eq!(wait_timeout_ms(now, None), INFINITE);
+
+        // The earliest tick wins.
+        let wake = next_wake([Some(now + ms(30)), None, Some(now + ms(10))]);
+        assert_eq!(wake, Some(now + ms(10)));
+        assert_eq!(wait_timeout_ms(now, wake), 10);
+
+        // Late ticks don't wait, and partial milliseconds round up.
+        assert_eq!(wait_timeout_ms(now, Some(now - ms(5))), 0);
+        assert_eq!(
+            wait_timeout_ms(now, Some(now + Duration::from_micros(1500))),
+            2
+        );
+    }
+
+    #[test]
+    fn due_ticks() {
+        let mut windows = Windows::new(|_, _: &mut dyn Control, _| EventLoopControl::Continue);
+        windows.updates_per_second = 100;
+        let a = windows.insert(window_state(1));
+        let b = windows.insert(window_state(2));
+        windows.states.get_mut(&a).unwrap().is_focused = true;
+
+        let start = Instant::now();
+        windows.schedule_ticks(a, start);
+        windows.schedule_ticks(b, start);
+
+        // The background window has no update rate.
+        let tick = start + Duration::from_millis(10);
+        assert_eq!(windows.states[&a].next_tick, Some(tick));
+        assert_eq!(windows.states[&b].next_tick, None);
+        assert_eq!(windows.next_wake(), Some(tick));
+
+        // A window that needs to be redrawn is woken by WM_PAINT, so it
+        // doesn't make the event loop stop waiting.
+        windows.states.get_mut(&b).unwrap().needs_redraw = true;
+        assert_eq!(windows.next_wake(), Some(tick));
+        windows.states.get_mut(&b).unwrap().needs_redraw = false;
+
+        assert!(windows.take_due_ticks(start).is_empty());
+        assert_eq!(windows.take_due_ticks(tick), [a]);
+        assert_eq!(
+            windows.states[&a].next_tick,
+            Some(tick + Duration::from_millis(10))
+        );
+
+        // Missed ticks are skipped.
+        let late = tick + Duration::from_millis(55);
+        assert_eq!(windows.take_due_ticks(late), [a]);
+        assert_eq!(
+            windows.states[&a].next_tick,
+            Some(late + Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn gamepads_poll_focused_window() {
+        let mut windows = Windows::new(|_, _: &mut dyn Control, _| EventLoopControl::Continue);
+        let a = windows.insert(window_state(1));
+        let b = windows.insert(window_state(2));
+
+        // Nothing has focus, so nothing receives gamepad input.
+        let start = Instant::now();
+        assert!(windows.poll_gamepads(start).is_none());
+
+        // The focused window does, whatever its update rate.
+        windows.states.get_mut(&b).unwrap().is_focused = true;
+        assert_eq!(windows.focused(), Some(b));
+        let (id, _) = windows.poll_gamepads(start).unwrap();
+        assert_eq!(id, b);
+
+        // Polls are spaced out.
+        assert!(windows
+            .poll_gamepads(start + Duration::from_millis(1))
+            .is_none());
+        let next = start + GAMEPAD_POLL_INTERVAL;
+        assert_eq!(windows.poll_gamepads(next).map(|(id, _)| id), Some(b));
+
+        // Without a connected controller, the loop isn't woken to poll.
+        assert_eq!(windows.next_wake(), None);
+        assert_ne!(windows.focused(), Some(a));
+    }
+
+    #[test]
+    fn frame_limiter_pacing() {
+        let start = Instant::now();
+        let end = start + Duration::from_secs(1);
+
+        // Simulates an event loop that asks for a frame every millisecond and
+        // waits as long as it is told to.
+        let count_frames = |limiter: &mut FrameLimiter| {
+            let mut now = start;
+            let mut frames = 0;
+            loop {
+                now += limiter.schedule(now);
+                if now >= end {
+                    break frames;
+                }
+                frames += 1;
+                now += Duration::from_millis(1);
+            }
+        };
+
+        assert_eq!(count_frames(&mut FrameLimiter::new(Some(50))), 50);
+        assert_eq!(count_frames(&mut FrameLimiter::new(Some(125))), 125);
+        assert_eq!(count_frames(&mut FrameLimiter::new(None)), 1000);
+        assert_eq!(count_frames(&mut FrameLimiter::new(Some(0))), 1000);
+
+        // A long pause doesn't cause a burst of frames afterwards.
+        let mut limiter = FrameLimiter::new(Some(60));
+        assert_eq!(limiter.schedule(start), Duration::ZERO);
+        let later = start + Duration::from_secs(5);
+        assert_eq!(limiter.schedule(later), Duration::ZERO);
+        assert_eq!(limiter.schedule(later), Duration::from_secs(1) / 60);
+    }
+
+    #[test]
+    fn update_clock_deltas() {
+        let tick = Duration::from_millis(16);
+        let start = Instant::now();
+        let mut clock = UpdateClock::default();
+
+        // The first update is a nominal tick, however long the window has
+        // existed.
+        assert_eq!(
+            clock.tick(start + Duration::from_secs(30), tick),
+            (tick, tick)
+        );
+
+        let mut now = start + Duration::from_secs(30);
+        let mut expected_total = tick;
+        for elapsed in [16, 17, 15, 16, 33, 0] {
+            now += Duration::from_millis(elapsed);
+            expected_total += Duration::from_millis(elapsed);
+            assert_eq!(
+                clock.tick(now, tick),
+                (Duration::from_millis(elapsed), expected_total)
+            );
+        }
+
+        // A stall is capped instead of reported in full.
+        now += Duration::from_secs(5);
+        let (delta, total) = clock.tick(now, tick);
+        assert_eq!(delta, MAX_UPDATE_DELTA);
+        assert_eq!(total, expected_total + MAX_UPDATE_DELTA);
+    }
+
+    #[test]
+    fn dropped_file_paths() {
+        let files = ["C:\\a.txt", "D:\\Fotos\\ünïcödé 🍁.png", ""]
+            .map(|path| path.encode_utf16().collect::<Vec<_>>());
+
+        let mut queries = vec![];
+        let paths = dropped_paths(|index, buffer| {
+            queries.push((index, buffer.as_ref().map(|buffer| buffer.len())));
+            if index == u32::MAX {
+                return files.len() as u32;
+            }
+
+            let file = &files[index as usize];
+            if let Some(buffer) = buffer {
+                let copied = file.len().min(buffer.len() - 1);
+                buffer[..copied].copy_from_slice(&file[..copied]);
+                buffer[copied] = 0;
+            }
+            file.len() as u32
+        });
+
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("C:\\a.txt"),
+                PathBuf::from("D:\\Fotos\\ünïcödé 🍁.png"),
+                PathBuf::new(),
+            ]
+        );
+        // Each path's length is checked before reading it into a buffer
+        // with room for the terminator. The emoji is two code units.
+        assert_eq!(
+            queries,
+            [
+                (u32::MAX, None),
+                (0, None),
+                (0, Some(9)),
+                (1, None),
+                (1, Some(24)),
+                (2, None),
+                (2, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn composition_strings() {
+        let utf16 = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+
+        assert_eq!(composition_text(&[], 0), (String::new(), 0));
+        assert_eq!(composition_text(&utf16("かな"), 1), ("かな".to_string(), 3));
+        assert_eq!(composition_text(&utf16("かな"), 2), ("かな".to_string(), 6));
+        assert_eq!(composition_text(&utf16("かな"), 9), ("かな".to_string(), 6));
+
+        // 𠀋 is outside the basic multilingual plane, so it is a surrogate
+        // pair in UTF-16 and 4 bytes in UTF-8.
+        let text = "a𠀋b";
+        assert_eq!(utf16(text).len(), 4);
+        assert_eq!(composition_text(&utf16(text), 3), (text.to_string(), 5));
+        assert_eq!(composition_text(&utf16("😀😀"), 2), ("😀😀".to_string(), 4));
+
+        // A lone surrogate is replaced rather than dropping the string.
+        assert_eq!(
+            composition_text(&[0x61, 0xD840], 2),
+            ("a\u{FFFD}".to_string(), 4)
+        );
+    }
+
+    #[test]
+    fn timers() {
+        assert_eq!(timer_interval_ms(Duration::from_millis(500)), 500);
+        assert_eq!(timer_interval_ms(Duration::from_micros(16_001)), 17);
+        assert_eq!(timer_interval_ms(Duration::ZERO), USER_TIMER_MINIMUM);
+        assert_eq!(
+            timer_interval_ms(Duration::from_millis(3)),
+            USER_TIMER_MINIMUM
+        );
+        assert_eq!(timer_interval_ms(Duration::MAX), USER_TIMER_MAXIMUM);
+
+        let mut timers = Timers::default();
+        assert!(!timers.stop(TimerId(1)));
+
+        timers.start(TimerId(1));
+        timers.start(TimerId(2));
+        // Restarting a timer doesn't add it again.
+        timers.start(TimerId(1));
+        assert!(timers.stop(TimerId(1)));
+        assert!(!timers.stop(TimerId(1)));
+        assert!(!timers.stop(TimerId(3)));
+
+        timers.start(TimerId(4));
+        assert_eq!(timers.take_all(), [TimerId(2), TimerId(4)]);
+        assert!(!timers.stop(TimerId(2)));
+    }
+
+    #[test]
+    fn topmost_style() {
+        assert!(!is_topmost(0));
+        assert!(is_topmost(WS_EX_TOPMOST.0 as isize));
+        assert!(is_topmost((WS_EX_TOPMOST.0 | WS_EX_LAYERED.0) as isize));
+        assert!(!is_topmost(WS_EX_LAYERED.0 as isize));
+        // GetWindowLongPtrW returns the style as a signed value.
+        assert!(!is_topmost(!(WS_EX_TOPMOST.0 as isize)));
+    }
+
+    #[test]
+    fn raw_mouse_changes() {
+        assert!(needs_raw_mouse_change(true, 1, 0));
+        assert!(needs_raw_mouse_change(true, 1, 2));
+        assert!(!needs_raw_mouse_change(true, 1, 1));
+
+        assert!(needs_raw_mouse_change(false, 1, 1));
+        assert!(!needs_raw_mouse_change(false, 1, 0));
+        // Only the registered window may remove the registration.
+        assert!(!needs_raw_mouse_change(false, 1, 2));
+        assert!(!needs_raw_mouse_change(false, 0, 0));
+    }
+
+    #[test]
+    fn scroll_notches_to_lines() {
+        assert_eq!(notches_to_lines(1.0, 3), 3.0);
+        assert_eq!(notches_to_lines(-2.0, 3), -6.0);
+        assert_eq!(notches_to_lines(1.0, 5), 5.0);
+        assert_eq!(notches_to_lines(1.0, 0), 0.0);
+
+        // High-resolution wheels and trackpads send fractions of a notch.
+        assert_eq!(notches_to_lines(0.25, 4), 1.0);
+        assert_eq!(notches_to_lines(30.0 / 120.0, 3), 0.75);
+
+        assert_eq!(notches_to_lines(1.0, WHEEL_PAGESCROLL), 3.0);
+    }
 }
@@ -1,11 +1,17 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
 
 use crate::{
-    gfx::Color,
-    px::Px,
+    px::{Logical, Px, Scale},
     shapes::{Extent, Point, Rect},
 };
 
+/// Re-exported so that code consuming [`DrawCommand`]s doesn't need to depend
+/// on the renderer.
+pub use crate::gfx::Color;
+
 mod widget;
 use ahash::AHasher;
 pub use widget::*;
@@ -13,15 +19,147 @@ pub use widget::*;
 mod layout;
 pub use layout::*;
 
-#[derive(Debug)]
+mod svg;
+pub use svg::export_svg;
+
+mod theme;
+pub use theme::Theme;
+
+/// A backend-independent description of what the UI looks like, produced by
+/// [`Builder::build()`]. Commands are in draw order, rects are in absolute
+/// pixels from the top-left corner of the UI, and colors are straight RGBA.
+/// They can be drawn with a [`Canvas`](crate::gfx::Canvas), or by anything
+/// else that can fill rects.
+#[derive(Debug, Clone, PartialEq)]
 pub enum DrawCommand {
-    ColoredRect { rect: Rect, color: Color },
+    ColoredRect {
+        rect: Rect,
+        color: Color,
+    },
+    /// A rect with its corners rounded off to `radius` pixels. The radius is
+    /// clamped to half of the rect's smaller dimension when drawn.
+    RoundedRect {
+        rect: Rect,
+        radius: f32,
+        color: Color,
+    },
+    /// Restricts subsequent commands to `rect` until the matching
+    /// [`DrawCommand::PopClip`]. Nested clips intersect.
+    PushClip {
+        rect: Rect,
+    },
+    PopClip,
+}
+
+impl Hash for DrawCommand {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            DrawCommand::ColoredRect { rect, color } => {
+                rect.hash(state);
+                color.hash(state);
+            }
+            DrawCommand::RoundedRect {
+                rect,
+                radius,
+                color,
+            } => {
+                rect.hash(state);
+                radius.to_bits().hash(state);
+                color.hash(state);
+            }
+            DrawCommand::PushClip { rect } => rect.hash(state),
+            DrawCommand::PopClip => {}
+        }
+    }
 }
 
 impl DrawCommand {
     pub fn in_bounds(&self, bounds: Rect) -> bool {
         match self {
-            DrawCommand::ColoredRect { rect, color: _ } => bounds.contains_rect(*rect),
+            DrawCommand::ColoredRect { rect, color: _ } | DrawCommand::RoundedRect { rect, .. } => {
+                bounds.contains_rect(*rect)
+            }
+            DrawCommand::PushClip { rect: _ } | DrawCommand::PopClip => true,
+        }
+    }
+
+    /// Moves the command by `dx` horizontally and `dy` vertically.
+    pub fn translated(&self, dx: Px, dy: Px) -> Self {
+        let translate = |rect: &Rect| Rect::from_extent(rect.x() + dx, rect.y() + dy, rect.extent);
+        match self {
+            DrawCommand::ColoredRect { rect, color } => DrawCommand::ColoredRect {
+                rect: translate(rect),
+                color: *color,
+            },
+            DrawCommand::RoundedRect {
+                rect,
+                radius,
+                color,
+            } => DrawCommand::RoundedRect {
+                rect: translate(rect),
+                radius: *radius,
+                color: *color,
+            },
+            DrawCommand::PushClip { rect } => DrawCommand::PushClip {
+                rect: translate(rect),
+            },
+            DrawCommand::PopClip => DrawCommand::PopClip,
+        }
+    }
+
+    /// Clips the command so that it lies within `bounds`. Returns [`None`] if
+    /// nothing would be drawn.
+    pub fn clipped(&self, bounds: Rect) -> Option<Self> {
+        match self {
+            DrawCommand::ColoredRect { rect, color } => {
+                rect.intersect(bounds).map(|rect| DrawCommand::ColoredRect {
+                    rect,
+                    color: *color,
+                })
+            }
+            // Cutting the rect down would move its corners, so leave it whole
+            // and rely on the renderer's clip instead.
+            DrawCommand::RoundedRect {
+                rect,
+                radius,
+                color,
+            } => rect.intersect(bounds).map(|_| DrawCommand::RoundedRect {
+                rect: *rect,
+                radius: *radius,
+                color: *color,
+            }),
+            DrawCommand::PushClip { rect } => Some(DrawCommand::PushClip {
+                rect: rect.intersect(bounds).unwrap_or_else(|| {
+                    Rect::from_extent(bounds.x(), bounds.y(), Extent::default())
+                }),
+            }),
+            DrawCommand::PopClip => Some(DrawCommand::PopClip),
+        }
+    }
+}
+
+impl Scale for DrawCommand {
+    /// Scales the command's rect, and the radius of rounded rects.
+    fn scale(self, factor: f32) -> Self {
+        match self {
+            DrawCommand::ColoredRect { rect, color } => DrawCommand::ColoredRect {
+                rect: rect.scale(factor),
+                color,
+            },
+            DrawCommand::RoundedRect {
+                rect,
+                radius,
+                color,
+            } => DrawCommand::RoundedRect {
+                rect: rect.scale(factor),
+                radius: radius * factor,
+                color,
+            },
+            DrawCommand::PushClip { rect } => DrawCommand::PushClip {
+                rect: rect.scale(factor),
+            },
+            DrawCommand::PopClip => DrawCommand::PopClip,
         }
     }
 }
@@ -45,21 +183,99 @@ impl Default for ActiveItem {
 pub struct Context {
     cursor: Point,
     is_lmb_pressed: bool,
+    /// Scroll wheel movement for this frame, in notches.
+    scroll_delta: (f32, f32),
+    /// Text input for this frame, delivered to the focused widget.
+    text_input: Option<TextInput>,
 
     hover_item: u64,
     active_item: ActiveItem,
+    /// The widget being dragged with the left mouse button, if any. The mouse
+    /// should be captured while this is set.
+    dragged_item: Option<u64>,
+
+    /// The widget that receives keyboard input, if any.
+    focused_item: Option<u64>,
+    /// Focusable widgets in the order they were declared. Focus moves through
+    /// them in this order, using the order from the previous frame.
+    focus_order: Vec<u64>,
+
+    scroll_areas: HashMap<u64, ScrollState>,
+    /// The region that widgets can be interacted with in, such as the visible
+    /// part of a [`ScrollArea`], or [`None`] if the whole UI is.
+    hit_clip: Option<Rect>,
+    /// The byte offset of the caret in each text edit widget.
+    carets: HashMap<u64, usize>,
+    /// The radio buttons laid out in this frame and in the last one.
+    radios: Vec<PlacedRadio>,
+    last_radios: Vec<PlacedRadio>,
+
+    /// What was drawn by each [`TopToBottom::cached_rows()`] group the last
+    /// time that it was built, keyed by the group's path.
+    subtrees: HashMap<u64, CachedSubtree>,
+    /// Identifies the sections, scroll areas and cached groups that widgets
+    /// are being laid out in, or 0 outside of all of them. See [`path_id()`].
+    path: u64,
+    /// The number of widgets laid out since the last call to `begin()`.
+    widgets_laid_out: usize,
+    /// The size of the UI being laid out, as passed to `begin()`.
+    ui_size: Extent,
+
+    theme: Theme,
+}
+
+/// Per-[`ScrollArea`] state that persists across frames.
+#[derive(Default)]
+struct ScrollState {
+    offset: Px,
+    content_height: Px,
+}
+
+/// Where a radio button was laid out, and which group it belongs to.
+#[derive(Clone, Copy, Debug)]
+struct PlacedRadio {
+    group: usize,
+    id: u64,
+    rect: Rect,
+}
+
+/// The output of a [`TopToBottom::cached_rows()`] group, which can be reused
+/// as long as nothing that it was built from has changed.
+struct CachedSubtree {
+    /// A hash of the inputs that the group was built from.
+    inputs: u64,
+    /// The size of the UI that the group was laid out in.
+    ui_size: Extent,
+    area: Rect,
+    commands: Vec<DrawCommand>,
+    /// The focusable widgets in the group, in order.
+    focus_ids: Vec<u64>,
+    /// The radios in the group, so that radios elsewhere in their groups can
+    /// tell when one of them is about to be selected.
+    radios: Vec<PlacedRadio>,
+    /// Whether the user was interacting with the group when it was built, in
+    /// which case it may look different once they stop.
+    was_interacting: bool,
+    /// Whether the group was laid out in the current frame. Groups that
+    /// weren't are dropped when the frame ends.
+    used: bool,
 }
 
 impl Context {
+    /// Starts laying out a frame of UI with the given size. The UI is laid
+    /// out in logical pixels; convert the window's size with
+    /// [`Physical::to_logical()`](crate::px::Physical::to_logical).
     pub fn begin<'a, 'b>(
         &'a mut self,
-        ui_size: Extent,
+        ui_size: Logical<Extent>,
         command_buffer: &'b mut Vec<DrawCommand>,
     ) -> InputHandler<'a, 'b> {
         command_buffer.clear();
+        self.widgets_laid_out = 0;
+        self.ui_size = ui_size.0;
         InputHandler {
             context: self,
-            ui_size,
+            ui_size: ui_size.0,
             command_buffer,
         }
     }
@@ -73,7 +289,108 @@ impl Context {
             }
         } else {
             self.active_item = ActiveItem::Available;
+            self.dragged_item = None;
+        }
+
+        self.scroll_delta = (0.0, 0.0);
+        self.text_input = None;
+
+        // Groups come and go with the UI, and would otherwise be kept for as
+        // long as the context is.
+        self.subtrees
+            .retain(|_, subtree| std::mem::replace(&mut subtree.used, false));
+    }
+
+    /// Moves focus `step` widgets along the focus order, wrapping around at
+    /// either end. If nothing has focus, stepping forward focuses the first
+    /// widget and stepping backward focuses the last.
+    fn move_focus(&mut self, step: isize) {
+        let len = self.focus_order.len() as isize;
+        if len == 0 {
+            self.focused_item = None;
+            return;
         }
+
+        let current = self
+            .focused_item
+            .and_then(|id| self.focus_order.iter().position(|item| *item == id));
+
+        let next = match current {
+            Some(index) => (index as isize + step).rem_euclid(len),
+            None if step < 0 => len - 1,
+            None => 0,
+        };
+
+        self.focused_item = Some(self.focus_order[next as usize]);
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Changes how widgets are drawn, starting with the next frame. Cached
+    /// [`cached_rows()`](TopToBottom::cached_rows) groups are rebuilt.
+    pub fn set_theme(&mut self, theme: Theme) {
+        if theme != self.theme {
+            self.theme = theme;
+            self.subtrees.clear();
+        }
+    }
+
+    /// Forces the [`cached_rows()`](TopToBottom::cached_rows) group at `path`
+    /// to be rebuilt the next time it is laid out, even if its inputs haven't
+    /// changed. `path` names the sections, scroll areas and cached groups that
+    /// contain the group, outermost first, followed by the group itself.
+    pub fn invalidate(&mut self, path: &[&str]) {
+        let id = path
+            .iter()
+            .fold(0, |parent, name| path_id(parent, self.named_id(name)));
+        self.subtrees.remove(&id);
+    }
+
+    /// The number of widgets laid out since the last call to `begin()`.
+    /// Widgets in reused [`cached_rows()`](TopToBottom::cached_rows) groups
+    /// are not counted.
+    pub fn widgets_laid_out(&self) -> usize {
+        self.widgets_laid_out
+    }
+
+    /// Whether the cursor is over `area`, or any of the widgets in `ids` is
+    /// active or focused, in which case they may change from frame to frame.
+    fn is_interacting(&self, area: Rect, ids: &[u64]) -> bool {
+        area.contains_point(self.cursor)
+            || ids.iter().any(|&id| {
+                self.active_item == Active(id)
+                    || self.dragged_item == Some(id)
+                    || self.focused_item == Some(id)
+            })
+    }
+
+    /// The part of `rect` that can be interacted with, or [`None`] if all of
+    /// it has been clipped away.
+    fn hit_rect(&self, rect: Rect) -> Option<Rect> {
+        match self.hit_clip {
+            Some(clip) => rect.intersect(clip),
+            None => Some(rect),
+        }
+    }
+
+    /// True if a press that starts this frame will select a radio in `group`
+    /// other than `id`. Each frame receives only one input, so the press lands
+    /// on whatever was under the cursor in the last frame, even if that radio
+    /// hasn't been laid out yet.
+    fn is_selecting_other_radio(&self, group: usize, id: u64) -> bool {
+        self.is_lmb_pressed
+            && self.active_item == ActiveItem::Available
+            && self.last_radios.iter().any(|radio| {
+                radio.group == group && radio.id != id && radio.rect.contains_point(self.cursor)
+            })
+    }
+
+    /// True while a widget is being dragged, during which the window should
+    /// capture the mouse so that the drag continues outside of it.
+    pub fn wants_mouse_capture(&self) -> bool {
+        self.dragged_item.is_some()
     }
 
     fn named_id(&self, s: &str) -> u64 {
@@ -83,6 +400,15 @@ impl Context {
     }
 }
 
+/// The path of the container `id` inside the one at `parent`, where the root
+/// of the UI is 0. Containers with the same name in different places get
+/// different paths.
+fn path_id(parent: u64, id: u64) -> u64 {
+    let mut hasher = AHasher::default();
+    (parent, id).hash(&mut hasher);
+    hasher.finish()
+}
+
 /// Type for enforcing 1 input event per rebuild. Could alternatively be done by
 /// allowing [`Context`]'s `begin()` function to take an input event. However,
 /// that would introduce a dependency upon the [`sys`](crate::sys) module.
@@ -107,7 +433,33 @@ impl<'a, 'b> InputHandler<'a, 'b> {
         self.finalize()
     }
 
+    pub fn scroll(self, delta_x: f32, delta_y: f32) -> Builder<'a, 'b> {
+        self.context.scroll_delta = (delta_x, delta_y);
+        self.finalize()
+    }
+
+    /// Sends text input to the focused widget.
+    pub fn text_input(self, input: TextInput) -> Builder<'a, 'b> {
+        self.context.text_input = Some(input);
+        self.finalize()
+    }
+
+    /// Moves focus to the next widget, as if Tab was pressed.
+    pub fn focus_next(self) -> Builder<'a, 'b> {
+        self.context.move_focus(1);
+        self.finalize()
+    }
+
+    /// Moves focus to the previous widget, as if Shift+Tab was pressed.
+    pub fn focus_previous(self) -> Builder<'a, 'b> {
+        self.context.move_focus(-1);
+        self.finalize()
+    }
+
     fn finalize(self) -> Builder<'a, 'b> {
+        self.context.focus_order.clear();
+        std::mem::swap(&mut self.context.radios, &mut self.context.last_radios);
+        self.context.radios.clear();
         Builder::new(self.ui_size, self.context, self.command_buffer)
     }
 }
@@ -1,18 +1,28 @@
+use std::hash::{Hash, Hasher};
+
+use ahash::AHasher;
+
 use crate::{
-    gfx::Color,
-    px::Px,
+    px::{Px, Scale},
     shapes::{Extent, Point, Rect},
     ui::SmoothSlider,
 };
 
 use super::{
-    widget::{Button, State as WidgetState, Widget},
-    Context, DrawCommand,
+    path_id,
+    widget::{clamp_caret, Button, Header, Radio, State as WidgetState, TextEdit, Widget},
+    CachedSubtree, Context, DrawCommand,
 };
 
-pub const UI_COLOR: Color = Color::rgb(100, 100, 100);
-pub const HOVER_COLOR: Color = Color::rgb(200, 200, 200);
-pub const ACTIVE_COLOR: Color = Color::rgb(100, 100, 255);
+/// The horizontal space given to each character of text.
+pub const GLYPH_WIDTH: Px = Px(8);
+
+/// The distance scrolled by one notch of the scroll wheel.
+pub const SCROLL_STEP: Px = Px(20);
+
+/// How far the body of a [`collapsing()`](TopToBottom::collapsing) section is
+/// indented from its header.
+pub const COLLAPSING_INDENT: Px = Px(16);
 
 /// Implementors of the [`LayoutState`] interface describe the current state
 /// of the layout such as advancing position offsets, and computes the actual
@@ -38,8 +48,11 @@ pub trait Layout: Drop {
         let state = self.state();
         let (min, max) = state.widget_extent();
         let rect = state.position_extent(widget.compute_size(min, max));
-        let state = widget.compute_state(rect, self.context());
-        widget.draw(state, rect, |cmd| {
+        let context = self.context();
+        context.widgets_laid_out += 1;
+        let state = widget.compute_state(rect, context);
+        let theme = context.theme;
+        widget.draw(state, rect, &theme, |cmd| {
             debug_assert!(
                 cmd.in_bounds(rect),
                 "widget \"{}\" rendered outside its bounds (bounds: {:?}, command: {:?})",
@@ -52,11 +65,68 @@ pub trait Layout: Drop {
         state
     }
 
+    /// A button with the theme's corner radius.
     fn button(&mut self, name: &str) -> WidgetState {
+        let radius = self.context().theme.corner_radius;
+        self.rounded_button(name, radius)
+    }
+
+    /// A button with its corners rounded off to `radius` pixels.
+    fn rounded_button(&mut self, name: &str, radius: f32) -> WidgetState {
         let widget = Button {
             id: self.context().named_id(name),
             min_size: Extent::new(Px(10), Px(20)),
             max_size: Extent::new(Px::MAX, Px::MAX),
+            rounded: radius,
+        };
+
+        self.widget(name, &widget)
+    }
+
+    /// A radio button that selects `value` when clicked. Radios that share the
+    /// same `current` form a group, only one of which is selected at a time.
+    fn radio<T: PartialEq + Copy>(&mut self, name: &str, current: &mut T, value: T) -> WidgetState {
+        let id = self.context().named_id(name);
+        // Radios that share `current` are in the same group. `current` is
+        // updated as soon as a radio is clicked, so radios after it in the
+        // group are drawn correctly, but one before it has to tell that it is
+        // about to be deselected.
+        let group = current as *const T as usize;
+        let widget = Radio {
+            id,
+            group,
+            selected: *current == value && !self.context().is_selecting_other_radio(group, id),
+            min_size: Extent::new(Px(10), Px(20)),
+        };
+
+        let state = self.widget(name, &widget);
+        if state.is_selected() {
+            *current = value;
+        }
+        state
+    }
+
+    /// A single line of editable text. Receives [`TextInput`](super::TextInput)
+    /// while it has focus.
+    fn text_edit(&mut self, name: &str, buffer: &mut String) -> WidgetState {
+        let id = self.context().named_id(name);
+        let context = self.context();
+
+        let caret = if context.focused_item == Some(id) {
+            let caret = context.carets.entry(id).or_insert(buffer.len());
+            if let Some(input) = &context.text_input {
+                input.apply(buffer, caret);
+            }
+            Some(clamp_caret(buffer, *caret))
+        } else {
+            None
+        };
+
+        let widget = TextEdit {
+            id,
+            text: buffer,
+            caret,
+            min_size: Extent::new(Px(10), Px(20)),
         };
 
         self.widget(name, &widget)
@@ -115,6 +185,19 @@ impl<'a, 'b, 'c> TopToBottom<'a, 'b, 'c> {
     }
 
     pub fn layout_columns(&mut self, num_columns: i16, margin: Px) -> Columns {
+        let blocks = split_evenly(self.state.max.width, num_columns.max(0) as u32, margin);
+        self.columns_with_blocks(blocks, margin)
+    }
+
+    /// Splits the available width into columns sized according to `weights`,
+    /// separated by `gap` pixels. Fixed columns are sized first, and the
+    /// remaining width is shared between the flexible columns.
+    pub fn layout_columns_weighted(&mut self, weights: &[Sizing], gap: Px) -> Columns {
+        let blocks = split_weighted(self.state.max.width, weights, gap);
+        self.columns_with_blocks(blocks, gap)
+    }
+
+    fn columns_with_blocks(&mut self, blocks: Vec<(Px, Px)>, margin: Px) -> Columns {
         let max = Extent::new(
             self.state.max.width,
             self.state.max.height - self.state.advancing_y,
@@ -129,7 +212,219 @@ impl<'a, 'b, 'c> TopToBottom<'a, 'b, 'c> {
             y,
             max,
             margin,
-            num_columns,
+            blocks,
+        )
+    }
+
+    /// Splits the remaining height into rows sized according to `weights`,
+    /// separated by `gap` pixels. Each row holds one widget, which fills it.
+    pub fn layout_rows_weighted(&mut self, weights: &[Sizing], gap: Px) -> Grid {
+        let (_, max) = self.state.widget_extent();
+        let area = Rect::new(self.state.x, self.state.advancing_y, max.width, max.height);
+        Grid::with_spans(
+            self.context,
+            self.command_buffer,
+            &mut self.state,
+            area,
+            split_weighted(area.height(), weights, gap),
+            vec![(Px(0), area.width())],
+        )
+    }
+
+    /// Divides the remaining space into a grid of `rows` by `cols` cells of
+    /// equal size, separated by `gap` pixels.
+    pub fn layout_grid(&mut self, rows: u32, cols: u32, gap: Px) -> Grid {
+        let (_, max) = self.state.widget_extent();
+        let area = Rect::new(self.state.x, self.state.advancing_y, max.width, max.height);
+        Grid::begin(
+            self.context,
+            self.command_buffer,
+            &mut self.state,
+            area,
+            rows,
+            cols,
+            gap,
+        )
+    }
+
+    /// Lays out a group of rows, like [`Columns::layout_rows()`], but reuses
+    /// what the group drew last time if `inputs` and its width are unchanged
+    /// and the user isn't interacting with it. `build` is skipped when the
+    /// group is reused, so `inputs` must cover everything that it reads. Use
+    /// [`Context::invalidate()`] to force the group to be rebuilt.
+    ///
+    /// Groups are told apart by their name and the sections, scroll areas and
+    /// groups that they are in, so groups with the same name can be used in
+    /// different places.
+    ///
+    /// Nothing in a group depends on the height of the window or on its
+    /// vertical position, so the group is reused across vertical resizes and
+    /// moved if the widgets above it change size. When the whole UI is resized
+    /// proportionally, and the group's position and width scale with it, the
+    /// group is scaled to match instead of being laid out again. Any other
+    /// change in width lays the group out again, since margins and minimum
+    /// sizes don't scale with it.
+    pub fn cached_rows(
+        &mut self,
+        name: &str,
+        inputs: impl Hash,
+        margin: Px,
+        build: impl FnOnce(&mut TopToBottom),
+    ) {
+        let id = path_id(self.context.path, self.context.named_id(name));
+        let inputs = {
+            let mut hasher = AHasher::default();
+            inputs.hash(&mut hasher);
+            hasher.finish()
+        };
+        let origin = Point::new(self.state.x, self.state.advancing_y);
+        let width = self.state.max.width;
+
+        if let Some(mut cached) = self.context.subtrees.remove(&id) {
+            let scale = if cached.area.x() == origin.x && cached.area.width() == width {
+                Some(1.0)
+            } else {
+                proportional_scale(cached.ui_size, self.context.ui_size).filter(|&scale| {
+                    cached.area.x().scale(scale) == origin.x
+                        && cached.area.width().scale(scale) == width
+                })
+            };
+
+            if let Some(scale) =
+                scale.filter(|_| cached.inputs == inputs && !cached.was_interacting)
+            {
+                let old = cached.area;
+                let area = Rect::from_extent(origin.x, origin.y, old.extent.scale(scale));
+                if !self.context.is_interacting(area, &cached.focus_ids) {
+                    if area != old {
+                        let (dx, dy) = (Px(0) - old.x(), Px(0) - old.y());
+                        for command in &mut cached.commands {
+                            *command = command
+                                .translated(dx, dy)
+                                .scale(scale)
+                                .translated(area.x(), area.y());
+                        }
+                        for radio in &mut cached.radios {
+                            let rect = radio.rect;
+                            let rect = Rect::from_extent(rect.x() + dx, rect.y() + dy, rect.extent)
+                                .scale(scale);
+                            radio.rect = Rect::from_extent(
+                                rect.x() + area.x(),
+                                rect.y() + area.y(),
+                                rect.extent,
+                            );
+                        }
+                        cached.area = area;
+                    }
+
+                    self.command_buffer.extend_from_slice(&cached.commands);
+                    self.context
+                        .focus_order
+                        .extend_from_slice(&cached.focus_ids);
+                    self.context.radios.extend_from_slice(&cached.radios);
+                    self.state.end_child(cached.area.extent);
+                    cached.ui_size = self.context.ui_size;
+                    cached.used = true;
+                    self.context.subtrees.insert(id, cached);
+                    return;
+                }
+            }
+        }
+
+        let first_command = self.command_buffer.len();
+        let first_focus = self.context.focus_order.len();
+        let first_radio = self.context.radios.len();
+        let max = self.state.max;
+        let outer_path = std::mem::replace(&mut self.context.path, id);
+        build(&mut TopToBottom::begin(
+            self.context,
+            self.command_buffer,
+            &mut self.state,
+            origin.x,
+            origin.y,
+            max,
+            margin,
+        ));
+        self.context.path = outer_path;
+
+        let height = self.state.advancing_y - origin.y - self.state.margin;
+        let area = Rect::from_extent(origin.x, origin.y, Extent::new(width, height));
+        let focus_ids = self.context.focus_order[first_focus..].to_vec();
+        let subtree = CachedSubtree {
+            inputs,
+            ui_size: self.context.ui_size,
+            area,
+            commands: self.command_buffer[first_command..].to_vec(),
+            was_interacting: self.context.is_interacting(area, &focus_ids),
+            focus_ids,
+            radios: self.context.radios[first_radio..].to_vec(),
+            used: true,
+        };
+        self.context.subtrees.insert(id, subtree);
+    }
+
+    /// A section with a clickable header that toggles `open`. `body` is only
+    /// called while the section is open, and lays out its widgets top to
+    /// bottom beneath the header, indented by [`COLLAPSING_INDENT`]. A closed
+    /// section takes up no more space than its header.
+    ///
+    /// Sections can be nested in `body` to build a tree.
+    pub fn collapsing(
+        &mut self,
+        label: &str,
+        open: &mut bool,
+        body: impl FnOnce(&mut TopToBottom),
+    ) -> WidgetState {
+        let widget = Header {
+            id: self.context.named_id(label),
+            open: *open,
+            min_size: Extent::new(Px(10), Px(20)),
+        };
+
+        let state = self.widget(label, &widget);
+        *open = state.is_selected();
+
+        if *open {
+            let indent = COLLAPSING_INDENT.min(self.state.max.width);
+            let max = Extent::new(self.state.max.width - indent, self.state.max.height);
+            let (x, y) = (self.state.x + indent, self.state.advancing_y);
+            let margin = self.state.margin;
+            let path = path_id(self.context.path, widget.id);
+            let outer_path = std::mem::replace(&mut self.context.path, path);
+            body(&mut TopToBottom::begin(
+                self.context,
+                self.command_buffer,
+                &mut self.state,
+                x,
+                y,
+                max,
+                margin,
+            ));
+            self.context.path = outer_path;
+        }
+
+        state
+    }
+
+    /// Creates a vertically scrolling region `height` pixels tall. Widgets
+    /// placed in it are laid out top to bottom, and are clipped to the visible
+    /// region.
+    pub fn scroll_area(&mut self, name: &str, height: Px, margin: Px) -> ScrollArea {
+        let (_, max) = self.state.widget_extent();
+        let visible = Rect::new(
+            self.state.x,
+            self.state.advancing_y,
+            max.width,
+            height.min(max.height),
+        );
+        let id = self.context.named_id(name);
+        ScrollArea::begin(
+            self.context,
+            self.command_buffer,
+            &mut self.state,
+            id,
+            visible,
+            margin,
         )
     }
 }
@@ -192,8 +487,9 @@ struct ColumnState {
     margin: Px,
     advancing_x: Px,
     max_widget_height: Px,
-    num_columns: i16,
-    column: i16,
+    /// The offset from `x` and width of each column.
+    blocks: Vec<(Px, Px)>,
+    column: usize,
     max: Extent,
 }
 
@@ -206,7 +502,7 @@ impl<'a, 'b, 'c> Columns<'a, 'b, 'c> {
         y: Px,
         max_size: Extent,
         margin: Px,
-        num_columns: i16,
+        blocks: Vec<(Px, Px)>,
     ) -> Self {
         Self {
             context,
@@ -218,7 +514,7 @@ impl<'a, 'b, 'c> Columns<'a, 'b, 'c> {
                 margin,
                 advancing_x: x,
                 max_widget_height: Px(0),
-                num_columns,
+                blocks,
                 column: 0,
                 max: max_size,
             },
@@ -249,14 +545,14 @@ impl LayoutState for ColumnState {
     }
 
     fn widget_extent(&self) -> (Extent, Extent) {
-        assert!(self.column < self.num_columns, "too many columns");
+        assert!(self.column < self.blocks.len(), "too many columns");
         (Extent::default(), Extent::new(self.block_width(), Px::MAX))
     }
 
     fn position_extent(&mut self, extent: Extent) -> Rect {
         let block_center = self.block_start() + (self.block_width() / 2);
         let point = Point {
-            x: self.x + block_center - (extent.width / 2),
+            x: block_center - (extent.width / 2),
             y: self.y,
         };
 
@@ -270,12 +566,11 @@ impl LayoutState for ColumnState {
 
 impl ColumnState {
     fn block_width(&self) -> Px {
-        let margins = (self.num_columns - 1) * self.margin;
-        (self.max.width - margins) / self.num_columns
+        self.blocks[self.column].1
     }
 
     fn block_start(&self) -> Px {
-        self.x + (self.block_width() + self.margin) * self.column
+        self.x + self.blocks[self.column].0
     }
 }
 
@@ -301,3 +596,901 @@ impl<'a, 'b, 'c> Drop for Columns<'a, 'b, 'c> {
         ))
     }
 }
+
+/// The [`Grid`] layout splits an area into rows and columns of equal size, and
+/// places one widget in each cell. Cells are filled left to right, top to
+/// bottom unless a cell is selected with [`Grid::cell`].
+pub struct Grid<'a, 'b, 'c> {
+    context: &'a mut Context,
+    command_buffer: &'b mut Vec<DrawCommand>,
+    parent: &'c mut dyn LayoutState,
+    state: GridState,
+}
+
+struct GridState {
+    area: Rect,
+    rows: Vec<(Px, Px)>,
+    columns: Vec<(Px, Px)>,
+    next: (usize, usize),
+}
+
+impl<'a, 'b, 'c> Grid<'a, 'b, 'c> {
+    pub fn begin(
+        context: &'a mut Context,
+        command_buffer: &'b mut Vec<DrawCommand>,
+        parent: &'c mut dyn LayoutState,
+        area: Rect,
+        rows: u32,
+        cols: u32,
+        gap: Px,
+    ) -> Self {
+        Self::with_spans(
+            context,
+            command_buffer,
+            parent,
+            area,
+            split_evenly(area.height(), rows, gap),
+            split_evenly(area.width(), cols, gap),
+        )
+    }
+
+    /// Creates a grid with rows and columns at the given offsets from the
+    /// top-left of `area` and with the given sizes.
+    pub fn with_spans(
+        context: &'a mut Context,
+        command_buffer: &'b mut Vec<DrawCommand>,
+        parent: &'c mut dyn LayoutState,
+        area: Rect,
+        rows: Vec<(Px, Px)>,
+        columns: Vec<(Px, Px)>,
+    ) -> Self {
+        Self {
+            context,
+            command_buffer,
+            parent,
+            state: GridState {
+                area,
+                rows,
+                columns,
+                next: (0, 0),
+            },
+        }
+    }
+
+    /// Places the next widget in the cell at `row` and `col`.
+    pub fn cell(&mut self, row: u32, col: u32) -> &mut Self {
+        self.state.next = (row as usize, col as usize);
+        self
+    }
+}
+
+impl GridState {
+    fn cell_rect(&self, row: usize, col: usize) -> Rect {
+        assert!(
+            row < self.rows.len() && col < self.columns.len(),
+            "grid cell ({}, {}) is out of bounds",
+            row,
+            col
+        );
+
+        let (y, height) = self.rows[row];
+        let (x, width) = self.columns[col];
+        Rect::new(self.area.x() + x, self.area.y() + y, width, height)
+    }
+
+    fn advance(&mut self) {
+        let (row, col) = self.next;
+        self.next = if col + 1 < self.columns.len() {
+            (row, col + 1)
+        } else {
+            (row + 1, 0)
+        };
+    }
+}
+
+impl LayoutState for GridState {
+    fn end_child(&mut self, _: Extent) {
+        self.advance();
+    }
+
+    fn widget_extent(&self) -> (Extent, Extent) {
+        let (row, col) = self.next;
+        (Extent::default(), self.cell_rect(row, col).extent)
+    }
+
+    fn position_extent(&mut self, extent: Extent) -> Rect {
+        let (row, col) = self.next;
+        let point = self.cell_rect(row, col).point;
+        self.advance();
+        Rect { point, extent }
+    }
+}
+
+impl<'a, 'b, 'c> Layout for Grid<'a, 'b, 'c> {
+    fn context(&mut self) -> &mut Context {
+        self.context
+    }
+
+    fn state(&mut self) -> &mut dyn LayoutState {
+        &mut self.state
+    }
+
+    fn draw(&mut self, command: DrawCommand) {
+        self.command_buffer.push(command);
+    }
+}
+
+impl<'a, 'b, 'c> Drop for Grid<'a, 'b, 'c> {
+    fn drop(&mut self) {
+        self.parent.end_child(self.state.area.extent)
+    }
+}
+
+/// Splits `total` pixels into `n` spans separated by `gap` pixels, returning
+/// the offset and size of each span. Pixels that don't divide evenly are given
+/// to the first spans so that the spans cover all of `total`.
+fn split_evenly(total: Px, n: u32, gap: Px) -> Vec<(Px, Px)> {
+    if n == 0 {
+        return vec![];
+    }
+
+    let n = n as i32;
+    let available = (i32::from(total) - i32::from(gap) * (n - 1)).max(0);
+    let (size, remainder) = (available / n, available % n);
+
+    let mut offset = 0;
+    (0..n)
+        .map(|i| {
+            let span = size + i32::from(i < remainder);
+            let result = (Px(offset as i16), Px(span as i16));
+            offset += span + i32::from(gap);
+            result
+        })
+        .collect()
+}
+
+/// How much space a column or row takes up in a weighted layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sizing {
+    /// Exactly this many pixels.
+    Fixed(Px),
+    /// A share of the space left over after fixed sizes, in proportion to the
+    /// weights of the other flexible columns or rows.
+    Flex(f32),
+}
+
+/// Splits `total` pixels into spans separated by `gap` pixels according to
+/// `weights`, returning the offset and size of each span. Fixed spans are
+/// allocated first; if they take up more than `total` pixels, flexible spans
+/// are given no space.
+fn split_weighted(total: Px, weights: &[Sizing], gap: Px) -> Vec<(Px, Px)> {
+    let gaps = i32::from(gap) * (weights.len() as i32 - 1).max(0);
+    let mut fixed = 0;
+    let mut flex = 0.0;
+    for weight in weights {
+        match *weight {
+            Sizing::Fixed(size) => fixed += i32::from(size.max(Px(0))),
+            Sizing::Flex(weight) => flex += weight.max(0.0),
+        }
+    }
+
+    let remaining = (i32::from(total) - gaps - fixed).max(0);
+    let mut sizes = weights
+        .iter()
+        .map(|weight| match *weight {
+            Sizing::Fixed(size) => i32::from(size.max(Px(0))),
+            Sizing::Flex(_) if flex <= 0.0 => 0,
+            Sizing::Flex(weight) => (remaining as f32 * weight.max(0.0) / flex) as i32,
+        })
+        .collect::<Vec<_>>();
+
+    // Rounding down may leave a few pixels unused; give them to the first
+    // flexible spans so that the spans cover all of `total`.
+    let flex_used: i32 = weights
+        .iter()
+        .zip(&sizes)
+        .filter(|(weight, _)| matches!(weight, Sizing::Flex(w) if *w > 0.0))
+        .map(|(_, size)| size)
+        .sum();
+    let mut leftover = if flex > 0.0 { remaining - flex_used } else { 0 };
+    for (weight, size) in weights.iter().zip(&mut sizes) {
+        if leftover == 0 {
+            break;
+        }
+
+        if matches!(weight, Sizing::Flex(w) if *w > 0.0) {
+            *size += 1;
+            leftover -= 1;
+        }
+    }
+
+    let mut offset = 0;
+    sizes
+        .into_iter()
+        .map(|size| {
+            let result = (Px(offset as i16), Px(size as i16));
+            offset += size + i32::from(gap);
+            result
+        })
+        .collect()
+}
+
+/// A [`ScrollArea`] displays a fixed-height window into a column of widgets
+/// that may be taller than the window itself. The scroll wheel moves the
+/// window while the cursor is over it.
+pub struct ScrollArea<'a, 'b, 'c> {
+    context: &'a mut Context,
+    command_buffer: &'b mut Vec<DrawCommand>,
+    parent: &'c mut dyn LayoutState,
+    id: u64,
+    visible: Rect,
+    /// The context's hit clip and path from before the area began, restored
+    /// when it ends.
+    outer_hit_clip: Option<Rect>,
+    outer_path: u64,
+    state: ScrollAreaState,
+}
+
+struct ScrollAreaState {
+    x: Px,
+    /// The top of the content, which may be above the visible region.
+    top: Px,
+    width: Px,
+    margin: Px,
+    advancing_y: Px,
+}
+
+impl<'a, 'b, 'c> ScrollArea<'a, 'b, 'c> {
+    pub fn begin(
+        context: &'a mut Context,
+        command_buffer: &'b mut Vec<DrawCommand>,
+        parent: &'c mut dyn LayoutState,
+        id: u64,
+        visible: Rect,
+        margin: Px,
+    ) -> Self {
+        let scroll_delta = context.scroll_delta.1;
+        let is_hovered = context
+            .hit_rect(visible)
+            .map_or(false, |rect| rect.contains_point(context.cursor));
+        let scroll = context.scroll_areas.entry(id).or_default();
+
+        if is_hovered {
+            // Scrolling up moves the content down.
+            scroll.offset -= Px((scroll_delta * f32::from(SCROLL_STEP)).round() as i16);
+        }
+
+        // The content height is from the previous frame, but that's the best
+        // we can do without laying everything out twice.
+        let max_offset = (scroll.content_height - visible.height()).max(Px(0));
+        scroll.offset = scroll.offset.clamp(Px(0), max_offset);
+
+        let top = visible.y() - scroll.offset;
+
+        // Rects are clipped as they're drawn, but the renderer also needs to
+        // know so that it can clip anything we can't.
+        command_buffer.push(DrawCommand::PushClip { rect: visible });
+
+        // Widgets scrolled out of view mustn't take input meant for whatever
+        // is drawn where they would be. An area that is clipped away entirely
+        // leaves nothing to interact with.
+        let outer_hit_clip = context.hit_clip;
+        context.hit_clip = Some(
+            context
+                .hit_rect(visible)
+                .unwrap_or_else(|| Rect::new(visible.x(), visible.y(), Px(0), Px(0))),
+        );
+        let path = path_id(context.path, id);
+        let outer_path = std::mem::replace(&mut context.path, path);
+
+        Self {
+            context,
+            command_buffer,
+            parent,
+            id,
+            visible,
+            outer_hit_clip,
+            outer_path,
+            state: ScrollAreaState {
+                x: visible.x(),
+                top,
+                width: visible.width(),
+                margin,
+                advancing_y: top,
+            },
+        }
+    }
+}
+
+impl LayoutState for ScrollAreaState {
+    fn end_child(&mut self, extent: Extent) {
+        self.advancing_y += extent.height + self.margin;
+    }
+
+    fn widget_extent(&self) -> (Extent, Extent) {
+        (Extent::default(), Extent::new(self.width, Px::MAX))
+    }
+
+    fn position_extent(&mut self, extent: Extent) -> Rect {
+        let point = Point::new(self.x, self.advancing_y);
+        self.advancing_y += extent.height + self.margin;
+        Rect { point, extent }
+    }
+}
+
+impl<'a, 'b, 'c> Layout for ScrollArea<'a, 'b, 'c> {
+    fn context(&mut self) -> &mut Context {
+        self.context
+    }
+
+    fn state(&mut self) -> &mut dyn LayoutState {
+        &mut self.state
+    }
+
+    fn draw(&mut self, command: DrawCommand) {
+        if let Some(command) = command.clipped(self.visible) {
+            self.command_buffer.push(command);
+        }
+    }
+}
+
+impl<'a, 'b, 'c> Drop for ScrollArea<'a, 'b, 'c> {
+    fn drop(&mut self) {
+        let scroll = self.context.scroll_areas.get_mut(&self.id).unwrap();
+        // Like `cached_rows()`, leave out the margin after the last child.
+        scroll.content_height =
+            (self.state.advancing_y - self.state.top - self.state.margin).max(Px(0));
+
+        let max_offset = (scroll.content_height - self.visible.height()).max(Px(0));
+        scroll.offset = scroll.offset.min(max_offset);
+
+        self.context.hit_clip = self.outer_hit_clip;
+        self.context.path = self.outer_path;
+        self.command_buffer.push(DrawCommand::PopClip);
+        self.parent
+            .end_child(Extent::new(Px(0), self.visible.height()))
+    }
+}
+
+/// The factor that `from` was multiplied by to get `to`, if both of its
+/// dimensions were scaled by the same amount, to the nearest pixel.
+fn proportional_scale(from: Extent, to: Extent) -> Option<f32> {
+    if from.width <= Px(0) || from.height <= Px(0) {
+        return None;
+    }
+
+    let scale = f32::from(to.width) / f32::from(from.width);
+    if from.scale(scale) == to {
+        Some(scale)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::px::Logical;
+
+    /// Builds a 40px tall scroll area containing five 20px tall buttons 5px
+    /// apart, and returns the rects that were drawn. The gaps keep rows from
+    /// lining up with [`SCROLL_STEP`], so that scrolling moves them visibly.
+    fn scroll_area_frame(context: &mut Context, scroll: f32) -> Vec<Rect> {
+        let mut commands = vec![];
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .scroll(0.0, scroll);
+        {
+            let mut rows = ui.top_to_bottom(Px(0));
+            let mut area = rows.scroll_area("area", Px(40), Px(5));
+            for name in ["a", "b", "c", "d", "e"] {
+                area.button(name);
+            }
+        }
+
+        ui.build()
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::ColoredRect { rect, .. } | DrawCommand::RoundedRect { rect, .. } => {
+                    Some(*rect)
+                }
+                DrawCommand::PushClip { .. } | DrawCommand::PopClip => None,
+            })
+            .collect()
+    }
+
+    /// Builds a header button followed by a cached group of three buttons,
+    /// returning what was drawn.
+    fn cached_panel_frame(context: &mut Context, inputs: u32) -> Vec<DrawCommand> {
+        resizable_panel_frame(context, Extent::new(Px(100), Px(200)), 1, 3, inputs)
+    }
+
+    /// Like `cached_panel_frame()`, but with a window of `size`, `headers`
+    /// buttons above the group, and `widgets` buttons in the group.
+    fn resizable_panel_frame(
+        context: &mut Context,
+        size: Extent,
+        headers: usize,
+        widgets: usize,
+        inputs: u32,
+    ) -> Vec<DrawCommand> {
+        let mut commands = vec![];
+        let mut ui = context.begin(Logical(size), &mut commands).no_input();
+        {
+            let mut rows = ui.top_to_bottom(Px(5));
+            for name in ["header", "subheader"].iter().take(headers) {
+                rows.button(name);
+            }
+            rows.cached_rows("panel", (widgets, inputs), Px(5), |rows| {
+                for name in ["a", "b", "c", "d"].iter().take(widgets) {
+                    rows.button(name);
+                }
+            });
+            rows.button("footer");
+        }
+
+        ui.build().clone()
+    }
+
+    /// Builds a tree of two nested sections, each containing a button, and
+    /// returns the rects of the buttons' fills.
+    fn tree_frame(
+        context: &mut Context,
+        cursor: Point,
+        lmb_pressed: bool,
+        open: &mut [bool; 2],
+    ) -> Vec<Rect> {
+        let mut commands = vec![];
+        context.is_lmb_pressed = lmb_pressed;
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(200))), &mut commands)
+            .move_cursor(cursor);
+        {
+            let [outer, inner] = open;
+            let mut rows = ui.top_to_bottom(Px(0));
+            rows.collapsing("outer", outer, |rows| {
+                rows.button("a");
+                rows.collapsing("inner", inner, |rows| {
+                    rows.button("b");
+                });
+            });
+            rows.button("footer");
+        }
+
+        ui.build()
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::ColoredRect { rect, .. } if rect.height() == Px(20) => Some(*rect),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collapsed_sections_are_skipped() {
+        let mut context = Context::default();
+        let away = Point::new(Px(0), Px(199));
+
+        // The closed section's body is neither laid out nor drawn, and the
+        // footer moves up to take its place.
+        let mut open = [false, true];
+        let rects = tree_frame(&mut context, away, false, &mut open);
+        assert_eq!(context.widgets_laid_out(), 2);
+        assert_eq!(
+            rects,
+            [
+                Rect::new(Px(0), Px(0), Px(100), Px(20)),
+                Rect::new(Px(0), Px(20), Px(100), Px(20)),
+            ]
+        );
+
+        open = [true, true];
+        let rects = tree_frame(&mut context, away, false, &mut open);
+        assert_eq!(context.widgets_laid_out(), 5);
+        assert_eq!(
+            rects,
+            [
+                Rect::new(Px(0), Px(0), Px(100), Px(20)),
+                Rect::new(Px(16), Px(20), Px(84), Px(20)),
+                Rect::new(Px(16), Px(40), Px(84), Px(20)),
+                Rect::new(Px(32), Px(60), Px(68), Px(20)),
+                Rect::new(Px(0), Px(80), Px(100), Px(20)),
+            ]
+        );
+
+        // Closing the inner section only hides its own body.
+        open = [true, false];
+        tree_frame(&mut context, away, false, &mut open);
+        assert_eq!(context.widgets_laid_out(), 4);
+    }
+
+    #[test]
+    fn clicking_header_toggles_section() {
+        let mut context = Context::default();
+        let header = Point::new(Px(50), Px(10));
+        let mut open = [false, false];
+
+        tree_frame(&mut context, header, true, &mut open);
+        assert_eq!(open, [true, false]);
+
+        // Holding the button down doesn't toggle it again.
+        tree_frame(&mut context, header, true, &mut open);
+        assert_eq!(open, [true, false]);
+
+        tree_frame(&mut context, header, false, &mut open);
+        tree_frame(&mut context, header, true, &mut open);
+        assert_eq!(open, [false, false]);
+    }
+
+    #[test]
+    fn unchanged_subtree_is_reused() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(190));
+
+        let first = cached_panel_frame(&mut context, 1);
+        assert_eq!(context.widgets_laid_out(), 5);
+
+        // Only the header and footer are laid out again, and the result is
+        // the same.
+        let second = cached_panel_frame(&mut context, 1);
+        assert_eq!(context.widgets_laid_out(), 2);
+        assert_eq!(second, first);
+        assert_eq!(context.focus_order.len(), 5);
+
+        cached_panel_frame(&mut context, 2);
+        assert_eq!(context.widgets_laid_out(), 5);
+        cached_panel_frame(&mut context, 2);
+        assert_eq!(context.widgets_laid_out(), 2);
+
+        context.invalidate(&["panel"]);
+        cached_panel_frame(&mut context, 2);
+        assert_eq!(context.widgets_laid_out(), 5);
+
+        // Hovering over the panel rebuilds it, as does moving away again so
+        // that the hover highlight goes away.
+        context.cursor = Point::new(Px(10), Px(40));
+        let hovered = cached_panel_frame(&mut context, 2);
+        assert_eq!(context.widgets_laid_out(), 5);
+        assert_ne!(hovered, first);
+        context.cursor = Point::new(Px(10), Px(190));
+        assert_eq!(cached_panel_frame(&mut context, 2), first);
+        assert_eq!(context.widgets_laid_out(), 5);
+        cached_panel_frame(&mut context, 2);
+        assert_eq!(context.widgets_laid_out(), 2);
+    }
+
+    #[test]
+    fn subtrees_are_keyed_by_path() {
+        // Builds a group called "panel" in each of two sections, returning the
+        // sections whose groups were built.
+        let frame = |context: &mut Context| {
+            let mut commands = vec![];
+            let mut built = vec![];
+            let mut ui = context
+                .begin(Logical(Extent::new(Px(100), Px(200))), &mut commands)
+                .no_input();
+            {
+                let mut rows = ui.top_to_bottom(Px(0));
+                for section in ["left", "right"] {
+                    rows.collapsing(section, &mut true, |rows| {
+                        rows.cached_rows("panel", 0, Px(0), |rows| {
+                            built.push(section);
+                            rows.button(section);
+                        });
+                    });
+                }
+            }
+            ui.build();
+            built
+        };
+
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(99), Px(199));
+
+        // The groups have the same name, inputs and width, but each is built
+        // and cached separately.
+        assert_eq!(frame(&mut context), ["left", "right"]);
+        assert_eq!(context.subtrees.len(), 2);
+        assert!(frame(&mut context).is_empty());
+
+        context.invalidate(&["right", "panel"]);
+        assert_eq!(frame(&mut context), ["right"]);
+        context.invalidate(&["panel"]);
+        assert!(frame(&mut context).is_empty());
+    }
+
+    #[test]
+    fn cached_radios_deselect_others() {
+        // Lays out radio 2, then radio 1 in a cached group, returning whether
+        // radio 2 reported being selected.
+        let frame = |context: &mut Context, current: &mut u32, cursor: Point, pressed: bool| {
+            let mut commands = vec![];
+            context.is_lmb_pressed = pressed;
+            let mut ui = context
+                .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+                .move_cursor(cursor);
+            let mut rows = ui.top_to_bottom(Px(0));
+            let selected = rows.radio("2", current, 2).is_selected();
+            let inputs = *current;
+            rows.cached_rows("group", inputs, Px(0), |rows| {
+                rows.radio("1", current, 1);
+            });
+            selected
+        };
+
+        let mut context = Context::default();
+        let mut current = 2;
+        let away = Point::new(Px(10), Px(90));
+        assert!(frame(&mut context, &mut current, away, false));
+        assert!(frame(&mut context, &mut current, away, false));
+        assert_eq!(context.widgets_laid_out(), 1);
+
+        // Clicking radio 1 deselects radio 2 in the same frame, even though
+        // radio 1 wasn't laid out in the frame before.
+        let one = Point::new(Px(10), Px(30));
+        assert!(!frame(&mut context, &mut current, one, true));
+        assert_eq!(current, 1);
+    }
+
+    #[test]
+    fn unused_subtrees_are_dropped() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(190));
+
+        cached_panel_frame(&mut context, 1);
+        cached_panel_frame(&mut context, 1);
+        assert_eq!(context.subtrees.len(), 1);
+
+        // A frame without the group forgets it.
+        let mut commands = vec![];
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(200))), &mut commands)
+            .no_input();
+        ui.top_to_bottom(Px(5)).button("header");
+        ui.build();
+        assert!(context.subtrees.is_empty());
+
+        // So it is laid out again when it comes back.
+        cached_panel_frame(&mut context, 1);
+        assert_eq!(context.widgets_laid_out(), 5);
+        assert_eq!(context.subtrees.len(), 1);
+    }
+
+    #[test]
+    fn relayout_after_resize() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(290));
+        let size = Extent::new(Px(100), Px(200));
+
+        resizable_panel_frame(&mut context, size, 1, 3, 0);
+        assert_eq!(context.widgets_laid_out(), 5);
+
+        // Making the window taller doesn't change the group.
+        let taller = Extent::new(Px(100), Px(300));
+        let before = resizable_panel_frame(&mut context, size, 1, 3, 0);
+        assert_eq!(resizable_panel_frame(&mut context, taller, 1, 3, 0), before);
+        assert_eq!(context.widgets_laid_out(), 2);
+
+        // Adding a widget above the group moves it down without laying it
+        // out again.
+        let moved = resizable_panel_frame(&mut context, taller, 2, 3, 0);
+        assert_eq!(context.widgets_laid_out(), 3);
+        context.invalidate(&["panel"]);
+        assert_eq!(resizable_panel_frame(&mut context, taller, 2, 3, 0), moved);
+        assert_eq!(context.widgets_laid_out(), 6);
+
+        // Adding a widget to the group, or changing the window's width, lays
+        // it out again.
+        resizable_panel_frame(&mut context, taller, 2, 4, 0);
+        assert_eq!(context.widgets_laid_out(), 7);
+        let wider = resizable_panel_frame(&mut context, Extent::new(Px(150), Px(300)), 2, 4, 0);
+        assert_eq!(context.widgets_laid_out(), 7);
+        assert!(wider.iter().all(|command| match command {
+            DrawCommand::ColoredRect { rect, .. } => rect.width() == Px(150),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn proportional_resize_scales_subtree() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(399));
+        let size = Extent::new(Px(100), Px(200));
+
+        let before = resizable_panel_frame(&mut context, size, 1, 3, 0);
+        assert_eq!(context.widgets_laid_out(), 5);
+
+        // Doubling the size of the UI scales the cached group instead of
+        // laying it out again. The header and footer are laid out as usual.
+        let doubled = Extent::new(Px(200), Px(400));
+        let after = resizable_panel_frame(&mut context, doubled, 1, 3, 0);
+        eprintln!("{:?}\n{:?}", before, after);
+        assert_eq!(context.widgets_laid_out(), 2);
+
+        let origin = Point::new(Px(0), Px(25));
+        let scaled = before[1..4]
+            .iter()
+            .map(|command| {
+                command
+                    .translated(Px(0), Px(0) - origin.y)
+                    .scale(2.0)
+                    .translated(Px(0), origin.y)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(after[1..4], scaled[..]);
+        assert!(after[1..4].iter().all(|command| match command {
+            DrawCommand::ColoredRect { rect, .. } => rect.extent == Extent::new(Px(200), Px(40)),
+            _ => false,
+        }));
+
+        // The footer follows the scaled group, which was 75px tall including
+        // the margin after its last button.
+        match &after[4] {
+            DrawCommand::ColoredRect { rect, .. } => assert_eq!(rect.y(), Px(25 + 150 + 5)),
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        // Scaling back is just as cheap.
+        resizable_panel_frame(&mut context, size, 1, 3, 0);
+        assert_eq!(context.widgets_laid_out(), 2);
+
+        // Adding a widget to the group lays all of it out again.
+        resizable_panel_frame(&mut context, doubled, 1, 4, 0);
+        assert_eq!(context.widgets_laid_out(), 6);
+    }
+
+    #[test]
+    fn grid_cells_tile() {
+        let area = Rect::new(Px(10), Px(20), Px(101), Px(50));
+        let state = GridState {
+            area,
+            rows: split_evenly(area.height(), 2, Px(4)),
+            columns: split_evenly(area.width(), 3, Px(4)),
+            next: (0, 0),
+        };
+
+        // 93 pixels of width over 3 columns, 46 pixels of height over 2 rows.
+        assert_eq!(
+            state.cell_rect(0, 0),
+            Rect::new(Px(10), Px(20), Px(31), Px(23))
+        );
+        assert_eq!(
+            state.cell_rect(0, 1),
+            Rect::new(Px(45), Px(20), Px(31), Px(23))
+        );
+        assert_eq!(
+            state.cell_rect(0, 2),
+            Rect::new(Px(80), Px(20), Px(31), Px(23))
+        );
+        assert_eq!(
+            state.cell_rect(1, 0),
+            Rect::new(Px(10), Px(47), Px(31), Px(23))
+        );
+
+        // Each cell starts exactly one gap after the previous one ends, and the
+        // last cell ends at the edge of the area.
+        for row in 0..2 {
+            for col in 1..3 {
+                let prev = state.cell_rect(row, col - 1);
+                assert_eq!(state.cell_rect(row, col).left(), prev.right() + Px(4));
+            }
+            assert_eq!(state.cell_rect(row, 2).right(), area.right());
+        }
+        assert_eq!(
+            state.cell_rect(1, 0).top(),
+            state.cell_rect(0, 0).bottom() + Px(4)
+        );
+        assert_eq!(state.cell_rect(1, 2).bottom(), area.bottom());
+
+        // Leftover pixels go to the first spans.
+        let spans = split_evenly(Px(10), 3, Px(0));
+        assert_eq!(spans, [(Px(0), Px(4)), (Px(4), Px(3)), (Px(7), Px(3))]);
+    }
+
+    #[test]
+    fn weighted_columns() {
+        let weights = [Sizing::Fixed(Px(100)), Sizing::Flex(1.0), Sizing::Flex(1.0)];
+
+        assert_eq!(
+            split_weighted(Px(500), &weights, Px(0)),
+            [(Px(0), Px(100)), (Px(100), Px(200)), (Px(300), Px(200))]
+        );
+        assert_eq!(
+            split_weighted(Px(500), &weights, Px(10)),
+            [(Px(0), Px(100)), (Px(110), Px(190)), (Px(310), Px(190))]
+        );
+
+        // Leftover pixels go to the first flexible column.
+        assert_eq!(
+            split_weighted(Px(501), &weights, Px(0)),
+            [(Px(0), Px(100)), (Px(100), Px(201)), (Px(301), Px(200))]
+        );
+
+        // Fixed columns that don't fit leave nothing for the flexible ones.
+        assert_eq!(
+            split_weighted(Px(80), &weights, Px(0)),
+            [(Px(0), Px(100)), (Px(100), Px(0)), (Px(100), Px(0))]
+        );
+    }
+
+    #[test]
+    fn scroll_area_scrolls() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(10));
+
+        let row = |y| Rect::new(Px(0), Px(y), Px(100), Px(20));
+        let clipped_row = |y, height| Rect::new(Px(0), Px(y), Px(100), Px(height));
+
+        // Only the first two buttons are visible, and the second is clipped.
+        assert_eq!(
+            scroll_area_frame(&mut context, 0.0),
+            [row(0), clipped_row(25, 15)]
+        );
+
+        // Scrolling down by one notch shifts everything up by one step,
+        // scrolling the first button out of view.
+        assert_eq!(
+            scroll_area_frame(&mut context, -1.0),
+            [row(5), clipped_row(30, 10)]
+        );
+        assert_eq!(
+            context.scroll_areas.values().next().unwrap().offset,
+            SCROLL_STEP
+        );
+
+        // Scrolling past the end clamps to the content height, so the last
+        // button ends at the bottom of the area without a margin after it.
+        assert_eq!(
+            scroll_area_frame(&mut context, -10.0),
+            [clipped_row(0, 15), row(20)]
+        );
+        assert_eq!(context.scroll_areas.values().next().unwrap().offset, Px(80));
+
+        // Scrolling up by half a step brings the fourth button fully into view.
+        assert_eq!(
+            scroll_area_frame(&mut context, 0.5),
+            [row(5), clipped_row(30, 10)]
+        );
+        assert_eq!(context.scroll_areas.values().next().unwrap().offset, Px(70));
+    }
+
+    #[test]
+    fn scroll_area_clips_input() {
+        let mut context = Context::default();
+        let mut commands = vec![];
+
+        // Click just below the area, where the third button would be if it
+        // weren't scrolled out of view.
+        context.is_lmb_pressed = true;
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .move_cursor(Point::new(Px(10), Px(55)));
+        let (hidden, below) = {
+            let mut rows = ui.top_to_bottom(Px(0));
+            let hidden = {
+                let mut area = rows.scroll_area("area", Px(40), Px(5));
+                area.button("a");
+                area.button("b");
+                area.button("c")
+            };
+            (hidden, rows.button("below"))
+        };
+        ui.build();
+
+        assert!(!hidden.is_hovered());
+        assert!(!hidden.is_active());
+        assert!(below.is_hovered());
+        assert!(below.is_active());
+        assert_eq!(context.hit_clip, None);
+    }
+
+    #[test]
+    fn scroll_area_ignores_scroll_outside() {
+        let mut context = Context::default();
+        context.cursor = Point::new(Px(10), Px(50));
+
+        scroll_area_frame(&mut context, 0.0);
+        scroll_area_frame(&mut context, -1.0);
+        assert_eq!(context.scroll_areas.values().next().unwrap().offset, Px(0));
+    }
+}
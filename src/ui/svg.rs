@@ -0,0 +1,185 @@
+use std::fmt::Write;
+
+use crate::{
+    gfx::Color,
+    shapes::{Extent, Rect},
+};
+
+use super::DrawCommand;
+
+/// Writes `commands` out as an SVG document `size` pixels in size, so that a
+/// layout can be inspected in a browser. Clips become nested groups with a
+/// `clip-path`, and are closed at the end if they were never popped.
+pub fn export_svg(commands: &[DrawCommand], size: Extent) -> String {
+    let mut svg = String::new();
+    let mut next_clip_id = 0;
+    let mut depth = 0;
+
+    // Writing to a String can't fail.
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = size.width.0,
+        h = size.height.0,
+    );
+
+    for command in commands {
+        let indent = "  ".repeat(depth + 1);
+        match command {
+            DrawCommand::ColoredRect { rect, color } => {
+                let _ = writeln!(
+                    svg,
+                    "{}<rect {}{}/>",
+                    indent,
+                    rect_attributes(*rect),
+                    fill(*color)
+                );
+            }
+            DrawCommand::RoundedRect {
+                rect,
+                radius,
+                color,
+            } => {
+                // SVG would clamp rx and ry separately, stretching the corners
+                // of narrow rects, so clamp the radius the way the canvas does.
+                let radius = radius
+                    .min(f32::from(rect.width().min(rect.height())) / 2.0)
+                    .max(0.0);
+                let _ = writeln!(
+                    svg,
+                    r#"{}<rect {}rx="{}" {}/>"#,
+                    indent,
+                    rect_attributes(*rect),
+                    radius,
+                    fill(*color)
+                );
+            }
+            DrawCommand::PushClip { rect } => {
+                let _ = writeln!(
+                    svg,
+                    r#"{}<clipPath id="clip{}"><rect {}/></clipPath>"#,
+                    indent,
+                    next_clip_id,
+                    rect_attributes(*rect).trim_end()
+                );
+                let _ = writeln!(
+                    svg,
+                    r#"{}<g clip-path="url(#clip{})">"#,
+                    indent, next_clip_id
+                );
+                next_clip_id += 1;
+                depth += 1;
+            }
+            DrawCommand::PopClip => {
+                if depth > 0 {
+                    depth -= 1;
+                    let _ = writeln!(svg, "{}</g>", "  ".repeat(depth + 1));
+                }
+            }
+        }
+    }
+
+    while depth > 0 {
+        depth -= 1;
+        let _ = writeln!(svg, "{}</g>", "  ".repeat(depth + 1));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn rect_attributes(rect: Rect) -> String {
+    format!(
+        r#"x="{}" y="{}" width="{}" height="{}" "#,
+        rect.x().0,
+        rect.y().0,
+        rect.width().0.max(0),
+        rect.height().0.max(0)
+    )
+}
+
+fn fill(color: Color) -> String {
+    format!(
+        r#"fill="rgba({}, {}, {}, {})" "#,
+        color.r,
+        color.g,
+        color.b,
+        f32::from(color.a) / 255.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::px::Px;
+
+    #[test]
+    fn rects_and_clips() {
+        let commands = [
+            DrawCommand::ColoredRect {
+                rect: Rect::new(Px(10), Px(20), Px(30), Px(40)),
+                color: Color::rgb(255, 0, 0),
+            },
+            DrawCommand::PushClip {
+                rect: Rect::new(Px(0), Px(0), Px(50), Px(50)),
+            },
+            DrawCommand::RoundedRect {
+                rect: Rect::new(Px(5), Px(6), Px(7), Px(8)),
+                radius: 2.5,
+                color: Color::rgba(0, 0, 255, 51),
+            },
+            DrawCommand::PopClip,
+        ];
+
+        let svg = export_svg(&commands, Extent::new(Px(100), Px(80)));
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(r#"width="100" height="80""#));
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let rects = svg
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("<rect "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rects,
+            [
+                r#"<rect x="10" y="20" width="30" height="40" fill="rgba(255, 0, 0, 1)" />"#,
+                r#"<rect x="5" y="6" width="7" height="8" rx="2.5" fill="rgba(0, 0, 255, 0.2)" />"#,
+            ]
+        );
+
+        // The rounded rect is inside the clip group.
+        let clip = svg.find(r#"<g clip-path="url(#clip0)">"#).unwrap();
+        let rounded = svg.find(r#"rx="2.5""#).unwrap();
+        let end = svg.find("</g>").unwrap();
+        assert!(clip < rounded && rounded < end);
+        assert!(svg.contains(
+            r#"<clipPath id="clip0"><rect x="0" y="0" width="50" height="50"/></clipPath>"#
+        ));
+
+        // Unbalanced clips are closed.
+        let svg = export_svg(&commands[..3], Extent::new(Px(100), Px(80)));
+        assert_eq!(svg.matches("<g ").count(), svg.matches("</g>").count());
+    }
+
+    #[test]
+    fn rounded_radius_is_clamped() {
+        let commands = [
+            DrawCommand::RoundedRect {
+                rect: Rect::new(Px(0), Px(0), Px(20), Px(6)),
+                radius: 10.0,
+                color: Color::rgb(0, 0, 0),
+            },
+            DrawCommand::RoundedRect {
+                rect: Rect::new(Px(0), Px(0), Px(20), Px(6)),
+                radius: -1.0,
+                color: Color::rgb(0, 0, 0),
+            },
+        ];
+
+        let svg = export_svg(&commands, Extent::new(Px(20), Px(6)));
+        assert!(svg.contains(r#"rx="3""#));
+        assert!(svg.contains(r#"rx="0""#));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{gfx::Color, px::Px};
+
+/// The colors and sizes that widgets are drawn with. Set with
+/// [`Context::set_theme()`](super::Context::set_theme).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// The fill of a widget that isn't being interacted with.
+    pub background: Color,
+    /// The fill of a widget under the cursor.
+    pub hover: Color,
+    /// The fill of a widget being pressed, and the color of the text caret.
+    pub active: Color,
+    /// The color of the ring drawn around the focused widget.
+    pub border: Color,
+    pub text: Color,
+    /// The width of the ring drawn around the focused widget.
+    pub border_width: Px,
+    /// The space between the edges of a widget and its contents, such as the
+    /// text in a text edit.
+    pub spacing: Px,
+    /// The radius of the corners of buttons made with
+    /// [`button()`](super::Layout::button), or 0 for square corners.
+    pub corner_radius: f32,
+}
+
+impl Theme {
+    /// Light widgets on a dark background. This is the default.
+    pub const fn dark() -> Self {
+        Self {
+            background: Color::rgb(100, 100, 100),
+            hover: Color::rgb(200, 200, 200),
+            active: Color::rgb(100, 100, 255),
+            border: Color::rgb(255, 200, 50),
+            text: Color::rgb(230, 230, 230),
+            border_width: Px(2),
+            spacing: Px(4),
+            corner_radius: 0.0,
+        }
+    }
+
+    /// Dark text on light widgets, for use on a light background.
+    pub const fn light() -> Self {
+        Self {
+            background: Color::rgb(220, 220, 220),
+            hover: Color::rgb(190, 190, 190),
+            active: Color::rgb(60, 110, 230),
+            border: Color::rgb(30, 120, 220),
+            text: Color::rgb(30, 30, 30),
+            border_width: Px(2),
+            spacing: Px(4),
+            corner_radius: 0.0,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
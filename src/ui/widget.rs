@@ -1,24 +1,78 @@
 use crate::{
+    gfx::Color,
     px::Px,
     shapes::{Extent, Rect},
 };
 
-use super::{Active, Available, Context, DrawCommand, ACTIVE_COLOR, HOVER_COLOR, UI_COLOR};
+use super::{Active, Available, Context, DrawCommand, PlacedRadio, Theme, GLYPH_WIDTH};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum State {
-    Idle,
-    Hover,
-    Active,
+/// How the user is interacting with a widget during the current frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct State {
+    hovered: bool,
+    active: bool,
+    focused: bool,
+    selected: bool,
 }
 
 impl State {
-    pub fn is_hover(self) -> bool {
-        self == Self::Hover
+    /// Computes the state of the widget `id` occupying `rect`, activating it
+    /// if it was just clicked.
+    fn interact(id: u64, rect: Rect, context: &mut Context) -> Self {
+        context.focus_order.push(id);
+
+        let hovered = context
+            .hit_rect(rect)
+            .map_or(false, |rect| rect.contains_point(context.cursor));
+        if hovered {
+            context.hover_item = id;
+        }
+
+        let active = if context.active_item == Active(id) {
+            true
+        } else if hovered & (context.active_item == Available) & context.is_lmb_pressed {
+            context.active_item = Active(id);
+            context.focused_item = Some(id);
+            true
+        } else {
+            false
+        };
+
+        Self {
+            hovered,
+            active,
+            focused: context.focused_item == Some(id),
+            selected: false,
+        }
+    }
+
+    /// True if the cursor is over the widget, whether or not it is pressed.
+    pub fn is_hovered(self) -> bool {
+        self.hovered
     }
 
     pub fn is_active(self) -> bool {
-        self == Self::Active
+        self.active
+    }
+
+    /// True if the widget receives keyboard input.
+    pub fn has_focus(self) -> bool {
+        self.focused
+    }
+
+    /// True if the widget is the chosen option, as with radio buttons.
+    pub fn is_selected(self) -> bool {
+        self.selected
+    }
+
+    fn color(self, theme: &Theme) -> Color {
+        if self.active {
+            theme.active
+        } else if self.hovered {
+            theme.hover
+        } else {
+            theme.background
+        }
     }
 }
 
@@ -29,13 +83,15 @@ pub trait Widget<T: Copy> {
 
     fn compute_state(&self, rect: Rect, context: &mut Context) -> T;
 
-    fn draw(&self, state: T, rect: Rect, draw: impl FnMut(DrawCommand));
+    fn draw(&self, state: T, rect: Rect, theme: &Theme, draw: impl FnMut(DrawCommand));
 }
 
 pub struct Button {
     pub id: u64,
     pub min_size: Extent,
     pub max_size: Extent,
+    /// The radius of the button's corners, or 0 for square corners.
+    pub rounded: f32,
 }
 
 impl Widget<State> for Button {
@@ -55,29 +111,307 @@ impl Widget<State> for Button {
     }
 
     fn compute_state(&self, rect: Rect, context: &mut Context) -> State {
-        if context.active_item == Active(self.id) {
-            State::Active
-        } else if rect.contains_point(context.cursor) {
-            context.hover_item = self.id;
-            if (context.active_item == Available) & context.is_lmb_pressed {
-                context.active_item = Active(self.id);
-                State::Active
-            } else {
-                State::Hover
-            }
+        State::interact(self.id, rect, context)
+    }
+
+    fn draw(&self, state: State, rect: Rect, theme: &Theme, mut draw: impl FnMut(DrawCommand)) {
+        let rect = draw_focus_ring(state, rect, theme, &mut draw);
+        if self.rounded > 0.0 {
+            draw(DrawCommand::RoundedRect {
+                rect,
+                radius: self.rounded,
+                color: state.color(theme),
+            });
         } else {
-            State::Idle
+            draw(DrawCommand::ColoredRect {
+                rect,
+                color: state.color(theme),
+            });
         }
     }
+}
 
-    fn draw(&self, state: State, rect: Rect, mut draw: impl FnMut(DrawCommand)) {
-        let color = match state {
-            State::Idle => UI_COLOR,
-            State::Hover => HOVER_COLOR,
-            State::Active => ACTIVE_COLOR,
-        };
+/// Draws a ring around the widget if it has focus, returning the area inside
+/// the ring.
+fn draw_focus_ring(
+    state: State,
+    rect: Rect,
+    theme: &Theme,
+    mut draw: impl FnMut(DrawCommand),
+) -> Rect {
+    if !state.has_focus() {
+        return rect;
+    }
+
+    draw(DrawCommand::ColoredRect {
+        rect,
+        color: theme.border,
+    });
 
-        draw(DrawCommand::ColoredRect { rect, color });
+    let ring = theme
+        .border_width
+        .max(Px(0))
+        .min(rect.width() / 2)
+        .min(rect.height() / 2);
+    Rect::new(
+        rect.x() + ring,
+        rect.y() + ring,
+        rect.width() - ring * 2,
+        rect.height() - ring * 2,
+    )
+}
+
+/// An edit to the contents of a text edit widget.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextInput {
+    Char(char),
+    Paste(String),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+impl TextInput {
+    /// Applies the edit to `buffer`, where `caret` is a byte offset into
+    /// `buffer`. Control characters are ignored since the text is a single
+    /// line.
+    pub fn apply(&self, buffer: &mut String, caret: &mut usize) {
+        *caret = clamp_caret(buffer, *caret);
+
+        let prev_len = buffer[..*caret]
+            .chars()
+            .next_back()
+            .map_or(0, char::len_utf8);
+        let next_len = buffer[*caret..].chars().next().map_or(0, char::len_utf8);
+
+        match self {
+            TextInput::Char(c) => {
+                if !c.is_control() {
+                    buffer.insert(*caret, *c);
+                    *caret += c.len_utf8();
+                }
+            }
+            TextInput::Paste(text) => {
+                let text = text.chars().filter(|c| !c.is_control()).collect::<String>();
+                buffer.insert_str(*caret, &text);
+                *caret += text.len();
+            }
+            TextInput::Backspace => {
+                if prev_len > 0 {
+                    *caret -= prev_len;
+                    buffer.remove(*caret);
+                }
+            }
+            TextInput::Delete => {
+                if next_len > 0 {
+                    buffer.remove(*caret);
+                }
+            }
+            TextInput::Left => *caret -= prev_len,
+            TextInput::Right => *caret += next_len,
+            TextInput::Home => *caret = 0,
+            TextInput::End => *caret = buffer.len(),
+        }
+    }
+}
+
+/// Moves `caret` back onto a character boundary in `buffer`, in case the
+/// buffer was changed since the caret was last placed.
+pub(super) fn clamp_caret(buffer: &str, caret: usize) -> usize {
+    let mut caret = caret.min(buffer.len());
+    while !buffer.is_char_boundary(caret) {
+        caret -= 1;
+    }
+    caret
+}
+
+pub struct TextEdit<'a> {
+    pub id: u64,
+    pub text: &'a str,
+    /// The byte offset of the caret, if the widget has focus.
+    pub caret: Option<usize>,
+    pub min_size: Extent,
+}
+
+impl<'a> Widget<State> for TextEdit<'a> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn compute_size(&self, min: Extent, max: Extent) -> Extent {
+        let size = Extent::new(max.width, min.height.max(self.min_size.height));
+        assert!(size <= max, "widget too big");
+        size
+    }
+
+    fn compute_state(&self, rect: Rect, context: &mut Context) -> State {
+        State::interact(self.id, rect, context)
+    }
+
+    /// Until there's a font to draw with, each character is drawn as a block.
+    fn draw(&self, state: State, rect: Rect, theme: &Theme, mut draw: impl FnMut(DrawCommand)) {
+        let rect = draw_focus_ring(state, rect, theme, &mut draw);
+        draw(DrawCommand::ColoredRect {
+            rect,
+            color: theme.background,
+        });
+
+        let padding = theme.spacing.max(Px(0)).min(rect.height() / 4);
+        let glyph_height = rect.height() - padding * 2;
+        let right = rect.right() - padding;
+        let glyph_x = |index: usize| rect.x() + padding + GLYPH_WIDTH * index as i16;
+
+        for (i, c) in self.text.chars().enumerate() {
+            let x = glyph_x(i);
+            if x + GLYPH_WIDTH > right {
+                break;
+            }
+
+            if !c.is_whitespace() {
+                draw(DrawCommand::ColoredRect {
+                    rect: Rect::new(
+                        x + Px(1),
+                        rect.y() + padding,
+                        GLYPH_WIDTH - Px(2),
+                        glyph_height,
+                    ),
+                    color: theme.text,
+                });
+            }
+        }
+
+        if let Some(caret) = self.caret {
+            let x = glyph_x(self.text[..caret].chars().count());
+            if x < right {
+                draw(DrawCommand::ColoredRect {
+                    rect: Rect::new(x, rect.y() + padding, Px(1), glyph_height),
+                    color: theme.active,
+                });
+            }
+        }
+    }
+}
+
+/// One of a set of mutually exclusive options.
+pub struct Radio {
+    pub id: u64,
+    /// Identifies the radios that share a value. Only one radio in a group is
+    /// selected at a time.
+    pub group: usize,
+    pub selected: bool,
+    pub min_size: Extent,
+}
+
+impl Widget<State> for Radio {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn compute_size(&self, min: Extent, max: Extent) -> Extent {
+        let size = Extent::new(max.width, min.height.max(self.min_size.height));
+        assert!(size <= max, "widget too big");
+        size
+    }
+
+    /// Clicking the radio selects it immediately.
+    fn compute_state(&self, rect: Rect, context: &mut Context) -> State {
+        if let Some(rect) = context.hit_rect(rect) {
+            context.radios.push(PlacedRadio {
+                group: self.group,
+                id: self.id,
+                rect,
+            });
+        }
+        let state = State::interact(self.id, rect, context);
+        State {
+            selected: self.selected | state.active,
+            ..state
+        }
+    }
+
+    /// Draws a square box at the left of the widget, with a dot in it if it is
+    /// selected.
+    fn draw(&self, state: State, rect: Rect, theme: &Theme, mut draw: impl FnMut(DrawCommand)) {
+        let side = rect.height().min(rect.width());
+        let square = Rect::new(rect.x(), rect.y(), side, side);
+        let rect = draw_focus_ring(state, square, theme, &mut draw);
+        draw(DrawCommand::ColoredRect {
+            rect,
+            color: state.color(theme),
+        });
+
+        if state.is_selected() {
+            let inset = rect.width().min(rect.height()) / 4;
+            draw(DrawCommand::ColoredRect {
+                rect: Rect::new(
+                    rect.x() + inset,
+                    rect.y() + inset,
+                    rect.width() - inset * 2,
+                    rect.height() - inset * 2,
+                ),
+                color: theme.text,
+            });
+        }
+    }
+}
+
+/// The clickable header of a collapsible section.
+pub struct Header {
+    pub id: u64,
+    pub open: bool,
+    pub min_size: Extent,
+}
+
+impl Widget<State> for Header {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn compute_size(&self, min: Extent, max: Extent) -> Extent {
+        let size = Extent::new(max.width, min.height.max(self.min_size.height));
+        assert!(size <= max, "widget too big");
+        size
+    }
+
+    /// The header is selected while the section is open. Pressing it toggles
+    /// the section once, no matter how long the button is held.
+    fn compute_state(&self, rect: Rect, context: &mut Context) -> State {
+        let was_active = context.active_item == Active(self.id);
+        let state = State::interact(self.id, rect, context);
+        State {
+            selected: self.open ^ (state.active & !was_active),
+            ..state
+        }
+    }
+
+    /// Draws a bar with a marker at its left, which is a square while the
+    /// section is closed and a line while it is open.
+    fn draw(&self, state: State, rect: Rect, theme: &Theme, mut draw: impl FnMut(DrawCommand)) {
+        let rect = draw_focus_ring(state, rect, theme, &mut draw);
+        draw(DrawCommand::ColoredRect {
+            rect,
+            color: state.color(theme),
+        });
+
+        let side = rect.height().min(rect.width());
+        let inset = side / 4;
+        let marker_height = if state.is_selected() {
+            Px(2).min(side - inset * 2)
+        } else {
+            side - inset * 2
+        };
+        draw(DrawCommand::ColoredRect {
+            rect: Rect::new(
+                rect.x() + inset,
+                rect.y() + (rect.height() - marker_height) / 2,
+                side - inset * 2,
+                marker_height,
+            ),
+            color: theme.text,
+        });
     }
 }
 
@@ -99,21 +433,11 @@ impl Widget<(State, f32)> for SmoothSlider {
     }
 
     fn compute_state(&self, rect: Rect, context: &mut Context) -> (State, f32) {
-        let state = if context.active_item == Active(self.id) {
-            State::Active
-        } else if rect.contains_point(context.cursor) {
-            context.hover_item = self.id;
-            if (context.active_item == Available) & context.is_lmb_pressed {
-                context.active_item = Active(self.id);
-                State::Active
-            } else {
-                State::Hover
-            }
-        } else {
-            State::Idle
-        };
+        let state = State::interact(self.id, rect, context);
 
         if state.is_active() {
+            context.dragged_item = Some(self.id);
+
             let active_area = rect.width() - self.slider_width;
             let proportion =
                 ((context.cursor.x - rect.x()).0 as f32 / active_area.0 as f32).clamp(0.0, 1.0);
@@ -123,27 +447,359 @@ impl Widget<(State, f32)> for SmoothSlider {
         }
     }
 
-    fn draw(&self, state: (State, f32), rect: Rect, mut draw: impl FnMut(DrawCommand)) {
+    fn draw(
+        &self,
+        state: (State, f32),
+        rect: Rect,
+        theme: &Theme,
+        mut draw: impl FnMut(DrawCommand),
+    ) {
         let bar_height = (rect.height() / 3).max(Px(1));
         assert!(rect.height() > bar_height);
         let bar_y = (rect.height() - bar_height) / 2 + rect.y();
         draw(DrawCommand::ColoredRect {
             rect: Rect::new(rect.x(), bar_y, rect.width(), bar_height),
-            color: UI_COLOR,
+            color: if state.0.has_focus() {
+                theme.border
+            } else {
+                theme.background
+            },
         });
 
         let slider_width = Px(5);
         let active_area = rect.width() - self.slider_width;
         let slider_x = rect.x() + (Px((state.1 * active_area.0 as f32) as i16));
-        let slider_color = match state.0 {
-            State::Idle => UI_COLOR,
-            State::Hover => HOVER_COLOR,
-            State::Active => ACTIVE_COLOR,
-        };
-
         draw(DrawCommand::ColoredRect {
             rect: Rect::new(slider_x, rect.y(), slider_width, rect.height()),
-            color: slider_color,
+            color: state.0.color(theme),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        px::Logical,
+        shapes::Point,
+        ui::{Builder, InputHandler, Layout},
+    };
+
+    /// Draws a single 100x20 button at the origin and returns its state along
+    /// with the color it was drawn in.
+    fn button_frame(context: &mut Context, cursor: Point, lmb_pressed: bool) -> (State, Color) {
+        let mut commands = vec![];
+        context.is_lmb_pressed = lmb_pressed;
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .move_cursor(cursor);
+
+        let state = ui.top_to_bottom(Px(0)).button("button");
+        // Clicking a button focuses it, which draws a focus ring first.
+        match ui.build().as_slice() {
+            [.., DrawCommand::ColoredRect { color, .. }] => (state, *color),
+            commands => panic!("unexpected draw commands: {:?}", commands),
+        }
+    }
+
+    #[test]
+    fn button_hover() {
+        let mut context = Context::default();
+
+        let (state, color) = button_frame(&mut context, Point::new(Px(10), Px(10)), false);
+        assert!(state.is_hovered());
+        assert!(!state.is_active());
+        assert_eq!(color, Theme::dark().hover);
+
+        let (state, color) = button_frame(&mut context, Point::new(Px(10), Px(50)), false);
+        assert!(!state.is_hovered());
+        assert_eq!(color, Theme::dark().background);
+
+        // Pressing the button doesn't stop it from being hovered.
+        let (state, color) = button_frame(&mut context, Point::new(Px(10), Px(10)), true);
+        assert!(state.is_hovered());
+        assert!(state.is_active());
+        assert_eq!(color, Theme::dark().active);
+    }
+
+    #[test]
+    fn theme_changes_button_colors() {
+        let mut context = Context::default();
+        let cursor = Point::new(Px(10), Px(50));
+
+        let (_, color) = button_frame(&mut context, cursor, false);
+        assert_eq!(color, Theme::dark().background);
+
+        context.set_theme(Theme::light());
+        let (_, color) = button_frame(&mut context, cursor, false);
+        assert_eq!(color, Theme::light().background);
+        assert_ne!(Theme::light().background, Theme::dark().background);
+
+        let (_, color) = button_frame(&mut context, Point::new(Px(10), Px(10)), false);
+        assert_eq!(color, Theme::light().hover);
+
+        // A rounded theme rounds every button, not just rounded ones.
+        context.set_theme(Theme {
+            corner_radius: 3.0,
+            ..Theme::light()
         });
+        let mut commands = vec![];
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .move_cursor(cursor);
+        ui.top_to_bottom(Px(0)).button("button");
+        assert!(matches!(
+            ui.build().as_slice(),
+            [DrawCommand::RoundedRect { radius, .. }] if *radius == 3.0
+        ));
+    }
+
+    #[test]
+    fn slider_drag_captures_mouse() {
+        let mut context = Context::default();
+        let mut value = 0.5;
+
+        let mut frame = |context: &mut Context, cursor: Point, lmb_pressed: bool| {
+            let mut commands = vec![];
+            context.is_lmb_pressed = lmb_pressed;
+            let mut ui = context
+                .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+                .move_cursor(cursor);
+            ui.top_to_bottom(Px(0)).smooth_slider("slider", &mut value);
+        };
+
+        frame(&mut context, Point::new(Px(10), Px(10)), false);
+        assert!(!context.wants_mouse_capture());
+
+        frame(&mut context, Point::new(Px(10), Px(10)), true);
+        assert!(context.wants_mouse_capture());
+
+        // The drag continues when the cursor leaves the window.
+        frame(&mut context, Point::new(Px(200), Px(10)), true);
+        assert!(context.wants_mouse_capture());
+
+        frame(&mut context, Point::new(Px(200), Px(10)), false);
+        assert!(!context.wants_mouse_capture());
+        assert_eq!(value, 1.0);
+    }
+
+    #[test]
+    fn rounded_button() {
+        let mut context = Context::default();
+        let mut commands = vec![];
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .no_input();
+        ui.top_to_bottom(Px(0)).rounded_button("button", 4.0);
+
+        match ui.build().as_slice() {
+            [DrawCommand::RoundedRect { rect, radius, .. }] => {
+                assert_eq!(*rect, Rect::new(Px(0), Px(0), Px(100), Px(20)));
+                assert_eq!(*radius, 4.0);
+            }
+            commands => panic!("unexpected draw commands: {:?}", commands),
+        }
+    }
+
+    /// Draws three buttons and returns the names of the ones with focus.
+    fn focus_frame(
+        context: &mut Context,
+        input: impl for<'a, 'b> FnOnce(InputHandler<'a, 'b>) -> Builder<'a, 'b>,
+    ) -> Vec<&'static str> {
+        let mut commands = vec![];
+        let mut ui = input(context.begin(Logical(Extent::new(Px(100), Px(100))), &mut commands));
+        let mut rows = ui.top_to_bottom(Px(0));
+
+        ["a", "b", "c"]
+            .into_iter()
+            .filter(|name| rows.button(name).has_focus())
+            .collect()
+    }
+
+    #[test]
+    fn tab_navigation() {
+        let mut context = Context::default();
+
+        assert!(focus_frame(&mut context, |input| input.no_input()).is_empty());
+        assert_eq!(focus_frame(&mut context, |input| input.focus_next()), ["a"]);
+        assert_eq!(focus_frame(&mut context, |input| input.no_input()), ["a"]);
+        assert_eq!(focus_frame(&mut context, |input| input.focus_next()), ["b"]);
+        assert_eq!(focus_frame(&mut context, |input| input.focus_next()), ["c"]);
+        assert_eq!(focus_frame(&mut context, |input| input.focus_next()), ["a"]);
+        assert_eq!(
+            focus_frame(&mut context, |input| input.focus_previous()),
+            ["c"]
+        );
+    }
+
+    #[test]
+    fn focus_ring() {
+        let mut context = Context::default();
+        focus_frame(&mut context, |input| input.no_input());
+
+        let mut commands = vec![];
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .focus_next();
+        ui.top_to_bottom(Px(0)).button("a");
+
+        let rects = ui
+            .build()
+            .iter()
+            .map(|command| match command {
+                DrawCommand::ColoredRect { rect, color } => (*rect, *color),
+                _ => panic!("unexpected draw command: {:?}", command),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rects,
+            [
+                (
+                    Rect::new(Px(0), Px(0), Px(100), Px(20)),
+                    Theme::dark().border
+                ),
+                (Rect::new(Px(2), Px(2), Px(96), Px(16)), Theme::dark().hover),
+            ]
+        );
+    }
+
+    /// Sends each input to a focused text edit in its own frame, returning the
+    /// final contents of the buffer.
+    fn edit_text(text: &str, inputs: impl IntoIterator<Item = TextInput>) -> String {
+        let mut context = Context::default();
+        let mut buffer = text.to_string();
+        let mut commands = vec![];
+
+        // Register the widget, then give it focus.
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .no_input();
+        ui.top_to_bottom(Px(0)).text_edit("edit", &mut buffer);
+        ui.build();
+
+        let mut ui = context
+            .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+            .focus_next();
+        ui.top_to_bottom(Px(0)).text_edit("edit", &mut buffer);
+        ui.build();
+
+        for input in inputs {
+            let mut ui = context
+                .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+                .text_input(input);
+            ui.top_to_bottom(Px(0)).text_edit("edit", &mut buffer);
+            ui.build();
+        }
+
+        buffer
+    }
+
+    #[test]
+    fn text_edit_typing() {
+        use TextInput::*;
+
+        assert_eq!(edit_text("", "hello".chars().map(Char)), "hello");
+        assert_eq!(
+            edit_text("helo", [Left, Char('l'), End, Char('!'), Home, Char('>')]),
+            ">hello!"
+        );
+        assert_eq!(
+            edit_text("abc", [Home, Delete, End, Backspace, Left, Left, Right]),
+            "b"
+        );
+
+        // Control characters (like the one Ctrl+V sends) are ignored.
+        assert_eq!(
+            edit_text("ab", [Char('\u{16}'), Paste("c\r\nd".to_string())]),
+            "abcd"
+        );
+    }
+
+    #[test]
+    fn text_edit_multibyte() {
+        use TextInput::*;
+
+        assert_eq!(edit_text("añ🍁", [Backspace]), "añ");
+        assert_eq!(edit_text("añ🍁", [Left, Backspace]), "a🍁");
+        assert_eq!(edit_text("añ🍁", [Home, Right, Delete, Char('é')]), "aé🍁");
+    }
+
+    #[test]
+    fn radio_group() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum Choice {
+            A,
+            B,
+            C,
+        }
+
+        let mut context = Context::default();
+        let mut current = Choice::A;
+
+        // Each radio is 20px tall, so the second one is at y = 20..40. Returns
+        // which radios reported being selected, and which were drawn with a
+        // dot in them.
+        let mut frame = |current: &mut Choice, cursor: Point, lmb_pressed: bool| {
+            let mut commands = vec![];
+            context.is_lmb_pressed = lmb_pressed;
+            let mut ui = context
+                .begin(Logical(Extent::new(Px(100), Px(100))), &mut commands)
+                .move_cursor(cursor);
+            let selected = {
+                let mut rows = ui.top_to_bottom(Px(0));
+                [Choice::A, Choice::B, Choice::C].map(|choice| {
+                    rows.radio(&format!("{:?}", choice), current, choice)
+                        .is_selected()
+                })
+            };
+
+            let mut dots = [false; 3];
+            for command in ui.build().iter() {
+                if let DrawCommand::ColoredRect { rect, color } = command {
+                    if *color == Theme::dark().text {
+                        dots[(rect.y().0 / 20) as usize] = true;
+                    }
+                }
+            }
+            (selected, dots)
+        };
+
+        let b = Point::new(Px(10), Px(30));
+        let away = Point::new(Px(10), Px(70));
+        assert_eq!(
+            frame(&mut current, away, false),
+            ([true, false, false], [true, false, false])
+        );
+
+        // The previously selected radio comes first, and is already cleared on
+        // the frame that the click happens.
+        frame(&mut current, b, false);
+        assert_eq!(
+            frame(&mut current, b, true),
+            ([false, true, false], [false, true, false])
+        );
+        assert_eq!(current, Choice::B);
+        assert_eq!(
+            frame(&mut current, b, false),
+            ([false, true, false], [false, true, false])
+        );
+
+        // The previously selected radio comes after the clicked one.
+        current = Choice::C;
+        frame(&mut current, b, false);
+        assert_eq!(
+            frame(&mut current, b, true),
+            ([false, true, false], [false, true, false])
+        );
+        assert_eq!(current, Choice::B);
+
+        // Pressing outside of the group doesn't deselect anything.
+        frame(&mut current, away, false);
+        assert_eq!(
+            frame(&mut current, away, true),
+            ([false, true, false], [false, true, false])
+        );
+        assert_eq!(current, Choice::B);
     }
 }